@@ -0,0 +1,261 @@
+//! Audits a block archive for gaps, conflicting duplicates and breaks in the parent-hash
+//! chain, and repairs it from an endpoint.
+//!
+//! An archive is a directory (or a single file) of JSON lines as gavel writes them:
+//! `subscribe heads --output` with or without `--rotate` (rotated `.N.gz` files included)
+//! or a capture's `blocks.jsonl`. Every record with a `number` and `hash` counts. The
+//! parent hash is taken from `header`, `block.header` or `parent_hash`. Best-head
+//! records are skipped, as competing forks at the same height are expected there.
+//!
+//! Repairs are appended as records marked `"repaired": true`, which take precedence
+//! over whatever else the archive holds at their height.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::probe::Target;
+
+/// Unparseable lines reported by location; the rest are only counted.
+const MAX_INVALID_SHOWN: usize = 20;
+
+struct Record {
+    hash: String,
+    parent: Option<String>,
+    repaired: bool,
+    source: String,
+}
+
+struct Archive {
+    heights: BTreeMap<u64, Vec<Record>>,
+    chains: BTreeSet<String>,
+    files: usize,
+    records: usize,
+    skipped_best: usize,
+    invalid: Vec<String>,
+    invalid_lines: usize,
+}
+
+/// The archive's files: `path` itself, or the regular files in it sorted by name.
+fn files(path: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn load(path: &Path) -> Result<Archive, Box<dyn std::error::Error>> {
+    let mut archive = Archive {
+        heights: BTreeMap::new(),
+        chains: BTreeSet::new(),
+        files: 0,
+        records: 0,
+        skipped_best: 0,
+        invalid: Vec::new(),
+        invalid_lines: 0,
+    };
+    for file in files(path)? {
+        let data = std::fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let data = if data.starts_with(&[0x1f, 0x8b]) { crate::gzip::decompress(&data)? } else { data };
+        archive.files += 1;
+        let name = file.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        for (index, line) in String::from_utf8_lossy(&data).lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let source = format!("{}:{}", name, index + 1);
+            let record: Value = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(_) => {
+                    archive.invalid_lines += 1;
+                    if archive.invalid.len() < MAX_INVALID_SHOWN {
+                        archive.invalid.push(source);
+                    }
+                    continue;
+                }
+            };
+            let (Some(number), Some(hash)) = (record["number"].as_u64(), record["hash"].as_str()) else { continue };
+            if record["kind"] == "best" {
+                archive.skipped_best += 1;
+                continue;
+            }
+            if let Some(chain) = record["chain"].as_str() {
+                archive.chains.insert(chain.to_string());
+            }
+            let parent = record["header"]["parentHash"].as_str()
+                .or_else(|| record["block"]["header"]["parentHash"].as_str())
+                .or_else(|| record["parent_hash"].as_str())
+                .map(str::to_string);
+            archive.records += 1;
+            archive.heights.entry(number).or_default().push(Record {
+                hash: hash.to_string(),
+                parent,
+                repaired: record["repaired"] == true,
+                source,
+            });
+        }
+    }
+    Ok(archive)
+}
+
+/// Consecutive heights merged into `[from, to]` ranges.
+fn ranges(heights: &BTreeSet<u64>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &height in heights {
+        match ranges.last_mut() {
+            Some((_, to)) if *to + 1 == height => *to = height,
+            _ => ranges.push((height, height)),
+        }
+    }
+    ranges
+}
+
+fn ranges_json(ranges: &[(u64, u64)]) -> Vec<Value> {
+    ranges.iter().map(|(from, to)| json!({ "from": from, "to": to, "blocks": to - from + 1 })).collect()
+}
+
+/// The heights to fetch again and the report on what is wrong with the archive.
+fn audit(archive: &Archive) -> (BTreeSet<u64>, Value) {
+    let mut refetch = BTreeSet::new();
+
+    // The hash each height settles on: the latest repair, else the only one recorded
+    let mut canonical: BTreeMap<u64, &Record> = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    let mut superseded = 0;
+    for (&number, records) in &archive.heights {
+        if let Some(repaired) = records.iter().rev().find(|record| record.repaired) {
+            superseded += records.iter().filter(|record| record.hash != repaired.hash).count();
+            canonical.insert(number, repaired);
+            continue;
+        }
+        let hashes: BTreeSet<&str> = records.iter().map(|record| record.hash.as_str()).collect();
+        if hashes.len() == 1 {
+            canonical.insert(number, &records[0]);
+        } else {
+            refetch.insert(number);
+            duplicates.push(json!({
+                "number": number,
+                "hashes": hashes.iter().map(|hash| json!({
+                    "hash": hash,
+                    "sources": records.iter().filter(|record| record.hash == *hash).map(|record| record.source.as_str()).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+            }));
+        }
+    }
+
+    let first = archive.heights.keys().next().copied();
+    let last = archive.heights.keys().next_back().copied();
+    let missing: BTreeSet<u64> = match (first, last) {
+        (Some(first), Some(last)) => (first..=last).filter(|number| !archive.heights.contains_key(number)).collect(),
+        _ => BTreeSet::new(),
+    };
+    refetch.extend(&missing);
+
+    let mut breaks = Vec::new();
+    for (&number, record) in &canonical {
+        let (Some(parent), Some(previous)) = (record.parent.as_deref(), number.checked_sub(1).and_then(|previous| canonical.get(&previous))) else { continue };
+        if !parent.eq_ignore_ascii_case(&previous.hash) {
+            // Either side may be the stale one, both are fetched again
+            refetch.extend([number - 1, number]);
+            breaks.push(json!({
+                "number": number,
+                "hash": record.hash,
+                "parent_hash": parent,
+                "previous_hash": previous.hash,
+                "source": record.source,
+                "previous_source": previous.source,
+            }));
+        }
+    }
+
+    let report = json!({
+        "files": archive.files,
+        "records": archive.records,
+        "skipped_best_heads": archive.skipped_best,
+        "invalid_lines": archive.invalid_lines,
+        "invalid": archive.invalid,
+        "chains": archive.chains,
+        "first": first,
+        "last": last,
+        "heights": archive.heights.len(),
+        "superseded_by_repairs": superseded,
+        "missing": ranges_json(&ranges(&missing)),
+        "missing_blocks": missing.len(),
+        "duplicates": duplicates,
+        "breaks": breaks,
+    });
+    (refetch, report)
+}
+
+/// Scans the archive at `path` and reports its gaps, conflicting duplicates and
+/// parent-hash breaks, with the plan `repair` would carry out.
+pub fn verify(path: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let archive = load(path)?;
+    if archive.records == 0 {
+        return Err(format!("No block records found in {}", path.display()).into());
+    }
+    let (refetch, mut report) = audit(&archive);
+    report["ok"] = json!(refetch.is_empty() && archive.invalid_lines == 0 && archive.chains.len() <= 1);
+    report["plan"] = json!({ "refetch": ranges_json(&ranges(&refetch)), "blocks": refetch.len() });
+    Ok(report)
+}
+
+/// Carries out the plan of `verify` against an endpoint: fetches the canonical hash and
+/// header of every planned height and appends them to `repair-<unix ms>.jsonl` in the
+/// archive directory.
+pub async fn repair(path: &Path, target: &Target<'_>) -> Result<Value, Box<dyn std::error::Error>> {
+    if !path.is_dir() {
+        return Err(format!("{} is not a directory, repairs are written next to the archive's files", path.display()).into());
+    }
+    let archive = load(path)?;
+    let (refetch, _) = audit(&archive);
+    if refetch.is_empty() {
+        return Ok(json!({ "repaired": 0, "file": null }));
+    }
+    if archive.chains.len() > 1 {
+        return Err(format!("The archive mixes {} chains, repair them separately", archive.chains.len()).into());
+    }
+
+    let mut socket = crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?;
+    let genesis = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([0])).await?;
+    let genesis = genesis.as_str().ok_or("Invalid genesis hash")?.to_string();
+    if let Some(chain) = archive.chains.iter().next().filter(|chain| !chain.eq_ignore_ascii_case(&genesis)) {
+        return Err(format!("{} serves genesis {}, the archive is of {}", target.endpoint, genesis, chain).into());
+    }
+
+    let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let file = path.join(format!("repair-{}.jsonl", unix_ms));
+    let mut output = std::io::BufWriter::new(std::fs::File::create(&file).map_err(|e| format!("Failed to create {}: {}", file.display(), e))?);
+    let mut unavailable = Vec::new();
+    eprintln!("Repairing {} blocks from {} into {}", refetch.len(), crate::redact::text(target.endpoint), file.display());
+    for (index, &number) in refetch.iter().enumerate() {
+        let hash = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([number])).await?;
+        let Some(hash) = hash.as_str().map(str::to_string) else {
+            // Pruned or not yet produced
+            unavailable.push(number);
+            continue;
+        };
+        let header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([hash])).await?;
+        let record = json!({ "chain": genesis, "kind": "finalized", "number": number, "hash": hash, "header": header, "repaired": true });
+        writeln!(output, "{}", record)?;
+        if (index + 1) % 1000 == 0 {
+            eprintln!("Repaired {}/{} blocks", index + 1, refetch.len());
+        }
+    }
+    output.flush()?;
+
+    Ok(json!({
+        "file": file,
+        "repaired": refetch.len() - unavailable.len(),
+        "unavailable": ranges_json(&ranges(&unavailable.into_iter().collect())),
+    }))
+}
@@ -0,0 +1,366 @@
+//! Upcoming Aura authoring slots. Aura gives slot `s` to authority `s % authorities`, so a
+//! collator's next slots follow from the authority set and the slot duration alone, for
+//! scheduling restarts between them.
+//!
+//! Authorities are session keys; `Session::KeyOwner` maps each back to its collator's
+//! account, so `--validator` takes either.
+//!
+//! `watch` follows the chain instead: who authored each block, which slots went empty and
+//! whose they were, and with the relay chain how long blocks took to be included.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::scale::{self, Input};
+use crate::{clock, epoch, ss58, storage};
+
+/// Upcoming slots listed when none is asked for.
+pub const DEFAULT_COUNT: usize = 10;
+
+/// Para blocks remembered for matching relay inclusions to.
+const MAX_RECENT_BLOCKS: usize = 256;
+
+#[derive(clap::Args, Debug)]
+pub struct WatchOptions {
+    #[clap(long, help = "Relay chain endpoint, to measure how long the para's blocks take to be included.")]
+    pub relay: Option<String>,
+    #[clap(long, help = "SS58 address of our collator account or its Aura key, alerted on when it stops authoring.")]
+    pub validator: Option<String>,
+    #[clap(long, default_value = "5m", value_parser = clock::parse_duration, help = "Time without a block authored by --validator before alerting, e.g. 90s or 10m.")]
+    pub threshold: Duration,
+    #[clap(long, default_value = "6s", value_parser = clock::parse_duration, help = "Time between polls for new blocks.")]
+    pub interval: Duration,
+}
+
+/// The account each Aura authority key belongs to, if the runtime records it.
+async fn owners(socket: &mut Connection, metadata: &Metadata, authorities: &[[u8; 32]], at: Option<&str>) -> Result<Vec<Option<[u8; 32]>>, Box<dyn std::error::Error>> {
+    if metadata.storage_entry("Session", "KeyOwner").is_none() {
+        return Ok(vec![None; authorities.len()]);
+    }
+    let mut keys = Vec::new();
+    for authority in authorities {
+        // The map key is the (KeyTypeId, Vec<u8>) pair, SCALE encoded
+        let mut key = b"aura".to_vec();
+        key.extend(scale::encode_compact(authority.len() as u128));
+        key.extend(authority);
+        keys.push(metadata.storage_key("Session", "KeyOwner", &[key])?);
+    }
+    let mut owners = Vec::new();
+    for chunk in keys.chunks(storage::MAX_PAGE_SIZE) {
+        for value in storage::query_many(socket, chunk, at).await? {
+            owners.push(value.as_deref().map(|bytes| Input::new(bytes).read_array::<32>()).transpose()?);
+        }
+    }
+    Ok(owners)
+}
+
+/// The Aura authorities at a block, with the collator account each key belongs to if the
+/// runtime records it.
+struct AuthoritySet {
+    prefix: u16,
+    authorities: Vec<[u8; 32]>,
+    owners: Vec<Option<[u8; 32]>>,
+}
+
+impl AuthoritySet {
+    async fn read(socket: &mut Connection, metadata: &Metadata, at: Option<&str>) -> Result<AuthoritySet, Box<dyn std::error::Error>> {
+        let authorities = read_authorities(socket, metadata, at).await?;
+        let owners = owners(socket, metadata, &authorities, at).await?;
+        let prefix = metadata.constant_u64("System", "SS58Prefix").unwrap_or(42) as u16;
+        Ok(AuthoritySet { prefix, authorities, owners })
+    }
+
+    fn len(&self) -> u64 {
+        self.authorities.len() as u64
+    }
+
+    /// The index of the authority that is `account` or belongs to it.
+    fn position(&self, account: &[u8; 32]) -> Option<usize> {
+        (0..self.authorities.len()).find(|&index| self.authorities[index] == *account || self.owners[index] == Some(*account))
+    }
+
+    fn author(&self, index: usize) -> Value {
+        json!({
+            "index": index,
+            "authority": ss58::encode(self.prefix, &self.authorities[index]),
+            "owner": self.owners[index].map(|owner| ss58::encode(self.prefix, &owner)),
+        })
+    }
+
+    /// The author of `slot`.
+    fn slot_author(&self, slot: u64) -> usize {
+        (slot % self.len()) as usize
+    }
+}
+
+async fn read_authorities(socket: &mut Connection, metadata: &Metadata, at: Option<&str>) -> Result<Vec<[u8; 32]>, Box<dyn std::error::Error>> {
+    let authorities = match storage::read_raw(socket, metadata, "Aura", "Authorities", &[], at).await? {
+        Some(bytes) => Input::new(&bytes).read_list(|input| input.read_array::<32>())?,
+        None => Vec::new(),
+    };
+    if authorities.is_empty() {
+        return Err("Aura::Authorities is empty".into());
+    }
+    Ok(authorities)
+}
+
+pub async fn next(socket: &mut Connection, validator: Option<&str>, count: usize, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.storage_entry("Aura", "Authorities").is_none() {
+        return Err("Aura pallet not found, the chain does not use Aura".into());
+    }
+    let slot_duration_ms = epoch::slot_duration_ms(&metadata).ok_or("Unable to determine the slot duration")?;
+    let set = AuthoritySet::read(socket, &metadata, at).await?;
+    let current_slot = storage::read_u64(socket, &metadata, "Aura", "CurrentSlot", &[], at).await?.ok_or("Aura::CurrentSlot not found")?;
+    let block = crate::fetch_header_number(socket, at).await?;
+    let now = storage::read_u64(socket, &metadata, "Timestamp", "Now", &[], at).await?.unwrap_or(current_slot * slot_duration_ms);
+
+    let slot = |slot: u64| {
+        let start = slot * slot_duration_ms;
+        json!({
+            "slot": slot,
+            "starts_at": clock::format_unix_millis(start),
+            "starts_in": clock::format_duration(start.saturating_sub(now) / 1000),
+            "author": set.author(set.slot_author(slot)),
+        })
+    };
+
+    let rotation = set.len();
+    let mut result = json!({
+        "block": block,
+        "current_slot": current_slot,
+        "current_author": set.author(set.slot_author(current_slot)),
+        "slot_duration_ms": slot_duration_ms,
+        "authorities": rotation,
+    });
+    match validator {
+        Some(validator) => {
+            let index = set.position(&ss58::parse_account(validator)?)
+                .ok_or_else(|| format!("{} is not an Aura authority or the owner of one at block {}", validator, block))?;
+            // The first slot after the current one that is this authority's, then every rotation
+            let first = current_slot + 1 + (index as u64 + rotation - (current_slot + 1) % rotation) % rotation;
+            result["validator"] = set.author(index);
+            result["next_slots"] = (0..count as u64).map(|n| slot(first + n * rotation)).collect();
+            // Between two of its slots the collator can restart without missing one
+            result["between_slots"] = json!(clock::format_duration((rotation - 1) * slot_duration_ms / 1000));
+        }
+        None => result["next_slots"] = (1..=count as u64).map(|n| slot(current_slot + n)).collect(),
+    }
+    Ok(result)
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// The Aura slot a header was authored in, from its pre-runtime digest.
+fn header_slot(header: &mut Value) -> Option<u64> {
+    crate::digest::decode_header_logs(header);
+    header["digest"]["logs"].as_array()?.iter()
+        .find(|log| log["PreRuntime"]["engine"] == "aura")
+        .and_then(|log| log["PreRuntime"]["data"]["slot"].as_u64())
+}
+
+/// The number of the para block in a `CandidateIncluded` event, from the head data (the
+/// encoded header, whose number follows the parent hash).
+fn included_number(fields: &Value) -> Option<u64> {
+    let head = crate::hex::decode(fields.get(1)?.as_str()?).ok()?;
+    let mut input = Input::new(&head);
+    input.read_bytes(32).ok()?;
+    input.read_compact().ok().map(|number| number as u64)
+}
+
+/// What the watch keeps between polls of the para chain.
+struct Collators {
+    set: AuthoritySet,
+    ours: Option<[u8; 32]>,
+    // Slot of the last block seen, to find the empty ones after it
+    last_slot: Option<u64>,
+    // Timestamps of recent blocks by number, for inclusion latency
+    recent: BTreeMap<u64, u64>,
+    // When our collator last authored, silence counts from the start of the watch until then
+    started_ms: u64,
+    last_ours_ms: Option<u64>,
+    alerted: bool,
+}
+
+impl Collators {
+    fn our_index(&self) -> Option<usize> {
+        self.ours.and_then(|account| self.set.position(&account))
+    }
+
+    async fn record_block(&mut self, socket: &mut Connection, metadata: &Metadata, number: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let hash = crate::fetch_block_hash(socket, number).await?;
+        let mut header = crate::send_and_receive(socket, "chain_getHeader", json!([hash])).await?;
+        let slot = header_slot(&mut header).ok_or_else(|| format!("Block {} has no Aura slot in its digest", number))?;
+        let timestamp = storage::read_u64(socket, metadata, "Timestamp", "Now", &[], Some(&hash)).await?.unwrap_or_default();
+
+        // A new authority set is read whole, owners included
+        if read_authorities(socket, metadata, Some(&hash)).await? != self.set.authorities {
+            self.set = AuthoritySet::read(socket, metadata, Some(&hash)).await?;
+            if self.ours.is_some() && self.our_index().is_none() {
+                eprintln!("ALERT: our collator is no longer an Aura authority at block {}", number);
+            }
+        }
+        let author = self.set.slot_author(slot);
+        let ours = self.our_index();
+        let mut record = json!({
+            "kind": "block",
+            "block": number,
+            "hash": hash,
+            "slot": slot,
+            "time": clock::format_unix_millis(timestamp),
+            "author": self.set.author(author),
+        });
+        if let Some(last_slot) = self.last_slot.filter(|last_slot| slot > last_slot + 1) {
+            let missed = slot - last_slot - 1;
+            // Authors repeat every rotation, so the last rotation of empty slots names them all
+            let listed = (slot - missed.min(self.set.len())..slot)
+                .map(|slot| json!({ "slot": slot, "author": self.set.author(self.set.slot_author(slot)) }))
+                .collect::<Vec<_>>();
+            record["missed_slots"] = json!(missed);
+            record["missed"] = json!(listed);
+            if let Some(ours) = ours {
+                let ours_missed = (last_slot + 1..slot).filter(|&slot| self.set.slot_author(slot) == ours).count();
+                if ours_missed > 0 {
+                    eprintln!("ALERT: our collator missed {} slot(s) before block {}", ours_missed, number);
+                    record["ours_missed"] = json!(ours_missed);
+                }
+            }
+        }
+        if let Some(ours) = ours {
+            record["ours"] = json!(author == ours);
+            if author == ours {
+                self.last_ours_ms = Some(timestamp.max(self.last_ours_ms.unwrap_or_default()));
+                if self.alerted {
+                    self.alerted = false;
+                    crate::output::line(&json!({ "kind": "recovered", "block": number, "author": self.set.author(ours) }).to_string())?;
+                }
+            }
+        }
+        self.last_slot = Some(slot);
+        self.recent.insert(number, timestamp);
+        while self.recent.len() > MAX_RECENT_BLOCKS {
+            self.recent.pop_first();
+        }
+        crate::output::line(&record.to_string())?;
+        Ok(())
+    }
+
+    /// Alerts once when our collator has authored nothing for longer than `threshold`.
+    fn check_threshold(&mut self, threshold: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(ours) = self.our_index().filter(|_| !self.alerted) else { return Ok(()) };
+        let silent_ms = unix_ms().saturating_sub(self.last_ours_ms.unwrap_or(self.started_ms));
+        if silent_ms > threshold.as_millis() as u64 {
+            self.alerted = true;
+            eprintln!("ALERT: our collator has not authored a block for {}", clock::format_duration(silent_ms / 1000));
+            crate::output::line(&json!({
+                "kind": "alert",
+                "author": self.set.author(ours),
+                "last_authored": self.last_ours_ms.map(clock::format_unix_millis),
+                "silent_for": clock::format_duration(silent_ms / 1000),
+            }).to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Relay chain blocks scanned for inclusions of one para.
+struct Relay<'a> {
+    socket: &'a mut Connection,
+    metadata: Metadata,
+    para_id: u64,
+    last_block: u64,
+}
+
+impl Relay<'_> {
+    async fn poll(&mut self, collators: &Collators) -> Result<(), Box<dyn std::error::Error>> {
+        let head = crate::fetch_header_number(self.socket, None).await?;
+        for block in self.last_block + 1..=head {
+            let hash = crate::fetch_block_hash(self.socket, block).await?;
+            let mut timestamp = None;
+            for event in storage::pallet_events(self.socket, &self.metadata, "ParaInclusion", &hash).await? {
+                let (Some("CandidateIncluded"), fields) = crate::extrinsic::variant(&event) else { continue };
+                if fields.get(0).and_then(|receipt| receipt["descriptor"]["para_id"].as_u64()) != Some(self.para_id) {
+                    continue;
+                }
+                let Some(number) = included_number(fields) else { continue };
+                if timestamp.is_none() {
+                    timestamp = storage::read_u64(self.socket, &self.metadata, "Timestamp", "Now", &[], Some(&hash)).await?;
+                }
+                let latency = match (timestamp, collators.recent.get(&number)) {
+                    (Some(included), Some(&authored)) => json!(included.saturating_sub(authored)),
+                    _ => Value::Null,
+                };
+                crate::output::line(&json!({
+                    "kind": "included",
+                    "block": number,
+                    "relay_block": block,
+                    "inclusion_latency_ms": latency,
+                }).to_string())?;
+            }
+        }
+        self.last_block = self.last_block.max(head);
+        Ok(())
+    }
+}
+
+/// Follows the para chain, printing a JSON line per block with its author and the slots
+/// missed before it, and with `--relay` a line per inclusion with its latency.
+pub async fn watch(socket: &mut Connection, relay: Option<&mut Connection>, options: &WatchOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = metadata::fetch(socket, None).await?;
+    if metadata.storage_entry("Aura", "Authorities").is_none() {
+        return Err("Aura pallet not found, the chain does not use Aura".into());
+    }
+    let head = crate::fetch_header_number(socket, None).await?;
+    let hash = crate::fetch_block_hash(socket, head).await?;
+    let set = AuthoritySet::read(socket, &metadata, Some(&hash)).await?;
+    let ours = options.validator.as_deref().map(ss58::parse_account).transpose()?;
+    let mut collators = Collators { set, ours, last_slot: None, recent: BTreeMap::new(), started_ms: unix_ms(), last_ours_ms: None, alerted: false };
+    if let (Some(validator), None) = (&options.validator, collators.our_index()) {
+        return Err(format!("{} is not an Aura authority or the owner of one at block {}", validator, head).into());
+    }
+
+    let mut relay = match relay {
+        Some(relay_socket) => {
+            let para_id = storage::read_u64(socket, &metadata, "ParachainInfo", "ParachainId", &[], Some(&hash)).await?
+                .ok_or("ParachainInfo::ParachainId not found, is this a parachain endpoint?")?;
+            let relay_metadata = metadata::fetch(relay_socket, None).await?;
+            if relay_metadata.pallet("ParaInclusion").is_none() {
+                return Err("No ParaInclusion pallet, is --relay a relay chain endpoint?".into());
+            }
+            let last_block = crate::fetch_header_number(relay_socket, None).await?;
+            Some(Relay { socket: relay_socket, metadata: relay_metadata, para_id, last_block })
+        }
+        None => None,
+    };
+
+    crate::output::line(&json!({
+        "kind": "authorities",
+        "block": head,
+        "para_id": relay.as_ref().map(|relay| relay.para_id),
+        "authorities": (0..collators.set.authorities.len()).map(|index| collators.set.author(index)).collect::<Vec<_>>(),
+        "validator": collators.our_index().map(|index| collators.set.author(index)),
+    }).to_string())?;
+
+    collators.record_block(socket, &metadata, head).await?;
+    let mut last_block = head;
+    loop {
+        tokio::time::sleep(options.interval).await;
+        let head = crate::fetch_header_number(socket, None).await?;
+        for block in last_block + 1..=head {
+            collators.record_block(socket, &metadata, block).await?;
+        }
+        last_block = last_block.max(head);
+        if let Some(relay) = relay.as_mut() {
+            relay.poll(&collators).await?;
+        }
+        collators.check_threshold(options.threshold)?;
+    }
+}
@@ -0,0 +1,108 @@
+//! BEEFY finality for bridge operators: the BEEFY-finalized head with the signed commitment
+//! that finalized it, and the stream of new justifications, decoded.
+//!
+//! Justifications are `VersionedFinalityProof::V1` of a `SignedCommitment` in its compact
+//! encoding: the commitment, a bitfield of the validators that signed, the validator set
+//! size, and the signatures present in order.
+
+use serde_json::{json, Value};
+
+use crate::hex;
+use crate::rpc::Connection;
+use crate::scale::Input;
+
+const ENGINE: &str = "BEEF";
+
+/// Decodes a `VersionedFinalityProof` into its commitment's block number, validator set
+/// id and payload (with the MMR root under `mmr_root`), and which validators signed.
+pub fn decode_finality_proof(bytes: &[u8]) -> Result<Value, String> {
+    let mut input = Input::new(bytes);
+    match input.read_u8()? {
+        1 => {}
+        version => return Err(format!("unknown BEEFY finality proof version {}", version)),
+    }
+    let payload = input.read_list(|input| {
+        let id = input.read_array::<2>()?;
+        Ok((String::from_utf8_lossy(&id).to_string(), input.read_vec()?))
+    })?;
+    let block_number = input.read_u32()?;
+    let validator_set_id = input.read_u64()?;
+    let bitfield = input.read_vec()?;
+    let validator_set_len = input.read_u32()?;
+    let signatures = input.read_list(|input| input.read_bytes(65).map(hex::encode))?;
+
+    // The bitfield is packed most significant bit first
+    let signed: Vec<u32> = (0..validator_set_len)
+        .filter(|index| bitfield.get(*index as usize / 8).is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0))
+        .collect();
+    if signed.len() != signatures.len() {
+        return Err(format!("{} validators marked as signing but {} signatures", signed.len(), signatures.len()));
+    }
+    let threshold = validator_set_len - validator_set_len.saturating_sub(1) / 3;
+    let mmr_root = payload.iter().find(|(id, _)| id == "mh").map(|(_, value)| hex::encode(value));
+    Ok(json!({
+        "block_number": block_number,
+        "validator_set_id": validator_set_id,
+        "mmr_root": mmr_root,
+        "payload": payload.iter().map(|(id, value)| json!({ "id": id, "value": hex::encode(value) })).collect::<Vec<_>>(),
+        "validator_set_len": validator_set_len,
+        "signatures": signatures.len(),
+        "threshold": threshold,
+        "supermajority": signatures.len() as u32 >= threshold,
+        "signed_by": signed,
+        "signature_list": signed.iter().zip(&signatures).map(|(index, signature)| json!({ "validator": index, "signature": signature })).collect::<Vec<_>>(),
+    }))
+}
+
+/// The BEEFY-finalized head with the decoded commitment from its justification, `null`
+/// when the block carries none (the node did not import one).
+pub async fn finalized_head(socket: &mut Connection) -> Result<Value, Box<dyn std::error::Error>> {
+    let head = crate::send_and_receive(socket, "beefy_getFinalizedHead", json!([])).await?;
+    let head = head.as_str().ok_or("beefy_getFinalizedHead returned no block, BEEFY may not be enabled")?.to_string();
+    let number = crate::fetch_header_number(socket, Some(&head)).await?;
+    let block = crate::send_and_receive(socket, "chain_getBlock", json!([head])).await?;
+    let justification = block["justifications"].as_array().into_iter().flatten()
+        .find(|entry| entry[0].as_array().is_some_and(|engine| engine.iter().filter_map(Value::as_u64).map(|byte| byte as u8).eq(ENGINE.bytes())))
+        .and_then(|entry| entry[1].as_str());
+    let commitment = match justification {
+        Some(justification) => decode_finality_proof(&hex::decode(justification)?)?,
+        None => Value::Null,
+    };
+    Ok(json!({
+        "finalized_head": head,
+        "finalized_number": number,
+        "justification": justification,
+        "commitment": commitment,
+    }))
+}
+
+/// Streams every new BEEFY justification as a JSON line until interrupted, decoded and with
+/// the hash of the block it finalizes, then unsubscribes.
+pub async fn follow(mut socket: Connection, endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let idle = crate::subscribe::idle_limit(&mut socket).await?;
+    let mut subscription = socket.subscribe("beefy_subscribeJustifications", &json!([])).await?;
+    let id = subscription.id().to_string();
+    eprintln!("Streaming BEEFY justifications from {} until Ctrl-C", endpoint);
+    loop {
+        let notification = tokio::select! {
+            notification = crate::subscribe::within(idle, subscription.next()) => notification?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let proof = notification.as_str().ok_or("BEEFY justification notification is not hex")?;
+        let mut record = match decode_finality_proof(&hex::decode(proof)?) {
+            Ok(commitment) => commitment,
+            Err(e) => json!({ "error": e }),
+        };
+        if let Some(number) = record["block_number"].as_u64() {
+            record["block_hash"] = json!(crate::fetch_block_hash(&mut socket, number).await?);
+        }
+        record["justification"] = json!(proof);
+        crate::output::line(&record.to_string())?;
+    }
+    drop(subscription);
+    if let Err(e) = crate::send_request(&mut socket, "beefy_unsubscribeJustifications", &json!([id])).await {
+        eprintln!("Warning: beefy_unsubscribeJustifications {} failed: {}", id, e);
+    }
+    socket.close().await;
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! `gavel bench`: round-trip latency of the calls most clients lean on, the head, a block,
+//! a storage value and the metadata, taken one at a time over a number of iterations so
+//! providers can be compared from where gavel runs.
+//!
+//! Endpoints are benchmarked one after the other, so they do not compete for the local
+//! link, and every iteration calls each method once at the same finalized block.
+
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use crate::probe::percentile;
+
+/// The benchmarked calls, by label.
+const METHODS: &[(&str, &str)] = &[
+    ("getHead", "chain_getHead"),
+    ("getBlock", "chain_getBlock"),
+    ("getStorage", "state_getStorage"),
+    ("getMetadata", "state_getMetadata"),
+];
+
+fn summary(latencies: &mut [f64], errors: u64) -> Value {
+    latencies.sort_by(f64::total_cmp);
+    json!({
+        "p50": percentile(latencies, 0.5),
+        "p95": percentile(latencies, 0.95),
+        "p99": percentile(latencies, 0.99),
+        "min": latencies.first().map(|ms| (ms * 100.0).round() / 100.0),
+        "max": percentile(latencies, 1.0),
+        "errors": errors,
+    })
+}
+
+/// Calls every benchmarked method `iterations` times on `endpoint`.
+async fn bench_endpoint(endpoint: &str, iterations: u32, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let block = crate::pinned_block(&mut socket, None).await?;
+    // `System::Number`, present on every chain and a few bytes long
+    let key = crate::hex::encode(&crate::metadata::storage_prefix("System", "Number"));
+    let params = [json!([]), json!([block]), json!([key, block]), json!([block])];
+
+    let mut latencies = vec![Vec::new(); METHODS.len()];
+    let mut errors = vec![0u64; METHODS.len()];
+    for _ in 0..iterations {
+        for (index, (_, method)) in METHODS.iter().enumerate() {
+            let start = Instant::now();
+            let answered = crate::send_request(&mut socket, method, &params[index]).await;
+            let ms = start.elapsed().as_secs_f64() * 1000.0;
+            match answered {
+                Ok(response) if response.get("error").is_none() => latencies[index].push(ms),
+                _ => errors[index] += 1,
+            }
+        }
+    }
+    let methods: serde_json::Map<String, Value> = METHODS.iter().zip(latencies.iter_mut().zip(errors))
+        .map(|((label, method), (latencies, errors))| {
+            let mut summary = summary(latencies, errors);
+            summary["method"] = json!(method);
+            (label.to_string(), summary)
+        })
+        .collect();
+    Ok(json!({
+        "endpoint": endpoint,
+        "block_hash": block,
+        "methods": methods,
+    }))
+}
+
+/// One row per endpoint, fastest median first, with each method's p50/p95/p99 in ms.
+fn comparison(results: &[Value]) -> Vec<Value> {
+    let total_p50 = |result: &Value| METHODS.iter().map(|(label, _)| result["methods"][label]["p50"].as_f64()).sum::<Option<f64>>();
+    let mut rows: Vec<(Option<f64>, Value)> = results.iter().map(|result| {
+        let mut row = json!({ "endpoint": result["endpoint"] });
+        for (label, _) in METHODS {
+            let latency = &result["methods"][label];
+            row[*label] = match latency["p50"].as_f64() {
+                Some(_) => json!(format!("{}/{}/{}", latency["p50"], latency["p95"], latency["p99"])),
+                None => json!("-"),
+            };
+        }
+        if let Some(error) = result.get("error") {
+            row["error"] = error.clone();
+        }
+        (total_p50(result), row)
+    }).collect();
+    // Endpoints with a method that never answered go last
+    rows.sort_by(|(a, _), (b, _)| a.is_none().cmp(&b.is_none()).then(a.unwrap_or(0.0).total_cmp(&b.unwrap_or(0.0))));
+    rows.into_iter().enumerate().map(|(rank, (_, mut row))| {
+        row["rank"] = json!(rank + 1);
+        row
+    }).collect()
+}
+
+/// Benchmarks each endpoint in turn. With several, a comparison table of p50/p95/p99 per
+/// method goes to stderr and under `comparison`.
+pub async fn bench(endpoints: &[String], iterations: u32, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let iterations = iterations.max(1);
+    let mut results = Vec::new();
+    for endpoint in endpoints {
+        eprintln!("Benchmarking {} over {} iterations", endpoint, iterations);
+        let result = bench_endpoint(endpoint, iterations, max_response_size).await
+            .unwrap_or_else(|e| json!({ "endpoint": endpoint, "error": crate::redact::text(&e.to_string()) }));
+        results.push(result);
+    }
+    if let [result] = results.as_slice() {
+        if let Some(error) = result["error"].as_str() {
+            return Err(error.into());
+        }
+        return Ok(result.clone());
+    }
+
+    let comparison = comparison(&results);
+    eprintln!("\nLatency p50/p95/p99 in ms:\n{}", crate::output::text_table(&json!(comparison)));
+    Ok(json!({
+        "iterations": iterations,
+        "endpoints": results,
+        "comparison": comparison,
+    }))
+}
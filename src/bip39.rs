@@ -0,0 +1,59 @@
+//! BIP-39 English mnemonics, and the Substrate way of turning one into a seed: PBKDF2
+//! over the mnemonic's entropy rather than its words.
+
+use crate::hashing;
+use rand::RngCore;
+
+const WORDLIST: &str = include_str!("bip39-english.txt");
+
+fn words() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// A new random mnemonic of 12, 15, 18, 21 or 24 words.
+pub fn generate(word_count: usize) -> Result<String, String> {
+    if !matches!(word_count, 12 | 15 | 18 | 21 | 24) {
+        return Err(format!("invalid mnemonic length {}, expected 12, 15, 18, 21 or 24 words", word_count));
+    }
+    let mut entropy = vec![0u8; word_count * 4 / 3];
+    rand::thread_rng().fill_bytes(&mut entropy);
+    Ok(from_entropy(&entropy))
+}
+
+fn from_entropy(entropy: &[u8]) -> String {
+    let checksum_bits = entropy.len() / 4;
+    let checksum = hashing::sha256(entropy)[0];
+    let bits: Vec<bool> = entropy.iter().flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .chain((0..checksum_bits).map(|i| (checksum >> (7 - i)) & 1 == 1))
+        .collect();
+    let words = words();
+    bits.chunks(11)
+        .map(|chunk| words[chunk.iter().fold(0usize, |index, bit| index << 1 | *bit as usize)])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Validates the words and checksum of `phrase` and returns its entropy.
+pub fn to_entropy(phrase: &str) -> Result<Vec<u8>, String> {
+    let words = words();
+    let indices = phrase.split_whitespace()
+        .map(|word| words.binary_search(&word).map_err(|_| format!("'{}' is not a BIP-39 English word", word)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !matches!(indices.len(), 12 | 15 | 18 | 21 | 24) {
+        return Err(format!("invalid mnemonic length {}, expected 12, 15, 18, 21 or 24 words", indices.len()));
+    }
+    let bits: Vec<bool> = indices.iter().flat_map(|index| (0..11).rev().map(move |i| (index >> i) & 1 == 1)).collect();
+    let entropy_bits = bits.len() * 32 / 33;
+    let entropy: Vec<u8> = bits[..entropy_bits].chunks(8).map(|chunk| chunk.iter().fold(0u8, |byte, bit| byte << 1 | *bit as u8)).collect();
+    if from_entropy(&entropy) != indices.iter().map(|index| words[*index]).collect::<Vec<_>>().join(" ") {
+        return Err("invalid mnemonic checksum".into());
+    }
+    Ok(entropy)
+}
+
+/// The 32-byte mini secret Substrate derives from a mnemonic and optional password.
+pub fn mini_secret(phrase: &str, password: &str) -> Result<[u8; 32], String> {
+    let entropy = to_entropy(phrase)?;
+    let seed = hashing::pbkdf2_sha512(&entropy, format!("mnemonic{}", password).as_bytes(), 2048, 64);
+    Ok(seed[..32].try_into().unwrap())
+}
@@ -0,0 +1,135 @@
+//! Finality proof bundles: everything a light-client relayer needs for one block
+//! (header, GRANDPA and BEEFY justifications, MMR proof and validator sets) in one document.
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::scale::Input;
+use crate::{hex, storage};
+
+const GRANDPA_ENGINE: &str = "FRNK";
+const BEEFY_ENGINE: &str = "BEEF";
+
+/// Calls `method`, turning a JSON-RPC error into an `Err` with its message.
+async fn rpc(socket: &mut Connection, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let response = crate::send_request(socket, method, &params).await?;
+    match response.get("error") {
+        Some(error) => Err(format!("{}: {}", method, error["message"].as_str().unwrap_or(&error.to_string())).into()),
+        None => Ok(response["result"].clone()),
+    }
+}
+
+/// The justifications attached to a block, as `(engine id, hex)` pairs.
+async fn justifications(socket: &mut Connection, hash: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let block = rpc(socket, "chain_getBlock", json!([hash])).await?;
+    let entries = block["justifications"].as_array().cloned().unwrap_or_default();
+    Ok(entries.iter().filter_map(|entry| {
+        let engine: Vec<u8> = entry[0].as_array()?.iter().filter_map(|byte| byte.as_u64().map(|byte| byte as u8)).collect();
+        Some((String::from_utf8_lossy(&engine).to_string(), entry[1].as_str()?.to_string()))
+    }).collect())
+}
+
+fn engine_justification(justifications: &[(String, String)], engine: &str) -> Option<String> {
+    justifications.iter().find(|(id, _)| id == engine).map(|(_, data)| data.clone())
+}
+
+/// GRANDPA's justification for the block itself when it ends a set, otherwise the
+/// `grandpa_proveFinality` proof (the finalizing block hash, its justification and the
+/// headers in between).
+async fn grandpa(socket: &mut Connection, hash: &str, number: u64) -> Result<Value, Box<dyn std::error::Error>> {
+    if let Some(justification) = engine_justification(&justifications(socket, hash).await?, GRANDPA_ENGINE) {
+        return Ok(json!({ "source": "block", "justification": justification }));
+    }
+    let proof = rpc(socket, "grandpa_proveFinality", json!([number])).await?;
+    let proof = proof.as_str().ok_or("grandpa_proveFinality returned no proof, the block may not be finalized yet")?;
+    let bytes = hex::decode(proof)?;
+    let finalized_by = bytes.get(..32).map(hex::encode);
+    Ok(json!({ "source": "grandpa_proveFinality", "finality_proof": proof, "finalized_by": finalized_by }))
+}
+
+/// The GRANDPA authority set at `hash`: set id and `(ed25519 key, weight)` pairs.
+async fn grandpa_authorities(socket: &mut Connection, metadata: &Metadata, hash: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let encoded = rpc(socket, "state_call", json!(["GrandpaApi_grandpa_authorities", "0x", hash])).await?;
+    let bytes = hex::decode(encoded.as_str().ok_or("GrandpaApi_grandpa_authorities returned nothing")?)?;
+    let mut input = Input::new(&bytes);
+    let authorities = input.read_list(|input| {
+        let key = input.read_array::<32>()?;
+        let weight = input.read_u64()?;
+        Ok(json!({ "key": hex::encode(&key), "weight": weight }))
+    })?;
+    let set_id = storage::read_u64(socket, metadata, "Grandpa", "CurrentSetId", &[], Some(hash)).await?;
+    Ok(json!({ "set_id": set_id, "authorities": authorities }))
+}
+
+/// BEEFY's latest finalized block with its signed commitment, and the validator set that
+/// signed it (plus the next set's MMR leaf commitment when `BeefyMmrLeaf` is present).
+async fn beefy(socket: &mut Connection) -> Result<(Value, u64), Box<dyn std::error::Error>> {
+    let head = rpc(socket, "beefy_getFinalizedHead", json!([])).await?;
+    let head = head.as_str().ok_or("beefy_getFinalizedHead returned no block")?.to_string();
+    let number = crate::fetch_header_number(socket, Some(&head)).await?;
+    let commitment = engine_justification(&justifications(socket, &head).await?, BEEFY_ENGINE);
+    let metadata = metadata::fetch(socket, Some(&head)).await?;
+    let mut validator_set = json!({
+        "id": storage::read_u64(socket, &metadata, "Beefy", "ValidatorSetId", &[], Some(&head)).await?,
+        "authorities": storage::read(socket, &metadata, "Beefy", "Authorities", &[], Some(&head)).await?,
+    });
+    if metadata.storage_entry("BeefyMmrLeaf", "BeefyNextAuthorities").is_some() {
+        validator_set["next"] = storage::read(socket, &metadata, "BeefyMmrLeaf", "BeefyNextAuthorities", &[], Some(&head)).await?;
+    }
+    Ok((json!({
+        "finalized_head": head,
+        "finalized_number": number,
+        "signed_commitment": commitment,
+        "validator_set": validator_set,
+    }), number))
+}
+
+/// Gathers the bundle for `block` (a number or hash, the finalized head by default).
+/// Parts the endpoint can't serve are listed under `missing` instead of failing the
+/// whole bundle.
+pub async fn bundle(socket: &mut Connection, block: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, block).await?;
+    let header = rpc(socket, "chain_getHeader", json!([hash])).await?;
+    let number = u64::from_str_radix(header["number"].as_str().ok_or("Block not found")?.trim_start_matches("0x"), 16)?;
+    let metadata = metadata::fetch(socket, Some(&hash)).await?;
+
+    let mut result = json!({ "block_number": number, "block_hash": hash, "header": header });
+    let mut missing = serde_json::Map::new();
+
+    match grandpa(socket, &hash, number).await {
+        Ok(grandpa) => result["grandpa"] = grandpa,
+        Err(e) => { missing.insert("grandpa".into(), json!(e.to_string())); }
+    }
+    match grandpa_authorities(socket, &metadata, &hash).await {
+        Ok(authorities) => result["grandpa_authorities"] = authorities,
+        Err(e) => { missing.insert("grandpa_authorities".into(), json!(e.to_string())); }
+    }
+
+    // MMR proofs are generated against the BEEFY-finalized MMR root relayers verify with
+    let mut best_known = None;
+    match beefy(socket).await {
+        Ok((beefy, beefy_number)) => {
+            if beefy_number < number {
+                missing.insert("beefy".into(), json!(format!("block {} is not BEEFY-finalized yet, the BEEFY head is {}", number, beefy_number)));
+            } else {
+                best_known = Some(beefy_number);
+            }
+            result["beefy"] = beefy;
+        }
+        Err(e) => { missing.insert("beefy".into(), json!(e.to_string())); }
+    }
+    let params = match best_known {
+        Some(best_known) => json!([[number], best_known]),
+        None => json!([[number]]),
+    };
+    match rpc(socket, "mmr_generateProof", params).await {
+        Ok(proof) => result["mmr_proof"] = proof,
+        Err(e) => { missing.insert("mmr_proof".into(), json!(e.to_string())); }
+    }
+
+    if !missing.is_empty() {
+        result["missing"] = Value::Object(missing);
+    }
+    Ok(result)
+}
@@ -0,0 +1,44 @@
+//! A size-bounded cache of immutable answers (anything pinned to a block hash), shared
+//! by the `serve` and `proxy` modes.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::Value;
+
+pub struct Cache {
+    // Bytes of JSON to keep at most, 0 disables the cache
+    capacity: usize,
+    size: usize,
+    entries: HashMap<String, (Value, usize)>,
+    // Insertion order, the oldest entry is evicted first
+    order: VecDeque<String>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Cache {
+        Cache { capacity, size: 0, entries: HashMap::new(), order: VecDeque::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.entries.get(key).map(|(value, _)| value.clone())
+    }
+
+    /// Keeps `value` under `key`, evicting the oldest entries to make room. Values larger
+    /// than a quarter of the capacity are not kept, so one metadata blob can't flush
+    /// everything else.
+    pub fn insert(&mut self, key: String, value: Value) {
+        let size = key.len() + value.to_string().len();
+        if size > self.capacity / 4 || self.entries.contains_key(&key) {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            if let Some((_, evicted)) = self.entries.remove(&oldest) {
+                self.size -= evicted;
+            }
+        }
+        self.size += size;
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (value, size));
+    }
+}
@@ -0,0 +1,467 @@
+//! Records what an endpoint does over a time window into one archive to share after an
+//! incident: every best and finalized head as it arrives, periodic health samples, the
+//! most recent blocks when the window closes and every error met along the way.
+//!
+//! Each stream keeps its own connection and reconnects when it drops, so an endpoint
+//! that flaps shows up as gaps and errors rather than ending the capture.
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::time::Instant;
+
+use crate::probe::Target;
+use crate::rpc::Connection;
+use crate::tar::Tar;
+use crate::{clock, subscribe};
+
+/// Time to wait before reconnecting a stream that failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct Window {
+    #[clap(long, default_value = "10m", value_parser = clock::parse_duration, help = "How long to record, e.g. 90s, 10m or 1h.")]
+    pub duration: Duration,
+    #[clap(long, default_value = "5s", value_parser = clock::parse_duration, help = "Time between health samples.")]
+    pub interval: Duration,
+    #[clap(long, default_value = "20", help = "Most recent blocks to fetch whole when the window closes.")]
+    pub blocks: u64,
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Adds the time to a record, readable and in unix milliseconds for `analyze`.
+fn stamped(mut record: Value) -> Value {
+    let now = unix_ms();
+    record["time"] = json!(clock::format_unix_millis(now));
+    record["unix_ms"] = json!(now);
+    record
+}
+
+/// The errors met by every stream, in the order they happened.
+struct Errors(Mutex<Vec<Value>>);
+
+impl Errors {
+    fn push(&self, source: &str, method: Option<&str>, error: Value) {
+        self.0.lock().unwrap().push(stamped(json!({ "source": source, "method": method, "error": error })));
+    }
+}
+
+/// Calls `method`, recording an error response, and returns its result.
+async fn call(socket: &mut Connection, errors: &Errors, source: &str, method: &str, params: Value) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    let response = crate::send_request(socket, method, &params).await?;
+    match response.get("error") {
+        Some(error) => {
+            errors.push(source, Some(method), error.clone());
+            Ok(None)
+        }
+        None => Ok(Some(response["result"].clone())),
+    }
+}
+
+fn header_number(header: &Value) -> Option<u64> {
+    header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+}
+
+/// Subscribes to the best and finalized heads and records each notification until the
+/// connection fails.
+async fn follow(target: &Target<'_>, records: &mut Vec<Value>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?;
+    let idle = subscribe::idle_limit(&mut socket).await?;
+    let mut best = socket.subscribe("chain_subscribeNewHeads", &json!([])).await?;
+    let mut finalized = socket.subscribe("chain_subscribeFinalizedHeads", &json!([])).await?;
+    let mut first = [true, true];
+    loop {
+        let (kind, header) = subscribe::within(idle, subscribe::next_head(&mut best, &mut finalized)).await?;
+        let header = &header;
+        let hash = crate::verify::encode_header(header).map(|encoded| crate::hex::encode(&crate::hashing::blake2_256(&encoded)));
+        let mut record = json!({
+            "kind": kind,
+            "number": header_number(header),
+            "hash": hash.ok(),
+            "parent_hash": header["parentHash"],
+        });
+        // The first head after (re)connecting may come from another backend
+        let first = &mut first[(kind == "finalized") as usize];
+        if std::mem::take(first) {
+            record["subscribed"] = json!(true);
+        }
+        records.push(stamped(record));
+    }
+}
+
+/// Follows the heads until `deadline`, reconnecting whenever the connection fails.
+async fn heads(target: &Target<'_>, deadline: Instant, errors: &Errors) -> Vec<Value> {
+    let mut records = Vec::new();
+    while Instant::now() < deadline {
+        match tokio::time::timeout_at(deadline, follow(target, &mut records)).await {
+            Ok(Err(e)) => {
+                errors.push("heads", None, json!(crate::redact::text(&e.to_string())));
+                tokio::time::sleep(RECONNECT_DELAY.min(deadline.saturating_duration_since(Instant::now()))).await;
+            }
+            Ok(Ok(())) | Err(_) => break,
+        }
+    }
+    records
+}
+
+/// Samples the node's health, sync state and heads every `interval` until `deadline`.
+async fn health(target: &Target<'_>, interval: Duration, deadline: Instant, errors: &Errors) -> Vec<Value> {
+    let mut records = Vec::new();
+    let mut socket: Option<Connection> = None;
+    let mut next = Instant::now();
+    while next < deadline {
+        tokio::time::sleep_until(next).await;
+        next += interval;
+        let sampled = async {
+            if socket.is_none() {
+                socket = Some(crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?);
+            }
+            let socket = socket.as_mut().unwrap();
+            let started = Instant::now();
+            let health = call(socket, errors, "health", "system_health", json!([])).await?;
+            let latency = started.elapsed();
+            let sync = call(socket, errors, "health", "system_syncState", json!([])).await?;
+            let best = call(socket, errors, "health", "chain_getHeader", json!([])).await?;
+            let finalized = call(socket, errors, "health", "chain_getFinalizedHead", json!([])).await?;
+            let finalized = match finalized {
+                Some(hash) => call(socket, errors, "health", "chain_getHeader", json!([hash])).await?,
+                None => None,
+            };
+            let health = health.unwrap_or_default();
+            let sync = sync.unwrap_or_default();
+            Ok::<Value, Box<dyn std::error::Error>>(stamped(json!({
+                "latency_ms": (latency.as_secs_f64() * 100000.0).round() / 100.0,
+                "peers": health["peers"],
+                "is_syncing": health["isSyncing"],
+                "should_have_peers": health["shouldHavePeers"],
+                "best": best.as_ref().and_then(header_number),
+                "finalized": finalized.as_ref().and_then(header_number),
+                "highest": sync["highestBlock"],
+            })))
+        };
+        match tokio::time::timeout_at(deadline, sampled).await {
+            Ok(Ok(record)) => records.push(record),
+            Ok(Err(e)) => {
+                errors.push("health", None, json!(crate::redact::text(&e.to_string())));
+                socket = None;
+            }
+            Err(_) => break,
+        }
+    }
+    records
+}
+
+/// Fetches the last `count` blocks, oldest first, and what the node says about itself.
+async fn snapshot(target: &Target<'_>, count: u64, errors: &Errors) -> Result<(Vec<Value>, Value), Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?;
+    let mut node = json!({});
+    for (field, method) in [("chain", "system_chain"), ("name", "system_name"), ("version", "system_version"), ("peer_id", "system_localPeerId"), ("runtime", "state_getRuntimeVersion")] {
+        node[field] = call(&mut socket, errors, "snapshot", method, json!([])).await?.unwrap_or_default();
+    }
+    node["genesis_hash"] = call(&mut socket, errors, "snapshot", "chain_getBlockHash", json!([0])).await?.unwrap_or_default();
+
+    let mut blocks = Vec::new();
+    let best = call(&mut socket, errors, "snapshot", "chain_getHeader", json!([])).await?;
+    if let Some(best) = best.as_ref().and_then(header_number) {
+        for number in best.saturating_sub(count.saturating_sub(1))..=best {
+            let Some(hash) = call(&mut socket, errors, "snapshot", "chain_getBlockHash", json!([number])).await? else { continue };
+            if let Some(block) = call(&mut socket, errors, "snapshot", "chain_getBlock", json!([hash])).await? {
+                blocks.push(json!({ "number": number, "hash": hash, "block": block["block"], "justifications": block["justifications"] }));
+            }
+        }
+    }
+    Ok((blocks, node))
+}
+
+fn json_lines(records: &[Value]) -> Vec<u8> {
+    records.iter().flat_map(|record| format!("{}\n", record).into_bytes()).collect()
+}
+
+/// How the tar archive is compressed, from the `--out` extension.
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    Zstd,
+    Gzip,
+    None,
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Archive file extensions with their compression.
+const EXTENSIONS: [(&str, Compression); 5] = [
+    (".tar.zst", Compression::Zstd),
+    (".tzst", Compression::Zstd),
+    (".tar.gz", Compression::Gzip),
+    (".tgz", Compression::Gzip),
+    (".tar", Compression::None),
+];
+
+fn compress(archive: &[u8], compression: Compression, out: &mut impl std::io::Write) -> std::io::Result<()> {
+    match compression {
+        // Level 0 is zstd's default, 3
+        Compression::Zstd => zstd::stream::copy_encode(archive, out, 0),
+        Compression::Gzip => crate::gzip::compress(archive, out),
+        Compression::None => out.write_all(archive),
+    }
+}
+
+/// The tar archive in `data`, decompressed by its magic bytes rather than its name.
+fn decompress(data: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Ok(if data.starts_with(&ZSTD_MAGIC) {
+        zstd::stream::decode_all(data.as_slice())?
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        crate::gzip::decompress(&data)?
+    } else {
+        data
+    })
+}
+
+/// Records `target` for `window.duration`, writes the archive to `out` (`.tar.zst`,
+/// `.tzst`, `.tar.gz`, `.tgz` or `.tar`) and returns its manifest.
+pub async fn capture(target: &Target<'_>, window: &Window, out: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let name = out.file_name().and_then(|name| name.to_str()).ok_or("--out must name a file")?;
+    let (stem, compression) = EXTENSIONS.iter()
+        .find_map(|(extension, compression)| Some((name.strip_suffix(extension)?, *compression)))
+        .ok_or("--out must end in .tar.zst, .tzst, .tar.gz, .tgz or .tar")?;
+    if window.interval.is_zero() {
+        return Err("--interval must be longer than zero".into());
+    }
+
+    let started = unix_ms();
+    let deadline = Instant::now() + window.duration;
+    eprintln!("Capturing {} for {} into {}", crate::redact::text(target.endpoint), clock::format_duration(window.duration.as_secs()), out.display());
+    let errors = Errors(Mutex::new(Vec::new()));
+    let (heads, health) = tokio::join!(heads(target, deadline, &errors), health(target, window.interval, deadline, &errors));
+    let (blocks, node) = match snapshot(target, window.blocks, &errors).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            errors.push("snapshot", None, json!(crate::redact::text(&e.to_string())));
+            (Vec::new(), Value::Null)
+        }
+    };
+    let errors = errors.0.into_inner().unwrap();
+    let (best, finalized): (Vec<Value>, Vec<Value>) = heads.into_iter().partition(|record| record["kind"] == "best");
+
+    let manifest = json!({
+        "endpoint": crate::redact::text(target.endpoint),
+        "started_at": clock::format_unix_millis(started),
+        "ended_at": clock::format_unix_millis(unix_ms()),
+        "started_unix_ms": started,
+        "ended_unix_ms": unix_ms(),
+        "duration_secs": window.duration.as_secs(),
+        "interval_secs": window.interval.as_secs_f64(),
+        "node": node,
+        "files": {
+            "heads.jsonl": best.len(),
+            "finalized.jsonl": finalized.len(),
+            "health.jsonl": health.len(),
+            "blocks.jsonl": blocks.len(),
+            "errors.jsonl": errors.len(),
+        },
+        "gavel_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    let mut tar = Tar::new(started / 1000);
+    tar.append(&format!("{}/manifest.json", stem), serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    for (file, records) in [("heads.jsonl", &best), ("finalized.jsonl", &finalized), ("health.jsonl", &health), ("blocks.jsonl", &blocks), ("errors.jsonl", &errors)] {
+        tar.append(&format!("{}/{}", stem, file), &json_lines(records))?;
+    }
+    let archive = tar.finish();
+    let mut file = std::io::BufWriter::new(std::fs::File::create(out).map_err(|e| format!("Failed to create {}: {}", out.display(), e))?);
+    compress(&archive, compression, &mut file)?;
+    std::io::Write::flush(&mut file)?;
+    let mut manifest = manifest;
+    manifest["out"] = json!(out.display().to_string());
+    Ok(manifest)
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Thresholds {
+    #[clap(long, default_value = "30s", value_parser = clock::parse_duration, help = "Time without a new best or finalized head that counts as a stall.")]
+    pub stall: Duration,
+    #[clap(long, default_value = "5", help = "Health sample latency over this multiple of the median that counts as a spike.")]
+    pub spike_factor: f64,
+}
+
+/// One timeline event at `unix_ms`.
+fn event(unix_ms: u64, kind: &str, mut fields: Value) -> (u64, Value) {
+    fields["time"] = json!(clock::format_unix_millis(unix_ms));
+    fields["kind"] = json!(kind);
+    (unix_ms, fields)
+}
+
+/// Stretches where the heads of `records` did not advance for longer than `stall_ms`,
+/// including one still going on when the capture ended.
+fn stalls(records: &[Value], kind: &str, ended_ms: u64, stall_ms: u64) -> Vec<(u64, Value)> {
+    let mut events = Vec::new();
+    let mut last: Option<(u64, u64)> = None;
+    for record in records {
+        let (Some(number), Some(ms)) = (record["number"].as_u64(), record["unix_ms"].as_u64()) else { continue };
+        match last {
+            Some((last_number, _)) if number <= last_number => continue,
+            Some((last_number, last_ms)) if ms - last_ms > stall_ms => events.push(event(last_ms, kind, json!({
+                "block": last_number,
+                "secs": (ms - last_ms) / 1000,
+                "resumed_at": clock::format_unix_millis(ms),
+            }))),
+            _ => {}
+        }
+        last = Some((number, ms));
+    }
+    if let Some((last_number, last_ms)) = last.filter(|(_, last_ms)| ended_ms.saturating_sub(*last_ms) > stall_ms) {
+        events.push(event(last_ms, kind, json!({ "block": last_number, "secs": (ended_ms - last_ms) / 1000, "ongoing": true })));
+    }
+    events
+}
+
+/// Best heads that replaced the previous best instead of building on it.
+fn reorgs(records: &[Value]) -> Vec<(u64, Value)> {
+    let mut events = Vec::new();
+    let mut previous: Option<(u64, &Value)> = None;
+    for record in records {
+        let (Some(number), Some(ms)) = (record["number"].as_u64(), record["unix_ms"].as_u64()) else { continue };
+        if record["subscribed"] == true {
+            previous = Some((number, &record["hash"]));
+            continue;
+        }
+        if let Some((previous_number, previous_hash)) = previous {
+            let replaced = if number <= previous_number {
+                (record["hash"] != *previous_hash).then_some(previous_number - number + 1)
+            } else if number == previous_number + 1 && record["parent_hash"] != *previous_hash {
+                Some(1)
+            } else {
+                None
+            };
+            if let Some(depth) = replaced {
+                events.push(event(ms, "reorg", json!({ "block": number, "depth": depth, "replaced": previous_hash, "hash": record["hash"] })));
+            }
+        }
+        previous = Some((number, &record["hash"]));
+    }
+    events
+}
+
+/// Reads a capture archive and reports its timeline: finality and block production
+/// stalls, reorgs, peer drops, latency spikes and errors, with a summary of the window.
+pub fn analyze(path: &Path, thresholds: &Thresholds) -> Result<Value, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let data = decompress(data).map_err(|e| format!("Failed to decompress {}: {}", path.display(), e))?;
+    let entries = crate::tar::entries(&data)?;
+    let file = |name: &str| entries.iter().find(|(entry, _)| entry.rsplit('/').next() == Some(name)).map(|(_, contents)| contents.as_slice());
+    let manifest: Value = serde_json::from_slice(file("manifest.json").ok_or("Not a gavel capture, manifest.json is missing")?)?;
+    let records = |name: &str| -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let contents = String::from_utf8_lossy(file(name).unwrap_or_default()).into_owned();
+        Ok(contents.lines().filter(|line| !line.trim().is_empty()).map(serde_json::from_str).collect::<Result<_, _>>()?)
+    };
+    let best = records("heads.jsonl")?;
+    let finalized = records("finalized.jsonl")?;
+    let health = records("health.jsonl")?;
+    let errors = records("errors.jsonl")?;
+    let ended_ms = manifest["ended_unix_ms"].as_u64().unwrap_or(0);
+    let stall_ms = thresholds.stall.as_millis() as u64;
+
+    let mut timeline = stalls(&finalized, "finality_stall", ended_ms, stall_ms);
+    timeline.extend(stalls(&best, "production_stall", ended_ms, stall_ms));
+    timeline.extend(reorgs(&best));
+
+    let mut previous_peers = None;
+    for sample in &health {
+        let (Some(peers), Some(ms)) = (sample["peers"].as_u64(), sample["unix_ms"].as_u64()) else { continue };
+        if let Some(previous) = previous_peers.filter(|previous| peers < *previous && (peers == 0 || peers * 2 <= *previous)) {
+            timeline.push(event(ms, "peer_drop", json!({ "from": previous, "to": peers })));
+        }
+        previous_peers = Some(peers);
+    }
+
+    let mut latencies: Vec<f64> = health.iter().filter_map(|sample| sample["latency_ms"].as_f64()).collect();
+    latencies.sort_by(f64::total_cmp);
+    let median = crate::probe::percentile(&latencies, 0.5).unwrap_or(0.0);
+    if median > 0.0 {
+        for sample in &health {
+            let (Some(latency), Some(ms)) = (sample["latency_ms"].as_f64(), sample["unix_ms"].as_u64()) else { continue };
+            if latency > median * thresholds.spike_factor {
+                timeline.push(event(ms, "latency_spike", json!({ "latency_ms": latency, "median_ms": median })));
+            }
+        }
+    }
+
+    for error in &errors {
+        timeline.push(event(error["unix_ms"].as_u64().unwrap_or(0), "error", json!({ "source": error["source"], "method": error["method"], "error": error["error"] })));
+    }
+    timeline.sort_by_key(|(ms, _)| *ms);
+
+    let mut findings = serde_json::Map::new();
+    for (_, event) in &timeline {
+        let kind = event["kind"].as_str().unwrap_or_default().to_string();
+        let count = findings.get(&kind).and_then(Value::as_u64).unwrap_or(0);
+        findings.insert(kind, json!(count + 1));
+    }
+    let numbered = |records: &[Value]| -> Vec<(u64, u64)> {
+        records.iter().filter_map(|record| Some((record["number"].as_u64()?, record["unix_ms"].as_u64()?))).collect()
+    };
+    let heads = |records: &[Value]| {
+        let numbered = numbered(records);
+        let (Some(first), Some(last)) = (numbered.iter().min_by_key(|(number, _)| *number), numbered.iter().max_by_key(|(number, _)| *number)) else {
+            return Value::Null;
+        };
+        let advanced = last.0 - first.0;
+        json!({
+            "first": first.0,
+            "last": last.0,
+            "advanced": advanced,
+            "average_block_secs": (advanced > 0).then(|| ((last.1.saturating_sub(first.1)) as f64 / advanced as f64 / 10.0).round() / 100.0),
+        })
+    };
+    let finality_lag = health.iter().filter_map(|sample| Some(sample["best"].as_u64()?.saturating_sub(sample["finalized"].as_u64()?))).max();
+    let peers: Vec<u64> = health.iter().filter_map(|sample| sample["peers"].as_u64()).collect();
+
+    Ok(json!({
+        "capture": {
+            "endpoint": manifest["endpoint"],
+            "chain": manifest["node"]["chain"],
+            "started_at": manifest["started_at"],
+            "ended_at": manifest["ended_at"],
+        },
+        "summary": {
+            "best": heads(&best),
+            "finalized": heads(&finalized),
+            "max_finality_lag": finality_lag,
+            "peers": { "min": peers.iter().min(), "max": peers.iter().max() },
+            "latency_ms": {
+                "p50": crate::probe::percentile(&latencies, 0.5),
+                "p95": crate::probe::percentile(&latencies, 0.95),
+                "max": latencies.last(),
+            },
+            "health_samples": health.len(),
+            "errors": errors.len(),
+        },
+        "findings": findings,
+        "timeline": timeline.into_iter().map(|(_, event)| event).collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_round_trip_in_every_compression() {
+        let mut tar = Tar::new(1_700_000_000);
+        tar.append("incident/manifest.json", br#"{"ended_unix_ms": 1000}"#).unwrap();
+        tar.append("incident/heads.jsonl", b"{\"kind\":\"best\",\"number\":1}\n").unwrap();
+        let archive = tar.finish();
+        for (extension, compression) in EXTENSIONS {
+            let mut compressed = Vec::new();
+            compress(&archive, compression, &mut compressed).unwrap();
+            assert_eq!(compressed.starts_with(&ZSTD_MAGIC), compression == Compression::Zstd, "{}", extension);
+            let entries = crate::tar::entries(&decompress(compressed).unwrap()).unwrap();
+            assert_eq!(entries.len(), 2, "{}", extension);
+            assert_eq!(entries[1].1, b"{\"kind\":\"best\",\"number\":1}\n", "{}", extension);
+        }
+    }
+}
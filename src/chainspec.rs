@@ -0,0 +1,376 @@
+//! Chain spec files: a summary of one spec (bootnodes, properties, genesis) and a diff of
+//! two, for reviewing network launches and spec updates.
+//!
+//! Raw specs are compared key by key, named and decoded through `--metadata` when given.
+//! Plain specs (a genesis config or patch) are compared as JSON. The runtime code is only
+//! ever shown as its size and hash.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use crate::metadata::Metadata;
+use crate::storage_diff::EntryIndex;
+use crate::{hashing, hex};
+
+/// Keys of the well-known storage items outside any pallet, as hex.
+const WELL_KNOWN_KEYS: &[(&str, &str)] = &[
+    ("0x3a636f6465", ":code"),
+    ("0x3a686561707061676573", ":heappages"),
+    ("0x3a65787472696e7369635f696e646578", ":extrinsic_index"),
+    ("0x3a696e747261626c6f636b5f656e74726f7079", ":intrablock_entropy"),
+];
+
+/// Pallets whose storage is named in raw specs even without `--metadata`.
+const COMMON_PALLETS: &[&str] = &[
+    "System", "Babe", "Aura", "Timestamp", "Indices", "Balances", "TransactionPayment", "Authorship",
+    "Staking", "Offences", "Historical", "Session", "Grandpa", "ImOnline", "AuthorityDiscovery",
+    "Treasury", "ConvictionVoting", "Referenda", "Whitelist", "Claims", "Vesting", "Utility", "Identity",
+    "Proxy", "Multisig", "Bounties", "ChildBounties", "ElectionProviderMultiPhase", "VoterList",
+    "NominationPools", "FastUnstake", "Configuration", "ParasShared", "ParaInclusion", "ParaInherent",
+    "ParaScheduler", "Paras", "Initializer", "Dmp", "Hrmp", "ParaSessionInfo", "ParasDisputes",
+    "ParasSlashing", "Registrar", "Slots", "Auctions", "Crowdloan", "XcmPallet", "PolkadotXcm",
+    "MessageQueue", "Beefy", "Mmr", "BeefyMmrLeaf", "Sudo", "ParachainSystem", "ParachainInfo",
+    "CollatorSelection", "Aux", "XcmpQueue", "CumulusXcm", "Assets", "Uniques", "Nfts",
+    "ForeignAssets", "PoolAssets", "AssetConversion", "Council", "TechnicalCommittee",
+    "TechnicalMembership", "Democracy", "PhragmenElection", "Society", "Recovery", "Scheduler",
+    "Preimage", "Contracts", "Ethereum", "EVM", "Collective", "Membership",
+];
+
+pub fn load(path: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let spec: Value = serde_json::from_str(&text).map_err(|e| format!("{} is not a chain spec: {}", path.display(), e))?;
+    if !spec["genesis"].is_object() {
+        return Err(format!("{} has no genesis, not a chain spec", path.display()).into());
+    }
+    Ok(spec)
+}
+
+fn code_summary(code: &str) -> Value {
+    match hex::decode(code) {
+        Ok(code) => json!({ "size": code.len(), "hash": hex::encode(&hashing::blake2_256(&code)) }),
+        Err(_) => json!({ "size": code.len() / 2 }),
+    }
+}
+
+/// Replaces `code` blobs anywhere in a plain genesis config with their summary.
+fn summarize_code(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value.as_str() {
+                    Some(code) if key == "code" && code.starts_with("0x") => *value = code_summary(code),
+                    _ => summarize_code(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(summarize_code),
+        _ => {}
+    }
+}
+
+/// The genesis format and its storage (raw) or config (plain), with the code summarized.
+enum Genesis<'s> {
+    Raw { top: &'s Map<String, Value>, children: BTreeMap<&'s str, &'s Map<String, Value>> },
+    Plain { format: &'static str, config: Value },
+}
+
+fn genesis(spec: &Value) -> Result<Genesis<'_>, String> {
+    let genesis = &spec["genesis"];
+    if let Some(top) = genesis["raw"]["top"].as_object() {
+        let children = genesis["raw"]["childrenDefault"].as_object().into_iter().flatten()
+            .filter_map(|(key, child)| Some((key.as_str(), child.as_object()?)))
+            .collect();
+        return Ok(Genesis::Raw { top, children });
+    }
+    let (format, mut config) = if let Some(runtime) = genesis.get("runtimeGenesis") {
+        // Newer specs carry the code next to a full config or a patch of the runtime default
+        let format = if runtime.get("patch").is_some() { "patch" } else { "config" };
+        let mut config = runtime.get(format).cloned().unwrap_or_default();
+        if let Some(code) = runtime["code"].as_str() {
+            config["code"] = json!(code);
+        }
+        (format, config)
+    } else if let Some(runtime) = genesis.get("runtime") {
+        ("runtime", runtime.clone())
+    } else {
+        return Err("unrecognized genesis, expected raw, runtimeGenesis or runtime".into());
+    };
+    summarize_code(&mut config);
+    Ok(Genesis::Plain { format, config })
+}
+
+/// Names and decodes raw storage keys: through the metadata when there is one, otherwise
+/// by the well-known keys and the pallet prefix.
+struct Namer<'m> {
+    index: Option<EntryIndex<'m>>,
+    pallets: HashMap<String, String>,
+}
+
+impl<'m> Namer<'m> {
+    fn new(metadata: Option<&'m Metadata>) -> Self {
+        let mut pallets: HashMap<String, String> = COMMON_PALLETS.iter()
+            .map(|name| (hex::encode(&hashing::twox_128(name.as_bytes())), name.to_string()))
+            .collect();
+        for pallet in metadata.iter().flat_map(|metadata| &metadata.pallets) {
+            let prefix = pallet.storage_prefix.as_deref().unwrap_or(&pallet.name);
+            pallets.insert(hex::encode(&hashing::twox_128(prefix.as_bytes())), pallet.name.clone());
+        }
+        Namer { index: metadata.map(EntryIndex::new), pallets }
+    }
+
+    fn pallet(&self, key: &str) -> String {
+        if let Some((_, name)) = WELL_KNOWN_KEYS.iter().find(|(known, _)| *known == key) {
+            return name.to_string();
+        }
+        let prefix = key.get(..34).unwrap_or(key);
+        self.pallets.get(prefix).cloned().unwrap_or_else(|| prefix.to_string())
+    }
+
+    fn describe(&self, key: &str, value: &str) -> (Value, Value) {
+        if key == WELL_KNOWN_KEYS[0].0 {
+            return (json!({ "key": key, "storage": ":code" }), code_summary(value));
+        }
+        let described = match (&self.index, hex::decode(key), hex::decode(value)) {
+            (Some(index), Ok(key), Ok(value)) => Some(index.describe(&key, &value)),
+            _ => None,
+        };
+        let (mut description, value) = described.unwrap_or_else(|| (json!({ "key": key }), json!(value)));
+        if description.get("storage").is_none() {
+            description["pallet"] = json!(self.pallet(key));
+        }
+        (description, value)
+    }
+}
+
+fn boot_nodes(spec: &Value, issues: &mut Vec<String>) -> Vec<Value> {
+    let mut seen = BTreeSet::new();
+    let mut nodes = Vec::new();
+    for address in spec["bootNodes"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+        let peer_id = address.split_once("/p2p/").map(|(_, peer_id)| peer_id.trim_end_matches('/'));
+        if peer_id.is_none() {
+            issues.push(format!("bootnode {} has no /p2p/ peer id", address));
+        }
+        if !seen.insert(address) {
+            issues.push(format!("bootnode {} is listed more than once", address));
+        }
+        nodes.push(json!({ "address": address, "peer_id": peer_id }));
+    }
+    if nodes.is_empty() && spec["chainType"].as_str() == Some("Live") {
+        issues.push("live chain without bootnodes".into());
+    }
+    nodes
+}
+
+/// Code substitutes by block, summarized.
+fn code_substitutes(spec: &Value) -> Value {
+    let substitutes: Map<String, Value> = spec["codeSubstitutes"].as_object().into_iter().flatten()
+        .map(|(block, code)| (block.clone(), code.as_str().map(code_summary).unwrap_or_default()))
+        .collect();
+    Value::Object(substitutes)
+}
+
+pub fn inspect(path: &Path, metadata: Option<&Metadata>) -> Result<Value, Box<dyn std::error::Error>> {
+    let spec = load(path)?;
+    let mut issues = Vec::new();
+    let boot_nodes = boot_nodes(&spec, &mut issues);
+
+    let genesis = match genesis(&spec)? {
+        Genesis::Raw { top, children } => {
+            let namer = Namer::new(metadata);
+            let mut pallets: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+            for (key, value) in top {
+                let size = value.as_str().map_or(0, |value| value.len().saturating_sub(2) / 2);
+                let pallet = pallets.entry(namer.pallet(key)).or_default();
+                pallet.0 += 1;
+                pallet.1 += size;
+            }
+            let code = top.get(WELL_KNOWN_KEYS[0].0).and_then(Value::as_str).map(code_summary);
+            if code.is_none() {
+                issues.push("genesis has no :code".into());
+            }
+            let pallets: Map<String, Value> = pallets.into_iter()
+                .map(|(pallet, (keys, bytes))| (pallet, json!({ "keys": keys, "bytes": bytes })))
+                .collect();
+            json!({
+                "format": "raw",
+                "keys": top.len(),
+                "bytes": pallets.values().map(|pallet| pallet["bytes"].as_u64().unwrap_or_default()).sum::<u64>(),
+                "code": code,
+                "child_tries": children.len(),
+                "pallets": pallets,
+            })
+        }
+        Genesis::Plain { format, mut config } => {
+            let code = config.as_object_mut().and_then(|config| config.remove("code"));
+            let pallets: Vec<&String> = config.as_object().into_iter().flat_map(|config| config.keys()).collect();
+            json!({ "format": format, "code": code, "pallets": pallets })
+        }
+    };
+
+    Ok(json!({
+        "name": spec["name"],
+        "id": spec["id"],
+        "chain_type": spec["chainType"],
+        "protocol_id": spec["protocolId"],
+        "fork_id": spec["forkId"],
+        "properties": spec["properties"],
+        "relay_chain": spec.get("relay_chain").or_else(|| spec.get("relayChain")),
+        "para_id": spec.get("para_id").or_else(|| spec.get("paraId")),
+        "boot_nodes": boot_nodes,
+        "telemetry_endpoints": spec["telemetryEndpoints"],
+        "fork_blocks": spec["forkBlocks"],
+        "bad_blocks": spec["badBlocks"],
+        "code_substitutes": code_substitutes(&spec),
+        "genesis": genesis,
+        "issues": issues,
+    }))
+}
+
+/// Writes the raw genesis storage of a spec as one JSON line per key, `{key, value}`, or
+/// named and decoded like the entries of `storage diff` with `decode`.
+pub fn genesis_state(path: &Path, decode: bool, metadata: Option<&Metadata>) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = load(path)?;
+    let Genesis::Raw { top, children } = genesis(&spec)? else {
+        return Err(format!("{} is not a raw spec, build it with --raw to get the genesis storage", path.display()).into());
+    };
+    let namer = Namer::new(metadata);
+    let tries = std::iter::once((None, top)).chain(children.into_iter().map(|(child, storage)| (Some(child), storage)));
+    for (child, storage) in tries {
+        for (key, value) in storage {
+            let value = value.as_str().unwrap_or_default();
+            let mut record = if decode {
+                let (mut description, decoded) = namer.describe(key, value);
+                description["value"] = decoded;
+                description
+            } else {
+                json!({ "key": key, "value": value })
+            };
+            if let Some(child) = child {
+                record["child"] = json!(child);
+            }
+            crate::output::line(&record.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Collects differences between two JSON values as `{path, before, after}`, or `added` and
+/// `removed` for object members and array items present on one side only.
+fn diff_json(path: &str, before: &Value, after: &Value, changes: &mut Vec<Value>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let path = format!("{}/{}", path, key);
+                match (before.get(key), after.get(key)) {
+                    (Some(before), Some(after)) => diff_json(&path, before, after, changes),
+                    (Some(before), None) => changes.push(json!({ "path": path, "removed": before })),
+                    (None, Some(after)) => changes.push(json!({ "path": path, "added": after })),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(before_items), Value::Array(after_items)) if before != after => {
+            // Lists like endowed balances are compared as sets, so one insertion isn't a
+            // change of every item after it
+            let removed: Vec<&Value> = before_items.iter().filter(|item| !after_items.contains(item)).collect();
+            let added: Vec<&Value> = after_items.iter().filter(|item| !before_items.contains(item)).collect();
+            if removed.is_empty() && added.is_empty() {
+                changes.push(json!({ "path": path, "reordered": true }));
+            } else {
+                changes.push(json!({ "path": path, "removed": removed, "added": added }));
+            }
+        }
+        _ if before != after => changes.push(json!({ "path": path, "before": before, "after": after })),
+        _ => {}
+    }
+}
+
+fn diff_storage(namer: &Namer, child: Option<&str>, before: &Map<String, Value>, after: &Map<String, Value>, added: &mut Vec<Value>, removed: &mut Vec<Value>, changed: &mut Vec<Value>) {
+    let describe = |key: &str, value: &Value| {
+        let (mut description, value) = namer.describe(key, value.as_str().unwrap_or_default());
+        if let Some(child) = child {
+            description["child"] = json!(child);
+        }
+        (description, value)
+    };
+    for (key, old) in before {
+        match after.get(key) {
+            None => {
+                let (mut entry, value) = describe(key, old);
+                entry["value"] = value;
+                removed.push(entry);
+            }
+            Some(new) if new != old => {
+                let (mut entry, old_value) = describe(key, old);
+                entry["before"] = old_value;
+                entry["after"] = describe(key, new).1;
+                changed.push(entry);
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, new) in after.iter().filter(|(key, _)| !before.contains_key(*key)) {
+        let (mut entry, value) = describe(key, new);
+        entry["value"] = value;
+        added.push(entry);
+    }
+}
+
+pub fn diff(path_a: &Path, path_b: &Path, metadata: Option<&Metadata>) -> Result<Value, Box<dyn std::error::Error>> {
+    let (a, b) = (load(path_a)?, load(path_b)?);
+
+    let mut fields = Vec::new();
+    // Everything but the genesis, with code substitutes summarized like the genesis code
+    let fields_of = |spec: &Value| {
+        let mut object = spec.as_object().cloned().unwrap_or_default();
+        object.remove("genesis");
+        if object.contains_key("codeSubstitutes") {
+            object.insert("codeSubstitutes".into(), code_substitutes(spec));
+        }
+        Value::Object(object)
+    };
+    diff_json("", &fields_of(&a), &fields_of(&b), &mut fields);
+
+    let genesis = match (genesis(&a)?, genesis(&b)?) {
+        (Genesis::Raw { top: top_a, children: children_a }, Genesis::Raw { top: top_b, children: children_b }) => {
+            let namer = Namer::new(metadata);
+            let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+            diff_storage(&namer, None, top_a, top_b, &mut added, &mut removed, &mut changed);
+            let empty = Map::new();
+            let child_keys: BTreeSet<&str> = children_a.keys().chain(children_b.keys()).copied().collect();
+            for child in child_keys {
+                let (before, after) = (children_a.get(child).copied().unwrap_or(&empty), children_b.get(child).copied().unwrap_or(&empty));
+                diff_storage(&namer, Some(child), before, after, &mut added, &mut removed, &mut changed);
+            }
+            json!({
+                "format": "raw",
+                "summary": {
+                    "keys_before": top_a.len(),
+                    "keys_after": top_b.len(),
+                    "added": added.len(),
+                    "removed": removed.len(),
+                    "changed": changed.len(),
+                },
+                "added": added,
+                "removed": removed,
+                "changed": changed,
+            })
+        }
+        (Genesis::Plain { format: format_a, config: config_a }, Genesis::Plain { format: format_b, config: config_b }) => {
+            let mut changes = Vec::new();
+            diff_json("", &config_a, &config_b, &mut changes);
+            json!({ "format": [format_a, format_b], "changes": changes })
+        }
+        _ => return Err("one spec is raw and the other is not, build both with --raw to compare their genesis storage".into()),
+    };
+
+    Ok(json!({
+        "a": path_a.display().to_string(),
+        "b": path_b.display().to_string(),
+        "fields": fields,
+        "genesis": genesis,
+    }))
+}
@@ -0,0 +1,105 @@
+//! `gavel check`: a one-shot health check for Nagios, cron and shell scripts, printing a
+//! single line and exiting with the plugin convention: 0 OK, 1 WARNING, 2 CRITICAL.
+//!
+//! The line is `STATUS - summary | perfdata`, with the peers, heads and finality lag as
+//! performance data for graphing.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use serde_json::json;
+
+#[derive(clap::Args, Debug)]
+pub struct Thresholds {
+    #[clap(long, default_value = "3", help = "Fewer peers than this is a WARNING, none is CRITICAL.")]
+    pub min_peers: u64,
+    #[clap(long, default_value = "10", help = "Blocks between the best and finalized heads that make a WARNING.")]
+    pub warn_finality_lag: u64,
+    #[clap(long, default_value = "30", help = "Blocks between the best and finalized heads that make it CRITICAL.")]
+    pub max_finality_lag: u64,
+    #[clap(long, help = "Report a syncing node as OK instead of CRITICAL.")]
+    pub allow_syncing: bool,
+    #[clap(long, default_value = "10", help = "Seconds for the whole check before it is CRITICAL.")]
+    pub timeout: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl Status {
+    /// The process exit code for the status.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+        }
+    }
+}
+
+fn header_number(header: &serde_json::Value) -> Result<u64, String> {
+    let number = header["number"].as_str().ok_or("header without a number")?;
+    u64::from_str_radix(number.trim_start_matches("0x"), 16).map_err(|e| format!("invalid header number: {}", e))
+}
+
+/// The findings and perfdata of a node that answered.
+async fn probe(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>, thresholds: &Thresholds) -> Result<(Status, Vec<String>, String), Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, ipv4, ipv6, max_response_size).await?;
+    let health = crate::send_and_receive(&mut socket, "system_health", json!([])).await?;
+    let best = header_number(&crate::send_and_receive(&mut socket, "chain_getHeader", json!([])).await?)?;
+    let finalized_hash = crate::send_and_receive(&mut socket, "chain_getFinalizedHead", json!([])).await?;
+    let finalized = header_number(&crate::send_and_receive(&mut socket, "chain_getHeader", json!([finalized_hash])).await?)?;
+    let peers = health["peers"].as_u64().unwrap_or_default();
+    let lag = best.saturating_sub(finalized);
+
+    let mut findings = Vec::new();
+    let mut status = Status::Ok;
+    let mut flag = |level: Status, finding: String| {
+        status = status.max(level);
+        findings.push(finding);
+    };
+    if health["isSyncing"] == true {
+        flag(if thresholds.allow_syncing { Status::Ok } else { Status::Critical }, "syncing".to_string());
+    }
+    // Dev chains and light setups may legitimately run without peers
+    if health["shouldHavePeers"] != false {
+        if peers == 0 {
+            flag(Status::Critical, "no peers".to_string());
+        } else if peers < thresholds.min_peers {
+            flag(Status::Warning, format!("{} peers (min {})", peers, thresholds.min_peers));
+        }
+    }
+    if lag > thresholds.max_finality_lag {
+        flag(Status::Critical, format!("finality lag {} blocks (max {})", lag, thresholds.max_finality_lag));
+    } else if lag > thresholds.warn_finality_lag {
+        flag(Status::Warning, format!("finality lag {} blocks (warn {})", lag, thresholds.warn_finality_lag));
+    }
+    if findings.is_empty() {
+        findings.push(format!("{} peers, best #{}, finalized #{}", peers, best, finalized));
+    }
+    let perfdata = format!(
+        "peers={};{}:;1: best={} finalized={} finality_lag={};{};{}",
+        peers, thresholds.min_peers, best, finalized, lag, thresholds.warn_finality_lag, thresholds.max_finality_lag,
+    );
+    Ok((status, findings, perfdata))
+}
+
+/// Checks `endpoint` and returns its status with the line to print. Failing to connect
+/// or to get an answer within `--timeout` is CRITICAL.
+pub async fn check(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>, thresholds: &Thresholds) -> (Status, String) {
+    let timeout = Duration::from_secs(thresholds.timeout.max(1));
+    let probed = tokio::time::timeout(timeout, probe(endpoint, ipv4, ipv6, max_response_size, thresholds)).await;
+    match probed {
+        Ok(Ok((status, findings, perfdata))) => (status, format!("{} - {} | {}", status.label(), findings.join("; "), perfdata)),
+        Ok(Err(e)) => (Status::Critical, format!("CRITICAL - {}: {}", endpoint, crate::redact::text(&e.to_string()))),
+        Err(_) => (Status::Critical, format!("CRITICAL - {}: no answer within {}s", endpoint, timeout.as_secs())),
+    }
+}
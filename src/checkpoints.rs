@@ -0,0 +1,83 @@
+//! Known block hashes at given heights, used to detect endpoints serving a different chain.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+static LOADED: OnceLock<Checkpoints> = OnceLock::new();
+
+/// Keeps the `--checkpoints` for the rest of the run, where `--verify light` anchors the
+/// GRANDPA authority set.
+pub fn set(checkpoints: Checkpoints) -> &'static Checkpoints {
+    LOADED.get_or_init(|| checkpoints)
+}
+
+pub fn get() -> Option<&'static Checkpoints> {
+    LOADED.get()
+}
+
+pub struct Checkpoints {
+    hashes: BTreeMap<u64, String>,
+}
+
+impl Checkpoints {
+    /// Loads checkpoints from either a JSON object (`{"1000": "0x.."}`) or lines of
+    /// `height hash` / `height,hash`, ignoring blank lines and `#` comments.
+    pub fn load(path: &Path) -> Result<Checkpoints, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read checkpoints file {}: {}", path.display(), e))?;
+        let mut hashes = BTreeMap::new();
+
+        if contents.trim_start().starts_with('{') {
+            let object: BTreeMap<String, String> = serde_json::from_str(&contents)?;
+            for (height, hash) in object {
+                hashes.insert(parse_height(&height)?, normalize(&hash)?);
+            }
+        } else {
+            for (line_number, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut parts = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|part| !part.is_empty());
+                match (parts.next(), parts.next()) {
+                    (Some(height), Some(hash)) => {
+                        hashes.insert(parse_height(height)?, normalize(hash)?);
+                    }
+                    _ => return Err(format!("Invalid checkpoint on line {}: expected 'height hash'", line_number + 1).into()),
+                }
+            }
+        }
+        Ok(Checkpoints { hashes })
+    }
+
+    /// Checks a fetched block against the checkpoint at its height, if there is one.
+    /// Returns whether a checkpoint was checked, and an error on mismatch.
+    pub fn verify(&self, height: u64, hash: &str) -> Result<bool, String> {
+        match self.hashes.get(&height) {
+            Some(expected) if expected.eq_ignore_ascii_case(hash) => Ok(true),
+            Some(expected) => Err(format!("Checkpoint mismatch at block {}: expected {}, endpoint served {}", height, expected, hash)),
+            None => Ok(false),
+        }
+    }
+
+    /// The highest checkpoint at or below `height`, as `(height, hash)`.
+    pub fn at_or_below(&self, height: u64) -> Option<(u64, &str)> {
+        self.hashes.range(..=height).next_back().map(|(height, hash)| (*height, hash.as_str()))
+    }
+}
+
+fn parse_height(height: &str) -> Result<u64, String> {
+    let height = height.trim();
+    match height.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => height.parse(),
+    }.map_err(|_| format!("Invalid checkpoint height '{}'", height))
+}
+
+fn normalize(hash: &str) -> Result<String, String> {
+    let bytes = crate::hex::decode(hash)?;
+    if bytes.len() != 32 {
+        return Err(format!("Checkpoint hash '{}' is not 32 bytes", hash));
+    }
+    Ok(crate::hex::encode(&bytes))
+}
@@ -0,0 +1,120 @@
+//! A typed client over one connection, for embedding gavel's requests in other programs.
+//!
+//! The client goes through the same paths as the commands: DNS overrides, the forced IP
+//! version, peer pinning, `--max-response-size`, method fallbacks, the in-flight limit
+//! and the run statistics all apply.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::rpc::Connection;
+
+/// How [`GavelClient::connect`] reaches the node, `Default` resolving it through DNS.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Address to connect to instead of resolving the endpoint's host over IPv4.
+    pub ipv4: Option<Ipv4Addr>,
+    /// Address to connect to instead of resolving the endpoint's host over IPv6.
+    pub ipv6: Option<Ipv6Addr>,
+    /// Largest response accepted, in bytes.
+    pub max_response_size: Option<usize>,
+}
+
+/// The block a request is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockRef {
+    Best,
+    Finalized,
+    Number(u64),
+    Hash(String),
+}
+
+/// A block with its hash and number resolved.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub number: u64,
+    pub hash: String,
+    pub header: Value,
+    pub extrinsics: Vec<String>,
+    pub justifications: Value,
+}
+
+/// The answer of `mmr_generateProof`, leaves and proof are SCALE encoded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MmrProof {
+    pub block_hash: String,
+    pub leaves: String,
+    pub proof: String,
+}
+
+pub struct GavelClient {
+    socket: Connection,
+}
+
+impl GavelClient {
+    /// Connects to `endpoint`, a URL, config profile name or chain name.
+    pub async fn connect(endpoint: &str, options: &ConnectOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let socket = crate::connect(endpoint, options.ipv4.as_ref(), options.ipv6.as_ref(), options.max_response_size).await?;
+        Ok(GavelClient { socket })
+    }
+
+    /// Wraps a connection taken from another client with [`GavelClient::into_connection`].
+    pub fn from_connection(socket: Connection) -> Self {
+        GavelClient { socket }
+    }
+
+    pub fn into_connection(self) -> Connection {
+        self.socket
+    }
+
+    /// Calls `method`, falling back to an equivalent method the node knows. A JSON-RPC
+    /// error is returned as an error rather than as a null result.
+    pub async fn rpc_call(&mut self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let response = crate::send_with_fallback(&mut self.socket, method, &params).await?;
+        if let Some(error) = response.get("error") {
+            crate::stats::record_failure("rpc");
+            return Err(format!("{} failed: {}", method, error).into());
+        }
+        Ok(response["result"].clone())
+    }
+
+    /// The hash of the block `block` refers to.
+    pub async fn block_hash(&mut self, block: &BlockRef) -> Result<String, Box<dyn std::error::Error>> {
+        let hash = match block {
+            BlockRef::Best => self.rpc_call("chain_getBlockHash", json!([])).await?,
+            BlockRef::Finalized => self.rpc_call("chain_getFinalizedHead", json!([])).await?,
+            BlockRef::Number(number) => return crate::fetch_block_hash(&mut self.socket, *number).await,
+            BlockRef::Hash(hash) => return Ok(hash.clone()),
+        };
+        Ok(hash.as_str().ok_or("Invalid block hash")?.to_string())
+    }
+
+    pub async fn block(&mut self, block: BlockRef) -> Result<Block, Box<dyn std::error::Error>> {
+        let hash = self.block_hash(&block).await?;
+        let response = self.rpc_call("chain_getBlock", json!([hash])).await?;
+        if response.is_null() {
+            return Err(format!("Block {} not found", hash).into());
+        }
+        let header = response["block"]["header"].clone();
+        let number = header["number"].as_str()
+            .and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+            .ok_or("Invalid block number in header")?;
+        let extrinsics = serde_json::from_value(response["block"]["extrinsics"].clone()).unwrap_or_default();
+        Ok(Block { number, hash, header, extrinsics, justifications: response["justifications"].clone() })
+    }
+
+    /// Proves the leaves of `blocks` against the MMR root at `at` (the best block if `None`).
+    pub async fn mmr_proof(&mut self, blocks: &[u64], at: Option<BlockRef>) -> Result<MmrProof, Box<dyn std::error::Error>> {
+        let mut params = vec![json!(blocks)];
+        if let Some(at) = at {
+            let hash = self.block_hash(&at).await?;
+            let number = self.block(BlockRef::Hash(hash.clone())).await?.number;
+            params.extend([json!(number), json!(hash)]);
+        }
+        let proof = self.rpc_call("mmr_generateProof", Value::Array(params)).await?;
+        Ok(serde_json::from_value(proof)?)
+    }
+}
@@ -0,0 +1,101 @@
+//! Wall-clock helpers for turning slots and timestamps into readable times.
+
+/// Formats milliseconds since the unix epoch as an RFC 3339 UTC timestamp.
+pub fn format_unix_millis(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    // Days to civil date, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Formats a duration in seconds as e.g. `2d 03h 04m 05s`.
+pub fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes, seconds) = (seconds / 86_400, seconds % 86_400 / 3600, seconds % 3600 / 60, seconds % 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m {:02}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m {:02}s", minutes, seconds)
+    }
+}
+
+/// Parses a duration such as `90`, `30s`, `10m`, `2h` or `1d`; bare numbers are seconds.
+pub fn parse_duration(input: &str) -> Result<std::time::Duration, String> {
+    let input = input.trim();
+    let (digits, multiplier) = match input.char_indices().last() {
+        Some((i, 's')) => (&input[..i], 1),
+        Some((i, 'm')) => (&input[..i], 60),
+        Some((i, 'h')) => (&input[..i], 3600),
+        Some((i, 'd')) => (&input[..i], 86_400),
+        _ => (input, 1),
+    };
+    let value = digits.parse::<u64>().map_err(|e| format!("invalid duration '{}': {}", input, e))?;
+    value.checked_mul(multiplier).map(std::time::Duration::from_secs).ok_or_else(|| format!("duration '{}' is too long", input))
+}
+
+/// Parses an RFC 3339 time such as `2024-06-01T00:00:00Z` or `2024-06-01 02:00+02:00` into
+/// milliseconds since the unix epoch. A bare date is midnight and no offset means UTC.
+pub fn parse_time(input: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid time '{}', expected e.g. 2024-06-01T00:00:00Z", input);
+    let input = input.trim();
+    let (date, rest) = input.split_at(input.find(['T', 't', ' ']).unwrap_or(input.len()));
+    let date: Vec<i64> = date.splitn(3, '-').map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid())?;
+    let [year, month, day] = date[..] else { return Err(invalid()) };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let rest = rest.get(1..).unwrap_or_default();
+    let (time, offset) = match rest.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => match rest.rfind(['+', '-']) {
+            Some(sign) => {
+                let (hours, minutes) = rest[sign + 1..].split_once(':').ok_or_else(invalid)?;
+                let offset = hours.parse::<i64>().map_err(|_| invalid())? * 3600 + minutes.parse::<i64>().map_err(|_| invalid())? * 60;
+                (&rest[..sign], if rest[sign..].starts_with('-') { -offset } else { offset })
+            }
+            None => (rest, 0),
+        },
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let fields: Vec<i64> = match time {
+        "" => Vec::new(),
+        time => time.split(':').map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid())?,
+    };
+    let (hour, minute, second) = match fields[..] {
+        [] => (0, 0, 0),
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    let millis = match fraction {
+        "" => 0,
+        fraction => format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse::<i64>().map_err(|_| invalid())?,
+    };
+
+    // Civil date to days, after Howard Hinnant's `days_from_civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146_097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(seconds * 1000 + millis).map_err(|_| format!("time '{}' is before 1970", input))
+}
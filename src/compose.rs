@@ -0,0 +1,285 @@
+//! Call data construction from metadata, and signing payloads for offline signers.
+
+use serde_json::{json, Value};
+
+use crate::encode::Encoder;
+use crate::metadata::{Metadata, TypeDef};
+use crate::metadata_hash::{self, ChainInfo};
+use crate::rpc::Connection;
+use crate::{extensions, hashing, hex, ss58};
+
+/// Call names compare equal in snake case and camel case, `transfer_keep_alive` is `transferKeepAlive`.
+pub fn same_name(a: &str, b: &str) -> bool {
+    let normalize = |name: &str| name.replace('_', "").to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+/// Encodes `pallet.call(args)` as call data: pallet index, call index and the SCALE encoded
+/// arguments, given in declaration order.
+pub fn encode_call(metadata: &Metadata, pallet: &str, call: &str, args: &[Value]) -> Result<Value, Box<dyn std::error::Error>> {
+    let pallet = metadata.pallets.iter().find(|candidate| same_name(&candidate.name, pallet))
+        .ok_or_else(|| format!("Pallet {} not found in metadata", pallet))?;
+    let calls = pallet.calls.ok_or_else(|| format!("Pallet {} has no calls", pallet.name))?;
+    let variants = match &metadata.resolve(calls)?.def {
+        TypeDef::Variant(variants) => variants,
+        _ => return Err(format!("Call type of {} is not an enum", pallet.name).into()),
+    };
+    let variant = variants.iter().find(|variant| same_name(&variant.name, call))
+        .ok_or_else(|| format!("Call {}.{} not found in metadata", pallet.name, call))?;
+    if variant.fields.len() != args.len() {
+        let names: Vec<&str> = variant.fields.iter().map(|field| field.name.as_deref().unwrap_or("_")).collect();
+        return Err(format!("{}.{} takes {} arguments ({}), got {}", pallet.name, variant.name, names.len(), names.join(", "), args.len()).into());
+    }
+
+    let encoder = Encoder::new(metadata);
+    let mut data = vec![pallet.index, variant.index];
+    for (field, arg) in variant.fields.iter().zip(args) {
+        let encoded = encoder.encode(field.ty, arg)
+            .map_err(|e| format!("argument {}: {}", field.name.as_deref().unwrap_or("_"), e))?;
+        data.extend(encoded);
+    }
+    Ok(describe(&pallet.name, &variant.name, &data))
+}
+
+/// Wraps already encoded calls into `Utility.batch` (or `batch_all`, which reverts every
+/// call if one fails).
+pub fn batch(metadata: &Metadata, calls: &[String], all: bool) -> Result<Value, Box<dyn std::error::Error>> {
+    encode_call(metadata, "Utility", if all { "batch_all" } else { "batch" }, &[json!(calls)])
+}
+
+fn describe(pallet: &str, call: &str, data: &[u8]) -> Value {
+    json!({
+        "pallet": pallet,
+        "call": call,
+        "call_data": hex::encode(data),
+        "call_hash": hex::encode(&hashing::blake2_256(data)),
+        "length": data.len(),
+    })
+}
+
+/// Transaction parameters for building a signing payload against a live chain.
+#[derive(clap::Args, Debug)]
+pub struct TxOptions {
+    #[clap(long, help = "Signer SS58 address or 0x-prefixed public key.")]
+    pub signer: Option<String>,
+    #[clap(long, default_value = "64", help = "Blocks the transaction stays valid for, 0 for an immortal transaction.")]
+    pub mortality: u64,
+    #[clap(long, help = "Transaction nonce, the signer's next index by default.")]
+    pub nonce: Option<u64>,
+    #[clap(long, default_value = "0", help = "Tip in planck.")]
+    pub tip: u128,
+    #[clap(long, help = "Sign with the Polkadot app on a Ledger device.")]
+    pub ledger: bool,
+    #[clap(long, default_value = "//0", help = "Ledger account as //<account>[//<index>].")]
+    pub derivation: String,
+    #[clap(long, help = "Sign locally with a secret URI or a polkadot-js keystore file.")]
+    pub key: Option<String>,
+    #[clap(long, value_enum, default_value = "sr25519", help = "Scheme of a --key secret URI.")]
+    pub scheme: crate::keys::Scheme,
+    #[clap(long, help = "Sign with a remote JSON-RPC signing service (ws, wss, http or https URL) for --signer.")]
+    pub signer_url: Option<String>,
+    #[clap(long, default_value = "sign", help = "JSON-RPC method of the remote signing service.")]
+    pub signer_method: String,
+    #[clap(long, help = "Leave the CheckMetadataHash extension disabled instead of signing the RFC 78 metadata hash.")]
+    pub no_metadata_hash: bool,
+    #[clap(long = "extension", value_name = "NAME=JSON", help = "Extra data of a signed extension, for ones gavel does not fill in or to override it (repeatable).")]
+    pub extensions: Vec<String>,
+    #[clap(long = "extension-signed", value_name = "NAME=JSON", help = "Additional signed data of a signed extension (repeatable).")]
+    pub extensions_signed: Vec<String>,
+    #[clap(long, help = "Submit the signed extrinsic with author_submitExtrinsic.")]
+    pub submit: bool,
+    #[clap(long, help = "Check the signed extrinsic with system_dryRun, with --submit only submitted when it would succeed.")]
+    pub dry_run: bool,
+}
+
+/// `MultiSignature` variant of ed25519 signatures.
+pub const SIGNATURE_ED25519: u8 = 0x00;
+
+/// What a signer signs: the call, the signed extension data that goes into the extrinsic
+/// (`extra`) and the data that is only signed (`additional`).
+pub struct SigningPayload {
+    pub call: Vec<u8>,
+    pub extra: Vec<u8>,
+    pub additional: Vec<u8>,
+    pub genesis_hash: Vec<u8>,
+    /// The V15 metadata and chain info of the metadata hash, when the payload signs one.
+    pub hashed_metadata: Option<(Metadata, ChainInfo)>,
+    pub details: Value,
+}
+
+impl SigningPayload {
+    /// Polkadot Vault (UOS) transaction payload: `0x53`, sr25519, the transaction command,
+    /// the signer public key, the length-prefixed call, the extensions and the genesis hash.
+    pub fn to_vault(&self, signer: &[u8; 32]) -> Vec<u8> {
+        let mut payload = vec![0x53, 0x01, 0x02];
+        payload.extend_from_slice(signer);
+        payload.extend(crate::scale::encode_compact(self.call.len() as u128));
+        payload.extend_from_slice(&self.call);
+        payload.extend_from_slice(&self.extra);
+        payload.extend_from_slice(&self.additional);
+        payload.extend_from_slice(&self.genesis_hash);
+        payload
+    }
+
+    /// Call, extra and additional data concatenated, as hardware signers expect it.
+    pub fn bytes(&self) -> Vec<u8> {
+        [self.call.as_slice(), &self.extra, &self.additional].concat()
+    }
+
+    /// What a key signs: the payload, or its blake2-256 hash when longer than 256 bytes.
+    pub fn to_sign(&self) -> Vec<u8> {
+        let bytes = self.bytes();
+        if bytes.len() > 256 {
+            hashing::blake2_256(&bytes)
+        } else {
+            bytes
+        }
+    }
+
+    /// The RFC 78 proof of the metadata needed to decode this payload, for signers that
+    /// check the metadata hash without holding the metadata.
+    pub fn metadata_proof(&self) -> Result<Vec<u8>, String> {
+        let (metadata, chain) = self.hashed_metadata.as_ref().ok_or("the payload signs no metadata hash")?;
+        metadata_hash::proof(metadata, chain, &self.call, &self.extra, &self.additional)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut details = self.details.clone();
+        details["payload"] = json!(hex::encode(&self.bytes()));
+        details["extra"] = json!(hex::encode(&self.extra));
+        details["additional"] = json!(hex::encode(&self.additional));
+        details
+    }
+}
+
+/// SCALE encoded `Era`: the period rounded to a power of two between 4 and 65536, and the
+/// phase of `block` within it, quantized for long periods. Returns the era and its birth block.
+fn mortal_era(period: u64, block: u64) -> (Vec<u8>, u64) {
+    let period = period.checked_next_power_of_two().unwrap_or(1 << 16).clamp(4, 1 << 16);
+    let quantize = (period >> 12).max(1);
+    let phase = block % period / quantize * quantize;
+    let encoded = (period.trailing_zeros() - 1).clamp(1, 15) as u16 | ((phase / quantize) << 4) as u16;
+    let birth = (block.max(phase) - phase) / period * period + phase;
+    (encoded.to_le_bytes().to_vec(), birth)
+}
+
+/// Builds the signing payload of `call` for `signer` at the finalized head, encoding every
+/// signed extension the runtime declares.
+pub async fn signing_payload(socket: &mut Connection, metadata: &Metadata, call: &[u8], signer: &[u8; 32], options: &TxOptions) -> Result<SigningPayload, Box<dyn std::error::Error>> {
+    let genesis_hash = crate::fetch_block_hash(socket, 0).await?;
+    let head = crate::send_and_receive(socket, "chain_getFinalizedHead", json!([])).await?;
+    let head = head.as_str().ok_or("Failed to get the finalized head")?.to_string();
+    let head_number = crate::fetch_header_number(socket, Some(&head)).await?;
+    let version = crate::send_and_receive(socket, "state_getRuntimeVersion", json!([head])).await?;
+    let spec_version = version["specVersion"].as_u64().ok_or("No specVersion in the runtime version")?;
+    let transaction_version = version["transactionVersion"].as_u64().ok_or("No transactionVersion in the runtime version")?;
+    let spec_name = version["specName"].as_str().ok_or("No specName in the runtime version")?;
+
+    let prefix = metadata.constant_u64("System", "SS58Prefix").unwrap_or(42) as u16;
+    let address = ss58::encode(prefix, signer);
+    let nonce = match options.nonce {
+        Some(nonce) => nonce,
+        None => crate::send_and_receive(socket, "system_accountNextIndex", json!([address])).await?
+            .as_u64().ok_or("Invalid system_accountNextIndex response")?,
+    };
+
+    let (era, birth_hash) = if options.mortality == 0 {
+        (vec![0x00], genesis_hash.clone())
+    } else {
+        let (era, birth) = mortal_era(options.mortality, head_number);
+        let birth_hash = if birth == head_number { head.clone() } else { crate::fetch_block_hash(socket, birth).await? };
+        (era, birth_hash)
+    };
+
+    let uses_metadata_hash = metadata.extrinsic.signed_extensions.iter().any(|extension| extension.identifier == "CheckMetadataHash");
+    let hashed_metadata = if uses_metadata_hash && !options.no_metadata_hash {
+        let failed = |e: &dyn std::fmt::Display| format!("Failed to compute the metadata hash ({}), pass --no-metadata-hash to sign without it", e);
+        let (metadata, chain) = metadata_hash::fetch(socket, &head, spec_version as u32, spec_name).await.map_err(|e| failed(&e))?;
+        let hash = metadata_hash::digest(&metadata, &chain).map_err(|e| failed(&e))?;
+        Some((hex::encode(&hash), (metadata, chain)))
+    } else {
+        None
+    };
+    let (metadata_hash, hashed_metadata) = hashed_metadata.unzip();
+
+    let extra_values = extensions::parse_values(&options.extensions)?;
+    let additional_values = extensions::parse_values(&options.extensions_signed)?;
+    let declared = &metadata.extrinsic.signed_extensions;
+    if let Some(name) = extra_values.keys().chain(additional_values.keys()).find(|name| !declared.iter().any(|extension| &extension.identifier == *name)) {
+        return Err(format!("The runtime has no signed extension {}", name).into());
+    }
+
+    let encoder = Encoder::new(metadata);
+    let (mut extra, mut additional) = (Vec::new(), Vec::new());
+    for extension in declared {
+        let builtin = match extension.identifier.as_str() {
+            "CheckSpecVersion" => Some((Value::Null, json!(spec_version))),
+            "CheckTxVersion" => Some((Value::Null, json!(transaction_version))),
+            "CheckGenesis" => Some((Value::Null, json!(genesis_hash))),
+            "CheckMortality" | "CheckEra" => Some((json!(hex::encode(&era)), json!(birth_hash))),
+            "CheckNonce" => Some((json!(nonce), Value::Null)),
+            "ChargeTransactionPayment" => Some((json!(options.tip.to_string()), Value::Null)),
+            "ChargeAssetTxPayment" => Some((json!({ "tip": options.tip.to_string(), "asset_id": null }), Value::Null)),
+            "CheckMetadataHash" => Some(match &metadata_hash {
+                Some(hash) => (json!({ "mode": "Enabled" }), json!(hash)),
+                None => (json!({ "mode": "Disabled" }), Value::Null),
+            }),
+            _ => None,
+        };
+        let given = (extra_values.get(&extension.identifier), additional_values.get(&extension.identifier));
+        let (extra_value, additional_value) = match (builtin, given) {
+            (Some((extra, additional)), (extra_value, additional_value)) => {
+                (extra_value.cloned().unwrap_or(extra), additional_value.cloned().unwrap_or(additional))
+            }
+            // An extension gavel doesn't know is only signed when it carries no data or is given
+            (None, (None, None)) if !extensions::is_zero_sized(metadata, extension.ty) || !extensions::is_zero_sized(metadata, extension.additional_signed) => {
+                return Err(format!(
+                    "Signed extension {} ({} in the extrinsic, {} signed) is not supported, pass its values with --extension {}=<JSON> and --extension-signed {}=<JSON>",
+                    extension.identifier,
+                    extensions::type_name(metadata, extension.ty),
+                    extensions::type_name(metadata, extension.additional_signed),
+                    extension.identifier,
+                    extension.identifier,
+                ).into());
+            }
+            (None, (extra_value, additional_value)) => {
+                (extra_value.cloned().unwrap_or(Value::Null), additional_value.cloned().unwrap_or(Value::Null))
+            }
+        };
+        let context = |e: String| format!("signed extension {}: {}", extension.identifier, e);
+        extra.extend(encoder.encode(extension.ty, &extra_value).map_err(context)?);
+        additional.extend(encoder.encode(extension.additional_signed, &additional_value).map_err(context)?);
+    }
+
+    Ok(SigningPayload {
+        call: call.to_vec(),
+        extra,
+        additional,
+        genesis_hash: hex::decode(&genesis_hash)?,
+        hashed_metadata,
+        details: json!({
+            "signer": address,
+            "nonce": nonce,
+            "tip": options.tip.to_string(),
+            "era": if options.mortality == 0 { json!("immortal") } else { json!({ "period": options.mortality, "birth_block_hash": birth_hash }) },
+            "spec_version": spec_version,
+            "transaction_version": transaction_version,
+            "metadata_hash": metadata_hash,
+            "genesis_hash": genesis_hash,
+            "block_hash": head,
+            "block_number": head_number,
+        }),
+    })
+}
+
+/// Assembles a signed extrinsic: version byte, `MultiAddress::Id` signer, `MultiSignature`,
+/// extra data and the call, prefixed with the compact length.
+pub fn signed_extrinsic(metadata: &Metadata, payload: &SigningPayload, signer: &[u8; 32], signature_type: u8, signature: &[u8]) -> Vec<u8> {
+    let mut body = vec![0x80 | metadata.extrinsic.version, 0x00];
+    body.extend_from_slice(signer);
+    body.push(signature_type);
+    body.extend_from_slice(signature);
+    body.extend_from_slice(&payload.extra);
+    body.extend_from_slice(&payload.call);
+    [crate::scale::encode_compact(body.len() as u128), body].concat()
+}
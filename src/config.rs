@@ -0,0 +1,416 @@
+//! The gavel config file: named endpoint profiles with their authentication and DNS
+//! override, chain bundles with what identifies a chain, its endpoints and pinned
+//! metadata, defaults for flags not given on the command line and named output profiles.
+//! The file is TOML when it ends in `.toml` and JSON otherwise.
+//!
+//! ```json
+//! { "profiles": { "dwellir": {
+//!     "endpoint": "wss://polkadot-rpc.dwellir.com",
+//!     "auth": { "type": "header", "name": "x-api-key", "value": { "env": "DWELLIR_KEY" } }
+//! } },
+//!   "chains": { "polkadot": {
+//!     "genesis_hash": "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3",
+//!     "ss58_prefix": 0, "decimals": 10,
+//!     "endpoints": ["dwellir", "wss://rpc.polkadot.io"],
+//!     "metadata": "polkadot.scale"
+//! } } }
+//! ```
+//!
+//! Secrets are never stored in the file, only where to get them: an environment variable
+//! or a command such as `pass show rpc/dwellir`. Resolved values only ever go into the
+//! handshake and are never printed.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use http::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, handshake::client::Request};
+use url::Url;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Config {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    chains: BTreeMap<String, Chain>,
+    #[serde(default)]
+    defaults: Defaults,
+    #[serde(default)]
+    output_profiles: BTreeMap<String, OutputProfile>,
+}
+
+/// A profile is either its endpoint URL alone or a table with its authentication and an
+/// address to connect to instead of resolving the host.
+#[derive(Deserialize, Debug)]
+#[serde(from = "ProfileEntry")]
+struct Profile {
+    endpoint: String,
+    auth: Option<Auth>,
+    resolve: Option<IpAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProfileEntry {
+    Endpoint(String),
+    Full { endpoint: String, auth: Option<Auth>, resolve: Option<IpAddr> },
+}
+
+impl From<ProfileEntry> for Profile {
+    fn from(entry: ProfileEntry) -> Self {
+        match entry {
+            ProfileEntry::Endpoint(endpoint) => Profile { endpoint, auth: None, resolve: None },
+            ProfileEntry::Full { endpoint, auth, resolve } => Profile { endpoint, auth, resolve },
+        }
+    }
+}
+
+/// Values used for flags left off the command line.
+#[derive(Deserialize, Debug, Default)]
+pub struct Defaults {
+    /// `fetch --request-timeout`, in seconds.
+    pub request_timeout: Option<u64>,
+    /// `--timeout` of the fleet commands, in seconds.
+    pub timeout: Option<u64>,
+    /// `--idle-timeout`, in expected blocks.
+    pub idle_timeout: Option<u64>,
+    /// `--summary`, printed after every command when set.
+    pub summary: Option<crate::stats::SummaryFormat>,
+    /// `--format` of command results.
+    pub format: Option<crate::output::Format>,
+    /// `--retries` of failed calls.
+    pub retries: Option<u32>,
+    /// `--call-timeout`, in seconds.
+    pub call_timeout: Option<u64>,
+    /// `--response-deadline`, in seconds.
+    pub response_deadline: Option<u64>,
+    /// `--verify` level.
+    pub verify: Option<crate::verify::Level>,
+}
+
+/// A named set of output options, selected with `--profile`. Flags given on the command
+/// line still win.
+#[derive(Deserialize, Debug, Default)]
+pub struct OutputProfile {
+    /// `--format` of command results.
+    pub format: Option<crate::output::Format>,
+    /// `fetch --decode`, for single blocks and ranges.
+    #[serde(default)]
+    pub decode: bool,
+    /// `--output` file.
+    pub output: Option<PathBuf>,
+    /// `--rotate` spec of the output file, e.g. `size=500M,keep=10`.
+    pub rotate: Option<String>,
+    /// `--sink` URL.
+    pub sink: Option<String>,
+    /// `--summary` format.
+    pub summary: Option<crate::stats::SummaryFormat>,
+}
+
+/// A chain bundle. The metadata file is relative to the config file.
+#[derive(Deserialize, Debug)]
+pub struct Chain {
+    pub genesis_hash: Option<String>,
+    pub ss58_prefix: Option<u16>,
+    pub decimals: Option<u32>,
+    #[serde(default)]
+    endpoints: Vec<String>,
+    pub metadata: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Auth {
+    Bearer { token: Secret },
+    Basic { username: String, password: Secret },
+    Header { name: String, value: Secret },
+    Query { name: String, value: Secret },
+}
+
+/// Where a secret comes from. `Debug` only shows the source, never the value.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+enum Secret {
+    Env(String),
+    Command(String),
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static API_KEY: OnceLock<String> = OnceLock::new();
+static CLIENT_HEADERS: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+static PIN_PEER: OnceLock<(String, u32)> = OnceLock::new();
+static CHAIN: OnceLock<String> = OnceLock::new();
+static IP_VERSION: OnceLock<IpVersion> = OnceLock::new();
+static OUTPUT_PROFILE: OnceLock<String> = OnceLock::new();
+
+// Commands like `op read` may prompt, so each secret is resolved once per run
+static RESOLVED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+impl Secret {
+    fn resolve(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let key = format!("{:?}", self);
+        if let Some(value) = RESOLVED.lock().unwrap().get(&key) {
+            return Ok(value.clone());
+        }
+        let value = match self {
+            Secret::Env(name) => std::env::var(name).map_err(|_| format!("Environment variable {} is not set", name))?,
+            Secret::Command(command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stderr(std::process::Stdio::inherit())
+                    .output()?;
+                if !output.status.success() {
+                    return Err(format!("Secret command `{}` failed with {}", command, output.status).into());
+                }
+                String::from_utf8(output.stdout)?.trim_end_matches(['\r', '\n']).to_string()
+            }
+        };
+        crate::redact::secret(&value);
+        RESOLVED.lock().unwrap().insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("GAVEL_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    let dir = base.join("gavel");
+    ["config.toml", "config.json"].into_iter().map(|name| dir.join(name)).find(|path| path.exists())
+}
+
+/// Loads the config file given with `--config`, `$GAVEL_CONFIG`, or else
+/// `~/.config/gavel/config.toml` or `config.json`, if any. Only the first call has an effect.
+pub fn load(path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = match path.map(Path::to_path_buf).or_else(default_path) {
+        Some(path) => {
+            let contents = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+            let parsed = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("toml") => toml::from_str(&contents).map_err(|e| e.to_string()),
+                _ => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+            };
+            let mut config: Config = parsed.map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+            let dir = path.parent().unwrap_or(Path::new("."));
+            for chain in config.chains.values_mut() {
+                chain.metadata = chain.metadata.take().map(|metadata| dir.join(metadata));
+            }
+            config
+        }
+        None => Config::default(),
+    };
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// Sets the key substituted for `{key}` in endpoint URLs, from `--api-key` or
+/// `$GAVEL_API_KEY` so it can stay out of shell history.
+pub fn set_api_key(key: Option<String>) {
+    if let Some(key) = key.or_else(|| std::env::var("GAVEL_API_KEY").ok()).filter(|key| !key.is_empty()) {
+        crate::redact::secret(&key);
+        let _ = API_KEY.set(key);
+    }
+}
+
+/// Sets the `User-Agent` and `Origin` sent in every handshake, for nodes that reject
+/// connections without a known origin.
+pub fn set_client_headers(user_agent: Option<String>, origin: Option<String>) {
+    let headers = [("user-agent", user_agent), ("origin", origin)]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect();
+    let _ = CLIENT_HEADERS.set(headers);
+}
+
+/// Makes every connection retry until the load balancer hands it to the node with this
+/// peer id, giving up after `attempts` connections.
+pub fn set_pin_peer(peer_id: Option<String>, attempts: u32) {
+    if let Some(peer_id) = peer_id {
+        let _ = PIN_PEER.set((peer_id, attempts.max(1)));
+    }
+}
+
+pub fn pin_peer() -> Option<(&'static str, u32)> {
+    PIN_PEER.get().map(|(peer_id, attempts)| (peer_id.as_str(), *attempts))
+}
+
+/// The address family connections are restricted to with `-4` or `-6`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    pub fn matches(self, address: &std::net::IpAddr) -> bool {
+        match self {
+            IpVersion::V4 => address.is_ipv4(),
+            IpVersion::V6 => address.is_ipv6(),
+        }
+    }
+}
+
+impl std::fmt::Display for IpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            IpVersion::V4 => "IPv4",
+            IpVersion::V6 => "IPv6",
+        })
+    }
+}
+
+/// Restricts name resolution and connections to one address family, like curl's `-4`
+/// and `-6`.
+pub fn set_ip_version(ipv4: bool, ipv6: bool) {
+    let version = match (ipv4, ipv6) {
+        (true, _) => IpVersion::V4,
+        (_, true) => IpVersion::V6,
+        _ => return,
+    };
+    let _ = IP_VERSION.set(version);
+}
+
+pub fn ip_version() -> Option<IpVersion> {
+    IP_VERSION.get().copied()
+}
+
+/// Selects the chain bundle from `--chain`, whose metadata, prefix and decimals become
+/// the defaults and whose genesis hash every connection must match.
+pub fn set_chain(name: Option<String>) -> Result<(), String> {
+    let Some(name) = name else { return Ok(()) };
+    if !config().chains.contains_key(&name) {
+        let known: Vec<&str> = config().chains.keys().map(String::as_str).collect();
+        return Err(format!("Unknown chain '{}', the config file defines: {}", name, if known.is_empty() { "none".to_string() } else { known.join(", ") }));
+    }
+    let _ = CHAIN.set(name);
+    Ok(())
+}
+
+/// The chain selected with `--chain`, if any.
+pub fn chain() -> Option<&'static Chain> {
+    CHAIN.get().and_then(|name| config().chains.get(name))
+}
+
+/// The chain a connection to `endpoint` must be on: the one it names, else `--chain`.
+pub fn chain_for(endpoint: &str) -> Option<(&'static str, &'static Chain)> {
+    config().chains.get_key_value(endpoint)
+        .or_else(|| CHAIN.get().and_then(|name| config().chains.get_key_value(name)))
+        .map(|(name, chain)| (name.as_str(), chain))
+}
+
+/// Substitutes the API key, percent-encoded, for `{key}` in an endpoint URL.
+fn expand_key(url: &str) -> Result<String, String> {
+    if !url.contains("{key}") {
+        return Ok(url.to_string());
+    }
+    let key = API_KEY.get().ok_or("The endpoint contains {key} but no --api-key or GAVEL_API_KEY was given")?;
+    let encoded: String = key.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect();
+    crate::redact::secret(&encoded);
+    Ok(url.replace("{key}", &encoded))
+}
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Selects the output profile from `--profile`, whose options apply where the flags are
+/// left off the command line.
+pub fn set_output_profile(name: Option<String>) -> Result<(), String> {
+    let Some(name) = name else { return Ok(()) };
+    if !config().output_profiles.contains_key(&name) {
+        let known: Vec<&str> = config().output_profiles.keys().map(String::as_str).collect();
+        return Err(format!("Unknown output profile '{}', the config file defines: {}", name, if known.is_empty() { "none".to_string() } else { known.join(", ") }));
+    }
+    let _ = OUTPUT_PROFILE.set(name);
+    Ok(())
+}
+
+/// The output profile selected with `--profile`, empty when there is none.
+pub fn output_profile() -> &'static OutputProfile {
+    static NONE: OutputProfile = OutputProfile { format: None, decode: false, output: None, rotate: None, sink: None, summary: None };
+    OUTPUT_PROFILE.get().and_then(|name| config().output_profiles.get(name)).unwrap_or(&NONE)
+}
+
+/// The defaults the config file sets for flags left off the command line.
+pub fn defaults() -> &'static Defaults {
+    &config().defaults
+}
+
+/// The URL an endpoint argument stands for: a profile name is replaced by its endpoint,
+/// a chain name by its first preferred endpoint, anything else is used as is.
+pub fn endpoint(endpoint: &str) -> &str {
+    endpoints(endpoint)[0]
+}
+
+/// The URLs to try, in order, for an endpoint argument: a chain's preferred endpoints,
+/// each of which may be a profile name, or the one URL it stands for.
+pub fn endpoints(endpoint: &str) -> Vec<&str> {
+    match config().chains.get(endpoint).filter(|chain| !chain.endpoints.is_empty()) {
+        Some(chain) => chain.endpoints.iter().map(|endpoint| profile_endpoint(endpoint)).collect(),
+        None => vec![profile_endpoint(endpoint)],
+    }
+}
+
+fn profile_endpoint(endpoint: &str) -> &str {
+    config().profiles.get(endpoint).map_or(endpoint, |profile| profile.endpoint.as_str())
+}
+
+/// The profile applying to `url`: the one with the longest endpoint that prefixes it.
+fn profile_for(url: &str) -> Option<&'static Profile> {
+    config().profiles.values()
+        .filter(|profile| url.starts_with(profile.endpoint.trim_end_matches('/')))
+        .max_by_key(|profile| profile.endpoint.len())
+}
+
+/// The addresses to connect to for `url`: the ones given on the command line, else its
+/// profile's `resolve`, else none and the host is resolved.
+pub fn resolve_for(url: &str, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    if ipv4.is_some() || ipv6.is_some() {
+        return (ipv4, ipv6);
+    }
+    match profile_for(url).and_then(|profile| profile.resolve) {
+        Some(IpAddr::V4(ip)) => (Some(ip), None),
+        Some(IpAddr::V6(ip)) => (None, Some(ip)),
+        None => (None, None),
+    }
+}
+
+/// The handshake request for `url` with the API key templated in, the client headers
+/// and the matching profile's authentication applied, as a header or an extra query
+/// parameter.
+pub fn authorize(url: &str) -> Result<Request, Box<dyn std::error::Error>> {
+    let mut parsed = Url::parse(&expand_key(url)?)?;
+    let mut header = None;
+    match profile_for(url).and_then(|profile| profile.auth.as_ref()) {
+        Some(Auth::Bearer { token }) => header = Some(("authorization", format!("Bearer {}", token.resolve()?))),
+        Some(Auth::Basic { username, password }) => {
+            let credentials = format!("{}:{}", username, password.resolve()?);
+            header = Some(("authorization", format!("Basic {}", crate::keystore::base64_encode(credentials.as_bytes()))));
+        }
+        Some(Auth::Header { name, value }) => header = Some((name.as_str(), value.resolve()?)),
+        Some(Auth::Query { name, value }) => {
+            parsed.query_pairs_mut().append_pair(name, &value.resolve()?);
+        }
+        None => {}
+    }
+    let mut request = parsed.as_str().into_client_request()?;
+    for (name, value) in CLIENT_HEADERS.get().into_iter().flatten() {
+        let value = HeaderValue::from_str(value).map_err(|_| format!("Invalid {} header '{}'", name, value))?;
+        request.headers_mut().insert(*name, value);
+    }
+    if let Some((name, value)) = header {
+        let mut value = HeaderValue::from_str(&value).map_err(|_| format!("The {} secret is not a valid header value", name))?;
+        value.set_sensitive(true);
+        request.headers_mut().insert(HeaderName::from_bytes(name.as_bytes())?, value);
+    }
+    Ok(request)
+}
@@ -0,0 +1,176 @@
+//! Type-driven decoding of SCALE values into JSON using the metadata type registry.
+
+use serde_json::{json, Map, Value};
+
+use crate::hex;
+use crate::metadata::{Field, Metadata, Primitive, StorageEntry, StorageType, TypeDef};
+use crate::scale::Input;
+use crate::ss58;
+
+/// Recursion guard against self-referential types in malformed metadata.
+const MAX_DEPTH: usize = 128;
+
+pub struct Decoder<'m> {
+    pub metadata: &'m Metadata,
+    pub ss58_prefix: Option<u16>,
+}
+
+impl<'m> Decoder<'m> {
+    pub fn new(metadata: &'m Metadata) -> Self {
+        let ss58_prefix = metadata.constant_u64("System", "SS58Prefix").map(|prefix| prefix as u16);
+        Decoder { metadata, ss58_prefix }
+    }
+
+    pub fn decode(&self, ty: u32, input: &mut Input) -> Result<Value, String> {
+        self.decode_at(ty, input, 0)
+    }
+
+    /// Decodes `bytes` as `ty`, failing if anything is left over.
+    pub fn decode_all(&self, ty: u32, bytes: &[u8]) -> Result<Value, String> {
+        let mut input = Input::new(bytes);
+        let value = self.decode(ty, &mut input)?;
+        if input.remaining() > 0 {
+            return Err(format!("{} trailing bytes after decoding type {}", input.remaining(), ty));
+        }
+        Ok(value)
+    }
+
+    /// Recovers the map keys from a full storage key (after the 32-byte entry prefix).
+    /// Keys behind opaque hashers are returned as the hash in hex.
+    pub fn decode_storage_key(&self, entry: &StorageEntry, key: &[u8]) -> Result<Value, String> {
+        let (hashers, key_ty) = match &entry.ty {
+            StorageType::Plain(_) => return Ok(Value::Null),
+            StorageType::Map { hashers, key, .. } => (hashers, *key),
+        };
+        let key_types = match &self.metadata.resolve(key_ty)?.def {
+            TypeDef::Tuple(types) if hashers.len() > 1 => types.clone(),
+            _ => vec![key_ty],
+        };
+        let mut input = Input::new(key.get(32..).ok_or("storage key shorter than its prefix")?);
+        let mut values = Vec::new();
+        for (hasher, ty) in hashers.iter().zip(key_types) {
+            let hash = input.read_bytes(hasher.hash_len())?;
+            values.push(if hasher.is_transparent() { self.decode(ty, &mut input)? } else { json!(hex::encode(hash)) });
+        }
+        Ok(if values.len() == 1 { values.remove(0) } else { Value::Array(values) })
+    }
+
+    fn decode_at(&self, ty: u32, input: &mut Input, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("type nesting too deep".into());
+        }
+        let resolved = self.metadata.resolve(ty)?;
+        let last = resolved.path.last().map(String::as_str);
+
+        if last == Some("AccountId32") {
+            let account = input.read_bytes(32)?;
+            return Ok(match self.ss58_prefix {
+                Some(prefix) => json!(ss58::encode(prefix, account)),
+                None => json!(hex::encode(account)),
+            });
+        }
+
+        match &resolved.def {
+            TypeDef::Composite(fields) => self.decode_fields(fields, input, depth),
+            TypeDef::Variant(variants) => {
+                let index = input.read_u8()?;
+                let variant = variants.iter().find(|variant| variant.index == index)
+                    .ok_or_else(|| format!("variant index {} not found in type {}", index, ty))?;
+                if last == Some("Option") && resolved.path.len() == 1 {
+                    return match variant.fields.first() {
+                        Some(field) => self.decode_at(field.ty, input, depth + 1),
+                        None => Ok(Value::Null),
+                    };
+                }
+                if variant.fields.is_empty() {
+                    return Ok(json!(variant.name));
+                }
+                let mut object = Map::new();
+                object.insert(variant.name.clone(), self.decode_fields(&variant.fields, input, depth)?);
+                Ok(Value::Object(object))
+            }
+            TypeDef::Sequence(inner) => {
+                if self.is_u8(*inner) {
+                    return Ok(json!(hex::encode(input.read_vec()?)));
+                }
+                let values = input.read_list(|input| self.decode_at(*inner, input, depth + 1))?;
+                Ok(Value::Array(values))
+            }
+            TypeDef::Array(len, inner) => {
+                if self.is_u8(*inner) {
+                    return Ok(json!(hex::encode(input.read_bytes(*len as usize)?)));
+                }
+                let values = (0..*len).map(|_| self.decode_at(*inner, input, depth + 1)).collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Array(values))
+            }
+            TypeDef::Tuple(types) => {
+                if types.is_empty() {
+                    return Ok(Value::Null);
+                }
+                let values = types.iter().map(|inner| self.decode_at(*inner, input, depth + 1)).collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Array(values))
+            }
+            TypeDef::Primitive(primitive) => decode_primitive(*primitive, input),
+            TypeDef::Compact(_) => Ok(number(input.read_compact()?)),
+            TypeDef::BitSequence(_, _) => {
+                let bits = input.read_compact()? as usize;
+                let bytes = input.read_bytes(bits.div_ceil(8))?;
+                let string: String = (0..bits).map(|bit| if bytes[bit / 8] & (1 << (bit % 8)) != 0 { '1' } else { '0' }).collect();
+                Ok(json!(string))
+            }
+        }
+    }
+
+    fn decode_fields(&self, fields: &[Field], input: &mut Input, depth: usize) -> Result<Value, String> {
+        if fields.len() == 1 && fields[0].name.is_none() {
+            return self.decode_at(fields[0].ty, input, depth + 1);
+        }
+        if fields.iter().all(|field| field.name.is_some()) {
+            let mut object = Map::new();
+            for field in fields {
+                object.insert(field.name.clone().unwrap_or_default(), self.decode_at(field.ty, input, depth + 1)?);
+            }
+            return Ok(Value::Object(object));
+        }
+        let values = fields.iter().map(|field| self.decode_at(field.ty, input, depth + 1)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Value::Array(values))
+    }
+
+    fn is_u8(&self, ty: u32) -> bool {
+        matches!(self.metadata.resolve(ty).map(|ty| &ty.def), Ok(TypeDef::Primitive(Primitive::U8)))
+    }
+}
+
+/// Numbers that do not fit in a u64 are rendered as decimal strings to keep JSON exact.
+fn number(value: u128) -> Value {
+    match u64::try_from(value) {
+        Ok(value) => json!(value),
+        Err(_) => json!(value.to_string()),
+    }
+}
+
+fn decode_primitive(primitive: Primitive, input: &mut Input) -> Result<Value, String> {
+    Ok(match primitive {
+        Primitive::Bool => json!(input.read_bool()?),
+        Primitive::Char => json!(char::from_u32(input.read_u32()?).map(String::from).unwrap_or_default()),
+        Primitive::Str => json!(input.read_string()?),
+        Primitive::U8 => json!(input.read_u8()?),
+        Primitive::U16 => json!(input.read_u16()?),
+        Primitive::U32 => json!(input.read_u32()?),
+        Primitive::U64 => json!(input.read_u64()?),
+        Primitive::U128 => number(input.read_u128()?),
+        Primitive::U256 => json!(hex::encode(input.read_bytes(32)?)),
+        Primitive::I8 => json!(input.read_u8()? as i8),
+        Primitive::I16 => json!(input.read_u16()? as i16),
+        Primitive::I32 => json!(input.read_u32()? as i32),
+        Primitive::I64 => json!(input.read_u64()? as i64),
+        Primitive::I128 => {
+            let value = input.read_u128()? as i128;
+            match i64::try_from(value) {
+                Ok(value) => json!(value),
+                Err(_) => json!(value.to_string()),
+            }
+        }
+        Primitive::I256 => json!(hex::encode(input.read_bytes(32)?)),
+    })
+}
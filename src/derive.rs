@@ -0,0 +1,253 @@
+//! Metrics derived from each followed block, added to its record under `derived` so
+//! simple dashboards can chart gavel's output directly, and alerts when a dispatch class
+//! stays above a share of the block weight.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
+
+use serde_json::{json, Value};
+
+use crate::metadata::Metadata;
+use crate::rpc::Connection;
+use crate::scale::Input;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// Signed transactions per second over the last blocks.
+    Tps,
+    /// Transaction fees and tips paid in the block.
+    Fees,
+    /// Block weight used, as a share of the maximum.
+    Fullness,
+}
+
+const CLASSES: [&str; 3] = ["normal", "operational", "mandatory"];
+
+/// `--alert-fullness normal=80,operational=95`: percentages of the maximum block weight
+/// per dispatch class that `--alert-blocks` blocks in a row may not exceed.
+#[derive(Clone, Debug)]
+pub struct Thresholds(Vec<(&'static str, f64)>);
+
+pub fn parse_thresholds(spec: &str) -> Result<Thresholds, String> {
+    let mut thresholds = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (class, percent) = part.split_once('=').ok_or_else(|| format!("expected <class>=<percent>, got '{}'", part))?;
+        let class = CLASSES.into_iter().find(|known| known.eq_ignore_ascii_case(class.trim()))
+            .ok_or_else(|| format!("unknown dispatch class '{}', expected normal, operational or mandatory", class))?;
+        let percent: f64 = percent.trim().trim_end_matches('%').parse().map_err(|_| format!("invalid percentage '{}'", percent))?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(format!("percentage {} is not between 0 and 100", percent));
+        }
+        thresholds.push((class, percent));
+    }
+    if thresholds.is_empty() {
+        return Err("--alert-fullness needs at least one class, e.g. normal=80".into());
+    }
+    Ok(Thresholds(thresholds))
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Alerts {
+    #[clap(long, value_parser = parse_thresholds, help = "Alert when a dispatch class uses more than this percentage of the block weight, e.g. normal=80,operational=95.")]
+    pub alert_fullness: Option<Thresholds>,
+    #[clap(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..), help = "Consecutive blocks above an --alert-fullness threshold before alerting.")]
+    pub alert_blocks: u64,
+}
+
+/// Timestamps and transaction counts of the last blocks of one kind of head.
+#[derive(Default)]
+struct Window {
+    blocks: VecDeque<(u64, u64, u64)>,
+}
+
+pub struct Deriver {
+    metrics: Vec<Metric>,
+    window: usize,
+    windows: BTreeMap<String, Window>,
+    metadata: BTreeMap<u64, Metadata>,
+    thresholds: Vec<(&'static str, f64)>,
+    alert_blocks: u64,
+    // Consecutive blocks above the threshold per kind of head and class, and whether that
+    // streak was alerted on
+    streaks: BTreeMap<(String, &'static str), (u64, bool)>,
+    alerts: Vec<Value>,
+}
+
+/// Whether an extrinsic (with its length prefix) carries a signature.
+fn is_signed(extrinsic: &str) -> bool {
+    let Ok(bytes) = crate::hex::decode(extrinsic) else { return false };
+    let mut input = Input::new(&bytes);
+    input.read_compact().is_ok() && input.read_u8().is_ok_and(|version| version & 0x80 != 0)
+}
+
+fn amount(value: &Value) -> u128 {
+    match value {
+        Value::Number(number) => number.as_u64().unwrap_or_default() as u128,
+        Value::String(number) => number.parse().unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+/// The `ref_time` and `proof_size` of a weight, older runtimes weigh time only.
+fn weight(value: &Value) -> (u128, Option<u128>) {
+    match value.get("ref_time") {
+        Some(ref_time) => (amount(ref_time), value.get("proof_size").map(amount)),
+        None => (amount(value), None),
+    }
+}
+
+fn ratio(used: u128, max: u128) -> Value {
+    if max == 0 {
+        return Value::Null;
+    }
+    json!((used as f64 / max as f64 * 10000.0).round() / 10000.0)
+}
+
+impl Deriver {
+    /// Fullness is derived whenever there are thresholds to check it against.
+    pub fn new(metrics: &[Metric], window: usize, alerts: &Alerts) -> Self {
+        let mut metrics = metrics.to_vec();
+        if alerts.alert_fullness.is_some() && !metrics.contains(&Metric::Fullness) {
+            metrics.push(Metric::Fullness);
+        }
+        Deriver {
+            metrics,
+            window,
+            windows: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            thresholds: alerts.alert_fullness.as_ref().map(|thresholds| thresholds.0.clone()).unwrap_or_default(),
+            alert_blocks: alerts.alert_blocks,
+            streaks: BTreeMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+
+    /// The alert and recovery records raised by the blocks augmented since the last call.
+    pub fn take_alerts(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    /// Counts the blocks in a row each class is above its threshold, alerting once when a
+    /// streak reaches `alert_blocks` and again when it ends.
+    fn check_fullness(&mut self, kind: &str, number: u64, fullness: &Value) {
+        for &(class, percent) in &self.thresholds {
+            let Some(share) = fullness["classes"][class].as_f64() else { continue };
+            let (streak, alerted) = self.streaks.entry((kind.to_string(), class)).or_default();
+            if share * 100.0 > percent {
+                *streak += 1;
+                if *streak >= self.alert_blocks && !*alerted {
+                    *alerted = true;
+                    eprintln!("ALERT: {} weight above {}% of the block for {} {} blocks, at block {}", class, percent, *streak, kind, number);
+                    self.alerts.push(json!({ "kind": "alert", "head": kind, "block": number, "class": class, "threshold": percent, "fullness": share, "blocks": *streak }));
+                }
+            } else {
+                if *alerted {
+                    self.alerts.push(json!({ "kind": "recovered", "head": kind, "block": number, "class": class, "threshold": percent, "fullness": share }));
+                }
+                *streak = 0;
+                *alerted = false;
+            }
+        }
+    }
+
+    /// The spec version of the runtime that built `hash`, with its metadata fetched
+    /// once per spec version.
+    async fn runtime(&mut self, socket: &mut Connection, hash: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        let runtime = crate::send_and_receive(socket, "state_getRuntimeVersion", json!([hash])).await?;
+        let spec = runtime["specVersion"].as_u64().ok_or_else(|| format!("No runtime version for block {}", hash))?;
+        if let Entry::Vacant(entry) = self.metadata.entry(spec) {
+            entry.insert(crate::metadata::fetch(socket, Some(hash)).await?);
+        }
+        Ok(spec)
+    }
+
+    /// Adds the metrics of the block `record` is about under `derived`.
+    pub async fn augment(&mut self, socket: &mut Connection, record: &mut Value) -> Result<(), Box<dyn std::error::Error>> {
+        let hash = record["hash"].as_str().ok_or("Record without a hash")?.to_string();
+        let number = record["number"].as_u64().ok_or("Record without a number")?;
+        let kind = record["kind"].as_str().unwrap_or_default().to_string();
+        let spec = self.runtime(socket, &hash).await?;
+        let Deriver { metrics, window, windows, metadata, .. } = self;
+        let metadata = &metadata[&spec];
+        let mut derived = json!({});
+
+        if metrics.contains(&Metric::Tps) {
+            let block = crate::send_and_receive(socket, "chain_getBlock", json!([hash])).await?;
+            let transactions = block["block"]["extrinsics"].as_array()
+                .map_or(0, |extrinsics| extrinsics.iter().filter(|extrinsic| extrinsic.as_str().is_some_and(is_signed)).count()) as u64;
+            let timestamp = match metadata.storage_entry("Timestamp", "Now") {
+                Some(_) => crate::storage::read_u64(socket, metadata, "Timestamp", "Now", &[], Some(&hash)).await?,
+                None => None,
+            };
+            let blocks = &mut windows.entry(kind.clone()).or_default().blocks;
+            // A reorg or a skipped head restarts the window
+            if blocks.back().is_some_and(|&(previous, _, _)| previous + 1 != number) {
+                blocks.clear();
+            }
+            blocks.push_back((number, timestamp.unwrap_or_default(), transactions));
+            while blocks.len() > *window + 1 {
+                blocks.pop_front();
+            }
+            // The first block of the window only marks where its time span starts
+            let (first, last) = (blocks.front().map(|block| block.1), blocks.back().map(|block| block.1));
+            let counted: u64 = blocks.iter().skip(1).map(|block| block.2).sum();
+            derived["transactions"] = json!(transactions);
+            derived["tps"] = match (timestamp, first, last) {
+                (Some(_), Some(first), Some(last)) if last > first => json!((counted as f64 * 1000.0 / (last - first) as f64 * 100.0).round() / 100.0),
+                _ => Value::Null,
+            };
+            derived["tps_window"] = json!(blocks.len() - 1);
+        }
+
+        if metrics.contains(&Metric::Fees) {
+            derived["fees"] = Value::Null;
+            derived["tips"] = Value::Null;
+            derived["fee_payments"] = Value::Null;
+            if metadata.pallet("TransactionPayment").is_some() {
+                let (mut fees, mut tips, mut paid) = (0u128, 0u128, 0);
+                for event in crate::storage::pallet_events(socket, metadata, "TransactionPayment", &hash).await? {
+                    if let (Some("TransactionFeePaid"), fields) = crate::extrinsic::variant(&event) {
+                        fees += amount(&fields["actual_fee"]);
+                        tips += amount(&fields["tip"]);
+                        paid += 1;
+                    }
+                }
+                derived["fees"] = json!(fees.to_string());
+                derived["tips"] = json!(tips.to_string());
+                derived["fee_payments"] = json!(paid);
+            }
+        }
+
+        if metrics.contains(&Metric::Fullness) {
+            let limits = metadata.constant("System", "BlockWeights").filter(|_| metadata.storage_entry("System", "BlockWeight").is_some())
+                .map(|constant| crate::decode::Decoder::new(metadata).decode_all(constant.ty, &constant.value))
+                .transpose()?;
+            derived["fullness"] = match limits {
+                Some(limits) => {
+                    let used = crate::storage::read(socket, metadata, "System", "BlockWeight", &[], Some(&hash)).await?;
+                    let (max_time, max_proof) = weight(&limits["max_block"]);
+                    let mut classes = json!({});
+                    let (mut time, mut proof) = (0, 0);
+                    for class in CLASSES {
+                        let (class_time, class_proof) = weight(&used[class]);
+                        time += class_time;
+                        proof += class_proof.unwrap_or_default();
+                        classes[class] = ratio(class_time, max_time);
+                    }
+                    json!({
+                        "ref_time": ratio(time, max_time),
+                        "proof_size": max_proof.map_or(Value::Null, |max_proof| ratio(proof, max_proof)),
+                        "classes": classes,
+                    })
+                }
+                None => Value::Null,
+            };
+        }
+
+        if let Some(fullness) = derived.get("fullness").filter(|fullness| !fullness.is_null()) {
+            self.check_fullness(&kind, number, fullness);
+        }
+        record["derived"] = derived;
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Decoding of header digest logs for the consensus engines gavel knows about.
+
+use serde_json::{json, Value};
+
+use crate::hex;
+use crate::scale::Input;
+
+fn engine_name(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+fn read_authority_weights(input: &mut Input) -> Result<Vec<Value>, String> {
+    input.read_list(|input| {
+        let authority = hex::encode(input.read_bytes(32)?);
+        let weight = input.read_u64()?;
+        Ok(json!({ "authority": authority, "weight": weight }))
+    })
+}
+
+fn read_vrf_signature(input: &mut Input) -> Result<Value, String> {
+    let pre_output = hex::encode(input.read_bytes(32)?);
+    let proof = hex::encode(input.read_bytes(64)?);
+    Ok(json!({ "pre_output": pre_output, "proof": proof }))
+}
+
+fn babe_pre_digest(input: &mut Input) -> Result<Value, String> {
+    let kind = input.read_u8()?;
+    let authority_index = input.read_u32()?;
+    let slot = input.read_u64()?;
+    Ok(match kind {
+        1 => json!({ "type": "Primary", "authority_index": authority_index, "slot": slot, "vrf_signature": read_vrf_signature(input)? }),
+        2 => json!({ "type": "SecondaryPlain", "authority_index": authority_index, "slot": slot }),
+        3 => json!({ "type": "SecondaryVRF", "authority_index": authority_index, "slot": slot, "vrf_signature": read_vrf_signature(input)? }),
+        other => return Err(format!("unknown BABE pre-digest {}", other)),
+    })
+}
+
+fn babe_consensus(input: &mut Input) -> Result<Value, String> {
+    Ok(match input.read_u8()? {
+        1 => {
+            let authorities = read_authority_weights(input)?;
+            let randomness = hex::encode(input.read_bytes(32)?);
+            json!({ "type": "NextEpochData", "authorities": authorities, "randomness": randomness })
+        }
+        2 => json!({ "type": "OnDisabled", "authority_index": input.read_u32()? }),
+        3 => {
+            if input.read_u8()? != 1 {
+                return Err("unknown BABE NextConfigDescriptor version".into());
+            }
+            let c = (input.read_u64()?, input.read_u64()?);
+            let allowed_slots = match input.read_u8()? {
+                0 => "PrimarySlots",
+                1 => "PrimaryAndSecondaryPlainSlots",
+                2 => "PrimaryAndSecondaryVRFSlots",
+                other => return Err(format!("unknown BABE allowed slots {}", other)),
+            };
+            json!({ "type": "NextConfigData", "c": [c.0, c.1], "allowed_slots": allowed_slots })
+        }
+        other => return Err(format!("unknown BABE consensus log {}", other)),
+    })
+}
+
+fn grandpa_scheduled_change(input: &mut Input) -> Result<Value, String> {
+    let next_authorities = read_authority_weights(input)?;
+    let delay = input.read_u32()?;
+    Ok(json!({ "next_authorities": next_authorities, "delay": delay }))
+}
+
+fn grandpa_consensus(input: &mut Input) -> Result<Value, String> {
+    Ok(match input.read_u8()? {
+        1 => json!({ "type": "ScheduledChange", "change": grandpa_scheduled_change(input)? }),
+        2 => {
+            let median_last_finalized = input.read_u32()?;
+            json!({ "type": "ForcedChange", "median_last_finalized": median_last_finalized, "change": grandpa_scheduled_change(input)? })
+        }
+        3 => json!({ "type": "OnDisabled", "authority_index": input.read_u64()? }),
+        4 => json!({ "type": "Pause", "delay": input.read_u32()? }),
+        5 => json!({ "type": "Resume", "delay": input.read_u32()? }),
+        other => return Err(format!("unknown GRANDPA consensus log {}", other)),
+    })
+}
+
+fn beefy_consensus(input: &mut Input) -> Result<Value, String> {
+    Ok(match input.read_u8()? {
+        1 => {
+            let validators = input.read_list(|input| Ok(hex::encode(input.read_bytes(33)?)))?;
+            let id = input.read_u64()?;
+            json!({ "type": "AuthoritiesChange", "validator_set_id": id, "validators": validators })
+        }
+        2 => json!({ "type": "OnDisabled", "authority_index": input.read_u32()? }),
+        3 => json!({ "type": "MmrRoot", "root": hex::encode(input.read_bytes(32)?) }),
+        other => return Err(format!("unknown BEEFY consensus log {}", other)),
+    })
+}
+
+fn aura_consensus(input: &mut Input) -> Result<Value, String> {
+    Ok(match input.read_u8()? {
+        1 => {
+            let authorities = input.read_list(|input| Ok(hex::encode(input.read_bytes(32)?)))?;
+            json!({ "type": "AuthoritiesChange", "authorities": authorities })
+        }
+        2 => json!({ "type": "OnDisabled", "authority_index": input.read_u32()? }),
+        other => return Err(format!("unknown Aura consensus log {}", other)),
+    })
+}
+
+/// Decodes an engine payload, leaving it as hex for engines or layouts we do not know.
+fn decode_payload(engine: &[u8; 4], kind: &str, data: &[u8]) -> Value {
+    let mut input = Input::new(data);
+    let decoded = match (engine, kind) {
+        (b"BABE", "PreRuntime") => babe_pre_digest(&mut input),
+        (b"BABE", "Consensus") => babe_consensus(&mut input),
+        (b"FRNK", "Consensus") => grandpa_consensus(&mut input),
+        (b"BEEF", "Consensus") => beefy_consensus(&mut input),
+        (b"aura", "PreRuntime") => input.read_u64().map(|slot| json!({ "slot": slot })),
+        (b"aura", "Consensus") => aura_consensus(&mut input),
+        _ => Err(String::new()),
+    };
+    match decoded {
+        Ok(value) if input.remaining() == 0 => value,
+        _ => json!(hex::encode(data)),
+    }
+}
+
+/// Decodes a single SCALE-encoded `DigestItem`.
+pub fn decode_item(input: &mut Input) -> Result<Value, String> {
+    let kind = match input.read_u8()? {
+        0 => return Ok(json!({ "Other": hex::encode(input.read_vec()?) })),
+        4 => "Consensus",
+        5 => "Seal",
+        6 => "PreRuntime",
+        8 => return Ok(json!("RuntimeEnvironmentUpdated")),
+        other => return Err(format!("unknown digest item {}", other)),
+    };
+    let engine: [u8; 4] = input.read_array()?;
+    let data = input.read_vec()?;
+    let payload = if kind == "Seal" { json!(hex::encode(data)) } else { decode_payload(&engine, kind, data) };
+    Ok(json!({ kind: { "engine": engine_name(&engine), "data": payload } }))
+}
+
+/// Decodes hex that is either a single digest item or a whole `Vec<DigestItem>`.
+pub fn decode_hex(encoded: &str) -> Result<Value, String> {
+    let bytes = hex::decode(encoded)?;
+    let mut input = Input::new(&bytes);
+    if let Ok(item) = decode_item(&mut input) {
+        if input.remaining() == 0 {
+            return Ok(item);
+        }
+    }
+    let mut input = Input::new(&bytes);
+    let items = input.read_list(decode_item)?;
+    if input.remaining() > 0 {
+        return Err(format!("{} trailing bytes after the digest", input.remaining()));
+    }
+    Ok(Value::Array(items))
+}
+
+/// Replaces the hex logs of a JSON header in place, keeping any log that fails to decode.
+pub fn decode_header_logs(header: &mut Value) {
+    if let Some(logs) = header["digest"]["logs"].as_array_mut() {
+        for log in logs.iter_mut() {
+            if let Some(decoded) = log.as_str().and_then(|encoded| decode_hex(encoded).ok()) {
+                *log = decoded;
+            }
+        }
+    }
+}
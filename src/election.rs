@@ -0,0 +1,98 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::storage;
+
+/// Phases worth raising an alert for: the signed phase did not produce a solution in time,
+/// or the election failed entirely and needs governance intervention.
+const ALERT_PHASES: &[&str] = &["Unsigned", "Emergency"];
+
+/// Name of an enum value as produced by the decoder, `"Off"` or `{"Signed": ..}`.
+fn variant_name(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(name) => Some(name),
+        Value::Object(object) => object.keys().next().map(String::as_str),
+        _ => None,
+    }
+}
+
+async fn read_optional(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, at: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    if metadata.storage_entry(pallet, entry).is_none() {
+        return Ok(Value::Null);
+    }
+    storage::read(socket, metadata, pallet, entry, &[], Some(at)).await
+}
+
+/// Reads the election status at block `at`.
+pub async fn status(socket: &mut Connection, metadata: &Metadata, pallet: &str, at: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    if metadata.pallet(pallet).is_none() {
+        return Err(format!("{} pallet not found in the runtime", pallet).into());
+    }
+    let phase = storage::read(socket, metadata, pallet, "CurrentPhase", &[], Some(at)).await?;
+    let round = read_optional(socket, metadata, pallet, "Round", at).await?;
+    let snapshot = read_optional(socket, metadata, pallet, "SnapshotMetadata", at).await?;
+    let queued = read_optional(socket, metadata, pallet, "QueuedSolution", at).await?;
+    let minimum_untrusted_score = read_optional(socket, metadata, pallet, "MinimumUntrustedScore", at).await?;
+    let signed = read_optional(socket, metadata, pallet, "SignedSubmissionIndices", at).await?;
+
+    // Indices are sorted by score, the best submission comes last
+    let signed_submissions = signed.as_array().map(|indices| indices.len()).unwrap_or_default();
+    let best_signed_score = signed.as_array().and_then(|indices| indices.last()).map(|best| best[0].clone()).unwrap_or(Value::Null);
+    let phase_name = variant_name(&phase).unwrap_or("Unknown").to_string();
+
+    Ok(json!({
+        "block_hash": at,
+        "round": round,
+        "phase": phase_name,
+        "phase_detail": phase,
+        "snapshot": snapshot,
+        "queued_solution_score": queued.get("score").cloned().unwrap_or(Value::Null),
+        "minimum_untrusted_score": minimum_untrusted_score,
+        "signed_submissions": signed_submissions,
+        "best_signed_score": best_signed_score,
+        "alert": ALERT_PHASES.contains(&phase_name.as_str()),
+    }))
+}
+
+/// Prints the status once, or keeps polling new blocks and reports every block with a
+/// phase change or election events.
+pub async fn watch(socket: &mut Connection, pallet: &str, follow: bool, interval: Duration, at: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let metadata = metadata::fetch(socket, at.as_deref()).await?;
+    let head = crate::fetch_header_number(socket, at.as_deref()).await?;
+    let hash = crate::fetch_block_hash(socket, head).await?;
+    let mut current = status(socket, &metadata, pallet, &hash).await?;
+    current["block"] = json!(head);
+    crate::output::line(&if follow { current.to_string() } else { serde_json::to_string_pretty(&current)? })?;
+    if current["alert"] == true {
+        eprintln!("ALERT: election is in the {} phase at block {}", current["phase"].as_str().unwrap_or_default(), head);
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut last_block = head;
+    loop {
+        tokio::time::sleep(interval).await;
+        let head = crate::fetch_header_number(socket, None).await?;
+        for block in last_block + 1..=head {
+            let hash = crate::fetch_block_hash(socket, block).await?;
+            let events = storage::pallet_events(socket, &metadata, pallet, &hash).await?;
+            let mut next = status(socket, &metadata, pallet, &hash).await?;
+            let phase_changed = next["phase"] != current["phase"];
+            if !phase_changed && events.is_empty() {
+                continue;
+            }
+            next["block"] = json!(block);
+            next["events"] = json!(events);
+            crate::output::line(&next.to_string())?;
+            if phase_changed && next["alert"] == true {
+                eprintln!("ALERT: election entered the {} phase at block {}", next["phase"].as_str().unwrap_or_default(), block);
+            }
+            current = next;
+        }
+        last_block = last_block.max(head);
+    }
+}
@@ -0,0 +1,228 @@
+//! Type-driven encoding of JSON values into SCALE using the metadata type registry,
+//! the inverse of `decode`.
+
+use serde_json::Value;
+
+use crate::metadata::{Field, Metadata, Primitive, TypeDef};
+use crate::scale::encode_compact;
+use crate::{hex, ss58};
+
+/// Recursion guard against self-referential types in malformed metadata.
+const MAX_DEPTH: usize = 128;
+
+/// Encodes JSON in the shapes `Decoder` produces: objects for named fields, arrays for
+/// tuples and unnamed fields, `"Name"` or `{"Name": fields}` for enum variants, `null` for
+/// `None`, hex for byte sequences and SS58 or hex for accounts. Composite and enum values
+/// may also be given as `0x` hex, which is taken as already encoded (e.g. a call in a batch).
+pub struct Encoder<'m> {
+    pub metadata: &'m Metadata,
+}
+
+impl<'m> Encoder<'m> {
+    pub fn new(metadata: &'m Metadata) -> Self {
+        Encoder { metadata }
+    }
+
+    pub fn encode(&self, ty: u32, value: &Value) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        self.encode_at(ty, value, &mut out, 0)?;
+        Ok(out)
+    }
+
+    fn encode_at(&self, ty: u32, value: &Value, out: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("type nesting too deep".into());
+        }
+        let resolved = self.metadata.resolve(ty)?;
+        let last = resolved.path.last().map(String::as_str);
+
+        if last == Some("AccountId32") {
+            out.extend(account(value)?);
+            return Ok(());
+        }
+        if let (TypeDef::Composite(_) | TypeDef::Variant(_) | TypeDef::Tuple(_), Some(encoded)) = (&resolved.def, value.as_str().filter(|s| s.starts_with("0x"))) {
+            out.extend(hex::decode(encoded)?);
+            return Ok(());
+        }
+
+        match &resolved.def {
+            TypeDef::Composite(fields) => self.encode_fields(fields, value, out, depth),
+            TypeDef::Variant(variants) => {
+                if last == Some("Option") && resolved.path.len() == 1 {
+                    return match (value, variants.iter().find(|variant| !variant.fields.is_empty())) {
+                        (Value::Null, _) => {
+                            out.push(0);
+                            Ok(())
+                        }
+                        (value, Some(some)) => {
+                            out.push(1);
+                            self.encode_at(some.fields[0].ty, value, out, depth + 1)
+                        }
+                        (_, None) => Err(format!("malformed Option type {}", ty)),
+                    };
+                }
+                let (name, fields) = match value {
+                    Value::String(name) if last == Some("MultiAddress") && !variants.iter().any(|variant| &variant.name == name) => {
+                        // A bare address stands for `MultiAddress::Id`
+                        ("Id", value.clone())
+                    }
+                    Value::String(name) => (name.as_str(), Value::Null),
+                    Value::Object(object) if object.len() == 1 => {
+                        let (name, fields) = object.iter().next().ok_or("empty variant object")?;
+                        (name.as_str(), fields.clone())
+                    }
+                    _ => return Err(format!("expected a variant name or {{\"Name\": fields}} for type {}, got {}", ty, value)),
+                };
+                let variant = variants.iter().find(|variant| variant.name == name)
+                    .ok_or_else(|| format!("variant {} not found in type {}", name, ty))?;
+                out.push(variant.index);
+                if variant.fields.is_empty() {
+                    return Ok(());
+                }
+                self.encode_fields(&variant.fields, &fields, out, depth)
+            }
+            TypeDef::Sequence(inner) => {
+                if self.is_u8(*inner) {
+                    let bytes = bytes(value)?;
+                    out.extend(encode_compact(bytes.len() as u128));
+                    out.extend(bytes);
+                    return Ok(());
+                }
+                let items = value.as_array().ok_or_else(|| format!("expected an array for type {}, got {}", ty, value))?;
+                out.extend(encode_compact(items.len() as u128));
+                items.iter().try_for_each(|item| self.encode_at(*inner, item, out, depth + 1))
+            }
+            TypeDef::Array(len, inner) => {
+                if self.is_u8(*inner) {
+                    let bytes = bytes(value)?;
+                    if bytes.len() != *len as usize {
+                        return Err(format!("expected {} bytes for type {}, got {}", len, ty, bytes.len()));
+                    }
+                    out.extend(bytes);
+                    return Ok(());
+                }
+                let items = value.as_array().filter(|items| items.len() == *len as usize)
+                    .ok_or_else(|| format!("expected an array of {} items for type {}", len, ty))?;
+                items.iter().try_for_each(|item| self.encode_at(*inner, item, out, depth + 1))
+            }
+            TypeDef::Tuple(types) => {
+                if types.is_empty() {
+                    return Ok(());
+                }
+                let items = value.as_array().filter(|items| items.len() == types.len())
+                    .ok_or_else(|| format!("expected an array of {} items for type {}", types.len(), ty))?;
+                types.iter().zip(items).try_for_each(|(inner, item)| self.encode_at(*inner, item, out, depth + 1))
+            }
+            TypeDef::Primitive(primitive) => encode_primitive(*primitive, value, out),
+            TypeDef::Compact(_) => {
+                out.extend(encode_compact(unsigned(value)?));
+                Ok(())
+            }
+            TypeDef::BitSequence(_, _) => {
+                let bits = value.as_str().ok_or("expected a string of 0 and 1 for a bit sequence")?;
+                out.extend(encode_compact(bits.len() as u128));
+                let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+                for (bit, c) in bits.chars().enumerate() {
+                    match c {
+                        '1' => bytes[bit / 8] |= 1 << (bit % 8),
+                        '0' => {}
+                        _ => return Err(format!("invalid bit '{}'", c)),
+                    }
+                }
+                out.extend(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    fn encode_fields(&self, fields: &[Field], value: &Value, out: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+        if fields.len() == 1 && fields[0].name.is_none() {
+            return self.encode_at(fields[0].ty, value, out, depth + 1);
+        }
+        if let Value::Object(object) = value {
+            for field in fields {
+                let name = field.name.as_deref().unwrap_or_default();
+                let item = object.get(name).ok_or_else(|| format!("missing field '{}'", name))?;
+                self.encode_at(field.ty, item, out, depth + 1)?;
+            }
+            return Ok(());
+        }
+        let items = value.as_array().filter(|items| items.len() == fields.len())
+            .ok_or_else(|| format!("expected an object or an array of {} fields, got {}", fields.len(), value))?;
+        fields.iter().zip(items).try_for_each(|(field, item)| self.encode_at(field.ty, item, out, depth + 1))
+    }
+
+    fn is_u8(&self, ty: u32) -> bool {
+        matches!(self.metadata.resolve(ty).map(|ty| &ty.def), Ok(TypeDef::Primitive(Primitive::U8)))
+    }
+}
+
+/// Bytes from `0x` hex, a plain string (as UTF-8) or an array of numbers.
+fn bytes(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::String(string) if string.starts_with("0x") => hex::decode(string),
+        Value::String(string) => Ok(string.as_bytes().to_vec()),
+        Value::Array(items) => items.iter().map(|item| item.as_u64().and_then(|byte| u8::try_from(byte).ok()).ok_or("expected byte values")).collect::<Result<_, _>>().map_err(String::from),
+        _ => Err(format!("expected bytes, got {}", value)),
+    }
+}
+
+fn account(value: &Value) -> Result<[u8; 32], String> {
+    ss58::parse_account(value.as_str().ok_or_else(|| format!("expected an SS58 address or hex account, got {}", value))?)
+}
+
+/// Unsigned integers given as JSON numbers or as decimal strings, for values above `u64`.
+fn unsigned(value: &Value) -> Result<u128, String> {
+    match value {
+        Value::Number(number) => number.as_u64().map(u128::from).ok_or_else(|| format!("expected an unsigned integer, got {}", number)),
+        Value::String(string) => string.parse().map_err(|_| format!("expected an unsigned integer, got '{}'", string)),
+        _ => Err(format!("expected an unsigned integer, got {}", value)),
+    }
+}
+
+fn signed(value: &Value) -> Result<i128, String> {
+    match value {
+        Value::Number(number) => number.as_i64().map(i128::from).ok_or_else(|| format!("expected an integer, got {}", number)),
+        Value::String(string) => string.parse().map_err(|_| format!("expected an integer, got '{}'", string)),
+        _ => Err(format!("expected an integer, got {}", value)),
+    }
+}
+
+fn encode_primitive(primitive: Primitive, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    fn fit<T: TryFrom<i128>>(value: i128) -> Result<T, String> {
+        T::try_from(value).map_err(|_| format!("{} is out of range", value))
+    }
+    match primitive {
+        Primitive::Bool => out.push(value.as_bool().ok_or_else(|| format!("expected a boolean, got {}", value))? as u8),
+        Primitive::Char => {
+            let c = value.as_str().and_then(|string| string.chars().next()).ok_or("expected a character")?;
+            out.extend((c as u32).to_le_bytes());
+        }
+        Primitive::Str => {
+            let string = value.as_str().ok_or_else(|| format!("expected a string, got {}", value))?;
+            out.extend(encode_compact(string.len() as u128));
+            out.extend(string.as_bytes());
+        }
+        Primitive::U8 => out.push(fit::<u8>(unsigned(value)? as i128)?),
+        Primitive::U16 => out.extend(fit::<u16>(unsigned(value)? as i128)?.to_le_bytes()),
+        Primitive::U32 => out.extend(fit::<u32>(unsigned(value)? as i128)?.to_le_bytes()),
+        Primitive::U64 => out.extend(u64::try_from(unsigned(value)?).map_err(|_| "value does not fit in u64")?.to_le_bytes()),
+        Primitive::U128 => out.extend(unsigned(value)?.to_le_bytes()),
+        Primitive::I8 => out.extend(fit::<i8>(signed(value)?)?.to_le_bytes()),
+        Primitive::I16 => out.extend(fit::<i16>(signed(value)?)?.to_le_bytes()),
+        Primitive::I32 => out.extend(fit::<i32>(signed(value)?)?.to_le_bytes()),
+        Primitive::I64 => out.extend(fit::<i64>(signed(value)?)?.to_le_bytes()),
+        Primitive::I128 => out.extend(signed(value)?.to_le_bytes()),
+        Primitive::U256 | Primitive::I256 => {
+            let bytes = bytes(value)?;
+            if bytes.len() != 32 {
+                return Err("expected 32 bytes of hex for a 256-bit integer".into());
+            }
+            out.extend(bytes);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::storage;
+
+/// BABE timing parameters and the current position within the epoch.
+pub struct EpochState {
+    pub epoch_index: u64,
+    pub epoch_duration: u64,
+    pub slot_duration_ms: u64,
+    pub genesis_slot: u64,
+    pub current_slot: u64,
+}
+
+impl EpochState {
+    pub fn start_slot(&self) -> u64 {
+        self.genesis_slot + self.epoch_index * self.epoch_duration
+    }
+
+    pub fn next_start_slot(&self) -> u64 {
+        self.start_slot() + self.epoch_duration
+    }
+
+    /// BABE slots are aligned to unix time, so a slot maps straight to a timestamp.
+    pub fn slot_to_millis(&self, slot: u64) -> u64 {
+        slot * self.slot_duration_ms
+    }
+}
+
+pub fn slot_duration_ms(metadata: &Metadata) -> Option<u64> {
+    // Newer pallet_aura declares it, parachains with async backing set the minimum period to 0
+    if let Some(duration) = metadata.constant_u64("Aura", "SlotDuration").filter(|duration| *duration > 0) {
+        return Some(duration);
+    }
+    // pallet_babe doubles the timestamp minimum period, see `Pallet::slot_duration`
+    metadata.constant_u64("Timestamp", "MinimumPeriod")
+        .map(|period| period * 2)
+        .filter(|duration| *duration > 0)
+        .or_else(|| metadata.constant_u64("Babe", "ExpectedBlockTime"))
+}
+
+pub async fn read_state(socket: &mut Connection, metadata: &Metadata, at: Option<&str>) -> Result<EpochState, Box<dyn std::error::Error>> {
+    if metadata.pallet("Babe").is_none() {
+        return Err("Babe pallet not found, the chain does not use BABE".into());
+    }
+    let epoch_duration = metadata.constant_u64("Babe", "EpochDuration").ok_or("Babe::EpochDuration constant not found")?;
+    let slot_duration_ms = slot_duration_ms(metadata).ok_or("Unable to determine the slot duration")?;
+    let epoch_index = storage::read_u64(socket, metadata, "Babe", "EpochIndex", &[], at).await?.ok_or("Babe::EpochIndex not found")?;
+    let genesis_slot = storage::read_u64(socket, metadata, "Babe", "GenesisSlot", &[], at).await?.ok_or("Babe::GenesisSlot not found")?;
+    let current_slot = storage::read_u64(socket, metadata, "Babe", "CurrentSlot", &[], at).await?.ok_or("Babe::CurrentSlot not found")?;
+    Ok(EpochState { epoch_index, epoch_duration, slot_duration_ms, genesis_slot, current_slot })
+}
+
+/// Where the chain is within the current era, in sessions. `None` when there is no staking.
+pub struct EraState {
+    pub index: u64,
+    pub sessions_per_era: u64,
+    pub start_session: u64,
+    pub current_session: u64,
+}
+
+impl EraState {
+    /// Sessions left until the next era starts, counting the current one.
+    pub fn sessions_remaining(&self) -> u64 {
+        (self.start_session + self.sessions_per_era).saturating_sub(self.current_session).max(1)
+    }
+}
+
+pub async fn read_era(socket: &mut Connection, metadata: &Metadata, at: Option<&str>) -> Result<Option<EraState>, Box<dyn std::error::Error>> {
+    if metadata.storage_entry("Staking", "ActiveEra").is_none() {
+        return Ok(None);
+    }
+    let sessions_per_era = match metadata.constant_u64("Staking", "SessionsPerEra") {
+        Some(sessions) => sessions,
+        None => return Ok(None),
+    };
+    let active_era = storage::read(socket, metadata, "Staking", "ActiveEra", &[], at).await?;
+    let index = match active_era["index"].as_u64() {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let key = (index as u32).to_le_bytes().to_vec();
+    let start_session = storage::read_u64(socket, metadata, "Staking", "ErasStartSessionIndex", &[key], at).await?;
+    let current_session = storage::read_u64(socket, metadata, "Session", "CurrentIndex", &[], at).await?;
+    Ok(match (start_session, current_session) {
+        (Some(start_session), Some(current_session)) => Some(EraState { index, sessions_per_era, start_session, current_session }),
+        _ => None,
+    })
+}
+
+pub async fn epoch_info(socket: &mut Connection, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    let state = read_state(socket, &metadata, at).await?;
+
+    let randomness = storage::read(socket, &metadata, "Babe", "Randomness", &[], at).await?;
+    let next_randomness = storage::read(socket, &metadata, "Babe", "NextRandomness", &[], at).await?;
+    let epoch_start = storage::read(socket, &metadata, "Babe", "EpochStart", &[], at).await?;
+    let next_authorities: Vec<Value> = storage::read(socket, &metadata, "Babe", "NextAuthorities", &[], at).await?
+        .as_array()
+        .map(|authorities| authorities.iter().map(|authority| json!({ "authority": authority[0], "weight": authority[1] })).collect())
+        .unwrap_or_default();
+
+    let now = state.slot_to_millis(state.current_slot);
+    let next_start_slot = state.next_start_slot();
+    let slots_remaining = next_start_slot.saturating_sub(state.current_slot);
+    let next_epoch_at = state.slot_to_millis(next_start_slot);
+
+    let next_era = match read_era(socket, &metadata, at).await? {
+        Some(era) => {
+            let slot = next_start_slot + (era.sessions_remaining() - 1) * state.epoch_duration;
+            let start = state.slot_to_millis(slot);
+            json!({
+                "era": era.index + 1,
+                "start_slot": slot,
+                "sessions_remaining": era.sessions_remaining(),
+                "estimated_at": clock::format_unix_millis(start),
+                "estimated_in": clock::format_duration(start.saturating_sub(now) / 1000),
+            })
+        }
+        None => Value::Null,
+    };
+
+    Ok(json!({
+        "epoch_index": state.epoch_index,
+        "start_slot": state.start_slot(),
+        "start_block": epoch_start.get(1).cloned().unwrap_or(Value::Null),
+        "current_slot": state.current_slot,
+        "epoch_duration": state.epoch_duration,
+        "slot_duration_ms": state.slot_duration_ms,
+        "randomness": randomness,
+        "next_randomness": next_randomness,
+        "next_authorities": next_authorities,
+        "next_epoch": {
+            "epoch_index": state.epoch_index + 1,
+            "start_slot": next_start_slot,
+            "slots_remaining": slots_remaining,
+            "estimated_at": clock::format_unix_millis(next_epoch_at),
+            "estimated_in": clock::format_duration(next_epoch_at.saturating_sub(now) / 1000),
+        },
+        "next_era": next_era,
+    }))
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Boundary {
+    Era,
+    Session,
+    Epoch,
+}
+
+/// Computes blocks and time left until the next boundary. Sessions follow BABE epochs,
+/// so both resolve to the same slot.
+pub async fn countdown(socket: &mut Connection, to: Boundary, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    let state = read_state(socket, &metadata, at).await?;
+    let block_time_ms = metadata.constant_u64("Babe", "ExpectedBlockTime").unwrap_or(state.slot_duration_ms).max(1);
+
+    let current_block = crate::fetch_header_number(socket, at).await?;
+
+    let (target_slot, target) = match to {
+        Boundary::Epoch | Boundary::Session => {
+            let name = if matches!(to, Boundary::Epoch) { "epoch" } else { "session" };
+            (state.next_start_slot(), json!({ name: state.epoch_index + 1 }))
+        }
+        Boundary::Era => {
+            let era = read_era(socket, &metadata, at).await?.ok_or("No staking era information on this chain")?;
+            let slot = state.next_start_slot() + (era.sessions_remaining() - 1) * state.epoch_duration;
+            (slot, json!({ "era": era.index + 1, "sessions_remaining": era.sessions_remaining() }))
+        }
+    };
+
+    let slots_remaining = target_slot.saturating_sub(state.current_slot);
+    let millis_remaining = slots_remaining * state.slot_duration_ms;
+    let blocks_remaining = millis_remaining / block_time_ms;
+    let mut result = json!({
+        "current_block": current_block,
+        "current_slot": state.current_slot,
+        "target_slot": target_slot,
+        "slots_remaining": slots_remaining,
+        "blocks_remaining": blocks_remaining,
+        "estimated_block": current_block + blocks_remaining,
+        "seconds_remaining": millis_remaining / 1000,
+        "remaining": clock::format_duration(millis_remaining / 1000),
+        "estimated_at": clock::format_unix_millis(state.slot_to_millis(target_slot)),
+    });
+    if let (Some(result), Some(target)) = (result.as_object_mut(), target.as_object()) {
+        result.extend(target.clone());
+    }
+    Ok(result)
+}
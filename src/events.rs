@@ -0,0 +1,44 @@
+//! The events of a block, read from `System::Events` and decoded with the metadata of the
+//! runtime that built it, one row per event.
+
+use serde_json::{json, Value};
+
+use crate::rpc::Connection;
+
+/// One row per decoded event record, with the block it is in, its index in the block, the
+/// extrinsic its phase belongs to (`null` during initialization and finalization), its
+/// pallet, name, fields and topics.
+pub fn rows(number: &Value, hash: &Value, events: &Value) -> Vec<Value> {
+    events.as_array().into_iter().flatten().enumerate().map(|(index, event)| {
+        let (pallet, inner) = crate::extrinsic::variant(&event["event"]);
+        let (name, fields) = crate::extrinsic::variant(inner);
+        json!({
+            "number": number,
+            "block_hash": hash,
+            "index": index,
+            "phase": event["phase"],
+            "extrinsic": event["phase"]["ApplyExtrinsic"],
+            "pallet": pallet,
+            "name": name,
+            "fields": fields,
+            "topics": event["topics"],
+        })
+    }).collect()
+}
+
+/// The events of `block` (a number or hash), else of the `--at` block or the best block,
+/// with dispatch errors resolved by name, optionally only those of `pallet`.
+pub async fn events(socket: &mut Connection, block: Option<&str>, pallet: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let hash = match crate::resolve_at(socket, block).await? {
+        Some(hash) => hash,
+        None => crate::fetch_block_head_hash(socket).await?,
+    };
+    let number = crate::fetch_header_number(socket, Some(&hash)).await?;
+    let metadata = crate::metadata::fetch(socket, Some(&hash)).await?;
+    let mut events = crate::storage::read(socket, &metadata, "System", "Events", &[], Some(&hash)).await?;
+    crate::extrinsic::resolve_dispatch_errors(&metadata, &mut events);
+    let rows = rows(&json!(number), &json!(hash), &events).into_iter()
+        .filter(|row| pallet.is_none_or(|pallet| row["pallet"].as_str().is_some_and(|name| name.eq_ignore_ascii_case(pallet))))
+        .collect();
+    Ok(Value::Array(rows))
+}
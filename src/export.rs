@@ -0,0 +1,172 @@
+//! `gavel export`: a Prometheus exporter for a set of nodes, seen from the RPC side.
+//!
+//! Each endpoint keeps one connection open and is polled every `--interval` for its best
+//! and finalized heights, peers and sync state, timing every call. A poll that fails or
+//! outlasts `--timeout` marks the node down and drops its connection, which is dialed
+//! again at the next poll. Scrapes are answered from the last poll, never from the nodes.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+
+use crate::rpc::Connection;
+use crate::{fleet, redact, stats};
+
+/// What the last poll of one endpoint found.
+#[derive(Default)]
+struct Sample {
+    up: bool,
+    best: Option<u64>,
+    finalized: Option<u64>,
+    peers: Option<u64>,
+    syncing: Option<bool>,
+    sync_target: Option<u64>,
+    // Round trip of each call of the last poll that answered
+    latency: BTreeMap<&'static str, Duration>,
+    connects: u64,
+    failed_polls: u64,
+}
+
+type Samples = Arc<Mutex<BTreeMap<String, Sample>>>;
+
+/// Calls `method`, recording how long the answer took unless the node has no result for it.
+async fn timed(socket: &mut Connection, sample: &mut Sample, method: &'static str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let sent = Instant::now();
+    let result = crate::send_and_receive(socket, method, params).await?;
+    if !result.is_null() {
+        sample.latency.insert(method, sent.elapsed());
+    }
+    Ok(result)
+}
+
+async fn poll(socket: &mut Connection, sample: &mut Sample) -> Result<(), Box<dyn std::error::Error>> {
+    let best = timed(socket, sample, "chain_getHeader", json!([])).await?;
+    let finalized = timed(socket, sample, "chain_getFinalizedHead", json!([])).await?;
+    let finalized = crate::send_and_receive(socket, "chain_getHeader", json!([finalized])).await?;
+    let health = timed(socket, sample, "system_health", json!([])).await?;
+    // Light clients and some providers do not expose the sync state
+    let sync = timed(socket, sample, "system_syncState", json!([])).await?;
+    sample.best = fleet::header_number(&best);
+    sample.finalized = fleet::header_number(&finalized);
+    sample.peers = health["peers"].as_u64();
+    sample.syncing = health["isSyncing"].as_bool();
+    sample.sync_target = sync["highestBlock"].as_u64();
+    Ok(())
+}
+
+/// Polls `endpoint` every `interval` until the process exits, on one connection for as
+/// long as it works.
+async fn watch(endpoint: String, samples: Samples, interval: Duration, timeout: Duration, max_response_size: Option<usize>) {
+    let mut socket: Option<Connection> = None;
+    let (mut connects, mut failed_polls, mut down) = (0, 0, false);
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let mut sample = Sample::default();
+        let polled = tokio::time::timeout(timeout, async {
+            if socket.as_ref().is_none_or(Connection::is_closed) {
+                socket = Some(crate::connect(&endpoint, None, None, max_response_size).await?);
+                connects += 1;
+            }
+            poll(socket.as_mut().unwrap(), &mut sample).await
+        }).await.unwrap_or_else(|_| Err(format!("No answer within {}s", timeout.as_secs_f64()).into()));
+        // The error is not Send, it must be gone before the next await
+        let failure = polled.err().map(|e| redact::text(&e.to_string()));
+
+        let mut samples = samples.lock().unwrap();
+        let last = samples.entry(endpoint.clone()).or_default();
+        match failure {
+            Some(e) => {
+                // Once per outage, the failed polls counter tells the rest
+                if !down {
+                    eprintln!("Warning: polling {} failed, reconnecting at the next poll: {}", redact::text(&endpoint), e);
+                }
+                socket = None;
+                down = true;
+                failed_polls += 1;
+                last.up = false;
+            }
+            None => {
+                if down {
+                    eprintln!("{} answers again", redact::text(&endpoint));
+                }
+                down = false;
+                sample.up = true;
+                *last = sample;
+            }
+        }
+        last.connects = connects;
+        last.failed_polls = failed_polls;
+    }
+}
+
+/// A label value with backslashes, quotes and line breaks escaped.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The samples in the Prometheus text format, followed by gavel's own run statistics.
+fn prometheus(samples: &BTreeMap<String, Sample>) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, value: &dyn Fn(&Sample) -> Option<f64>| {
+        out.push_str(&format!("# TYPE {} {}\n", name, kind));
+        for (endpoint, sample) in samples {
+            if let Some(value) = value(sample) {
+                out.push_str(&format!("{}{{endpoint=\"{}\"}} {}\n", name, label(&redact::text(endpoint)), value));
+            }
+        }
+    };
+    metric("gavel_node_up", "gauge", &|sample| Some(u8::from(sample.up) as f64));
+    metric("gavel_node_best_block", "gauge", &|sample| sample.best.filter(|_| sample.up).map(|best| best as f64));
+    metric("gavel_node_finalized_block", "gauge", &|sample| sample.finalized.filter(|_| sample.up).map(|finalized| finalized as f64));
+    metric("gavel_node_peers", "gauge", &|sample| sample.peers.filter(|_| sample.up).map(|peers| peers as f64));
+    metric("gavel_node_syncing", "gauge", &|sample| sample.syncing.filter(|_| sample.up).map(|syncing| u8::from(syncing) as f64));
+    metric("gavel_node_sync_target_block", "gauge", &|sample| sample.sync_target.filter(|_| sample.up).map(|target| target as f64));
+    metric("gavel_node_connects_total", "counter", &|sample| Some(sample.connects as f64));
+    metric("gavel_node_failed_polls_total", "counter", &|sample| Some(sample.failed_polls as f64));
+    out.push_str("# TYPE gavel_node_rpc_latency_seconds gauge\n");
+    for (endpoint, sample) in samples.iter().filter(|(_, sample)| sample.up) {
+        for (method, latency) in &sample.latency {
+            out.push_str(&format!(
+                "gavel_node_rpc_latency_seconds{{endpoint=\"{}\",method=\"{}\"}} {}\n",
+                label(&redact::text(endpoint)), method, latency.as_secs_f64(),
+            ));
+        }
+    }
+    out.push_str(&stats::prometheus());
+    out
+}
+
+/// Polls `endpoints` and serves their metrics in the Prometheus text format on `listen`,
+/// whatever the path, until the process is stopped.
+pub async fn export(endpoints: &[String], listen: SocketAddr, interval: Duration, timeout: Duration, max_response_size: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(listen).await.map_err(|e| format!("Failed to listen on {}: {}", listen, e))?;
+    eprintln!("Exporting metrics of {} endpoints on http://{}/metrics", endpoints.len(), listen);
+    let samples: Samples = Arc::default();
+    for endpoint in endpoints {
+        samples.lock().unwrap().insert(endpoint.clone(), Sample::default());
+        tokio::spawn(watch(endpoint.clone(), samples.clone(), interval, timeout, max_response_size));
+    }
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let samples = samples.clone();
+        tokio::spawn(async move {
+            // The request itself doesn't matter, read enough of it to answer politely
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let body = prometheus(&samples.lock().unwrap());
+            let response = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(), body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
@@ -0,0 +1,100 @@
+//! The signed extensions a runtime declares and how each is filled in when signing: by
+//! gavel for the ones it knows, left empty when the extension carries no data, or from
+//! `--extension` values. Anything else is an error instead of a payload the chain rejects.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::metadata::{Metadata, TypeDef};
+
+/// Extensions gavel fills in, with what goes into the extrinsic and what is only signed.
+pub const BUILTIN: &[(&str, &str)] = &[
+    ("CheckSpecVersion", "signs the runtime spec version"),
+    ("CheckTxVersion", "signs the transaction version"),
+    ("CheckGenesis", "signs the genesis hash"),
+    ("CheckMortality", "the era from --mortality, signs its birth block hash"),
+    ("CheckEra", "the era from --mortality, signs its birth block hash"),
+    ("CheckNonce", "the nonce, from --nonce or the signer's next index"),
+    ("ChargeTransactionPayment", "the --tip"),
+    ("ChargeAssetTxPayment", "the --tip, paid in the native asset"),
+    ("CheckMetadataHash", "the mode, signs the RFC 78 metadata hash unless --no-metadata-hash"),
+];
+
+const MAX_DEPTH: usize = 32;
+
+/// Whether values of `ty` encode to nothing, like `()` or `PhantomData` wrappers.
+pub fn is_zero_sized(metadata: &Metadata, ty: u32) -> bool {
+    fn check(metadata: &Metadata, ty: u32, depth: usize) -> bool {
+        let Ok(resolved) = metadata.resolve(ty) else {
+            return false;
+        };
+        depth < MAX_DEPTH && match &resolved.def {
+            TypeDef::Composite(fields) => fields.iter().all(|field| check(metadata, field.ty, depth + 1)),
+            TypeDef::Tuple(types) => types.iter().all(|ty| check(metadata, *ty, depth + 1)),
+            TypeDef::Array(len, inner) => *len == 0 || check(metadata, *inner, depth + 1),
+            _ => false,
+        }
+    }
+    check(metadata, ty, 0)
+}
+
+/// A Rust-like name for `ty`, such as `Option<[u8; 32]>` or `Compact<u128>`.
+pub fn type_name(metadata: &Metadata, ty: u32) -> String {
+    fn name(metadata: &Metadata, ty: u32, depth: usize) -> String {
+        let Ok(resolved) = metadata.resolve(ty) else {
+            return format!("#{}", ty);
+        };
+        if depth >= MAX_DEPTH {
+            return resolved.path.last().cloned().unwrap_or_else(|| format!("#{}", ty));
+        }
+        if let Some(last) = resolved.path.last() {
+            let params: Vec<String> = resolved.params.iter().filter_map(|(_, param)| *param).map(|param| name(metadata, param, depth + 1)).collect();
+            return if params.is_empty() { last.clone() } else { format!("{}<{}>", last, params.join(", ")) };
+        }
+        match &resolved.def {
+            TypeDef::Primitive(primitive) => format!("{:?}", primitive).to_lowercase(),
+            TypeDef::Tuple(types) => format!("({})", types.iter().map(|ty| name(metadata, *ty, depth + 1)).collect::<Vec<_>>().join(", ")),
+            TypeDef::Sequence(inner) => format!("Vec<{}>", name(metadata, *inner, depth + 1)),
+            TypeDef::Array(len, inner) => format!("[{}; {}]", name(metadata, *inner, depth + 1), len),
+            TypeDef::Compact(inner) => format!("Compact<{}>", name(metadata, *inner, depth + 1)),
+            TypeDef::BitSequence(_, _) => "BitVec".to_string(),
+            TypeDef::Composite(_) | TypeDef::Variant(_) => format!("#{}", ty),
+        }
+    }
+    name(metadata, ty, 0)
+}
+
+/// Values for extensions given on the command line as `NAME=JSON`, a plain string
+/// when the value is not JSON.
+pub fn parse_values(values: &[String]) -> Result<HashMap<String, Value>, String> {
+    values.iter().map(|value| {
+        let (name, json) = value.split_once('=').ok_or_else(|| format!("Invalid extension value '{}', expected NAME=JSON", value))?;
+        Ok((name.to_string(), serde_json::from_str(json).unwrap_or_else(|_| json!(json))))
+    }).collect()
+}
+
+/// Every signed extension of the runtime in order, with its types and how gavel handles it.
+pub fn list(metadata: &Metadata) -> Value {
+    let extensions: Vec<Value> = metadata.extrinsic.signed_extensions.iter().map(|extension| {
+        let empty = is_zero_sized(metadata, extension.ty) && is_zero_sized(metadata, extension.additional_signed);
+        let (handling, description) = match BUILTIN.iter().find(|(name, _)| *name == extension.identifier) {
+            Some((_, description)) => ("builtin", *description),
+            None if empty => ("empty", "carries no data"),
+            None => ("unsupported", "needs --extension and --extension-signed values to sign"),
+        };
+        json!({
+            "identifier": extension.identifier,
+            "extra_type": type_name(metadata, extension.ty),
+            "additional_signed_type": type_name(metadata, extension.additional_signed),
+            "handling": handling,
+            "description": description,
+        })
+    }).collect();
+    let unsupported = extensions.iter().filter(|extension| extension["handling"] == "unsupported").count();
+    json!({
+        "extrinsic_version": metadata.extrinsic.version,
+        "signed_extensions": extensions,
+        "unsupported": unsupported,
+    })
+}
@@ -0,0 +1,197 @@
+//! Decodes the extrinsics of a block with the runtime metadata and matches each with the
+//! events it emitted, so a failed extrinsic shows its dispatch error by name.
+
+use serde_json::{json, Map, Value};
+
+use crate::decode::Decoder;
+use crate::hex;
+use crate::metadata::{Metadata, Pallet, TypeDef, Variant};
+use crate::scale::Input;
+
+/// Bit of the version byte set on signed extrinsics.
+const SIGNED: u8 = 0b1000_0000;
+
+/// Splits a decoded enum value such as `{"Balances": {"transfer": {...}}}` into its
+/// variant name and fields.
+pub fn variant(value: &Value) -> (Option<&str>, &Value) {
+    match value {
+        Value::String(name) => (Some(name), &Value::Null),
+        Value::Object(object) if object.len() == 1 => object.iter().next().map(|(name, fields)| (Some(name.as_str()), fields)).unwrap(),
+        _ => (None, &Value::Null),
+    }
+}
+
+/// Decodes one SCALE-encoded extrinsic (with its length prefix) into its signer, signed
+/// extensions and call.
+pub fn decode(metadata: &Metadata, bytes: &[u8]) -> Result<Value, String> {
+    let decoder = Decoder::new(metadata);
+    let mut input = Input::new(bytes);
+    input.read_compact()?;
+    let version = input.read_u8()?;
+    if version & !SIGNED != 4 {
+        return Err(format!("unsupported extrinsic version {}", version & !SIGNED));
+    }
+    let mut extrinsic = json!({ "signed": version & SIGNED != 0 });
+    if version & SIGNED != 0 {
+        let address_ty = metadata.extrinsic.address_ty.ok_or("no address type in metadata")?;
+        let signature_ty = metadata.extrinsic.signature_ty.ok_or("no signature type in metadata")?;
+        let address = decoder.decode(address_ty, &mut input)?;
+        // `MultiAddress::Id` is by far the most common, show it as the plain account
+        extrinsic["signer"] = match variant(&address) {
+            (Some("Id"), account) => account.clone(),
+            _ => address,
+        };
+        decoder.decode(signature_ty, &mut input)?;
+        let mut extensions = Map::new();
+        for extension in &metadata.extrinsic.signed_extensions {
+            let value = decoder.decode(extension.ty, &mut input)?;
+            if !value.is_null() {
+                extensions.insert(extension.identifier.clone(), value);
+            }
+        }
+        extrinsic["extensions"] = Value::Object(extensions);
+    }
+    let call_ty = metadata.extrinsic.call_ty.ok_or("no call type in metadata")?;
+    let call = decoder.decode(call_ty, &mut input)?;
+    if input.remaining() > 0 {
+        return Err(format!("{} trailing bytes after the call", input.remaining()));
+    }
+    let (pallet, call) = variant(&call);
+    let (name, args) = variant(call);
+    extrinsic["pallet"] = json!(pallet);
+    extrinsic["call"] = json!(name);
+    extrinsic["args"] = args.clone();
+    Ok(extrinsic)
+}
+
+/// The pallet and error variant of a `DispatchError::Module`, looked up in the erroring
+/// pallet's error enum.
+fn module_error<'m>(metadata: &'m Metadata, module: &Value) -> (Option<&'m Pallet>, Option<&'m Variant>, Option<u8>) {
+    let index = module["index"].as_u64();
+    // The error is a single byte on older runtimes, four bytes (the first one the
+    // variant) on newer ones
+    let code = match &module["error"] {
+        Value::String(bytes) => hex::decode(bytes).ok().and_then(|bytes| bytes.first().copied()),
+        code => code.as_u64().map(|code| code as u8),
+    };
+    let pallet = index.and_then(|index| metadata.pallets.iter().find(|pallet| pallet.index as u64 == index));
+    let found = pallet.and_then(|pallet| pallet.error).and_then(|ty| metadata.resolve(ty).ok()).and_then(|ty| match &ty.def {
+        TypeDef::Variant(variants) => variants.iter().find(|variant| Some(variant.index) == code),
+        _ => None,
+    });
+    (pallet, found, code)
+}
+
+/// Names a `DispatchError`: module errors are looked up in the erroring pallet's error
+/// enum, with its docs; other errors are already named by their variant.
+pub fn explain_error(metadata: &Metadata, error: &Value) -> Value {
+    let (Some("Module"), module) = variant(error) else { return error.clone() };
+    match module_error(metadata, module) {
+        (Some(pallet), Some(found), _) => json!({
+            "module": pallet.name,
+            "error": found.name,
+            "docs": found.docs.join(" ").trim(),
+        }),
+        (pallet, _, code) => json!({ "module": pallet.map(|pallet| pallet.name.as_str()), "index": module["index"], "error": code }),
+    }
+}
+
+/// Adds the pallet, error name and docs to every decoded `DispatchError::Module` in
+/// `value`, wherever it is nested: failed extrinsics, `Utility` batch interruptions,
+/// `Result`s of proxy, multisig and sudo calls. The raw index and error stay.
+pub fn resolve_dispatch_errors(metadata: &Metadata, value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(module) = object.get_mut("Module").filter(|module| module.get("index").is_some() && module.get("error").is_some()) {
+                if let (Some(pallet), Some(found), _) = module_error(metadata, module) {
+                    module["pallet"] = json!(pallet.name);
+                    module["name"] = json!(found.name);
+                    module["docs"] = json!(found.docs.join(" ").trim());
+                }
+                return;
+            }
+            object.values_mut().for_each(|value| resolve_dispatch_errors(metadata, value));
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| resolve_dispatch_errors(metadata, value)),
+        _ => {}
+    }
+}
+
+/// `InvalidTransaction` and `UnknownTransaction` variants, which are not in the metadata.
+const INVALID_TRANSACTION: [&str; 13] = [
+    "Call", "Payment", "Future", "Stale", "BadProof", "AncientBirthBlock", "ExhaustsResources",
+    "Custom", "BadMandatory", "MandatoryValidation", "BadSigner", "IndeterminateImplicit", "UnknownOrigin",
+];
+const UNKNOWN_TRANSACTION: [&str; 3] = ["CannotLookup", "NoUnsignedValidator", "Custom"];
+
+/// Decodes the `ApplyExtrinsicResult` that `system_dryRun` returns: whether the
+/// transaction is valid and, if so, whether its call would dispatch or fail and why.
+pub fn dry_run_result(metadata: &Metadata, bytes: &[u8]) -> Result<Value, String> {
+    let mut input = Input::new(bytes);
+    if input.read_u8()? == 1 {
+        let (kind, names) = match input.read_u8()? {
+            0 => ("Invalid", INVALID_TRANSACTION.as_slice()),
+            _ => ("Unknown", UNKNOWN_TRANSACTION.as_slice()),
+        };
+        let reason = input.read_u8()?;
+        let mut error = json!({ "validity": kind, "reason": names.get(reason as usize).copied().unwrap_or("unknown") });
+        if error["reason"] == "Custom" {
+            error["code"] = json!(input.read_u8()?);
+        }
+        return Ok(json!({ "valid": false, "error": error }));
+    }
+    if input.read_u8()? == 0 {
+        return Ok(json!({ "valid": true, "success": true }));
+    }
+    let ty = metadata.types.iter().position(|ty| ty.path.last().map(String::as_str) == Some("DispatchError"))
+        .ok_or("no DispatchError type in metadata")?;
+    let mut error = Decoder::new(metadata).decode(ty as u32, &mut input)?;
+    resolve_dispatch_errors(metadata, &mut error);
+    Ok(json!({ "valid": true, "success": false, "error": error }))
+}
+
+/// Decodes every extrinsic of a block and attaches the events of its `ApplyExtrinsic`
+/// phase, its outcome, the dispatch error when it failed and the fee paid.
+pub fn annotate(metadata: &Metadata, extrinsics: &[Value], events: &Value) -> Vec<Value> {
+    let records = events.as_array().map(Vec::as_slice).unwrap_or_default();
+    extrinsics.iter().enumerate().map(|(index, extrinsic)| {
+        let decoded = extrinsic.as_str()
+            .ok_or_else(|| "extrinsic is not a hex string".to_string())
+            .and_then(hex::decode)
+            .and_then(|bytes| decode(metadata, &bytes));
+        let mut report = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => json!({ "decode_error": e }),
+        };
+        let mut events: Vec<Value> = records.iter()
+            .filter(|record| record["phase"]["ApplyExtrinsic"].as_u64() == Some(index as u64))
+            .map(|record| record["event"].clone())
+            .collect();
+        events.iter_mut().for_each(|event| resolve_dispatch_errors(metadata, event));
+
+        let mut outcome = Value::Null;
+        let mut error = Value::Null;
+        let mut fee = Value::Null;
+        for event in &events {
+            let (pallet, inner) = variant(event);
+            let (name, fields) = variant(inner);
+            match (pallet, name) {
+                (Some("System"), Some("ExtrinsicSuccess")) => outcome = json!(true),
+                (Some("System"), Some("ExtrinsicFailed")) => {
+                    outcome = json!(false);
+                    // The first field is the error, named on V14+ runtimes
+                    let dispatch_error = fields.get("dispatch_error").or_else(|| fields.get(0)).unwrap_or(&Value::Null);
+                    error = explain_error(metadata, dispatch_error);
+                }
+                (Some("TransactionPayment"), Some("TransactionFeePaid")) => fee = fields["actual_fee"].clone(),
+                _ => {}
+            }
+        }
+        report["index"] = json!(index);
+        report["success"] = outcome;
+        report["error"] = error;
+        report["fee"] = fee;
+        report["events"] = json!(events);
+        report
+    }).collect()
+}
@@ -0,0 +1,171 @@
+//! Connection failures with what the endpoint actually said: the HTTP status and body of a
+//! rejected WebSocket upgrade, TLS alerts and close codes, rather than a bare "connection
+//! closed".
+
+use serde_json::{json, Map, Value};
+use tokio_tungstenite::tungstenite::error::TlsError;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// Rejection bodies are HTML error pages as often as not, keep the start.
+const MAX_BODY: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionFailure {
+    // Where it failed: tcp, tls, handshake, http, closed or stalled
+    pub stage: &'static str,
+    pub message: String,
+    pub details: Map<String, Value>,
+}
+
+impl std::fmt::Display for ConnectionFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        let details: Vec<String> = self.details.iter().map(|(key, value)| match value.as_str() {
+            Some(value) => format!("{}: {}", key, value),
+            None => format!("{}: {}", key, value),
+        }).collect();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectionFailure {}
+
+impl ConnectionFailure {
+    pub(crate) fn new(stage: &'static str, message: impl Into<String>) -> Self {
+        ConnectionFailure { stage, message: message.into(), details: Map::new() }
+    }
+
+    fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut object = json!({ "stage": self.stage, "message": self.message });
+        object.as_object_mut().unwrap().extend(self.details.clone());
+        object
+    }
+}
+
+/// The structured form of an error, when it is (or was caused by) a connection failure.
+pub fn details(error: &(dyn std::error::Error + 'static)) -> Option<Value> {
+    let cause = error.downcast_ref::<crate::retry::Ambiguous>().map_or(error, |ambiguous| ambiguous.cause.as_ref());
+    cause.downcast_ref::<ConnectionFailure>().map(ConnectionFailure::to_json)
+}
+
+fn tls(error: &native_tls::Error) -> ConnectionFailure {
+    tls_message(error.to_string())
+}
+
+fn tls_message(message: String) -> ConnectionFailure {
+    let mut failure = ConnectionFailure::new("tls", "TLS handshake failed").with("tls_error", message.as_str());
+    // OpenSSL reports alerts from the server as e.g. "sslv3 alert handshake failure"
+    if let Some(start) = message.find("alert ") {
+        let alert = message[start + "alert ".len()..].split([':', ')']).next().unwrap_or_default().trim();
+        failure = failure.with("alert", alert);
+    }
+    if message.contains("certificate verify failed") {
+        failure = failure.with("certificate", "verification failed");
+    }
+    failure
+}
+
+/// The failure that ended a connection, for every call still waiting on it.
+pub fn ended(error: Box<dyn std::error::Error>) -> ConnectionFailure {
+    match error.downcast::<ConnectionFailure>() {
+        Ok(failure) => *failure,
+        Err(error) => ConnectionFailure::new("closed", format!("Connection lost: {}", error)),
+    }
+}
+
+/// A connection that went silent while calls waited on it, past `--response-deadline` and
+/// a ping: sends still succeed on a half-open TCP connection, but nothing comes back.
+pub(crate) fn stalled(deadline: std::time::Duration, waiting: usize) -> ConnectionFailure {
+    ConnectionFailure::new("stalled", format!("Nothing received from the endpoint in {}s while calls waited, the connection is presumed half-open", deadline.as_secs_f64()))
+        .with("waiting_calls", waiting)
+}
+
+/// Whether `error` is the end of a connection found half-open.
+pub fn is_stalled(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<ConnectionFailure>().is_some_and(|failure| failure.stage == "stalled")
+}
+
+/// An HTTP response the endpoint turned `what` down with, its status, the headers that say
+/// why and when to retry, and the start of its body.
+pub(crate) fn rejected(stage: &'static str, what: &str, response: &http::Response<Option<Vec<u8>>>) -> ConnectionFailure {
+    let status = response.status();
+    let mut failure = ConnectionFailure::new(stage, format!("{} rejected with HTTP {}", what, status))
+        .with("status", status.as_u16());
+    for header in ["retry-after", "www-authenticate", "server"] {
+        if let Some(value) = response.headers().get(header).and_then(|value| value.to_str().ok()) {
+            failure = failure.with(header, value);
+        }
+    }
+    let body = response.body().as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+    if !body.trim().is_empty() {
+        failure = failure.with("body", body.trim().chars().take(MAX_BODY).collect::<String>());
+    }
+    failure
+}
+
+/// A call the endpoint's answer to an HTTP request has no response for, e.g. a batch
+/// refused as a whole with a single error.
+pub(crate) fn unanswered(body: &str) -> ConnectionFailure {
+    ConnectionFailure::new("http", "No response to the call in the endpoint's answer")
+        .with("body", body.trim().chars().take(MAX_BODY).collect::<String>())
+}
+
+/// Explains an error from connecting, kept as is unless there is more to say.
+pub fn connect(error: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+    let failure = if let Some(ws_error) = error.downcast_ref::<WsError>() {
+        match ws_error {
+            WsError::Http(response) => Some(rejected("handshake", "WebSocket upgrade", response)),
+            WsError::Tls(TlsError::Native(tls_error)) => Some(tls(tls_error)),
+            WsError::Io(io_error) => Some(tcp(io_error)),
+            _ => None,
+        }
+    } else if let Some(tls_error) = error.downcast_ref::<native_tls::Error>() {
+        Some(tls(tls_error))
+    } else {
+        error.downcast_ref::<std::io::Error>().map(tcp)
+    };
+    match failure {
+        Some(failure) => Box::new(failure),
+        None => error,
+    }
+}
+
+fn tcp(error: &std::io::Error) -> ConnectionFailure {
+    let message = error.to_string();
+    // With TLS 1.3, alerts such as "certificate required" arrive after the handshake, on
+    // the first read
+    if message.contains("SSL routines") {
+        return tls_message(message);
+    }
+    ConnectionFailure::new("tcp", message).with("kind", format!("{:?}", error.kind()))
+}
+
+/// Unwraps the next message from the endpoint, turning a close frame, a reset or the end of
+/// the stream into a failure that says which, with the close code and reason.
+pub fn message(next: Option<Result<Message, WsError>>) -> Result<Message, Box<dyn std::error::Error>> {
+    let failure = match next {
+        Some(Ok(Message::Close(Some(frame)))) => {
+            ConnectionFailure::new("closed", "Connection closed by the endpoint")
+                .with("close_code", u16::from(frame.code))
+                .with("close_reason", frame.reason.to_string())
+        }
+        Some(Ok(Message::Close(None))) => ConnectionFailure::new("closed", "Connection closed by the endpoint without a close code"),
+        Some(Ok(message)) => return Ok(message),
+        Some(Err(WsError::ConnectionClosed | WsError::AlreadyClosed)) | None => {
+            ConnectionFailure::new("closed", "Connection closed before receiving response")
+        }
+        Some(Err(WsError::Protocol(error))) => ConnectionFailure::new("closed", format!("Connection lost: {}", error)),
+        Some(Err(WsError::Io(error))) => ConnectionFailure { stage: "closed", ..tcp(&error) },
+        Some(Err(error)) => return Err(error.into()),
+    };
+    Err(Box::new(failure))
+}
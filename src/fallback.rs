@@ -0,0 +1,92 @@
+use serde_json::{json, Value};
+
+/// JSON-RPC error code returned by nodes that do not expose a method.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// An equivalent method to retry with when the requested one is missing.
+///
+/// `params` adapts the original params (returning `None` when the alternative cannot
+/// express the request) and `result` maps the alternative's result back to the shape
+/// the legacy method would have returned.
+pub struct Fallback {
+    pub method: &'static str,
+    pub params: fn(&Value) -> Option<Value>,
+    pub result: fn(Value) -> Value,
+}
+
+fn same_params(params: &Value) -> Option<Value> {
+    Some(params.clone())
+}
+
+fn same_result(result: Value) -> Value {
+    result
+}
+
+fn no_params(params: &Value) -> Option<Value> {
+    let empty = params.as_array().is_none_or(|params| params.iter().all(Value::is_null));
+    if empty { Some(json!([])) } else { None }
+}
+
+fn height_param(params: &Value) -> Option<Value> {
+    let height = match params.get(0)? {
+        Value::Number(number) => number.as_u64()?,
+        Value::String(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok()?,
+        _ => return None,
+    };
+    Some(json!([height]))
+}
+
+fn first_hash(result: Value) -> Value {
+    result.get(0).cloned().unwrap_or(Value::Null)
+}
+
+fn archive_storage_params(params: &Value) -> Option<Value> {
+    // archive_v1_storage always needs an explicit block hash
+    let key = params.get(0)?.as_str()?;
+    let at = params.get(1)?.as_str()?;
+    Some(json!([at, [{ "key": key, "type": "value" }], null]))
+}
+
+fn archive_storage_value(result: Value) -> Value {
+    let items = result.get("result").unwrap_or(&result);
+    items.get(0).and_then(|item| item.get("value")).cloned().unwrap_or(Value::Null)
+}
+
+/// Returns the methods to try, in order, when `method` is not exposed by the node.
+///
+/// `chainHead_v1_*` methods are not listed as they only work on blocks pinned by an
+/// active `chainHead_v1_follow` subscription, which a one-shot retry cannot provide.
+pub fn alternatives(method: &str) -> &'static [Fallback] {
+    match method {
+        "chain_getHead" => &[
+            Fallback { method: "chain_getBlockHash", params: same_params, result: same_result },
+        ],
+        "chain_getBlockHash" => &[
+            Fallback { method: "chain_getHead", params: no_params, result: same_result },
+            Fallback { method: "archive_v1_hashByHeight", params: height_param, result: first_hash },
+        ],
+        "chain_getFinalizedHead" => &[
+            Fallback { method: "chain_getFinalisedHead", params: same_params, result: same_result },
+        ],
+        "chain_getFinalisedHead" => &[
+            Fallback { method: "chain_getFinalizedHead", params: same_params, result: same_result },
+        ],
+        "state_getRuntimeVersion" => &[
+            Fallback { method: "chain_getRuntimeVersion", params: same_params, result: same_result },
+        ],
+        "state_getStorage" => &[
+            Fallback { method: "state_getStorageAt", params: same_params, result: same_result },
+            Fallback { method: "archive_v1_storage", params: archive_storage_params, result: archive_storage_value },
+        ],
+        _ => &[],
+    }
+}
+
+pub fn is_method_not_found(response: &Value) -> bool {
+    let error = match response.get("error") {
+        Some(error) => error,
+        None => return false,
+    };
+    error["code"].as_i64() == Some(METHOD_NOT_FOUND)
+        || error["message"].as_str().is_some_and(|message| message.to_lowercase().contains("method not found"))
+}
@@ -0,0 +1,101 @@
+//! Distribution of the transaction fees and tips paid over recent blocks, read from the
+//! `TransactionPayment::TransactionFeePaid` events.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+
+fn amount(value: &Value) -> u128 {
+    match value {
+        Value::Number(number) => number.as_u64().unwrap_or_default() as u128,
+        Value::String(number) => number.parse().unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+/// The nearest-rank percentile of sorted amounts.
+fn percentile(sorted: &[u128], fraction: f64) -> Option<u128> {
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).checked_sub(1)?;
+    sorted.get(index).copied()
+}
+
+/// Percentiles, mean and total of some amounts, as strings since they overflow `u64`.
+fn distribution(mut amounts: Vec<u128>) -> Value {
+    if amounts.is_empty() {
+        return Value::Null;
+    }
+    amounts.sort_unstable();
+    let total: u128 = amounts.iter().sum();
+    let mut summary = json!({
+        "count": amounts.len(),
+        "min": amounts[0].to_string(),
+        "max": amounts[amounts.len() - 1].to_string(),
+        "mean": (total / amounts.len() as u128).to_string(),
+        "total": total.to_string(),
+    });
+    for (name, fraction) in [("p25", 0.25), ("p50", 0.5), ("p75", 0.75), ("p90", 0.9), ("p99", 0.99)] {
+        summary[name] = json!(percentile(&amounts, fraction).map(|amount| amount.to_string()));
+    }
+    summary
+}
+
+/// Counts of amounts per power of ten, the empty buckets left out.
+fn histogram(amounts: &[u128]) -> Vec<Value> {
+    let mut buckets: BTreeMap<u32, u64> = BTreeMap::new();
+    for amount in amounts {
+        *buckets.entry(amount.checked_ilog10().unwrap_or(0)).or_default() += 1;
+    }
+    buckets.into_iter().map(|(exponent, count)| json!({
+        "from": if exponent == 0 { "0".to_string() } else { 10u128.pow(exponent).to_string() },
+        "to": 10u128.checked_pow(exponent + 1).map(|to| to.to_string()),
+        "count": count,
+    })).collect()
+}
+
+/// Reads the fees and tips paid in the `blocks` blocks up to the finalized head (or `--at`)
+/// and reports their distribution, with how often and how much users tipped.
+pub async fn fees(socket: &mut Connection, blocks: u64, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let head = crate::pinned_block(socket, at).await?;
+    let last = crate::fetch_header_number(socket, Some(&head)).await?;
+    let first = last.saturating_sub(blocks.max(1) - 1);
+    let mut runtimes: BTreeMap<u64, Metadata> = BTreeMap::new();
+    let (mut fees, mut tips) = (Vec::new(), Vec::new());
+
+    for number in first..=last {
+        let hash = if number == last { head.clone() } else { crate::fetch_block_hash(socket, number).await? };
+        // Blocks are read across runtime upgrades, each with the metadata of its spec version
+        let runtime = crate::send_and_receive(socket, "state_getRuntimeVersion", json!([hash])).await?;
+        let spec = runtime["specVersion"].as_u64().ok_or_else(|| format!("No runtime version for block {}", number))?;
+        let metadata = match runtimes.entry(spec) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(metadata::fetch(socket, Some(&hash)).await?),
+        };
+        if metadata.pallet("TransactionPayment").is_none() {
+            return Err(format!("No TransactionPayment pallet in the runtime of block {}", number).into());
+        }
+        for event in crate::storage::pallet_events(socket, metadata, "TransactionPayment", &hash).await? {
+            if let (Some("TransactionFeePaid"), fields) = crate::extrinsic::variant(&event) {
+                fees.push(amount(&fields["actual_fee"]));
+                tips.push(amount(&fields["tip"]));
+            }
+        }
+    }
+
+    let tipped: Vec<u128> = tips.iter().copied().filter(|tip| *tip > 0).collect();
+    Ok(json!({
+        "from_block": first,
+        "to_block": last,
+        "blocks": last - first + 1,
+        "payments": fees.len(),
+        "decimals": crate::staking::token_decimals(socket).await?,
+        "fees": distribution(fees.clone()),
+        "fee_histogram": histogram(&fees),
+        "tipped": tipped.len(),
+        "tipped_share": if fees.is_empty() { Value::Null } else { json!((tipped.len() as f64 / fees.len() as f64 * 10000.0).round() / 10000.0) },
+        "tips": distribution(tipped),
+    }))
+}
@@ -0,0 +1,462 @@
+//! Commands over a list of endpoints: finding which URLs are served by the same node
+//! or infrastructure, which node and runtime versions a fleet runs, how healthy it is and
+//! whether its nodes agree on the chain.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use serde_json::{json, Value};
+use url::Url;
+
+/// The endpoints given as arguments followed by those in `input` (`-` for stdin), one
+/// per line, skipping blank lines and `#` comments.
+pub fn read_endpoints(endpoints: Vec<String>, input: Option<&Path>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut all = endpoints;
+    if let Some(input) = input {
+        let reader: Box<dyn BufRead> = if input == Path::new("-") {
+            Box::new(std::io::stdin().lock())
+        } else {
+            Box::new(std::io::BufReader::new(std::fs::File::open(input)?))
+        };
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                all.push(line.to_string());
+            }
+        }
+    }
+    if all.is_empty() {
+        return Err("No endpoints given, pass them as arguments or with --input".into());
+    }
+    Ok(all)
+}
+
+/// Every address the endpoint's host resolves to, sorted, of the `-4` or `-6` family
+/// only when one is set.
+pub async fn addresses(endpoint: &str) -> Result<Vec<IpAddr>, Box<dyn std::error::Error>> {
+    let url = Url::parse(crate::config::endpoint(endpoint))?;
+    let host = url.host_str().ok_or("Missing host in URL")?;
+    let port = url.port_or_known_default().ok_or("Unknown port for the URL scheme")?;
+    let version = crate::config::ip_version();
+    let mut addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port)).await?
+        .map(|addr| addr.ip())
+        .filter(|address| version.is_none_or(|version| version.matches(address)))
+        .collect();
+    if let (Some(version), true) = (version, addresses.is_empty()) {
+        return Err(format!("{} has no {} address", host, version).into());
+    }
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+/// The /24 (IPv4) or /48 (IPv6) an address belongs to, a rough stand-in for "same
+/// provider network".
+fn network(address: &IpAddr) -> String {
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+    }
+}
+
+async fn identity(endpoint: &str, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let addresses = addresses(endpoint).await?;
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let peer_id = crate::send_and_receive(&mut socket, "system_localPeerId", json!([])).await?;
+    let genesis = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([0])).await?;
+    let version = crate::send_and_receive(&mut socket, "system_version", json!([])).await?;
+    Ok(json!({
+        "endpoint": endpoint,
+        "addresses": addresses,
+        "peer_id": peer_id,
+        "genesis": genesis,
+        "version": version,
+    }))
+}
+
+/// Runs `probe` against every endpoint at once, reporting a failure or timeout in place
+/// of the endpoint's result.
+pub async fn probe_all<'a, F, Fut>(endpoints: &'a [String], timeout: Duration, probe: F) -> Vec<Value>
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: std::future::Future<Output = Result<Value, Box<dyn std::error::Error>>>,
+{
+    join_all(endpoints.iter().map(|endpoint| {
+        let probe = probe(endpoint);
+        async move {
+            match tokio::time::timeout(timeout, probe).await {
+                Ok(Ok(report)) => report,
+                Ok(Err(e)) => {
+                    let mut report = json!({ "endpoint": endpoint, "error": e.to_string() });
+                    if let Some(details) = crate::failure::details(e.as_ref()) {
+                        report["failure"] = details;
+                    }
+                    report
+                }
+                Err(_) => json!({ "endpoint": endpoint, "error": format!("no answer within {}s", timeout.as_secs()) }),
+            }
+        }
+    })).await
+}
+
+/// Groups endpoints by every key `keys` yields for them, keeping keys shared by more
+/// than one endpoint unless `all` is set.
+fn group(reports: &[Value], label: &str, all: bool, keys: impl Fn(&Value) -> Vec<String>) -> Vec<Value> {
+    let mut groups: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for report in reports {
+        for key in keys(report) {
+            groups.entry(key).or_default().insert(report["endpoint"].as_str().unwrap_or_default());
+        }
+    }
+    groups.into_iter()
+        .filter(|(_, endpoints)| all || endpoints.len() > 1)
+        .map(|(key, endpoints)| json!({ label: key, "endpoints": endpoints }))
+        .collect()
+}
+
+/// Probes every endpoint for its addresses, peer id and genesis hash and reports which
+/// URLs are the same node (same peer id), the same host (a shared address) or likely the
+/// same provider (a shared /24 or /48), and which chain each serves.
+pub async fn aliases(endpoints: &[String], timeout: Duration, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let reports = probe_all(endpoints, timeout, |endpoint| identity(endpoint, max_response_size)).await;
+
+    let strings = |report: &Value, field: &str| -> Vec<String> {
+        match &report[field] {
+            Value::String(value) => vec![value.clone()],
+            Value::Array(values) => values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        }
+    };
+    let same_node = group(&reports, "peer_id", false, |report| strings(report, "peer_id"));
+    let answered = reports.iter().filter(|report| report.get("error").is_none()).count();
+    let distinct_nodes = answered - same_node.iter().map(|group| group["endpoints"].as_array().map_or(0, |endpoints| endpoints.len() - 1)).sum::<usize>();
+    let chains = group(&reports, "genesis", true, |report| strings(report, "genesis"));
+    if chains.len() > 1 {
+        eprintln!("Warning: the endpoints serve {} different chains", chains.len());
+    }
+
+    Ok(json!({
+        "endpoints": reports,
+        "answered": answered,
+        "distinct_nodes": distinct_nodes,
+        "same_node": same_node,
+        "same_address": group(&reports, "address", false, |report| strings(report, "addresses")),
+        "same_network": group(&reports, "network", false, |report| {
+            strings(report, "addresses").iter().filter_map(|address| address.parse().ok()).map(|address| network(&address)).collect()
+        }),
+        "chains": chains,
+    }))
+}
+
+/// The number of nodes per distinct value `key` yields, most common first.
+fn count(reports: &[Value], label: &str, key: impl Fn(&Value) -> Option<String>) -> Vec<Value> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for report in reports {
+        if let Some(value) = key(report) {
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.into_iter().map(|(value, nodes)| json!({ label: value, "nodes": nodes })).collect()
+}
+
+/// The numeric release in a `system_version` string, e.g. `[1, 15, 2]` for
+/// `1.15.2-7f5a2e1c4b9`.
+fn release(version: &str) -> Vec<u64> {
+    version.split(['-', '+']).next().unwrap_or_default().split('.').map_while(|part| part.parse().ok()).collect()
+}
+
+async fn node_version(endpoint: &str, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let client = crate::send_and_receive(&mut socket, "system_name", json!([])).await?;
+    let version = crate::send_and_receive(&mut socket, "system_version", json!([])).await?;
+    let runtime = crate::send_and_receive(&mut socket, "state_getRuntimeVersion", json!([])).await?;
+    Ok(json!({
+        "endpoint": endpoint,
+        "client": client,
+        "version": version,
+        "spec_name": runtime["specName"],
+        "spec_version": runtime["specVersion"],
+    }))
+}
+
+/// Inventories the node and runtime versions across a fleet: how many endpoints run each
+/// distinct `system_version` and runtime, and which ones are behind. A node is behind when
+/// its release is older than `latest` (the newest seen when not given), a runtime when its
+/// spec version is older than the newest seen for the same spec name.
+pub async fn versions(endpoints: &[String], latest: Option<&str>, timeout: Duration, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut reports = probe_all(endpoints, timeout, |endpoint| node_version(endpoint, max_response_size)).await;
+
+    let latest_release = match latest {
+        Some(latest) => release(latest),
+        None => reports.iter().filter_map(|report| report["version"].as_str()).map(release).max().unwrap_or_default(),
+    };
+    let mut latest_spec: BTreeMap<String, u64> = BTreeMap::new();
+    for report in &reports {
+        if let (Some(name), Some(spec)) = (report["spec_name"].as_str(), report["spec_version"].as_u64()) {
+            let newest = latest_spec.entry(name.to_string()).or_insert(spec);
+            *newest = (*newest).max(spec);
+        }
+    }
+
+    let mut behind = Vec::new();
+    for report in reports.iter_mut().filter(|report| report.get("error").is_none()) {
+        let node_behind = report["version"].as_str().is_some_and(|version| release(version) < latest_release);
+        let runtime_behind = match (report["spec_name"].as_str(), report["spec_version"].as_u64()) {
+            (Some(name), Some(spec)) => latest_spec.get(name).is_some_and(|newest| spec < *newest),
+            _ => false,
+        };
+        report["node_behind"] = json!(node_behind);
+        report["runtime_behind"] = json!(runtime_behind);
+        if node_behind || runtime_behind {
+            behind.push(report["endpoint"].clone());
+        }
+    }
+
+    let node_versions = count(&reports, "version", |report| report["version"].as_str().map(str::to_string));
+    let runtimes = count(&reports, "runtime", |report| Some(format!("{}/{}", report["spec_name"].as_str()?, report["spec_version"].as_u64()?)));
+    let failed = reports.iter().filter(|report| report.get("error").is_some()).count();
+
+    Ok(json!({
+        "nodes": reports.len(),
+        "failed": failed,
+        "latest_release": latest_release.iter().map(u64::to_string).collect::<Vec<_>>().join("."),
+        "latest_runtimes": latest_spec,
+        "versions": node_versions,
+        "runtimes": runtimes,
+        "behind": behind,
+        "endpoints": reports,
+    }))
+}
+
+/// Peer count buckets for the distribution, by lower bound.
+const PEER_BUCKETS: [u64; 6] = [0, 1, 5, 10, 25, 50];
+
+async fn node_health(endpoint: &str, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let health = crate::send_and_receive(&mut socket, "system_health", json!([])).await?;
+    let version = crate::send_and_receive(&mut socket, "system_version", json!([])).await?;
+    let header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([])).await?;
+    // Light clients and some providers do not expose the sync state
+    let sync = crate::send_and_receive(&mut socket, "system_syncState", json!([])).await.ok();
+    let best = header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok());
+    Ok(json!({
+        "endpoint": endpoint,
+        "version": version,
+        "is_syncing": health["isSyncing"],
+        "peers": health["peers"],
+        "should_have_peers": health["shouldHavePeers"],
+        "best": best,
+        "highest": sync.as_ref().and_then(|sync| sync["highestBlock"].as_u64()),
+    }))
+}
+
+/// Probes every endpoint's `system_health`, sync state and version and aggregates them:
+/// how many nodes are syncing or without peers, the distribution of peer counts, how far
+/// each lags the best block seen, and the skew between the node releases run. A node is
+/// healthy when it is not syncing, has peers and lags at most `probe::MAX_LAG` blocks.
+pub async fn health(endpoints: &[String], timeout: Duration, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut reports = probe_all(endpoints, timeout, |endpoint| node_health(endpoint, max_response_size)).await;
+    let answered: Vec<&Value> = reports.iter().filter(|report| report.get("error").is_none()).collect();
+    let failed = reports.len() - answered.len();
+
+    let syncing = answered.iter().filter(|report| report["is_syncing"] == true).count();
+    let without_peers = answered.iter().filter(|report| report["peers"] == 0 && report["should_have_peers"] != false).count();
+    let mut peers: Vec<u64> = answered.iter().filter_map(|report| report["peers"].as_u64()).collect();
+    peers.sort_unstable();
+    let distribution: Vec<Value> = PEER_BUCKETS.iter().enumerate().map(|(index, low)| {
+        let high = PEER_BUCKETS.get(index + 1).map(|next| next - 1);
+        let nodes = peers.iter().filter(|&&count| count >= *low && high.is_none_or(|high| count <= high)).count();
+        let range = match high {
+            Some(high) if high == *low => low.to_string(),
+            Some(high) => format!("{}-{}", low, high),
+            None => format!("{}+", low),
+        };
+        json!({ "peers": range, "nodes": nodes })
+    }).collect();
+
+    let best_seen = answered.iter().filter_map(|report| report["best"].as_u64()).max();
+    let mut lags = Vec::new();
+    let mut lagging = Vec::new();
+    let mut unhealthy = Vec::new();
+    for report in reports.iter_mut().filter(|report| report.get("error").is_none()) {
+        let lag = best_seen.zip(report["best"].as_u64()).map(|(seen, best)| seen.saturating_sub(best));
+        let has_peers = report["should_have_peers"] == false || report["peers"].as_u64().is_some_and(|peers| peers > 0);
+        let healthy = report["is_syncing"] == false && has_peers && lag.is_some_and(|lag| lag <= crate::probe::MAX_LAG);
+        if let Some(lag) = lag {
+            lags.push(lag);
+            if lag > crate::probe::MAX_LAG {
+                lagging.push(report["endpoint"].clone());
+            }
+        }
+        if !healthy {
+            unhealthy.push(report["endpoint"].clone());
+        }
+        report["lag"] = json!(lag);
+        report["healthy"] = json!(healthy);
+    }
+    lags.sort_unstable();
+    let p50 = |sorted: &[u64]| sorted.get(sorted.len().div_ceil(2).saturating_sub(1)).copied();
+
+    let releases: BTreeSet<Vec<u64>> = reports.iter().filter_map(|report| report["version"].as_str()).map(release).filter(|release| !release.is_empty()).collect();
+    let dotted = |release: &Vec<u64>| release.iter().map(u64::to_string).collect::<Vec<_>>().join(".");
+
+    Ok(json!({
+        "nodes": reports.len(),
+        "failed": failed,
+        "summary": {
+            "healthy": reports.len() - failed - unhealthy.len(),
+            "syncing": syncing,
+            "without_peers": without_peers,
+            "peers": {
+                "min": peers.first(),
+                "p50": p50(&peers),
+                "max": peers.last(),
+                "distribution": distribution,
+            },
+            "best_seen": best_seen,
+            "lag": {
+                "p50": p50(&lags),
+                "max": lags.last(),
+            },
+            "lagging": lagging,
+            "version_skew": {
+                "releases": releases.len(),
+                "oldest": releases.first().map(dotted),
+                "newest": releases.last().map(dotted),
+                "versions": count(&reports, "version", |report| report["version"].as_str().map(str::to_string)),
+            },
+            "unhealthy": unhealthy,
+        },
+        "endpoints": reports,
+    }))
+}
+
+/// The number of a JSON header.
+pub fn header_number(header: &Value) -> Option<u64> {
+    header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+}
+
+async fn chain_view(endpoint: &str, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let genesis = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([0])).await?;
+    let finalized = crate::send_and_receive(&mut socket, "chain_getFinalizedHead", json!([])).await?;
+    let finalized_header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([finalized])).await?;
+    let best_header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([])).await?;
+    let runtime = crate::send_and_receive(&mut socket, "state_getRuntimeVersion", json!([])).await?;
+    Ok(json!({
+        "endpoint": endpoint,
+        "genesis": genesis,
+        "finalized": header_number(&finalized_header),
+        "finalized_hash": finalized,
+        "best": header_number(&best_header),
+        "spec_name": runtime["specName"],
+        "spec_version": runtime["specVersion"],
+    }))
+}
+
+async fn block_hash_at(endpoint: &str, number: u64, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let hash = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([number])).await?;
+    Ok(json!({ "endpoint": endpoint, "hash": hash }))
+}
+
+/// Queries every endpoint at once for its genesis hash, finalized and best heads and
+/// runtime, and flags the nodes that disagree with the rest: on another chain (a genesis
+/// other than the most common), lagging (finalized or best head more than
+/// `probe::MAX_LAG` blocks behind the highest seen), on another fork (a different hash
+/// at the highest height every node has finalized) or running another runtime than most.
+pub async fn compare(endpoints: &[String], timeout: Duration, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut reports = probe_all(endpoints, timeout, |endpoint| chain_view(endpoint, max_response_size)).await;
+    let failed = reports.iter().filter(|report| report.get("error").is_some()).count();
+    let most_common = |reports: &[Value], label: &str, key: &dyn Fn(&Value) -> Option<String>| {
+        count(reports, label, key).first().and_then(|top| top[label].as_str().map(str::to_string))
+    };
+    let genesis = most_common(&reports, "genesis", &|report| report["genesis"].as_str().map(str::to_string));
+    let same_chain = |report: &Value| report.get("error").is_none() && report["genesis"].as_str() == genesis.as_deref();
+
+    let chain: Vec<Value> = reports.iter().filter(|report| same_chain(report)).cloned().collect();
+    let runtime_of = |report: &Value| Some(format!("{}/{}", report["spec_name"].as_str()?, report["spec_version"].as_u64()?));
+    let runtime = most_common(&chain, "runtime", &runtime_of);
+    let best_seen = chain.iter().filter_map(|report| report["best"].as_u64()).max();
+    let finalized_seen = chain.iter().filter_map(|report| report["finalized"].as_u64()).max();
+
+    // Every node should have finalized the lowest finalized head, so it must agree on its hash
+    let common = chain.iter().filter_map(|report| report["finalized"].as_u64()).min();
+    let mut hashes = BTreeMap::new();
+    if let Some(common) = common {
+        let on_chain: Vec<String> = chain.iter().filter_map(|report| report["endpoint"].as_str().map(str::to_string)).collect();
+        for answer in probe_all(&on_chain, timeout, |endpoint| block_hash_at(endpoint, common, max_response_size)).await {
+            if let (Some(endpoint), Some(hash)) = (answer["endpoint"].as_str(), answer["hash"].as_str()) {
+                hashes.insert(endpoint.to_string(), hash.to_string());
+            }
+        }
+    }
+    let common_hash = most_common(&chain, "hash", &|report| hashes.get(report["endpoint"].as_str()?).cloned());
+
+    let (mut different_chain, mut lagging, mut forked, mut different_runtime) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for report in reports.iter_mut().filter(|report| report.get("error").is_none()) {
+        let endpoint = report["endpoint"].clone();
+        if !same_chain(report) {
+            report["different_chain"] = json!(true);
+            different_chain.push(endpoint);
+            continue;
+        }
+        let finalized_lag = finalized_seen.zip(report["finalized"].as_u64()).map(|(seen, finalized)| seen.saturating_sub(finalized));
+        let best_lag = best_seen.zip(report["best"].as_u64()).map(|(seen, best)| seen.saturating_sub(best));
+        let is_lagging = finalized_lag.max(best_lag).is_some_and(|lag| lag > crate::probe::MAX_LAG);
+        let hash = hashes.get(endpoint.as_str().unwrap_or_default());
+        let is_forked = hash.is_some() && hash != common_hash.as_ref();
+        let is_different_runtime = runtime_of(report) != runtime;
+        report["finalized_lag"] = json!(finalized_lag);
+        report["best_lag"] = json!(best_lag);
+        report["hash_at_common"] = json!(hash);
+        report["lagging"] = json!(is_lagging);
+        report["forked"] = json!(is_forked);
+        report["different_runtime"] = json!(is_different_runtime);
+        if is_lagging {
+            lagging.push(endpoint.clone());
+        }
+        if is_forked {
+            forked.push(endpoint.clone());
+        }
+        if is_different_runtime {
+            different_runtime.push(endpoint);
+        }
+    }
+    let consistent = failed == 0 && different_chain.is_empty() && lagging.is_empty() && forked.is_empty() && different_runtime.is_empty();
+    if !consistent {
+        eprintln!(
+            "Warning: {} on another chain, {} lagging, {} forked, {} on another runtime, {} failed",
+            different_chain.len(), lagging.len(), forked.len(), different_runtime.len(), failed,
+        );
+    }
+
+    Ok(json!({
+        "nodes": reports.len(),
+        "failed": failed,
+        "consistent": consistent,
+        "genesis": genesis,
+        "runtime": runtime,
+        "best_seen": best_seen,
+        "finalized_seen": finalized_seen,
+        "common_finalized": { "number": common, "hash": common_hash },
+        "different_chain": different_chain,
+        "lagging": lagging,
+        "forked": forked,
+        "different_runtime": different_runtime,
+        "endpoints": reports,
+    }))
+}
@@ -0,0 +1,421 @@
+//! A small gzip encoder (LZ77 with the fixed deflate Huffman codes) for compressing
+//! rotated output files. It trades ratio for simplicity, JSON lines still shrink well.
+
+use std::io::{Read, Write};
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+// Inputs are compressed in independent blocks so memory stays bounded on big files
+const CHUNK: usize = 1 << 20;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `count` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Appends a Huffman code, which deflate stores most significant bit first.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0;
+        }
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol as u32, 8),
+        144..=255 => writer.write_code(0x190 + (symbol as u32 - 144), 9),
+        256..=279 => writer.write_code(symbol as u32 - 256, 7),
+        _ => writer.write_code(0xc0 + (symbol as u32 - 280), 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+    write_literal(writer, 257 + code as u16);
+    writer.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` as one fixed-Huffman deflate block.
+fn deflate_block(writer: &mut BitWriter, data: &[u8], last: bool) {
+    writer.write(last as u32, 1);
+    writer.write(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            let mut candidate = head[h];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let limit = (data.len() - i).min(MAX_MATCH);
+                let length = data[candidate..].iter().zip(&data[i..i + limit]).take_while(|(a, b)| a == b).count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == limit {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+            prev[i] = head[h];
+            head[h] = i;
+        }
+        if best.0 >= MIN_MATCH {
+            write_match(writer, best.0, best.1);
+            for j in i + 1..i + best.0 {
+                if j + MIN_MATCH <= data.len() {
+                    let h = hash(&data[j..]);
+                    prev[j] = head[h];
+                    head[h] = j;
+                }
+            }
+            i += best.0;
+        } else {
+            write_literal(writer, data[i] as u16);
+            i += 1;
+        }
+    }
+    write_literal(writer, 256);
+}
+
+/// Reads until `buffer` is full or the input ends, returning how much was read.
+fn fill(input: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Streams `input` into `output` as a gzip member.
+pub fn compress(mut input: impl Read, mut output: impl Write) -> std::io::Result<()> {
+    output.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff])?;
+    let table = crc32_table();
+    let mut crc = !0u32;
+    let mut size = 0u32;
+    let mut writer = BitWriter { out: Vec::new(), bits: 0, count: 0 };
+    let mut chunk = vec![0u8; CHUNK];
+    let mut filled = fill(&mut input, &mut chunk)?;
+    loop {
+        // Read ahead to know whether this is the final block
+        let mut next = vec![0u8; CHUNK];
+        let next_filled = if filled == CHUNK { fill(&mut input, &mut next)? } else { 0 };
+        let data = &chunk[..filled];
+        for byte in data {
+            crc = table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        size = size.wrapping_add(data.len() as u32);
+        let last = next_filled == 0;
+        deflate_block(&mut writer, data, last);
+        output.write_all(&writer.out)?;
+        writer.out.clear();
+        if last {
+            break;
+        }
+        (chunk, filled) = (next, next_filled);
+    }
+    writer.flush();
+    output.write_all(&writer.out)?;
+    output.write_all(&(!crc).to_le_bytes())?;
+    output.write_all(&size.to_le_bytes())?;
+    output.flush()
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    // Position in bits
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.position / 8).ok_or("truncated deflate stream")?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+}
+
+/// A canonical Huffman code as deflate builds it from code lengths.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        // Codes of each length are consecutive, walk them one bit at a time
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code in deflate stream".into())
+    }
+}
+
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &position in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[position] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("deflate length repeat with no previous length")?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err("deflate code lengths overrun".into());
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Inflates one raw deflate stream onto `out`, returning the bytes it took.
+fn inflate(data: &[u8], out: &mut Vec<u8>) -> Result<usize, String> {
+    let mut reader = BitReader { data, position: 0 };
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let start = reader.position / 8;
+                let header = data.get(start..start + 4).ok_or("truncated stored deflate block")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let block = data.get(start + 4..start + 4 + length).ok_or("truncated stored deflate block")?;
+                out.extend_from_slice(block);
+                reader.position = (start + 4 + length) * 8;
+            }
+            kind @ (1 | 2) => {
+                let (literal_code, distance_code) = if kind == 1 { fixed_codes() } else { dynamic_codes(&mut reader)? };
+                loop {
+                    let symbol = literal_code.decode(&mut reader)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let code = symbol - 257;
+                    let length = *LENGTH_BASE.get(code).ok_or("invalid deflate length code")? as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = distance_code.decode(&mut reader)? as usize;
+                    let distance = *DISTANCE_BASE.get(code).ok_or("invalid deflate distance code")? as usize + reader.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+                    if distance > out.len() {
+                        return Err("deflate distance reaches before the start of the output".into());
+                    }
+                    let from = out.len() - distance;
+                    for i in 0..length {
+                        out.push(out[from + i]);
+                    }
+                }
+            }
+            _ => return Err("invalid deflate block type".into()),
+        }
+        if last {
+            return Ok(reader.position.div_ceil(8));
+        }
+    }
+}
+
+/// Decompresses gzip data, every member of it, checking each one's CRC.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let table = crc32_table();
+    let mut out = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 18 || rest[..3] != [0x1f, 0x8b, 8] {
+            return Err("not gzip data".into());
+        }
+        let flags = rest[3];
+        let mut position = 10;
+        if flags & 0x04 != 0 {
+            let extra = u16::from_le_bytes([rest[10], rest[11]]) as usize;
+            position += 2 + extra;
+        }
+        // File name and comment, zero-terminated
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                position += rest.get(position..).and_then(|field| field.iter().position(|&byte| byte == 0)).ok_or("truncated gzip header")? + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            position += 2;
+        }
+        let start = out.len();
+        let used = inflate(rest.get(position..).ok_or("truncated gzip header")?, &mut out)?;
+        let trailer = rest.get(position + used..position + used + 8).ok_or("truncated gzip trailer")?;
+        let crc = !out[start..].iter().fold(!0u32, |crc, byte| table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8));
+        if crc != u32::from_le_bytes(trailer[..4].try_into().unwrap()) {
+            return Err("gzip CRC mismatch, the data is corrupt".into());
+        }
+        rest = &rest[position + used + 8..];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Python's gzip at level 9 (dynamic Huffman codes, a file name in the header) and
+    // level 0 (a stored block)
+    const DYNAMIC: &str = "0x1f8b08080000000002ff68656164732e6a736f6e6c00add6c96dc3301484e1bbab50097a0bb7744352946dc440826c305c7d6c84e306320478fd2fd237e0b1fe8ccb72bc9ddf97fd7cfdfafe182f4bbbbcf5d7655d4ef5f3b4acd7f59fe7f0d713464fc6d8674f193ddbb6317bc6e885deb7d973462fb5d6672f307aa5d6367b91d16ba5d4d94b8cde967399bdcce8ed29e5d92b849e488c09ff33038858081141861009ee0141061149668e20c3881455439081449a8822c85022dbfd22c860f258ade70c329ce8ba6dd841614051ed1d43280c29eaad610995214563ad98426548d15c0ab6501952b4e68c31548614ed29610d952145478c98436548b13504eca132a498ba630f9521c5dc0c7ba80c291655b187ca906259047b680c2956ef5f1a4186146b63c71e1a43cae3d5853d348614db7bc71e1a438a4b6bcf8721438a5badd8436348f1500af6d018523ce58c3d3486142f29610f8d21c55b8ce5f00bcff56b8db20c0000";
+    const STORED: &str = "0x1f8b0800000000000403010d00f2ff73746f72656420626c6f636b0a6d7588c50d000000";
+
+    fn fixture_text() -> Vec<u8> {
+        let mut text = b"gavel gzip fixture: ".to_vec();
+        for i in 0..40u64 {
+            text.extend(format!("block {} hash 0x{:064x}\n", i, i * 7919).into_bytes());
+        }
+        text
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        compress(data, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn compresses_with_fixed_codes() {
+        // zlib inflates this to the input
+        assert_eq!(crate::hex::encode(&gzip(b"hello hello hello hello\n")), "0x1f8b08000000000000ffcb48cdc9c957c020b9000088590b18000000");
+        assert_eq!(decompress(&gzip(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn round_trips_across_chunks() {
+        // Past one chunk, so the stream has a second block, with matches and literals
+        let data: Vec<u8> = (0..CHUNK as u64 + 70_000)
+            .map(|i| if i % 1000 < 900 { b"{\"number\":1}\n"[(i % 13) as usize] } else { ((i * 2_654_435_761) >> 13) as u8 })
+            .collect();
+        let compressed = gzip(&data);
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn decompresses_what_other_tools_write() {
+        assert_eq!(decompress(&crate::hex::decode(DYNAMIC).unwrap()).unwrap(), fixture_text());
+        assert_eq!(decompress(&crate::hex::decode(STORED).unwrap()).unwrap(), b"stored block\n");
+        // Concatenated members, as appending to a .gz file makes
+        let members = [crate::hex::decode(STORED).unwrap(), gzip(b"appended\n")].concat();
+        assert_eq!(decompress(&members).unwrap(), b"stored block\nappended\n");
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let mut corrupt = gzip(b"hello hello hello hello\n");
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert_eq!(decompress(&corrupt).unwrap_err(), "gzip CRC mismatch, the data is corrupt");
+        let truncated = gzip(b"hello hello hello hello\n");
+        assert!(decompress(&truncated[..truncated.len() - 3]).is_err());
+        assert!(decompress(b"{\"number\": 1}\n and more text").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! `gavel hammer`: load-tests an endpoint with a weighted mix of requests spread over
+//! several connections, then reports throughput, error rate and latency percentiles,
+//! overall and per method.
+//!
+//! With a target rate, requests go out on a fixed schedule without waiting for answers,
+//! so a slow node shows up as latency rather than as a lower rate. Without one, each
+//! connection sends its next request as soon as the previous one is answered.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rand::distributions::{Distribution, WeightedIndex};
+use serde_json::{json, Value};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::probe::percentile;
+
+/// Time to wait for the answers still outstanding when the run ends.
+const DRAIN: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args, Debug)]
+pub struct Load {
+    #[clap(long, default_value = "4", help = "Concurrent WebSocket connections.")]
+    pub connections: u32,
+    #[clap(long, value_delimiter = ',', default_value = "chain_getHeader,system_health", value_name = "METHOD[PARAMS][=WEIGHT]", help = "Requests to send, picked by weight (1 by default), with optional JSON params, e.g. 'chain_getBlockHash[1000]=3'.")]
+    pub mix: Vec<String>,
+    #[clap(long, default_value = "100", help = "Target requests per second over all connections, 0 for as fast as the answers come.")]
+    pub rps: u64,
+    #[clap(long, default_value = "30", help = "Seconds to send requests for.")]
+    pub duration: u64,
+}
+
+/// One entry of the request mix.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub params: Value,
+    pub weight: u32,
+}
+
+/// Parses `METHOD[PARAMS][=WEIGHT]` entries, e.g. `state_getStorage["0x26aa..."]=2`.
+pub fn parse_mix(entries: &[String]) -> Result<Vec<Request>, String> {
+    let requests: Vec<Request> = entries.iter().map(|entry| {
+        let (call, weight) = match entry.rsplit_once('=') {
+            Some((call, weight)) if !weight.contains(']') => {
+                (call, weight.parse().map_err(|_| format!("Invalid weight '{}' in '{}'", weight, entry))?)
+            }
+            _ => (entry.as_str(), 1),
+        };
+        let (method, params) = match call.find('[') {
+            Some(start) => {
+                let params: Value = serde_json::from_str(&call[start..]).map_err(|e| format!("Invalid params in '{}': {}", entry, e))?;
+                (&call[..start], params)
+            }
+            None => (call, json!([])),
+        };
+        if method.is_empty() {
+            return Err(format!("No method in '{}'", entry));
+        }
+        Ok(Request { method: method.to_string(), params, weight })
+    }).collect::<Result<_, String>>()?;
+    if requests.iter().all(|request| request.weight == 0) {
+        return Err("The request mix is empty or every weight is 0".into());
+    }
+    Ok(requests)
+}
+
+#[derive(Default)]
+struct Tally {
+    sent: u64,
+    answered: u64,
+    errors: u64,
+    limited: u64,
+    latencies_ms: Vec<f64>,
+    // Per method: sent, errors and latencies of the successful answers
+    methods: BTreeMap<String, (u64, u64, Vec<f64>)>,
+    dropped: Option<String>,
+}
+
+impl Tally {
+    fn merge(&mut self, other: Tally) {
+        self.sent += other.sent;
+        self.answered += other.answered;
+        self.errors += other.errors;
+        self.limited += other.limited;
+        self.latencies_ms.extend(other.latencies_ms);
+        for (method, (sent, errors, latencies)) in other.methods {
+            let entry = self.methods.entry(method).or_default();
+            entry.0 += sent;
+            entry.1 += errors;
+            entry.2.extend(latencies);
+        }
+    }
+}
+
+fn latency_summary(latencies: &mut [f64]) -> Value {
+    latencies.sort_by(f64::total_cmp);
+    let mean = (!latencies.is_empty()).then(|| (latencies.iter().sum::<f64>() / latencies.len() as f64 * 100.0).round() / 100.0);
+    json!({
+        "min": latencies.first().map(|ms| (ms * 100.0).round() / 100.0),
+        "p50": percentile(latencies, 0.5),
+        "p90": percentile(latencies, 0.9),
+        "p99": percentile(latencies, 0.99),
+        "max": percentile(latencies, 1.0),
+        "mean": mean,
+    })
+}
+
+/// Runs one connection until `end`, at `interval` between requests or, without one, a
+/// request at a time.
+async fn run_connection(endpoint: String, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>, max_response_size: Option<usize>, mix: Vec<Request>, interval: Option<Duration>, end: Instant) -> Result<Tally, String> {
+    let socket = crate::connect_websocket(&endpoint, ipv4.as_ref(), ipv6.as_ref(), max_response_size).await.map_err(|e| e.to_string())?;
+    let (mut sink, mut stream) = socket.split();
+    let weights = WeightedIndex::new(mix.iter().map(|request| request.weight)).map_err(|e| e.to_string())?;
+    let mut tally = Tally::default();
+    let mut pending: HashMap<u64, (usize, Instant)> = HashMap::new();
+    let mut ticks = tokio::time::interval(interval.unwrap_or(Duration::from_secs(3600)));
+    let mut next_id = 0u64;
+
+    loop {
+        let sending = Instant::now() < end;
+        if !sending && (pending.is_empty() || Instant::now() > end + DRAIN) {
+            break;
+        }
+        let ready = match interval {
+            Some(_) => false,
+            None => sending && pending.is_empty(),
+        };
+        tokio::select! {
+            _ = ticks.tick(), if sending && interval.is_some() => {}
+            _ = std::future::ready(()), if ready => {}
+            message = stream.next() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let response: Value = serde_json::from_str(&text).unwrap_or_default();
+                        let Some((index, sent)) = response["id"].as_u64().and_then(|id| pending.remove(&id)) else { continue };
+                        let method = tally.methods.entry(mix[index].method.clone()).or_default();
+                        tally.answered += 1;
+                        match response.get("error") {
+                            Some(error) => {
+                                method.1 += 1;
+                                tally.errors += 1;
+                                if crate::probe::is_rate_limit(error) {
+                                    tally.limited += 1;
+                                }
+                            }
+                            None => {
+                                let ms = sent.elapsed().as_secs_f64() * 1000.0;
+                                method.2.push(ms);
+                                tally.latencies_ms.push(ms);
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        tally.dropped = Some(match frame {
+                            Some(frame) => format!("close {}: {}", u16::from(frame.code), frame.reason),
+                            None => "close without a code".into(),
+                        });
+                        break;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        tally.dropped = Some(format!("connection dropped: {}", e));
+                        break;
+                    }
+                    None => {
+                        tally.dropped = Some("connection dropped".into());
+                        break;
+                    }
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(end), if sending => continue,
+            _ = tokio::time::sleep_until(end + DRAIN), if !sending => continue,
+        }
+
+        let index = weights.sample(&mut rand::thread_rng());
+        next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": next_id, "method": mix[index].method, "params": mix[index].params }).to_string();
+        if let Err(e) = sink.send(Message::Text(request)).await {
+            tally.dropped = Some(format!("connection dropped while sending: {}", e));
+            break;
+        }
+        pending.insert(next_id, (index, Instant::now()));
+        tally.methods.entry(mix[index].method.clone()).or_default().0 += 1;
+        tally.sent += 1;
+    }
+    Ok(tally)
+}
+
+/// Hammers `endpoint` with `load`, reporting what it sustained.
+pub async fn hammer(endpoint: &str, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>, max_response_size: Option<usize>, load: &Load) -> Result<Value, Box<dyn std::error::Error>> {
+    let mix = parse_mix(&load.mix)?;
+    let connections = load.connections.max(1);
+    let interval = (load.rps > 0).then(|| Duration::from_secs_f64(connections as f64 / load.rps as f64));
+    let duration = Duration::from_secs(load.duration.max(1));
+    eprintln!(
+        "Hammering {} with {} connections at {} for {} s",
+        endpoint, connections, if load.rps > 0 { format!("{} rps", load.rps) } else { "full speed".to_string() }, duration.as_secs(),
+    );
+
+    let start = Instant::now();
+    let end = start + duration;
+    let tasks: Vec<_> = (0..connections).map(|_| {
+        tokio::spawn(run_connection(endpoint.to_string(), ipv4, ipv6, max_response_size, mix.clone(), interval, end))
+    }).collect();
+
+    let mut tally = Tally::default();
+    let mut failed_connections = Vec::new();
+    let mut dropped = Vec::new();
+    for task in tasks {
+        match task.await? {
+            Ok(mut connection) => {
+                dropped.extend(connection.dropped.take());
+                tally.merge(connection);
+            }
+            Err(e) => failed_connections.push(e),
+        }
+    }
+    if failed_connections.len() == connections as usize {
+        return Err(format!("No connection could be opened: {}", failed_connections[0]).into());
+    }
+    let elapsed = start.elapsed().min(duration).as_secs_f64();
+
+    let timeouts = tally.sent - tally.answered;
+    let methods: serde_json::Map<String, Value> = tally.methods.iter_mut().map(|(method, (sent, errors, latencies))| {
+        (method.clone(), json!({ "sent": sent, "errors": errors, "latency_ms": latency_summary(latencies) }))
+    }).collect();
+    let rate = |count: u64| if tally.sent == 0 { 0.0 } else { (count as f64 / tally.sent as f64 * 10000.0).round() / 10000.0 };
+    Ok(json!({
+        "endpoint": endpoint,
+        "connections": connections,
+        "failed_connections": failed_connections,
+        "dropped_connections": dropped,
+        "target_rps": load.rps,
+        "duration_secs": duration.as_secs(),
+        "sent": tally.sent,
+        "answered": tally.answered,
+        "errors": tally.errors,
+        "rate_limited": tally.limited,
+        "timeouts": timeouts,
+        "error_rate": rate(tally.errors + timeouts),
+        "throughput_rps": (tally.answered as f64 / elapsed * 10.0).round() / 10.0,
+        "latency_ms": latency_summary(&mut tally.latencies_ms),
+        "methods": methods,
+    }))
+}
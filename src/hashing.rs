@@ -0,0 +1,129 @@
+//! Hashers used by Substrate storage keys, headers, keys and the PostgreSQL sink, as
+//! thin wrappers over the RustCrypto, `twox-hash` and `blake3` crates returning owned
+//! bytes.
+
+use blake2::digest::{Mac, Update, VariableOutput};
+use blake2::Blake2bVar;
+use hmac::Hmac;
+use md5::{Digest, Md5};
+use sha2::{Sha256, Sha512};
+use sha3::Keccak256;
+use twox_hash::XxHash64;
+
+/// xxHash64 of `input` under `seeds` 0, 1, ..., concatenated little-endian, as Substrate's
+/// `twox_*` hashers do.
+fn twox(input: &[u8], rounds: u64) -> Vec<u8> {
+    (0..rounds).flat_map(|seed| XxHash64::oneshot(seed, input).to_le_bytes()).collect()
+}
+
+pub fn twox_64(input: &[u8]) -> Vec<u8> {
+    twox(input, 1)
+}
+
+pub fn twox_128(input: &[u8]) -> Vec<u8> {
+    twox(input, 2)
+}
+
+pub fn twox_256(input: &[u8]) -> Vec<u8> {
+    twox(input, 4)
+}
+
+/// Unkeyed BLAKE2b with an `out_len` byte digest (1 to 64).
+pub fn blake2b(out_len: usize, input: &[u8]) -> Vec<u8> {
+    let mut hasher = Blake2bVar::new(out_len).expect("BLAKE2b digests are 1 to 64 bytes");
+    hasher.update(input);
+    hasher.finalize_boxed().into_vec()
+}
+
+pub fn blake2_128(input: &[u8]) -> Vec<u8> {
+    blake2b(16, input)
+}
+
+pub fn blake2_256(input: &[u8]) -> Vec<u8> {
+    blake2b(32, input)
+}
+
+pub fn sha256(input: &[u8]) -> Vec<u8> {
+    Sha256::digest(input).to_vec()
+}
+
+pub fn md5(input: &[u8]) -> Vec<u8> {
+    Md5::digest(input).to_vec()
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    Mac::update(&mut mac, message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], rounds: u32, out_len: usize) -> Vec<u8> {
+    let mut out = vec![0; out_len];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, rounds, &mut out);
+    out
+}
+
+pub fn pbkdf2_sha512(password: &[u8], salt: &[u8], rounds: u32, out_len: usize) -> Vec<u8> {
+    let mut out = vec![0; out_len];
+    pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, rounds, &mut out);
+    out
+}
+
+/// Keccak-256 with the original padding, as Ethereum and the BEEFY MMR use, not SHA3-256.
+pub fn keccak_256(input: &[u8]) -> Vec<u8> {
+    Keccak256::digest(input).to_vec()
+}
+
+pub fn blake3_256(input: &[u8]) -> Vec<u8> {
+    blake3::hash(input).as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+
+    #[test]
+    fn twox_matches_substrate() {
+        assert_eq!(hex::encode(&twox_128(b"System")), "0x26aa394eea5630e07c48ae0c9558cef7");
+        assert_eq!(hex::encode(&twox_128(b"Account")), "0xb99d880ec681799c0cf30e8886371da9");
+        assert_eq!(hex::encode(&twox_64(b"")), "0x99e9d85137db46ef");
+        assert_eq!(hex::encode(&twox_256(b"abc")), "0x990977adf52cbc440889329981caa9bef7da5770b2b8a05303b75d95360dd62b");
+    }
+
+    #[test]
+    fn blake2b_matches_the_reference() {
+        assert_eq!(hex::encode(&blake2_128(b"abc")), "0xcf4ab791c62b8d2b2109c90275287816");
+        assert_eq!(hex::encode(&blake2_256(b"")), "0x0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8");
+        // Three blocks, the last one partial
+        assert_eq!(
+            hex::encode(&blake2b(64, &[b'a'; 300])),
+            "0xa2ff3040eda405b929c2fc2fd93e8add6ac3bb5369b679bae170ac6956863ca006285f132a868000fc3fae5bc696e5d17fe3fddfb4a342876c40451184742986",
+        );
+    }
+
+    #[test]
+    fn sha2_and_md5_match_the_reference() {
+        assert_eq!(hex::encode(&sha256(b"abc")), "0xba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex::encode(&md5(b"abc")), "0x900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hex::encode(&hmac_sha256(b"key", b"abc")), "0x9c196e32dc0175f86f4b1cb89289d6619de6bee699e4c378e68309ed97a1a6ab");
+        assert_eq!(hex::encode(&pbkdf2_sha256(b"password", b"salt", 4096, 32)), "0xc5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        assert_eq!(
+            hex::encode(&pbkdf2_sha512(b"password", b"mnemonic", 2048, 64)),
+            "0xe0fb520d01112056904730f762ad263b806953f1fe5b4662c97b1f301bb5f8628f9fb4e82f5906b33938a1fb6da4aa252a1293945fb9b0d2b6bc83c30693889d",
+        );
+    }
+
+    #[test]
+    fn keccak_is_not_sha3() {
+        assert_eq!(hex::encode(&keccak_256(b"")), "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+        assert_eq!(hex::encode(&keccak_256(b"abc")), "0x4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45");
+    }
+
+    #[test]
+    fn blake3_matches_the_reference() {
+        assert_eq!(hex::encode(&blake3_256(b"abc")), "0x6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        // More than two chunks, so parent nodes are hashed
+        assert_eq!(hex::encode(&blake3_256(&[7; 2049])), "0x627f256058ef1450962e5582d4826e786ed3ec08ef4090f08784f49993e2f770");
+    }
+}
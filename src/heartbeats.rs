@@ -0,0 +1,87 @@
+//! Audits the current session's `ImOnline` heartbeats: which validators have shown
+//! they are online, by a heartbeat or an authored block, and which have not yet.
+//!
+//! A validator with neither by the end of the session is reported offline and, with
+//! enough others, slashed. Heartbeats are indexed by position in the session's
+//! validator set, authored blocks by validator id.
+
+use serde_json::{json, Value};
+
+use crate::metadata;
+use crate::rpc::Connection;
+use crate::scale::Input;
+use crate::{clock, epoch, ss58, storage};
+
+/// The generic Substrate prefix, for runtimes that don't declare theirs.
+const DEFAULT_SS58_PREFIX: u16 = 42;
+
+pub async fn audit(socket: &mut Connection, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.storage_entry("ImOnline", "ReceivedHeartbeats").is_none() {
+        return Err("ImOnline pallet not found, the runtime does not track heartbeats".into());
+    }
+    let prefix = metadata.constant_u64("System", "SS58Prefix").map_or(DEFAULT_SS58_PREFIX, |prefix| prefix as u16);
+    let session = storage::read_u64(socket, &metadata, "Session", "CurrentIndex", &[], at).await?.ok_or("Session::CurrentIndex not found")? as u32;
+    let validators = match storage::read_raw(socket, &metadata, "Session", "Validators", &[], at).await? {
+        Some(bytes) => Input::new(&bytes).read_list(|input| input.read_array::<32>())?,
+        None => Vec::new(),
+    };
+    let block = crate::fetch_header_number(socket, at).await?;
+    let heartbeat_after = storage::read_u64(socket, &metadata, "ImOnline", "HeartbeatAfter", &[], at).await?;
+
+    let session_key = session.to_le_bytes().to_vec();
+    let mut keys = Vec::new();
+    for (index, validator) in validators.iter().enumerate() {
+        keys.push(metadata.storage_key("ImOnline", "ReceivedHeartbeats", &[session_key.clone(), (index as u32).to_le_bytes().to_vec()])?);
+        keys.push(metadata.storage_key("ImOnline", "AuthoredBlocks", &[session_key.clone(), validator.to_vec()])?);
+    }
+    let mut values = Vec::new();
+    for chunk in keys.chunks(storage::MAX_PAGE_SIZE) {
+        values.extend(storage::query_many(socket, chunk, at).await?);
+    }
+
+    let mut reports = Vec::new();
+    for ((index, validator), values) in validators.iter().enumerate().zip(values.chunks(2)) {
+        let heartbeat = values[0].is_some();
+        let authored = values[1].as_deref().map(|bytes| Input::new(bytes).read_u32()).transpose()?.unwrap_or(0);
+        reports.push(json!({
+            "index": index,
+            "validator": ss58::encode(prefix, validator),
+            "heartbeat": heartbeat,
+            "authored_blocks": authored,
+            "online": heartbeat || authored > 0,
+        }));
+    }
+    // The validators still to prove they are online come first
+    reports.sort_by_key(|report| (report["online"].as_bool(), report["heartbeat"].as_bool()));
+    let pending = reports.iter().filter(|report| report["online"] == false).count();
+
+    // Sessions follow BABE epochs, so the session ends when the epoch does
+    let remaining = match epoch::read_state(socket, &metadata, at).await {
+        Ok(state) => {
+            let slots = state.next_start_slot().saturating_sub(state.current_slot);
+            let end = state.slot_to_millis(state.next_start_slot());
+            json!({
+                "slots": slots,
+                "ends_at": clock::format_unix_millis(end),
+                "ends_in": clock::format_duration(slots * state.slot_duration_ms / 1000),
+            })
+        }
+        Err(_) => Value::Null,
+    };
+
+    Ok(json!({
+        "session": session,
+        "block": block,
+        "heartbeat_after": heartbeat_after,
+        "heartbeats_open": heartbeat_after.is_none_or(|after| block >= after),
+        "session_remaining": remaining,
+        "validators": validators.len(),
+        "online": validators.len() - pending,
+        "pending": pending,
+        "heartbeats": reports.iter().filter(|report| report["heartbeat"] == true).count(),
+        "report": reports,
+    }))
+}
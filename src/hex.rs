@@ -0,0 +1,58 @@
+/// Encodes bytes as a `0x`-prefixed lowercase hex string.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    hex.push_str("0x");
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+/// Decodes a hex string, with or without the `0x` prefix.
+pub fn decode(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim();
+    let digits = hex.strip_prefix("0x").unwrap_or(hex).as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in '{}'", hex));
+    }
+    let value = |digit: u8| (digit as char).to_digit(16).map(|value| value as u8);
+    digits.chunks(2)
+        .map(|pair| match (value(pair[0]), value(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(format!("invalid hex '{}'", hex)),
+        })
+        .collect()
+}
+
+/// Accepts either `0x`-prefixed hex or a plain string (taken as its UTF-8 bytes) and
+/// returns the hex form RPC methods expect.
+pub fn from_hex_or_string(input: &str) -> Result<String, String> {
+    if input.starts_with("0x") {
+        decode(input).map(|bytes| encode(&bytes))
+    } else {
+        Ok(encode(input.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(decode("0x00ff7A").unwrap(), vec![0x00, 0xff, 0x7a]);
+        assert_eq!(decode(" abcd ").unwrap(), vec![0xab, 0xcd]);
+        assert_eq!(decode("0x").unwrap(), Vec::<u8>::new());
+        assert_eq!(encode(&[0x00, 0xff, 0x7a]), "0x00ff7a");
+    }
+
+    #[test]
+    fn rejects_what_is_not_hex() {
+        assert!(decode("0xaé0").is_err());
+        assert!(decode("0xé0").is_err());
+        assert!(decode("0x+1").is_err());
+        assert!(decode("0x-1").is_err());
+        assert!(decode("0x0x00").is_err());
+        assert!(decode("0x123").is_err());
+    }
+}
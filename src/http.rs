@@ -0,0 +1,190 @@
+//! JSON-RPC over HTTP POST, for providers that rate-limit or block WebSockets but take
+//! plain requests. Each call (or batch) is one POST; connections are kept alive and
+//! reused, one request at a time each, so concurrent calls open more of them.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+
+use http::header::{HeaderMap, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, TRANSFER_ENCODING, UPGRADE};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+use url::Url;
+
+use crate::{config, failure};
+
+type Stream = BufReader<MaybeTlsStream<TcpStream>>;
+
+/// Whether `url` is called over HTTP rather than a WebSocket.
+pub fn is_http(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+pub struct Http {
+    url: Url,
+    headers: HeaderMap,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    max_response_size: Option<usize>,
+    // Kept-alive connections waiting for the next request
+    idle: Mutex<Vec<Stream>>,
+}
+
+/// Opens a connection to the first of a chain's preferred endpoints that accepts one,
+/// with the profile's authentication applied to every request.
+pub async fn connect(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<Http, Box<dyn std::error::Error>> {
+    if config::pin_peer().is_some() {
+        return Err("--pin-peer needs a WebSocket endpoint, HTTP requests may each reach another backend".into());
+    }
+    let urls = config::endpoints(endpoint);
+    for (index, url) in urls.iter().enumerate() {
+        let (ipv4, ipv6) = config::resolve_for(url, ipv4.copied(), ipv6.copied());
+        let connected = Http::open(url, ipv4, ipv6, max_response_size).await;
+        // Fall back to the next preferred endpoint, the last one's failure is reported
+        if connected.is_ok() || index + 1 == urls.len() {
+            return connected.map_err(failure::connect);
+        }
+    }
+    unreachable!("an endpoint argument stands for at least one URL")
+}
+
+impl Http {
+    /// Connects once up front, so an unreachable endpoint fails here like a WebSocket would.
+    async fn open(url: &str, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>, max_response_size: Option<usize>) -> Result<Http, Box<dyn std::error::Error>> {
+        let request = config::authorize(url)?;
+        // The authorized request is a WebSocket upgrade, keep only what the profile added
+        let headers = request.headers().iter()
+            .filter(|(name, _)| ![HOST, CONNECTION, UPGRADE].contains(name) && !name.as_str().starts_with("sec-websocket-"))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let http = Http {
+            url: Url::parse(&request.uri().to_string())?,
+            headers,
+            ipv4,
+            ipv6,
+            max_response_size,
+            idle: Mutex::new(Vec::new()),
+        };
+        let stream = http.open_stream().await?;
+        http.idle.lock().unwrap().push(stream);
+        Ok(http)
+    }
+
+    async fn open_stream(&self) -> Result<Stream, Box<dyn std::error::Error>> {
+        Ok(BufReader::new(crate::open_stream(&self.url, self.ipv4, self.ipv6).await?))
+    }
+
+    /// Posts `body` and returns the endpoint's answer. Anything but a 2xx status fails with
+    /// the status, headers and body the endpoint sent.
+    pub async fn post(&self, body: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let reused = self.idle.lock().unwrap().pop();
+        // A kept-alive connection may have been closed by the endpoint since, which only
+        // shows when it ends without an answer, then the request goes out on a new one
+        let answer = match reused {
+            Some(stream) => self.exchange(stream, body).await?,
+            None => None,
+        };
+        let (response, stream) = match answer {
+            Some(answer) => answer,
+            None => {
+                let stream = match self.open_stream().await {
+                    Ok(stream) => stream,
+                    Err(e) => return Err(failure::connect(e)),
+                };
+                let answer = self.exchange(stream, body).await?;
+                answer.ok_or_else(|| failure::ConnectionFailure::new("closed", "Connection closed before receiving response"))?
+            }
+        };
+        if let Some(stream) = stream {
+            self.idle.lock().unwrap().push(stream);
+        }
+        if !response.status().is_success() {
+            return Err(Box::new(failure::rejected("http", "Request", &response)));
+        }
+        Ok(String::from_utf8(response.into_body().unwrap_or_default())?)
+    }
+
+    /// Sends one request on `stream` and reads the answer, with the stream back if it can
+    /// be reused. `None` if the connection closed before answering.
+    async fn exchange(&self, mut stream: Stream, body: &str) -> Result<Option<(http::Response<Option<Vec<u8>>>, Option<Stream>)>, Box<dyn std::error::Error>> {
+        let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\n", &self.url[url::Position::BeforePath..url::Position::AfterQuery], &self.url[url::Position::BeforeHost..url::Position::AfterPort]);
+        request.push_str(&format!("{}: application/json\r\n{}: {}\r\n", CONTENT_TYPE, CONTENT_LENGTH, body.len()));
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value.to_str()?));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        if stream.get_mut().write_all(request.as_bytes()).await.is_err() {
+            return Ok(None);
+        }
+        let mut status_line = String::new();
+        if stream.read_line(&mut status_line).await.unwrap_or_default() == 0 {
+            return Ok(None);
+        }
+        let mut parts = status_line.split_whitespace();
+        let version = parts.next().unwrap_or_default().to_string();
+        let status = parts.next().and_then(|status| status.parse::<u16>().ok()).ok_or_else(|| format!("Invalid HTTP status line '{}'", status_line.trim()))?;
+
+        let mut builder = http::Response::builder().status(status);
+        let mut headers = HeaderMap::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 {
+                return Err("Connection closed in the response headers".into());
+            }
+            let Some((name, value)) = line.trim_end().split_once(':') else { break };
+            headers.append(http::HeaderName::from_bytes(name.trim().as_bytes())?, value.trim().parse()?);
+        }
+        let header = |name: http::HeaderName| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default().to_ascii_lowercase();
+        let mut keep_alive = version == "HTTP/1.1" && header(CONNECTION) != "close";
+
+        let mut body = Vec::new();
+        if header(TRANSFER_ENCODING).contains("chunked") {
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await?;
+                let size = usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16)
+                    .map_err(|_| format!("Invalid chunk size '{}'", line.trim()))?;
+                if size == 0 {
+                    // Trailers end at an empty line
+                    while stream.read_line(&mut line).await? > 2 {
+                        line.clear();
+                    }
+                    break;
+                }
+                self.check_size(body.len() + size)?;
+                let start = body.len();
+                body.resize(start + size, 0);
+                stream.read_exact(&mut body[start..]).await?;
+                stream.read_line(&mut line).await?;
+            }
+        } else if let Ok(length) = header(CONTENT_LENGTH).parse::<usize>() {
+            self.check_size(length)?;
+            body.resize(length, 0);
+            stream.read_exact(&mut body).await?;
+        } else {
+            // Without a length the body runs to the end of the connection
+            keep_alive = false;
+            let limit = self.max_response_size.map_or(u64::MAX, |size| size as u64 + 1);
+            (&mut stream).take(limit).read_to_end(&mut body).await?;
+            self.check_size(body.len())?;
+        }
+        for (name, value) in &headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.body(Some(body))?;
+        Ok(Some((response, keep_alive.then_some(stream))))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), Box<dyn std::error::Error>> {
+        match self.max_response_size {
+            Some(max) if size > max => Err(format!("Response of at least {} bytes exceeds --max-response-size {}", size, max).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops the kept-alive connections.
+    pub fn close(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,22 @@
+//! The global cap on requests awaiting a response, across every socket and batch a run
+//! opens, set with `--max-in-flight`.
+
+use std::sync::OnceLock;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+static LIMIT: OnceLock<(Semaphore, u32)> = OnceLock::new();
+
+pub fn set_limit(limit: Option<u32>) {
+    if let Some(limit) = limit.filter(|limit| *limit > 0) {
+        let _ = LIMIT.set((Semaphore::new(limit as usize), limit));
+    }
+}
+
+/// Waits until `count` more requests may be in flight; the slots are released when the
+/// permit is dropped. A batch larger than the cap takes every slot rather than waiting
+/// forever. Without a cap this returns immediately.
+pub async fn acquire(count: usize) -> Option<SemaphorePermit<'static>> {
+    let (semaphore, limit) = LIMIT.get()?;
+    semaphore.acquire_many((count as u32).clamp(1, *limit)).await.ok()
+}
@@ -0,0 +1,74 @@
+use std::io::{self, Write};
+
+/// Pretty-prints a raw JSON document to `out` without building a `serde_json::Value` tree.
+///
+/// The output matches `serde_json::to_string_pretty`, so large results (`state_getPairs`,
+/// traces, metadata) print the same as small ones while only the raw text is held in memory.
+pub fn write_pretty<W: Write>(raw: &str, out: &mut W) -> io::Result<()> {
+    let bytes = raw.as_bytes();
+    let mut indent = 0usize;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        match b {
+            b'"' => {
+                // Copy the whole string in one go, it is the bulk of most payloads
+                let start = i;
+                let mut in_string = true;
+                let mut escaped = false;
+                i += 1;
+                while i < bytes.len() && in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if bytes[i] == b'\\' {
+                        escaped = true;
+                    } else if bytes[i] == b'"' {
+                        in_string = false;
+                    }
+                    i += 1;
+                }
+                out.write_all(&bytes[start..i])?;
+                continue;
+            }
+            b'{' | b'[' => {
+                let close = if b == b'{' { b'}' } else { b']' };
+                let next = next_significant(bytes, i + 1);
+                if next.map(|j| bytes[j]) == Some(close) {
+                    out.write_all(&[b, close])?;
+                    i = next.unwrap_or(i) + 1;
+                    continue;
+                }
+                indent += 1;
+                out.write_all(&[b, b'\n'])?;
+                write_indent(out, indent)?;
+            }
+            b'}' | b']' => {
+                indent = indent.saturating_sub(1);
+                out.write_all(b"\n")?;
+                write_indent(out, indent)?;
+                out.write_all(&[b])?;
+            }
+            b',' => {
+                out.write_all(b",\n")?;
+                write_indent(out, indent)?;
+            }
+            b':' => out.write_all(b": ")?,
+            b' ' | b'\n' | b'\r' | b'\t' => {}
+            _ => out.write_all(&[b])?,
+        }
+        i += 1;
+    }
+    out.write_all(b"\n")
+}
+
+fn next_significant(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len()).find(|&j| !matches!(bytes[j], b' ' | b'\n' | b'\r' | b'\t'))
+}
+
+fn write_indent<W: Write>(out: &mut W, indent: usize) -> io::Result<()> {
+    for _ in 0..indent {
+        out.write_all(b"  ")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,392 @@
+//! GRANDPA justifications: the precommits of a finalized block, decoded and optionally
+//! checked against the authority set, as proof that the block is final rather than
+//! merely reported so by the node.
+//!
+//! A justification is `(round, Commit { target_hash, target_number, precommits },
+//! votes_ancestries)`. Each precommit votes for the target or one of its descendants and
+//! is signed over `(Message::Precommit(vote), round, set_id)`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+use ed25519_dalek::Verifier;
+use serde_json::{json, Value};
+
+use crate::hashing::blake2_256;
+use crate::metadata;
+use crate::rpc::Connection;
+use crate::scale::Input;
+use crate::verify::{self, Level};
+use crate::{checkpoints, digest, hex};
+
+const ENGINE: &str = "FRNK";
+
+/// Most headers walked from a justification's target back to the block it finalizes.
+const MAX_LINK: u64 = 16_384;
+
+/// Blocks already proven final this run, by lowercase hash.
+static FINALIZED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The last authority set followed this run, so later blocks need not start over from the
+/// checkpoint.
+static TRUSTED: Mutex<Option<TrustedSet>> = Mutex::new(None);
+
+/// Headers by hash: (parent, number).
+type Ancestry = HashMap<[u8; 32], ([u8; 32], u64)>;
+
+/// A GRANDPA authority set known to be genuine: read from the proven state of a
+/// checkpoint, or of the block enacting it once the set before proved that block final.
+#[derive(Clone)]
+struct TrustedSet {
+    set_id: u64,
+    authorities: HashMap<[u8; 32], u64>,
+    // The block whose state holds the set, which votes on its descendants
+    block: u64,
+}
+
+struct Precommit {
+    target_hash: [u8; 32],
+    target_number: u32,
+    signature: [u8; 64],
+    id: [u8; 32],
+}
+
+struct Justification {
+    round: u64,
+    target_hash: [u8; 32],
+    target_number: u32,
+    precommits: Vec<Precommit>,
+    // Headers of the blocks between the target and the votes
+    ancestry: Ancestry,
+}
+
+/// Whether `signature` is the ed25519 signature of `message` by `public`.
+fn verify_ed25519(public: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    ed25519_dalek::VerifyingKey::from_bytes(public)
+        .is_ok_and(|key| key.verify(message, &ed25519_dalek::Signature::from_bytes(signature)).is_ok())
+}
+
+/// Reads a header, returning its hash, parent hash and number.
+fn read_header(input: &mut Input) -> Result<([u8; 32], [u8; 32], u64), String> {
+    let rest = input.read_bytes(input.remaining())?;
+    let mut header = Input::new(rest);
+    let parent = header.read_array::<32>()?;
+    let number = header.read_compact()? as u64;
+    header.read_bytes(64)?;
+    header.read_list(digest::decode_item)?;
+    let len = rest.len() - header.remaining();
+    let hash: [u8; 32] = blake2_256(&rest[..len]).try_into().unwrap();
+    *input = Input::new(&rest[len..]);
+    Ok((hash, parent, number))
+}
+
+fn decode(bytes: &[u8]) -> Result<Justification, String> {
+    let mut input = Input::new(bytes);
+    let round = input.read_u64()?;
+    let target_hash = input.read_array::<32>()?;
+    let target_number = input.read_u32()?;
+    let precommits = input.read_list(|input| Ok(Precommit {
+        target_hash: input.read_array()?,
+        target_number: input.read_u32()?,
+        signature: input.read_array()?,
+        id: input.read_array()?,
+    }))?;
+    let ancestry = input.read_list(|input| read_header(input).map(|(hash, parent, number)| (hash, (parent, number))))?;
+    if input.remaining() > 0 {
+        return Err(format!("{} trailing bytes after the justification", input.remaining()));
+    }
+    Ok(Justification { round, target_hash, target_number, precommits, ancestry: ancestry.into_iter().collect() })
+}
+
+impl Justification {
+    /// Whether `hash` is the commit target or descends from it through the ancestry headers.
+    fn descends_from_target(&self, mut hash: [u8; 32]) -> bool {
+        while hash != self.target_hash {
+            match self.ancestry.get(&hash) {
+                Some((parent, number)) if *number > self.target_number as u64 => hash = *parent,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// What a precommit signs: `(Message::Precommit(vote), round, set_id)`.
+    fn signed_message(&self, precommit: &Precommit, set_id: u64) -> Vec<u8> {
+        let mut message = vec![1];
+        message.extend_from_slice(&precommit.target_hash);
+        message.extend(precommit.target_number.to_le_bytes());
+        message.extend(self.round.to_le_bytes());
+        message.extend(set_id.to_le_bytes());
+        message
+    }
+}
+
+/// The GRANDPA justification that finalizes block `number` (with hash `hash`): its own when
+/// it carries one, as blocks ending an authority set do, else the one from the
+/// `grandpa_proveFinality` proof, which belongs to a later block and comes with the headers
+/// leading up to it.
+async fn fetch_justification(socket: &mut Connection, hash: &str, number: u64) -> Result<(Vec<u8>, &'static str, Ancestry), Box<dyn std::error::Error>> {
+    let block = crate::send_and_receive(socket, "chain_getBlock", json!([hash])).await?;
+    let own = block["justifications"].as_array().into_iter().flatten()
+        .find(|entry| entry[0].as_array().is_some_and(|engine| engine.iter().filter_map(Value::as_u64).map(|byte| byte as u8).eq(ENGINE.bytes())))
+        .and_then(|entry| entry[1].as_str());
+    if let Some(justification) = own {
+        return Ok((hex::decode(justification)?, "block", Ancestry::new()));
+    }
+    let proof = crate::send_and_receive(socket, "grandpa_proveFinality", json!([number])).await?;
+    let proof = proof.as_str().ok_or("grandpa_proveFinality returned no proof, the block may not be finalized yet")?;
+    // `FinalityProof { block, justification, unknown_headers }`
+    let bytes = hex::decode(proof)?;
+    let mut input = Input::new(&bytes);
+    input.read_bytes(32)?;
+    let justification = input.read_vec()?.to_vec();
+    let unknown_headers = input.read_list(|input| read_header(input).map(|(hash, parent, number)| (hash, (parent, number))))?;
+    Ok((justification, "grandpa_proveFinality", unknown_headers.into_iter().collect()))
+}
+
+/// The GRANDPA set id and weighted authorities in the state of block `parent`, from read
+/// proofs under `--verify proofs` and up.
+async fn authority_set(socket: &mut Connection, parent: &str) -> Result<(u64, HashMap<[u8; 32], u64>), Box<dyn std::error::Error>> {
+    let metadata = metadata::fetch(socket, Some(parent)).await?;
+    let set_id_key = metadata.storage_key("Grandpa", "CurrentSetId", &[])?;
+    let (set_id, authorities) = if verify::level() >= Level::Proofs {
+        // Older runtimes keep the set under a well-known key, behind a version byte
+        let (authorities_key, versioned) = match metadata.storage_entry("Grandpa", "Authorities") {
+            Some(_) => (metadata.storage_key("Grandpa", "Authorities", &[])?, false),
+            None => (hex::encode(b":grandpa_authorities"), true),
+        };
+        let mut values = verify::read_proof(socket, &[set_id_key, authorities_key], parent).await?;
+        let authorities = values.pop().flatten().ok_or("The proven state holds no GRANDPA authority set")?;
+        let authorities = if versioned { authorities.get(1..).unwrap_or_default().to_vec() } else { authorities };
+        (values.pop().flatten(), authorities)
+    } else {
+        let set_id = crate::send_and_receive(socket, "state_getStorage", json!([set_id_key, parent])).await?;
+        let encoded = crate::send_and_receive(socket, "state_call", json!(["GrandpaApi_grandpa_authorities", "0x", parent])).await?;
+        let authorities = hex::decode(encoded.as_str().ok_or("GrandpaApi_grandpa_authorities returned nothing")?)?;
+        (set_id.as_str().map(hex::decode).transpose()?, authorities)
+    };
+    let set_id = match set_id {
+        Some(bytes) => Input::new(&bytes).read_u64()?,
+        None => 0,
+    };
+    let authorities = Input::new(&authorities).read_list(|input| Ok((input.read_array::<32>()?, input.read_u64()?)))?;
+    Ok((set_id, authorities.into_iter().collect()))
+}
+
+/// Checks every precommit against `authorities`, the set with id `set_id`.
+fn tally(justification: &Justification, set_id: u64, authorities: &HashMap<[u8; 32], u64>, precommits: &mut [Value]) -> Value {
+    let total: u64 = authorities.values().sum();
+    let threshold = total - total.saturating_sub(1) / 3;
+    let mut voted = HashSet::new();
+    let mut signed_weight = 0;
+    for (precommit, record) in justification.precommits.iter().zip(precommits.iter_mut()) {
+        let problem = if !authorities.contains_key(&precommit.id) {
+            Some("not in the authority set")
+        } else if !justification.descends_from_target(precommit.target_hash) {
+            Some("votes for a block that does not descend from the target")
+        } else if !verify_ed25519(&precommit.id, &justification.signed_message(precommit, set_id), &precommit.signature) {
+            Some("invalid signature")
+        } else if !voted.insert(precommit.id) {
+            Some("duplicate vote")
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => record["invalid"] = json!(problem),
+            None => signed_weight += authorities[&precommit.id],
+        }
+        record["valid"] = json!(problem.is_none());
+    }
+    json!({
+        "set_id": set_id,
+        "authorities": authorities.len(),
+        "total_weight": total,
+        "threshold": threshold,
+        "signed_weight": signed_weight,
+        "valid_precommits": voted.len(),
+        "finalized": signed_weight >= threshold,
+    })
+}
+
+/// The set in the proven state of the highest checkpoint at or below block `number`.
+async fn anchor(socket: &mut Connection, number: u64) -> Result<TrustedSet, Box<dyn std::error::Error>> {
+    let checkpoints = checkpoints::get().ok_or("--verify light needs --checkpoints to anchor the GRANDPA authority set")?;
+    let (height, hash) = checkpoints.at_or_below(number)
+        .ok_or_else(|| format!("No checkpoint at or below block {} to anchor the GRANDPA authority set", number))?;
+    let header = verify::header(socket, hash).await?;
+    if header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok()) != Some(height) {
+        return Err(format!("The header of checkpoint {} is not block {}", hash, height).into());
+    }
+    let (set_id, authorities) = authority_set(socket, hash).await?;
+    Ok(TrustedSet { set_id, authorities, block: height })
+}
+
+/// The set id the endpoint reports at block `number`, unproven.
+async fn set_id_at(socket: &mut Connection, key: &str, number: u64) -> Result<u64, Box<dyn std::error::Error>> {
+    let hash = crate::fetch_block_hash(socket, number).await?;
+    let value = crate::send_and_receive(socket, "state_getStorage", json!([key, hash])).await?;
+    Ok(match value.as_str() {
+        Some(value) => Input::new(&hex::decode(value)?).read_u64()?,
+        None => 0,
+    })
+}
+
+/// The set following `trusted`, enacted before block `below`. The endpoint is only asked
+/// where the change happened: the block found by bisection must be proven final by
+/// `trusted` itself and hold the next set id in its proven state.
+async fn next_set(socket: &mut Connection, trusted: &TrustedSet, below: u64) -> Result<TrustedSet, Box<dyn std::error::Error>> {
+    let hash = crate::fetch_block_hash(socket, trusted.block).await?;
+    let key = metadata::fetch(socket, Some(&hash)).await?.storage_key("Grandpa", "CurrentSetId", &[])?;
+    let (mut low, mut high) = (trusted.block, below);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if set_id_at(socket, &key, middle).await? > trusted.set_id {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+
+    let change = crate::fetch_block_hash(socket, high).await?;
+    let (bytes, _, known) = fetch_justification(socket, &change, high).await?;
+    let justification = decode(&bytes)?;
+    let chain = link(socket, &justification, known, &change, high).await?;
+    let mut precommits = vec![Value::Null; justification.precommits.len()];
+    if tally(&justification, trusted.set_id, &trusted.authorities, &mut precommits)["finalized"] != true {
+        return Err(format!("Authority set {} does not finalize block {}, where the endpoint says the next set begins", trusted.set_id, high).into());
+    }
+    let (set_id, authorities) = authority_set(socket, &change).await?;
+    if set_id != trusted.set_id + 1 {
+        return Err(format!("Block {} holds authority set {} rather than the {} following set {}", high, set_id, trusted.set_id + 1, trusted.set_id).into());
+    }
+    FINALIZED.lock().unwrap().extend(chain);
+    Ok(TrustedSet { set_id, authorities, block: high })
+}
+
+/// The trusted set `set_id` voting on the children of block `parent`, followed set by set
+/// from the last one trusted this run, or from the checkpoint below.
+async fn trusted_set(socket: &mut Connection, set_id: u64, parent: u64) -> Result<TrustedSet, Box<dyn std::error::Error>> {
+    let latest = TRUSTED.lock().unwrap().clone();
+    let mut trusted = match latest {
+        Some(latest) if latest.block <= parent && latest.set_id <= set_id => latest,
+        _ => anchor(socket, parent).await?,
+    };
+    if trusted.set_id > set_id {
+        return Err(format!("Block {} is voted on by authority set {}, older than set {} at checkpoint {}", parent + 1, set_id, trusted.set_id, trusted.block).into());
+    }
+    while trusted.set_id < set_id {
+        if trusted.block >= parent {
+            return Err(format!("The endpoint reports authority set {} at block {}, the proven state holds set {}", set_id, parent, trusted.set_id).into());
+        }
+        trusted = next_set(socket, &trusted, parent).await?;
+        *TRUSTED.lock().unwrap() = Some(trusted.clone());
+    }
+    Ok(trusted)
+}
+
+/// Checks every precommit against the authority set in the state of the target's parent,
+/// the set that voted on it even when the target itself enacts a new one. Under `--verify
+/// light` that set must also be reached from a checkpoint.
+async fn verify(socket: &mut Connection, justification: &Justification, precommits: &mut [Value]) -> Result<Value, Box<dyn std::error::Error>> {
+    let target = hex::encode(&justification.target_hash);
+    let header = verify::header(socket, &target).await?;
+    let parent = header["parentHash"].as_str().ok_or("Commit target header has no parent hash")?.to_string();
+    let (set_id, authorities) = authority_set(socket, &parent).await?;
+    if verify::level() >= Level::Light {
+        // The set id served for the parent only says which set to follow up to
+        let trusted = trusted_set(socket, set_id, (justification.target_number as u64).saturating_sub(1)).await?;
+        return Ok(tally(justification, trusted.set_id, &trusted.authorities, precommits));
+    }
+    Ok(tally(justification, set_id, &authorities, precommits))
+}
+
+/// The decoded GRANDPA justification finalizing `block` (a number or hash), with every
+/// precommit checked against the authority set when `check` is set.
+pub async fn justification(socket: &mut Connection, block: &str, check: bool) -> Result<Value, Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, Some(block)).await?;
+    let number = crate::fetch_header_number(socket, Some(&hash)).await?;
+    let (bytes, source, _) = fetch_justification(socket, &hash, number).await?;
+    let justification = decode(&bytes)?;
+
+    let mut precommits: Vec<Value> = justification.precommits.iter().map(|precommit| json!({
+        "authority": hex::encode(&precommit.id),
+        "target_hash": hex::encode(&precommit.target_hash),
+        "target_number": precommit.target_number,
+        "signature": hex::encode(&precommit.signature),
+    })).collect();
+    let mut result = json!({
+        "block_number": number,
+        "block_hash": hash,
+        "source": source,
+        "round": justification.round,
+        "target_hash": hex::encode(&justification.target_hash),
+        "target_number": justification.target_number,
+        "votes_ancestries": justification.ancestry.len(),
+        "justification": hex::encode(&bytes),
+    });
+    if check {
+        result["verification"] = verify(socket, &justification, &mut precommits).await?;
+    }
+    result["precommits"] = json!(precommits);
+    Ok(result)
+}
+
+/// Walks the headers back from the justification's target to block `number` (with hash
+/// `hash`), through `known` where it has them, returning the hashes passed.
+async fn link(socket: &mut Connection, justification: &Justification, mut known: Ancestry, hash: &str, number: u64) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let target_number = justification.target_number as u64;
+    if target_number < number {
+        return Err(format!("The justification served for block {} finalizes earlier block {}", number, target_number).into());
+    }
+    if target_number - number > MAX_LINK {
+        return Err(format!("The justification for block {} targets block {}, too far to link by headers", number, target_number).into());
+    }
+
+    let mut chain = vec![hex::encode(&justification.target_hash)];
+    let mut current = justification.target_hash;
+    let mut current_number = target_number;
+    while current_number > number {
+        let (parent, parent_number) = match known.remove(&current) {
+            Some((parent, header_number)) if header_number == current_number => (parent, header_number - 1),
+            _ => {
+                let header = verify::header(socket, &hex::encode(&current)).await?;
+                let parent = hex::decode(header["parentHash"].as_str().unwrap_or_default())?;
+                (parent.try_into().map_err(|_| "Invalid parent hash")?, current_number - 1)
+            }
+        };
+        current = parent;
+        current_number = parent_number;
+        chain.push(hex::encode(&current));
+    }
+    if !hex::encode(&current).eq_ignore_ascii_case(hash) {
+        return Err(format!("Block {} ({}) is not an ancestor of justification target {}", number, hash, target_number).into());
+    }
+    Ok(chain)
+}
+
+/// Proves block `hash` final under `--verify light`: a GRANDPA justification for it or a
+/// descendant must carry enough valid precommits of the authority set followed from a
+/// checkpoint, and the headers linking the two must hash to what they claim. Blocks proven
+/// once are not checked again.
+pub async fn prove_finalized(socket: &mut Connection, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = hash.to_lowercase();
+    if FINALIZED.lock().unwrap().contains(&hash) {
+        return Ok(());
+    }
+    let header = verify::header(socket, &hash).await?;
+    let number = u64::from_str_radix(header["number"].as_str().unwrap_or_default().trim_start_matches("0x"), 16)?;
+    let (bytes, _, known) = fetch_justification(socket, &hash, number).await?;
+    let justification = decode(&bytes)?;
+    let chain = link(socket, &justification, known, &hash, number).await?;
+
+    let mut precommits = vec![Value::Null; justification.precommits.len()];
+    let verification = verify(socket, &justification, &mut precommits).await?;
+    if verification["finalized"] != true {
+        return Err(format!(
+            "The justification for block {} carries {} of the {} authority weight needed",
+            number, verification["signed_weight"], verification["threshold"],
+        ).into());
+    }
+    FINALIZED.lock().unwrap().extend(chain);
+    Ok(())
+}
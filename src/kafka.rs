@@ -0,0 +1,440 @@
+//! A minimal Kafka producer for the `kafka://broker:9092/topic` sink: Metadata to find the
+//! partition leaders, then Produce v3 with `acks=all` and key-hashed partitioning that
+//! matches the Java client's default partitioner.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+const PRODUCE: i16 = 0;
+const METADATA: i16 = 3;
+const CLIENT_ID: &str = "gavel";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Partition {
+    index: i32,
+    leader: i32,
+}
+
+pub struct Producer {
+    bootstrap: String,
+    topic: String,
+    brokers: BTreeMap<i32, String>,
+    partitions: Vec<Partition>,
+    connections: BTreeMap<i32, TcpStream>,
+    correlation_id: i32,
+}
+
+fn put_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as i16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn put_varint(buffer: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        if zigzag < 0x80 {
+            buffer.push(zigzag as u8);
+            return;
+        }
+        buffer.push((zigzag as u8 & 0x7f) | 0x80);
+        zigzag >>= 7;
+    }
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { 0x82f63b78 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The Java client's murmur2, so gavel and other producers put a key on the same partition.
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1e995;
+    let mut h = 0x9747b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+/// Encodes `records` (key, value) as one v2 record batch.
+fn record_batch(records: &[&(String, String)]) -> Vec<u8> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let mut body = Vec::new();
+    body.extend_from_slice(&0i16.to_be_bytes()); // attributes: no compression
+    body.extend_from_slice(&(records.len() as i32 - 1).to_be_bytes()); // last offset delta
+    body.extend_from_slice(&now.to_be_bytes());
+    body.extend_from_slice(&now.to_be_bytes());
+    body.extend_from_slice(&(-1i64).to_be_bytes()); // producer id
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // producer epoch
+    body.extend_from_slice(&(-1i32).to_be_bytes()); // base sequence
+    body.extend_from_slice(&(records.len() as i32).to_be_bytes());
+    for (offset, (key, value)) in records.iter().enumerate() {
+        let mut record = vec![0u8]; // attributes
+        put_varint(&mut record, 0); // timestamp delta
+        put_varint(&mut record, offset as i64);
+        put_varint(&mut record, key.len() as i64);
+        record.extend_from_slice(key.as_bytes());
+        put_varint(&mut record, value.len() as i64);
+        record.extend_from_slice(value.as_bytes());
+        put_varint(&mut record, 0); // headers
+        put_varint(&mut body, record.len() as i64);
+        body.extend_from_slice(&record);
+    }
+
+    let mut batch = Vec::new();
+    batch.extend_from_slice(&0i64.to_be_bytes()); // base offset
+    batch.extend_from_slice(&(body.len() as i32 + 9).to_be_bytes()); // length after this field
+    batch.extend_from_slice(&(-1i32).to_be_bytes()); // partition leader epoch
+    batch.push(2); // magic
+    batch.extend_from_slice(&crc32c(&body).to_be_bytes());
+    batch.extend_from_slice(&body);
+    batch
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.data.len() < len {
+            return Err("Truncated Kafka response".into());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(String::new());
+        }
+        Ok(String::from_utf8_lossy(self.take(len as usize)?).to_string())
+    }
+}
+
+impl Producer {
+    /// Connects to the bootstrap broker in `url` and looks up the topic's partitions.
+    pub fn connect(url: &Url) -> Result<Producer, Box<dyn std::error::Error>> {
+        let host = url.host_str().ok_or("Missing broker host in the Kafka URL")?;
+        let topic = url.path().trim_start_matches('/');
+        if topic.is_empty() {
+            return Err("Missing topic in the Kafka URL, expected kafka://broker:9092/topic".into());
+        }
+        let mut producer = Producer {
+            bootstrap: format!("{}:{}", host, url.port().unwrap_or(9092)),
+            topic: topic.to_string(),
+            brokers: BTreeMap::new(),
+            partitions: Vec::new(),
+            connections: BTreeMap::new(),
+            correlation_id: 0,
+        };
+        producer.refresh_metadata()?;
+        Ok(producer)
+    }
+
+    fn request(&mut self, broker: i32, api_key: i16, api_version: i16, body: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.correlation_id += 1;
+        let mut message = Vec::new();
+        message.extend_from_slice(&api_key.to_be_bytes());
+        message.extend_from_slice(&api_version.to_be_bytes());
+        message.extend_from_slice(&self.correlation_id.to_be_bytes());
+        put_string(&mut message, CLIENT_ID);
+        message.extend_from_slice(body);
+
+        if !self.connections.contains_key(&broker) {
+            let address = match self.brokers.get(&broker) {
+                Some(address) => address.clone(),
+                None => self.bootstrap.clone(),
+            };
+            let stream = TcpStream::connect(&address).map_err(|e| format!("Failed to connect to Kafka broker {}: {}", address, e))?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            self.connections.insert(broker, stream);
+        }
+        let stream = self.connections.get_mut(&broker).unwrap();
+        let result = (|| -> std::io::Result<Vec<u8>> {
+            stream.write_all(&(message.len() as i32).to_be_bytes())?;
+            stream.write_all(&message)?;
+            let mut size = [0u8; 4];
+            stream.read_exact(&mut size)?;
+            let mut response = vec![0u8; i32::from_be_bytes(size).max(0) as usize];
+            stream.read_exact(&mut response)?;
+            Ok(response)
+        })();
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                self.connections.remove(&broker);
+                return Err(e.into());
+            }
+        };
+        // Skip the correlation id of the response header
+        Ok(response.get(4..).ok_or("Truncated Kafka response")?.to_vec())
+    }
+
+    fn refresh_metadata(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1i32.to_be_bytes());
+        put_string(&mut body, &self.topic);
+        let broker = self.brokers.keys().next().copied().unwrap_or(-1);
+        let response = self.request(broker, METADATA, 1, &body)?;
+
+        let mut reader = Reader { data: &response };
+        let mut brokers = BTreeMap::new();
+        for _ in 0..reader.i32()? {
+            let node_id = reader.i32()?;
+            let host = reader.string()?;
+            let port = reader.i32()?;
+            reader.string()?; // rack
+            brokers.insert(node_id, format!("{}:{}", host, port));
+        }
+        reader.i32()?; // controller id
+        let mut partitions = Vec::new();
+        for _ in 0..reader.i32()? {
+            let error = reader.i16()?;
+            let name = reader.string()?;
+            reader.take(1)?; // is internal
+            if error != 0 {
+                return Err(format!("Kafka topic {} is unavailable (error code {})", name, error).into());
+            }
+            for _ in 0..reader.i32()? {
+                reader.i16()?;
+                let index = reader.i32()?;
+                let leader = reader.i32()?;
+                let replicas = reader.i32()?;
+                reader.take(replicas.max(0) as usize * 4)?;
+                let isr = reader.i32()?;
+                reader.take(isr.max(0) as usize * 4)?;
+                partitions.push(Partition { index, leader });
+            }
+        }
+        if partitions.is_empty() {
+            return Err(format!("Kafka topic {} has no partitions", self.topic).into());
+        }
+        partitions.sort_by_key(|partition| partition.index);
+        self.brokers = brokers;
+        self.partitions = partitions;
+        // Connections made before the broker ids were known are keyed by -1
+        self.connections.remove(&-1);
+        Ok(())
+    }
+
+    /// Produces `records` (key, value) with `acks=all`, returning once every partition
+    /// leader has acknowledged them.
+    pub fn send(&mut self, records: &[(String, String)]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut by_partition: BTreeMap<usize, Vec<&(String, String)>> = BTreeMap::new();
+        for record in records {
+            let partition = (murmur2(record.0.as_bytes()) & 0x7fffffff) as usize % self.partitions.len();
+            by_partition.entry(partition).or_default().push(record);
+        }
+        for (partition, records) in by_partition {
+            let Partition { index, leader } = self.partitions[partition];
+            let batch = record_batch(&records);
+            let mut body = Vec::new();
+            body.extend_from_slice(&(-1i16).to_be_bytes()); // no transactional id
+            body.extend_from_slice(&(-1i16).to_be_bytes()); // acks: all in-sync replicas
+            body.extend_from_slice(&(TIMEOUT.as_millis() as i32).to_be_bytes());
+            body.extend_from_slice(&1i32.to_be_bytes());
+            put_string(&mut body, &self.topic);
+            body.extend_from_slice(&1i32.to_be_bytes());
+            body.extend_from_slice(&index.to_be_bytes());
+            body.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+            body.extend_from_slice(&batch);
+
+            let response = self.request(leader, PRODUCE, 3, &body)?;
+            let mut reader = Reader { data: &response };
+            reader.i32()?; // topics
+            reader.string()?;
+            reader.i32()?; // partitions
+            reader.i32()?;
+            let error = reader.i16()?;
+            if error != 0 {
+                // Leadership may have moved, look it up again before the retry
+                let _ = self.refresh_metadata();
+                return Err(format!("Kafka rejected the records for partition {} (error code {})", index, error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn hashes_keys_as_the_java_client_does() {
+        // Kafka's UtilsTest.testMurmur2
+        for (key, hash) in [
+            (&b"21"[..], -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8", -58897971),
+            (b"abc", 479470107),
+        ] {
+            assert_eq!(murmur2(key) as i32, hash, "{}", String::from_utf8_lossy(key));
+        }
+    }
+
+    #[test]
+    fn encodes_varints_and_checksums() {
+        for (value, encoded) in [(0, &[0x00][..]), (-1, &[0x01]), (1, &[0x02]), (63, &[0x7e]), (-64, &[0x7f]), (64, &[0x80, 0x01]), (300, &[0xd8, 0x04])] {
+            let mut buffer = Vec::new();
+            put_varint(&mut buffer, value);
+            assert_eq!(buffer, encoded, "{}", value);
+        }
+        // The CRC-32C check value
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn encodes_v2_record_batches() {
+        let records = [("k".to_string(), "v".to_string()), ("key".to_string(), "value".to_string())];
+        let batch = record_batch(&records.iter().collect::<Vec<_>>());
+        assert_eq!(batch[..8], 0i64.to_be_bytes());
+        assert_eq!(i32::from_be_bytes(batch[8..12].try_into().unwrap()) as usize, batch.len() - 12);
+        assert_eq!(batch[12..16], (-1i32).to_be_bytes());
+        assert_eq!(batch[16], 2);
+        assert_eq!(u32::from_be_bytes(batch[17..21].try_into().unwrap()), crc32c(&batch[21..]));
+        assert_eq!(batch[21..23], 0i16.to_be_bytes());
+        assert_eq!(batch[23..27], 1i32.to_be_bytes());
+        assert_eq!(batch[27..35], batch[35..43]);
+        assert_eq!(batch[43..57], [0xff; 14]);
+        assert_eq!(batch[57..61], 2i32.to_be_bytes());
+        // Length, attributes, timestamp and offset deltas, key, value, no headers
+        assert_eq!(batch[61..], [
+            0x10, 0, 0, 0, 0x02, b'k', 0x02, b'v', 0,
+            0x1c, 0, 0, 0x02, 0x06, b'k', b'e', b'y', 0x0a, b'v', b'a', b'l', b'u', b'e', 0,
+        ]);
+    }
+
+    /// Answers the requests of one connection with `answer`, passing each on as
+    /// (api key, version, client id, body), until the client hangs up.
+    fn serve(stream: &mut TcpStream, requests: &mpsc::Sender<(i16, i16, String, Vec<u8>)>, answer: &dyn Fn(i16, &[u8]) -> Vec<u8>) {
+        let mut size = [0u8; 4];
+        while stream.read_exact(&mut size).is_ok() {
+            let mut message = vec![0u8; i32::from_be_bytes(size) as usize];
+            stream.read_exact(&mut message).unwrap();
+            let mut reader = Reader { data: &message };
+            let (api_key, version) = (reader.i16().unwrap(), reader.i16().unwrap());
+            let correlation_id = reader.i32().unwrap();
+            let client_id = reader.string().unwrap();
+            let mut response = correlation_id.to_be_bytes().to_vec();
+            response.extend(answer(api_key, reader.data));
+            stream.write_all(&(response.len() as i32).to_be_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+            requests.send((api_key, version, client_id, reader.data.to_vec())).unwrap();
+        }
+    }
+
+    #[test]
+    fn produces_to_the_partition_leaders() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            let answer = |api_key: i16, _: &[u8]| -> Vec<u8> {
+                let mut response = Vec::new();
+                if api_key == METADATA {
+                    // One broker leading both partitions of the topic
+                    response.extend(1i32.to_be_bytes());
+                    response.extend(7i32.to_be_bytes());
+                    put_string(&mut response, "127.0.0.1");
+                    response.extend((port as i32).to_be_bytes());
+                    response.extend((-1i16).to_be_bytes());
+                    response.extend(7i32.to_be_bytes());
+                    response.extend(1i32.to_be_bytes());
+                    response.extend(0i16.to_be_bytes());
+                    put_string(&mut response, "heads");
+                    response.push(0);
+                    response.extend(2i32.to_be_bytes());
+                    for index in [1i32, 0] {
+                        response.extend(0i16.to_be_bytes());
+                        response.extend(index.to_be_bytes());
+                        response.extend(7i32.to_be_bytes());
+                        response.extend([1i32, 7, 1, 7].iter().flat_map(|value| value.to_be_bytes()));
+                    }
+                } else {
+                    response.extend(1i32.to_be_bytes());
+                    put_string(&mut response, "heads");
+                    response.extend(1i32.to_be_bytes());
+                    response.extend(0i32.to_be_bytes());
+                    response.extend(0i16.to_be_bytes());
+                    response.extend([0u8; 16]);
+                    response.extend(0i32.to_be_bytes());
+                }
+                response
+            };
+            for stream in listener.incoming() {
+                serve(&mut stream.unwrap(), &sender, &answer);
+            }
+        });
+
+        let mut producer = Producer::connect(&Url::parse(&format!("kafka://127.0.0.1:{}/heads", port)).unwrap()).unwrap();
+        assert_eq!(producer.partitions.iter().map(|partition| (partition.index, partition.leader)).collect::<Vec<_>>(), [(0, 7), (1, 7)]);
+        let (api_key, version, client_id, body) = requests.recv().unwrap();
+        assert_eq!((api_key, version, client_id.as_str()), (METADATA, 1, CLIENT_ID));
+        assert_eq!(body, [&1i32.to_be_bytes()[..], &5i16.to_be_bytes(), b"heads"].concat());
+
+        let records: Vec<(String, String)> = (0..6).map(|i| (format!("0x{:064x}", i), format!("{{\"number\":{}}}", i))).collect();
+        let expected = |partition: i32| records.iter().filter(|(key, _)| (murmur2(key.as_bytes()) & 0x7fffffff) % 2 == partition as u32).count() as i32;
+        let expected: Vec<_> = [0, 1].into_iter().map(|partition| (partition, expected(partition))).filter(|(_, count)| *count > 0).collect();
+        producer.send(&records).unwrap();
+        let mut produced = Vec::new();
+        for _ in &expected {
+            let (api_key, version, _, body) = requests.recv().unwrap();
+            assert_eq!((api_key, version), (PRODUCE, 3));
+            let mut reader = Reader { data: &body };
+            assert_eq!(reader.i16().unwrap(), -1);
+            assert_eq!(reader.i16().unwrap(), -1);
+            assert_eq!(reader.i32().unwrap(), 30_000);
+            assert_eq!(reader.i32().unwrap(), 1);
+            assert_eq!(reader.string().unwrap(), "heads");
+            assert_eq!(reader.i32().unwrap(), 1);
+            let partition = reader.i32().unwrap();
+            let size = reader.i32().unwrap() as usize;
+            let batch = reader.take(size).unwrap().to_vec();
+            assert!(reader.data.is_empty());
+            produced.push((partition, i32::from_be_bytes(batch[57..61].try_into().unwrap())));
+        }
+        assert_eq!(produced.len(), 2, "the keys should spread over both partitions");
+        assert_eq!(produced, expected);
+    }
+}
@@ -0,0 +1,422 @@
+//! Substrate key pairs from secret URIs (`<phrase|0xseed>[//hard][/soft][///password]`)
+//! in the sr25519, ed25519 and ecdsa schemes, as `subkey` derives them, and the
+//! `gavel keys` subcommands built on them.
+
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+
+use ed25519_dalek::Signer;
+use schnorrkel::derive::{ChainCode, Derivation};
+use schnorrkel::{ExpansionMode, MiniSecretKey};
+
+use crate::{bip39, hashing, hex, keystore, scale, ss58};
+
+/// The well-known development phrase used when a URI starts with a junction, e.g. `//Alice`.
+const DEV_PHRASE: &str = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    Sr25519,
+    Ed25519,
+    Ecdsa,
+}
+
+impl Scheme {
+    fn name(self) -> &'static str {
+        match self {
+            Scheme::Sr25519 => "sr25519",
+            Scheme::Ed25519 => "ed25519",
+            Scheme::Ecdsa => "ecdsa",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "sr25519" => Ok(Scheme::Sr25519),
+            "ed25519" => Ok(Scheme::Ed25519),
+            "ecdsa" | "ethereum" => Ok(Scheme::Ecdsa),
+            other => Err(format!("unsupported key type '{}'", other)),
+        }
+    }
+}
+
+/// Parses an SS58 network given as a prefix number or a well-known network name.
+pub fn parse_network(input: &str) -> Result<u16, String> {
+    match input.to_lowercase().as_str() {
+        "polkadot" | "paseo" => Ok(0),
+        "kusama" => Ok(2),
+        "substrate" | "westend" | "rococo" | "generic" => Ok(42),
+        other => other.parse().map_err(|_| format!("unknown network '{}', use an SS58 prefix number", input)),
+    }
+}
+
+enum Junction {
+    Hard([u8; 32]),
+    Soft([u8; 32]),
+}
+
+impl Junction {
+    /// Numeric junctions are encoded as a u64, anything else as a SCALE string; codes
+    /// longer than 32 bytes are hashed.
+    fn chain_code(name: &str) -> [u8; 32] {
+        let encoded = match name.parse::<u64>() {
+            Ok(number) => number.to_le_bytes().to_vec(),
+            Err(_) => [scale::encode_compact(name.len() as u128), name.as_bytes().to_vec()].concat(),
+        };
+        let mut code = [0u8; 32];
+        if encoded.len() > 32 {
+            code.copy_from_slice(&hashing::blake2_256(&encoded));
+        } else {
+            code[..encoded.len()].copy_from_slice(&encoded);
+        }
+        code
+    }
+}
+
+/// Splits a secret URI into its phrase or seed, junctions and password.
+fn parse_suri(suri: &str) -> Result<(&str, Vec<Junction>, &str), String> {
+    let (rest, password) = match suri.find("///") {
+        Some(index) => (&suri[..index], &suri[index + 3..]),
+        None => (suri, ""),
+    };
+    let (phrase, mut path) = match rest.find('/') {
+        Some(index) => (rest[..index].trim(), &rest[index..]),
+        None => (rest.trim(), ""),
+    };
+    let mut junctions = Vec::new();
+    while !path.is_empty() {
+        let hard = path.starts_with("//");
+        path = path.trim_start_matches('/');
+        let end = path.find('/').unwrap_or(path.len());
+        if end == 0 {
+            return Err(format!("empty junction in '{}'", suri));
+        }
+        let code = Junction::chain_code(&path[..end]);
+        junctions.push(if hard { Junction::Hard(code) } else { Junction::Soft(code) });
+        path = &path[end..];
+    }
+    Ok((if phrase.is_empty() { DEV_PHRASE } else { phrase }, junctions, password))
+}
+
+/// Hard derivation for ed25519 and ecdsa: the blake2-256 hash of the SCALE encoded
+/// `(domain, seed, chain_code)`.
+fn derive_seed(domain: &str, seed: &[u8; 32], chain_code: &[u8; 32]) -> [u8; 32] {
+    let encoded = [&scale::encode_compact(domain.len() as u128), domain.as_bytes(), seed, chain_code].concat();
+    hashing::blake2_256(&encoded).try_into().unwrap()
+}
+
+/// The sr25519 pair of a mini secret, expanded the way Substrate does.
+fn sr25519_from_seed(seed: &[u8; 32]) -> schnorrkel::Keypair {
+    // A 32-byte seed is always a valid mini secret
+    MiniSecretKey::from_bytes(seed).unwrap().expand_to_keypair(ExpansionMode::Ed25519)
+}
+
+pub enum Pair {
+    Sr25519(schnorrkel::Keypair),
+    Ed25519 { seed: [u8; 32], pair: ed25519_dalek::SigningKey },
+    Ecdsa { seed: [u8; 32], pair: k256::ecdsa::SigningKey },
+}
+
+impl Pair {
+    /// Derives the pair for `suri`, also returning the seed when the derivation path
+    /// leaves one (soft junctions don't).
+    pub fn from_suri(scheme: Scheme, suri: &str) -> Result<(Pair, Option<[u8; 32]>), String> {
+        let (phrase, junctions, password) = parse_suri(suri)?;
+        let mut seed: [u8; 32] = if phrase.starts_with("0x") {
+            hex::decode(phrase)?.try_into().map_err(|_| "a hex seed must be 32 bytes".to_string())?
+        } else {
+            bip39::mini_secret(phrase, password)?
+        };
+        match scheme {
+            Scheme::Sr25519 => {
+                let mut pair = sr25519_from_seed(&seed);
+                let mut has_seed = true;
+                for junction in junctions {
+                    match junction {
+                        Junction::Hard(code) => {
+                            seed = pair.secret.hard_derive_mini_secret_key(Some(ChainCode(code)), b"").0.to_bytes();
+                            pair = sr25519_from_seed(&seed);
+                        }
+                        Junction::Soft(code) => {
+                            pair = pair.derived_key_simple(ChainCode(code), []).0;
+                            has_seed = false;
+                        }
+                    }
+                }
+                Ok((Pair::Sr25519(pair), has_seed.then_some(seed)))
+            }
+            Scheme::Ed25519 | Scheme::Ecdsa => {
+                let domain = if scheme == Scheme::Ed25519 { "Ed25519HDKD" } else { "Secp256k1HDKD" };
+                for junction in junctions {
+                    match junction {
+                        Junction::Hard(code) => seed = derive_seed(domain, &seed, &code),
+                        Junction::Soft(_) => return Err(format!("{} keys only support hard (//) derivation", scheme.name())),
+                    }
+                }
+                Ok((Self::from_seed(scheme, seed)?, Some(seed)))
+            }
+        }
+    }
+
+    fn from_seed(scheme: Scheme, seed: [u8; 32]) -> Result<Pair, String> {
+        Ok(match scheme {
+            Scheme::Sr25519 => Pair::Sr25519(sr25519_from_seed(&seed)),
+            Scheme::Ed25519 => Pair::Ed25519 { seed, pair: ed25519_dalek::SigningKey::from_bytes(&seed) },
+            Scheme::Ecdsa => Pair::Ecdsa {
+                seed,
+                pair: k256::ecdsa::SigningKey::from_slice(&seed).map_err(|_| "the seed is not a valid secp256k1 secret key".to_string())?,
+            },
+        })
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match self {
+            Pair::Sr25519(_) => Scheme::Sr25519,
+            Pair::Ed25519 { .. } => Scheme::Ed25519,
+            Pair::Ecdsa { .. } => Scheme::Ecdsa,
+        }
+    }
+
+    /// The public key, compressed for ecdsa.
+    pub fn public(&self) -> Vec<u8> {
+        match self {
+            Pair::Sr25519(pair) => pair.public.to_bytes().to_vec(),
+            Pair::Ed25519 { pair, .. } => pair.verifying_key().to_bytes().to_vec(),
+            Pair::Ecdsa { pair, .. } => pair.verifying_key().to_encoded_point(true).as_bytes().to_vec(),
+        }
+    }
+
+    /// The `AccountId32` of the `MultiSigner`: the public key, or its blake2-256 hash
+    /// for ecdsa.
+    pub fn account_id(&self) -> [u8; 32] {
+        match self {
+            Pair::Ecdsa { .. } => hashing::blake2_256(&self.public()).try_into().unwrap(),
+            _ => self.public().try_into().unwrap(),
+        }
+    }
+
+    /// The `MultiSignature` variant index of this scheme.
+    pub fn signature_type(&self) -> u8 {
+        match self {
+            Pair::Ed25519 { .. } => 0,
+            Pair::Sr25519(_) => 1,
+            Pair::Ecdsa { .. } => 2,
+        }
+    }
+
+    /// Signs `message`: sr25519 in the `substrate` signing context, ecdsa recoverably as
+    /// `r ‖ s ‖ v` over its blake2-256 hash.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Pair::Sr25519(pair) => pair.sign(schnorrkel::signing_context(b"substrate").bytes(message)).to_bytes().to_vec(),
+            Pair::Ed25519 { pair, .. } => pair.sign(message).to_bytes().to_vec(),
+            Pair::Ecdsa { pair, .. } => {
+                // RFC 6979 nonces and a normalized low s, as Substrate signs
+                let (signature, recovery) = pair.sign_prehash_recoverable(&hashing::blake2_256(message)).expect("a 32-byte prehash is always signable");
+                [signature.to_bytes().as_slice(), &[recovery.to_byte()]].concat()
+            }
+        }
+    }
+
+    /// The secret in the layout polkadot-js keystores use.
+    fn keystore_secret(&self) -> Vec<u8> {
+        match self {
+            Pair::Sr25519(pair) => pair.secret.to_ed25519_bytes().to_vec(),
+            Pair::Ed25519 { seed, pair } => [&seed[..], pair.verifying_key().as_bytes()].concat(),
+            Pair::Ecdsa { seed, .. } => seed.to_vec(),
+        }
+    }
+
+    fn from_keystore(decrypted: &keystore::Decrypted) -> Result<Pair, String> {
+        let scheme = Scheme::from_name(&decrypted.key_type)?;
+        let pair = match scheme {
+            Scheme::Sr25519 => {
+                let secret = schnorrkel::SecretKey::from_ed25519_bytes(&decrypted.secret).map_err(|e| format!("invalid sr25519 keystore secret: {}", e))?;
+                Pair::Sr25519(secret.to_keypair())
+            }
+            _ => Self::from_seed(scheme, decrypted.secret[..32].try_into().unwrap())?,
+        };
+        if pair.public() != decrypted.public {
+            return Err("the keystore public key does not match its secret".into());
+        }
+        Ok(pair)
+    }
+}
+
+/// The keystore password from `GAVEL_KEYSTORE_PASSWORD`, or read from stdin.
+fn keystore_password() -> Result<String, Box<dyn std::error::Error>> {
+    if let Ok(password) = std::env::var("GAVEL_KEYSTORE_PASSWORD") {
+        crate::redact::secret(&password);
+        return Ok(password);
+    }
+    eprint!("Keystore password: ");
+    std::io::stderr().flush()?;
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    crate::redact::secret(&password);
+    Ok(password)
+}
+
+fn read_keystore(path: &Path) -> Result<Pair, Box<dyn std::error::Error>> {
+    let document: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let decrypted = keystore::decrypt(&document, &keystore_password()?)?;
+    Ok(Pair::from_keystore(&decrypted)?)
+}
+
+/// Loads a signing key from a polkadot-js keystore file or a secret URI.
+pub fn load(input: &str, scheme: Scheme) -> Result<Pair, Box<dyn std::error::Error>> {
+    let path = Path::new(input);
+    if path.is_file() {
+        return read_keystore(path);
+    }
+    crate::redact::suri(input);
+    Ok(Pair::from_suri(scheme, input)?.0)
+}
+
+fn describe(pair: &Pair, network: u16) -> Value {
+    let public = pair.public();
+    let account_id = pair.account_id();
+    json!({
+        "scheme": pair.scheme().name(),
+        "network_id": network,
+        "public_key": hex::encode(&public),
+        "account_id": hex::encode(&account_id),
+        "ss58_address": ss58::encode(network, &account_id),
+    })
+}
+
+fn write_keystore(pair: &Pair, network: u16, path: &Path, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let password = keystore_password()?;
+    let address = ss58::encode(network, &pair.account_id());
+    let document = keystore::encrypt(&pair.keystore_secret(), &pair.public(), pair.scheme().name(), &address, name, &password);
+    std::fs::write(path, serde_json::to_string_pretty(&document)?)?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Generates a new mnemonic and reports the key it derives, optionally saving it as a keystore.
+pub fn generate(scheme: Scheme, words: usize, network: u16, keystore: Option<&Path>, name: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let phrase = bip39::generate(words)?;
+    let (pair, seed) = Pair::from_suri(scheme, &phrase)?;
+    let mut result = describe(&pair, network);
+    result["secret_phrase"] = json!(phrase);
+    result["secret_seed"] = json!(seed.map(|seed| hex::encode(&seed)));
+    if let Some(path) = keystore {
+        write_keystore(&pair, network, path, name)?;
+    }
+    Ok(result)
+}
+
+/// Reports the keys of a secret URI, the account behind an SS58 address, or the
+/// contents of a polkadot-js keystore file.
+pub fn inspect(input: &str, scheme: Scheme, network: u16) -> Result<Value, Box<dyn std::error::Error>> {
+    if Path::new(input).is_file() {
+        return Ok(describe(&read_keystore(Path::new(input))?, network));
+    }
+    if let Ok((prefix, account_id)) = ss58::decode(input) {
+        return Ok(json!({
+            "network_id": prefix,
+            "account_id": hex::encode(&account_id),
+            "ss58_address": ss58::encode(network, &account_id),
+        }));
+    }
+    let (pair, seed) = Pair::from_suri(scheme, input)?;
+    let mut result = describe(&pair, network);
+    result["secret_seed"] = json!(seed.map(|seed| hex::encode(&seed)));
+    Ok(result)
+}
+
+/// Saves the key of a secret URI as a polkadot-js keystore file.
+pub fn import(suri: &str, scheme: Scheme, network: u16, keystore: &Path, name: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let (pair, _) = Pair::from_suri(scheme, suri)?;
+    write_keystore(&pair, network, keystore, name)?;
+    Ok(describe(&pair, network))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(scheme: Scheme, suri: &str) -> String {
+        hex::encode(&Pair::from_suri(scheme, suri).unwrap().0.public())
+    }
+
+    #[test]
+    fn derives_the_subkey_dev_accounts() {
+        assert_eq!(public(Scheme::Sr25519, DEV_PHRASE), "0x46ebddef8cd9bb167dc30878d7113b7e168e6f0646beffd77d69d39bad76b47a");
+        assert_eq!(public(Scheme::Sr25519, "//Alice"), "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d");
+        assert_eq!(public(Scheme::Sr25519, &format!("{}//Alice", DEV_PHRASE)), public(Scheme::Sr25519, "//Alice"));
+        assert_eq!(public(Scheme::Sr25519, "//Alice//stash"), "0xbe5ddb1579b72e84524fc29e78609e3caf42e85aa118ebfe0b0ad404b5bdd25f");
+        assert_eq!(public(Scheme::Sr25519, "//Bob"), "0x8eaf04151687736326c9fea17e25fc5287613693c912909cb226aa4794f26a48");
+        assert_eq!(public(Scheme::Ed25519, "//Alice"), "0x88dc3417d5058ec4b4503e0c12ea1a0a89be200fe98922423d4334014fa6b0ee");
+        assert_eq!(public(Scheme::Ecdsa, "//Alice"), "0x020a1091341fe5664bfa1782d5e04779689068c916b04cb365ec3153755684d9a1");
+
+        let (_, seed) = Pair::from_suri(Scheme::Sr25519, "//Alice").unwrap();
+        assert_eq!(hex::encode(&seed.unwrap()), "0xe5be9a5092b81bca64be81d212e7f2f9eba183bb7a90954f7b76361f6edb5c0a");
+        assert_eq!(Pair::from_suri(Scheme::Sr25519, "//Alice/soft").unwrap().1, None);
+        assert!(Pair::from_suri(Scheme::Ecdsa, "//Alice/soft").is_err());
+    }
+
+    #[test]
+    fn signs_as_substrate_does() {
+        let message = b"gavel";
+        let (alice, _) = Pair::from_suri(Scheme::Sr25519, "//Alice").unwrap();
+        let signature = schnorrkel::Signature::from_bytes(&alice.sign(message)).unwrap();
+        let public = schnorrkel::PublicKey::from_bytes(&alice.public()).unwrap();
+        assert!(public.verify(schnorrkel::signing_context(b"substrate").bytes(message), &signature).is_ok());
+
+        // Both are deterministic
+        let (alice, _) = Pair::from_suri(Scheme::Ed25519, "//Alice").unwrap();
+        assert_eq!(
+            hex::encode(&alice.sign(message)),
+            "0x6e17cbcbc1107f96c050efd0e7044067d01015ef24c2df984b40f1fb7052ca3f42ca89d34353e85ef42e4719c5ab6c6da474eafada84fb0fc2178333170e8f0e",
+        );
+        let (alice, _) = Pair::from_suri(Scheme::Ecdsa, "//Alice").unwrap();
+        let signature = alice.sign(message);
+        assert_eq!(
+            hex::encode(&signature),
+            "0xdfcce113f3d83d1f0927a56065e0ece51b60616f9f4f657e77a4aebc4a753608275e2293c1099e01f96d960e09fd43ba3a00e9fe386293fa0d089e82957a6e6400",
+        );
+        let recovered = k256::ecdsa::VerifyingKey::recover_from_prehash(
+            &hashing::blake2_256(message),
+            &k256::ecdsa::Signature::from_slice(&signature[..64]).unwrap(),
+            k256::ecdsa::RecoveryId::from_byte(signature[64]).unwrap(),
+        ).unwrap();
+        assert_eq!(recovered.to_encoded_point(true).as_bytes(), alice.public());
+    }
+
+    #[test]
+    fn opens_polkadot_js_keystores() {
+        // Sealed independently of gavel, the password is "gavel"
+        let path = format!("{}/fixtures/keystore/alice_sr25519.json", env!("CARGO_MANIFEST_DIR"));
+        let document: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let pair = Pair::from_keystore(&keystore::decrypt(&document, "gavel").unwrap()).unwrap();
+        assert_eq!(hex::encode(&pair.public()), "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d");
+        assert!(keystore::decrypt(&document, "wrong").is_err());
+    }
+
+    #[test]
+    fn keystores_round_trip() {
+        for scheme in [Scheme::Sr25519, Scheme::Ed25519, Scheme::Ecdsa] {
+            let (pair, _) = Pair::from_suri(scheme, "//Alice").unwrap();
+            let document = keystore::encrypt(&pair.keystore_secret(), &pair.public(), scheme.name(), "", "Alice", "gavel");
+            let opened = Pair::from_keystore(&keystore::decrypt(&document, "gavel").unwrap()).unwrap();
+            assert_eq!(opened.scheme(), scheme);
+            assert_eq!(opened.public(), pair.public());
+        }
+    }
+
+    #[test]
+    fn generate_leaves_a_keystore_that_parses() {
+        std::env::set_var("GAVEL_KEYSTORE_PASSWORD", "gavel");
+        let path = std::env::temp_dir().join(format!("gavel-keystore-{}.json", std::process::id()));
+        let result = generate(Scheme::Sr25519, 12, 42, Some(&path), "gavel").unwrap();
+        let document: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(document.get("secret_phrase").is_none());
+        let opened = Pair::from_keystore(&keystore::decrypt(&document, "gavel").unwrap()).unwrap();
+        assert_eq!(json!(hex::encode(&opened.public())), result["public_key"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Encrypted JSON key files in the polkadot-js format: a PKCS#8-style envelope around
+//! the secret and public key, sealed with xsalsa20-poly1305 under a scrypt-derived key.
+
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::XSalsa20Poly1305;
+use rand::RngCore;
+use serde_json::{json, Value};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const PKCS8_DIVIDER: [u8; 5] = [161, 35, 3, 33, 0];
+
+const SCRYPT_N: u32 = 1 << 15;
+const SCRYPT_P: u32 = 1;
+const SCRYPT_R: u32 = 8;
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn base64_decode(input: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in input.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c).ok_or_else(|| format!("invalid base64 character '{}'", c as char))?;
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// The secretbox key: the first 32 bytes of the scrypt hash of the password.
+fn derive_key(password: &str, salt: &[u8], n: u32, r: u32, p: u32) -> Result<crypto_secretbox::Key, String> {
+    let params = scrypt::Params::new(n.trailing_zeros() as u8, r, p, 64).map_err(|e| format!("invalid scrypt parameters: {}", e))?;
+    let mut derived = [0u8; 64];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut derived).map_err(|e| format!("scrypt failed: {}", e))?;
+    Ok(*crypto_secretbox::Key::from_slice(&derived[..32]))
+}
+
+/// Seals `secret` and `public` into a polkadot-js keystore JSON document.
+pub fn encrypt(secret: &[u8], public: &[u8], key_type: &str, address: &str, name: &str, password: &str) -> Value {
+    let mut salt = [0u8; 32];
+    let mut nonce = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let key = derive_key(password, &salt, SCRYPT_N, SCRYPT_R, SCRYPT_P).expect("the default scrypt parameters are valid");
+    let plain = [&PKCS8_HEADER[..], secret, &PKCS8_DIVIDER, public].concat();
+    // NaCl layout, the tag before the ciphertext
+    let sealed = XSalsa20Poly1305::new(&key).encrypt(&nonce.into(), plain.as_slice()).expect("sealing cannot fail");
+    let encoded = [&salt[..], &SCRYPT_N.to_le_bytes(), &SCRYPT_P.to_le_bytes(), &SCRYPT_R.to_le_bytes(), &nonce, &sealed].concat();
+    let created = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+    json!({
+        "encoded": base64_encode(&encoded),
+        "encoding": {
+            "content": ["pkcs8", key_type],
+            "type": ["scrypt", "xsalsa20-poly1305"],
+            "version": "3",
+        },
+        "address": address,
+        "meta": { "name": name, "whenCreated": created },
+    })
+}
+
+/// A decrypted keystore: the key type and the stored secret and public key.
+pub struct Decrypted {
+    pub key_type: String,
+    pub secret: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+/// Opens a polkadot-js keystore JSON document (version 3, scrypt).
+pub fn decrypt(document: &Value, password: &str) -> Result<Decrypted, String> {
+    let encoding = &document["encoding"];
+    let key_type = encoding["content"].get(1).and_then(Value::as_str).unwrap_or("sr25519").to_string();
+    let types: Vec<&str> = encoding["type"].as_array().map(|types| types.iter().filter_map(Value::as_str).collect()).unwrap_or_default();
+    if !types.contains(&"scrypt") || !types.contains(&"xsalsa20-poly1305") {
+        return Err(format!("unsupported keystore encoding {}, expected scrypt and xsalsa20-poly1305", encoding["type"]));
+    }
+    let encoded = base64_decode(document["encoded"].as_str().ok_or("keystore has no 'encoded' field")?)?;
+    if encoded.len() < 32 + 12 + 24 + 16 {
+        return Err("keystore payload too short".into());
+    }
+    let param = |offset: usize| u32::from_le_bytes(encoded[offset..offset + 4].try_into().unwrap());
+    let (n, p, r) = (param(32), param(36), param(40));
+    if !n.is_power_of_two() || n > 1 << 20 || p == 0 || p > 16 || r == 0 || r > 16 {
+        return Err(format!("unsupported scrypt parameters N={} p={} r={}", n, p, r));
+    }
+    let key = derive_key(password, &encoded[..32], n, r, p)?;
+    let nonce: [u8; 24] = encoded[44..68].try_into().unwrap();
+    let plain = XSalsa20Poly1305::new(&key).decrypt(&nonce.into(), &encoded[68..]).map_err(|_| "unable to decrypt the keystore, wrong password?")?;
+    if plain.len() < PKCS8_HEADER.len() || plain[..PKCS8_HEADER.len()] != PKCS8_HEADER {
+        return Err("invalid PKCS#8 header in keystore".into());
+    }
+    let body = &plain[PKCS8_HEADER.len()..];
+    for secret_len in [64, 32] {
+        if body.len() >= secret_len + PKCS8_DIVIDER.len() && body[secret_len..secret_len + PKCS8_DIVIDER.len()] == PKCS8_DIVIDER {
+            return Ok(Decrypted {
+                key_type,
+                secret: body[..secret_len].to_vec(),
+                public: body[secret_len + PKCS8_DIVIDER.len()..].to_vec(),
+            });
+        }
+    }
+    Err("invalid PKCS#8 divider in keystore".into())
+}
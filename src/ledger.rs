@@ -0,0 +1,254 @@
+//! Ledger hardware wallet support for the Polkadot (generic) app over Linux hidraw:
+//! APDU framing, address lookup and transaction signing with ed25519 keys.
+//!
+//! The generic app holds no metadata. A transaction goes with the RFC 78 proof of the
+//! metadata needed to decode it, which the app checks against the metadata hash the
+//! transaction signs with `CheckMetadataHash` enabled.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+const LEDGER_VENDOR_ID: &str = "00002C97";
+const CHANNEL: u16 = 0x0101;
+const TAG_APDU: u8 = 0x05;
+const PACKET_SIZE: usize = 64;
+
+const CLA: u8 = 0xF9;
+const INS_GET_ADDRESS: u8 = 0x01;
+const INS_SIGN: u8 = 0x02;
+const SCHEME_ED25519: u8 = 0x00;
+const CHUNK_SIZE: usize = 250;
+
+// Signing chunk kinds, in P1
+const SIGN_INIT: u8 = 0x00;
+const SIGN_ADD: u8 = 0x01;
+const SIGN_LAST: u8 = 0x02;
+
+/// SLIP-44 coin type the generic app derives every chain's keys under.
+const COIN_TYPE: u32 = 354;
+const HARDENED: u32 = 0x8000_0000;
+
+pub struct Ledger {
+    device: File,
+}
+
+/// Turns `//account` or `//account//index` into the BIP-44 path
+/// `m/44'/354'/account'/0'/index'` the app expects, all levels hardened.
+pub fn derivation_path(derivation: &str) -> Result<[u32; 5], String> {
+    let levels: Vec<u32> = derivation.split("//").skip(1)
+        .map(|level| level.parse::<u32>().map_err(|_| format!("Invalid derivation level '{}', expected //<account>[//<index>]", level)))
+        .collect::<Result<_, _>>()?;
+    if !derivation.starts_with("//") || levels.is_empty() || levels.len() > 2 {
+        return Err(format!("Invalid derivation '{}', expected //<account>[//<index>]", derivation));
+    }
+    let (account, index) = (levels[0], levels.get(1).copied().unwrap_or(0));
+    if account >= HARDENED || index >= HARDENED {
+        return Err(format!("Derivation '{}' is out of range", derivation));
+    }
+    Ok([44 | HARDENED, COIN_TYPE | HARDENED, account | HARDENED, HARDENED, index | HARDENED])
+}
+
+fn encode_path(path: &[u32; 5]) -> Vec<u8> {
+    path.iter().flat_map(|level| level.to_le_bytes()).collect()
+}
+
+/// An APDU of the Polkadot app.
+fn apdu(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![CLA, ins, p1, p2, data.len() as u8];
+    apdu.extend_from_slice(data);
+    apdu
+}
+
+/// `apdu` split into the HID reports of the Ledger transport: the channel, the APDU tag and
+/// a sequence number, the first one followed by the APDU length. Each starts with the
+/// report id hidraw writes expect.
+fn hid_packets(apdu: &[u8]) -> Vec<Vec<u8>> {
+    let mut payload = (apdu.len() as u16).to_be_bytes().to_vec();
+    payload.extend_from_slice(apdu);
+    payload.chunks(PACKET_SIZE - 5).enumerate().map(|(sequence, chunk)| {
+        let mut packet = vec![0x00];
+        packet.extend(CHANNEL.to_be_bytes());
+        packet.push(TAG_APDU);
+        packet.extend((sequence as u16).to_be_bytes());
+        packet.extend_from_slice(chunk);
+        packet.resize(PACKET_SIZE + 1, 0);
+        packet
+    }).collect()
+}
+
+/// The P1 and data of the signing APDUs: the path first, then `blob_len ‖ blob ‖ proof`,
+/// with the blob length as u16 little endian, in chunks of at most 250 bytes.
+fn sign_chunks(path: &[u32; 5], blob: &[u8], metadata_proof: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, String> {
+    let len = u16::try_from(blob.len()).map_err(|_| format!("The {} byte transaction is too long for the Ledger", blob.len()))?;
+    let message = [len.to_le_bytes().as_slice(), blob, metadata_proof].concat();
+    let mut chunks = vec![(SIGN_INIT, encode_path(path))];
+    let count = message.len().div_ceil(CHUNK_SIZE);
+    for (index, chunk) in message.chunks(CHUNK_SIZE).enumerate() {
+        chunks.push((if index + 1 == count { SIGN_LAST } else { SIGN_ADD }, chunk.to_vec()));
+    }
+    Ok(chunks)
+}
+
+/// The data of an APDU response, or the error its status word stands for.
+fn response_data(mut response: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if response.len() < 2 {
+        return Err("Truncated Ledger response".into());
+    }
+    let status = u16::from_be_bytes([response[response.len() - 2], response[response.len() - 1]]);
+    response.truncate(response.len() - 2);
+    match status {
+        0x9000 => Ok(response),
+        0x6986 => Err("Rejected on the Ledger".into()),
+        0x6E00 | 0x6E01 | 0x6D00 => Err("The Polkadot app is not open on the Ledger".into()),
+        0x5515 => Err("The Ledger is locked".into()),
+        status => Err(format!("Ledger error 0x{:04x}{}", status, String::from_utf8_lossy(&response)).into()),
+    }
+}
+
+/// The ed25519 signature of a signing response, which comes prefixed with its
+/// `MultiSignature` type byte.
+fn signature(response: &[u8]) -> Result<[u8; 64], Box<dyn std::error::Error>> {
+    let signature = match response.len() {
+        65 => &response[1..],
+        64 => response,
+        len => return Err(format!("Unexpected {} byte signature from the Ledger", len).into()),
+    };
+    Ok(signature.try_into()?)
+}
+
+/// The hidraw node of the first Ledger APDU interface (interface 0, the others are FIDO).
+fn find_device() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let entries = fs::read_dir("/sys/class/hidraw").map_err(|_| "No hidraw devices, Ledger support needs Linux hidraw")?;
+    for entry in entries.flatten() {
+        let uevent = fs::read_to_string(entry.path().join("device/uevent")).unwrap_or_default();
+        let is_ledger = uevent.lines().any(|line| line.starts_with("HID_ID=") && line.to_uppercase().contains(LEDGER_VENDOR_ID));
+        let is_apdu = uevent.lines().any(|line| line.starts_with("HID_PHYS=") && line.ends_with("/input0"));
+        if is_ledger && is_apdu {
+            return Ok(PathBuf::from("/dev").join(entry.file_name()));
+        }
+    }
+    Err("No Ledger device found, is it connected and unlocked?".into())
+}
+
+impl Ledger {
+    pub fn open() -> Result<Ledger, Box<dyn std::error::Error>> {
+        let path = find_device()?;
+        let device = OpenOptions::new().read(true).write(true).open(&path)
+            .map_err(|e| format!("Failed to open {}: {} (check the udev rules for Ledger devices)", path.display(), e))?;
+        Ok(Ledger { device })
+    }
+
+    /// Sends one APDU split into HID packets and reassembles the response, returning the
+    /// data without the status word.
+    fn exchange(&mut self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        for packet in hid_packets(&apdu(ins, p1, p2, data)) {
+            self.device.write_all(&packet)?;
+        }
+
+        let mut response = Vec::new();
+        let mut expected = None;
+        let mut sequence = 0u16;
+        while expected.is_none_or(|expected| response.len() < expected) {
+            let mut packet = [0u8; PACKET_SIZE];
+            let read = self.device.read(&mut packet)?;
+            if read < 5 || u16::from_be_bytes([packet[0], packet[1]]) != CHANNEL || packet[2] != TAG_APDU {
+                return Err("Unexpected packet from the Ledger".into());
+            }
+            if u16::from_be_bytes([packet[3], packet[4]]) != sequence {
+                return Err("Ledger response packets out of sequence".into());
+            }
+            let mut data = &packet[5..read];
+            if sequence == 0 {
+                expected = Some(u16::from_be_bytes([data[0], data[1]]) as usize);
+                data = &data[2..];
+            }
+            response.extend_from_slice(data);
+            sequence += 1;
+        }
+        response.truncate(expected.unwrap_or_default());
+        response_data(response)
+    }
+
+    /// Public key at `path`, shown on the device for confirmation when `confirm` is set.
+    pub fn public_key(&mut self, path: &[u32; 5], ss58_prefix: u16, confirm: bool) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+        let mut data = encode_path(path);
+        data.extend(ss58_prefix.to_le_bytes());
+        let response = self.exchange(INS_GET_ADDRESS, u8::from(confirm), SCHEME_ED25519, &data)?;
+        Ok(response.get(..32).ok_or("Truncated Ledger address response")?.try_into()?)
+    }
+
+    /// Signs the transaction `blob` with the ed25519 key at `path` after the user reviews it
+    /// on the device, which decodes it with the types in `metadata_proof`.
+    pub fn sign(&mut self, path: &[u32; 5], blob: &[u8], metadata_proof: &[u8]) -> Result<[u8; 64], Box<dyn std::error::Error>> {
+        let mut response = Vec::new();
+        for (p1, data) in sign_chunks(path, blob, metadata_proof)? {
+            response = self.exchange(INS_SIGN, p1, SCHEME_ED25519, &data)?;
+        }
+        signature(&response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: [u32; 5] = [0x8000_002c, 0x8000_0162, 0x8000_0000, 0x8000_0000, 0x8000_0000];
+
+    #[test]
+    fn derives_bip44_paths() {
+        assert_eq!(derivation_path("//0").unwrap(), PATH);
+        assert_eq!(derivation_path("//1//2").unwrap(), [0x8000_002c, 0x8000_0162, 0x8000_0001, 0x8000_0000, 0x8000_0002]);
+        for invalid in ["0", "//", "//a", "//1//2//3", "//2147483648"] {
+            assert!(derivation_path(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sends_the_path_then_the_blob_and_proof() {
+        let chunks = sign_chunks(&PATH, &[0xaa; 3], &[0xbb; 2]).unwrap();
+        assert_eq!(chunks, [
+            (SIGN_INIT, vec![0x2c, 0, 0, 0x80, 0x62, 0x01, 0, 0x80, 0, 0, 0, 0x80, 0, 0, 0, 0x80, 0, 0, 0, 0x80]),
+            (SIGN_LAST, vec![0x03, 0x00, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb]),
+        ]);
+        assert_eq!(apdu(INS_SIGN, chunks[1].0, SCHEME_ED25519, &chunks[1].1), [0xf9, 0x02, 0x02, 0x00, 0x07, 0x03, 0x00, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb]);
+    }
+
+    #[test]
+    fn splits_the_message_in_250_byte_chunks() {
+        // 2 length bytes, 300 of blob and 300 of proof
+        let chunks = sign_chunks(&PATH, &[0xaa; 300], &[0xbb; 300]).unwrap();
+        let kinds: Vec<(u8, usize)> = chunks.iter().map(|(p1, data)| (*p1, data.len())).collect();
+        assert_eq!(kinds, [(SIGN_INIT, 20), (SIGN_ADD, 250), (SIGN_ADD, 250), (SIGN_LAST, 102)]);
+        assert_eq!(chunks[1].1[..3], [0x2c, 0x01, 0xaa]);
+        assert!(sign_chunks(&PATH, &vec![0; 70_000], &[]).is_err());
+    }
+
+    #[test]
+    fn frames_apdus_in_hid_packets() {
+        let apdu = apdu(INS_SIGN, SIGN_ADD, SCHEME_ED25519, &[0xcc; 250]);
+        let packets = hid_packets(&apdu);
+        // 2 length bytes and 255 of APDU, 59 per packet
+        assert_eq!(packets.len(), 5);
+        assert!(packets.iter().all(|packet| packet.len() == PACKET_SIZE + 1));
+        assert_eq!(packets[0][..12], [0x00, 0x01, 0x01, 0x05, 0x00, 0x00, 0x00, 0xff, 0xf9, 0x02, 0x01, 0x00]);
+        assert_eq!(packets[4][..6], [0x00, 0x01, 0x01, 0x05, 0x00, 0x04]);
+        let data: Vec<u8> = packets.iter().flat_map(|packet| packet[6..].to_vec()).collect();
+        assert_eq!(data[2..257], apdu);
+        assert!(data[257..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn reads_status_words_and_signatures() {
+        assert_eq!(response_data(vec![0x01, 0x90, 0x00]).unwrap(), [0x01]);
+        assert_eq!(response_data(vec![0x69, 0x86]).unwrap_err().to_string(), "Rejected on the Ledger");
+        assert_eq!(response_data(vec![0x6e, 0x01]).unwrap_err().to_string(), "The Polkadot app is not open on the Ledger");
+        assert!(response_data(vec![0x90]).is_err());
+
+        let mut response = vec![0x00];
+        response.extend([0x11; 64]);
+        assert_eq!(signature(&response).unwrap(), [0x11; 64]);
+        assert_eq!(signature(&response[1..]).unwrap(), [0x11; 64]);
+        assert!(signature(&response[2..]).is_err());
+    }
+}
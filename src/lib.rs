@@ -0,0 +1,439 @@
+//! Gavel as a library, for embedding its requests in a monitoring daemon instead of
+//! running the binary. [`GavelClient`] goes through the same connection handling as the
+//! commands (config profiles, chain bundles, DNS overrides, IP version, peer pinning) and
+//! the same method fallbacks. The modules behind the commands are internal, the `cli`
+//! module is only there for the binary.
+
+pub(crate) mod archive;
+pub(crate) mod author;
+pub(crate) mod beefy;
+pub(crate) mod bench;
+pub(crate) mod bip39;
+pub(crate) mod bundle;
+pub(crate) mod cache;
+pub(crate) mod capture;
+pub(crate) mod chainspec;
+pub(crate) mod check;
+pub(crate) mod checkpoints;
+#[doc(hidden)]
+pub mod cli;
+pub(crate) mod client;
+pub(crate) mod clock;
+pub(crate) mod compose;
+pub(crate) mod config;
+pub(crate) mod decode;
+pub(crate) mod derive;
+pub(crate) mod digest;
+pub(crate) mod election;
+pub(crate) mod encode;
+pub(crate) mod epoch;
+pub(crate) mod events;
+pub(crate) mod export;
+pub(crate) mod extensions;
+pub(crate) mod extrinsic;
+pub(crate) mod failure;
+pub(crate) mod fallback;
+pub(crate) mod fees;
+pub(crate) mod fleet;
+pub(crate) mod gzip;
+pub(crate) mod hammer;
+pub(crate) mod hashing;
+pub(crate) mod heartbeats;
+pub(crate) mod hex;
+pub(crate) mod http;
+pub(crate) mod in_flight;
+pub(crate) mod json_stream;
+pub(crate) mod justification;
+pub(crate) mod kafka;
+pub(crate) mod keys;
+pub(crate) mod keystore;
+pub(crate) mod ledger;
+pub(crate) mod liveness;
+pub(crate) mod metadata;
+pub(crate) mod metadata_hash;
+pub(crate) mod metrics;
+pub(crate) mod mmr;
+pub(crate) mod nats;
+pub(crate) mod offchain;
+pub(crate) mod output;
+pub(crate) mod postgres;
+pub(crate) mod probe;
+pub(crate) mod proxy;
+pub(crate) mod qr;
+pub(crate) mod queues;
+pub(crate) mod range;
+pub(crate) mod redact;
+pub(crate) mod redis;
+pub(crate) mod remote_signer;
+pub(crate) mod retry;
+pub(crate) mod rpc;
+pub(crate) mod scale;
+pub(crate) mod serve;
+pub(crate) mod sink;
+pub(crate) mod slots;
+pub(crate) mod ss58;
+pub(crate) mod staking;
+pub(crate) mod state_size;
+pub(crate) mod stats;
+pub(crate) mod storage;
+pub(crate) mod storage_diff;
+pub(crate) mod storage_keys;
+pub(crate) mod subscribe;
+pub(crate) mod tar;
+pub(crate) mod templates;
+pub(crate) mod trie;
+pub(crate) mod type_expr;
+pub(crate) mod verify;
+pub(crate) mod view;
+pub(crate) mod watchdog;
+pub(crate) mod when;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use futures_util::{SinkExt, StreamExt};
+use ::http::header::{HeaderValue, HOST};
+use native_tls::TlsConnector;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_native_tls::TlsConnector as TokioTlsConnector;
+use tokio_tungstenite::tungstenite::{handshake::client::Request, protocol::Message, protocol::WebSocketConfig};
+use tokio_tungstenite::{connect_async_with_config, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+pub use client::{Block, BlockRef, ConnectOptions, GavelClient, MmrProof};
+pub use failure::ConnectionFailure;
+pub use retry::Ambiguous;
+pub use rpc::Connection;
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub(crate) fn parse_byte_size(input: &str) -> Result<usize, String> {
+    let input = input.trim();
+    let (digits, multiplier) = match input.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&input[..i], 1024),
+        Some((i, 'm' | 'M')) => (&input[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&input[..i], 1024 * 1024 * 1024),
+        _ => (input, 1),
+    };
+    let value = digits.parse::<usize>().map_err(|e| format!("invalid size '{}': {}", input, e))?;
+    value.checked_mul(multiplier).ok_or_else(|| format!("size '{}' is too large", input))
+}
+
+pub(crate) fn websocket_config(max_response_size: Option<usize>) -> Option<WebSocketConfig> {
+    // A single frame can never be larger than the whole message, so cap both
+    max_response_size.map(|size| WebSocketConfig {
+        max_message_size: Some(size),
+        max_frame_size: Some(size),
+        ..Default::default()
+    })
+}
+
+/// Connects to an endpoint URL, config profile name or chain name, with the profile's
+/// authentication, and checks the node is on the chain's genesis. `http` and `https`
+/// endpoints are called with POST requests instead of over a WebSocket.
+pub(crate) async fn connect(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<Connection, Box<dyn std::error::Error>> {
+    let origin = rpc::Origin { endpoint: endpoint.to_string(), ipv4: ipv4.copied(), ipv6: ipv6.copied(), max_response_size };
+    if !config::endpoints(endpoint).iter().all(|url| http::is_http(url)) {
+        return Ok(Connection::new(connect_websocket(endpoint, ipv4, ipv6, max_response_size).await?).with_origin(origin));
+    }
+    let socket = Connection::http(http::connect(endpoint, ipv4, ipv6, max_response_size).await?).with_origin(origin);
+    if let Some((name, genesis)) = genesis_for(endpoint) {
+        check_genesis(endpoint, name, genesis, &socket.request("chain_getBlockHash", &json!([0])).await?)?;
+    }
+    Ok(socket)
+}
+
+/// The checked connection of [`connect`] as a plain WebSocket, for relaying raw frames.
+pub(crate) async fn connect_websocket(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<WebSocket, Box<dyn std::error::Error>> {
+    let mut socket = connect_pinned(endpoint, ipv4, ipv6, max_response_size).await?;
+    if let Some((name, genesis)) = genesis_for(endpoint) {
+        check_genesis(endpoint, name, genesis, &check(&mut socket, "chain_getBlockHash", json!([0])).await?)?;
+    }
+    Ok(socket)
+}
+
+/// The name and genesis hash of the configured chain `endpoint` belongs to, if known.
+fn genesis_for(endpoint: &str) -> Option<(&'static str, &'static str)> {
+    config::chain_for(endpoint).and_then(|(name, chain)| Some((name, chain.genesis_hash.as_deref()?)))
+}
+
+fn check_genesis(endpoint: &str, name: &str, genesis: &str, response: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    let found = response["result"].as_str().unwrap_or_default();
+    if !found.eq_ignore_ascii_case(genesis) {
+        return Err(format!("{} is not on chain {}: genesis {} instead of {}", config::endpoint(endpoint), name, found, genesis).into());
+    }
+    Ok(())
+}
+
+/// One request on a connection that is still being checked, before anything else uses it.
+async fn check(socket: &mut WebSocket, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let request = json!({ "jsonrpc": "2.0", "id": 0, "method": method, "params": params }).to_string();
+    stats::record_sent(1, request.len());
+    let sent = Instant::now();
+    socket.send(Message::Text(request)).await?;
+    loop {
+        let message = failure::message(socket.next().await)?;
+        if let Message::Text(text) = message {
+            stats::record_received(text.len());
+            let response: Value = serde_json::from_str(&text)?;
+            if response["id"] == 0 {
+                stats::record_latency(method, sent.elapsed());
+                return Ok(response);
+            }
+        }
+    }
+}
+
+pub(crate) async fn connect_pinned(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<WebSocket, Box<dyn std::error::Error>> {
+    let Some((peer_id, attempts)) = config::pin_peer() else {
+        return connect_once(endpoint, ipv4, ipv6, max_response_size).await;
+    };
+    // Every new connection may be routed to another backend, keep trying until it is the one
+    let mut seen = std::collections::BTreeSet::new();
+    for _ in 0..attempts {
+        let mut socket = connect_once(endpoint, ipv4, ipv6, max_response_size).await?;
+        let response = check(&mut socket, "system_localPeerId", json!([])).await?;
+        match response["result"].as_str() {
+            Some(id) if id == peer_id => return Ok(socket),
+            Some(id) => seen.insert(id.to_string()),
+            None => return Err(format!("--pin-peer needs system_localPeerId, which {} does not answer", endpoint).into()),
+        };
+        let _ = socket.close(None).await;
+    }
+    Err(format!(
+        "No connection to {} landed on peer {} in {} attempts, saw {}",
+        endpoint, peer_id, attempts, seen.into_iter().collect::<Vec<_>>().join(", "),
+    ).into())
+}
+
+/// Opens one connection, to the first of a chain's preferred endpoints that accepts it.
+pub(crate) async fn connect_once(endpoint: &str, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<WebSocket, Box<dyn std::error::Error>> {
+    let urls = config::endpoints(endpoint);
+    for (index, url) in urls.iter().enumerate() {
+        let request = config::authorize(url)?;
+        let websocket = websocket_config(max_response_size);
+        let (ipv4, ipv6) = config::resolve_for(url, ipv4.copied(), ipv6.copied());
+        let connected = if ipv4.is_some() || ipv6.is_some() || config::ip_version().is_some() {
+            custom_dns_connect(request, ipv4, ipv6, websocket).await
+        } else {
+            connect_async_with_config(request, websocket, false).await.map(|(socket, _)| socket).map_err(Into::into)
+        };
+        // Fall back to the next preferred endpoint, the last one's failure is reported
+        if connected.is_ok() || index + 1 == urls.len() {
+            return connected.map_err(failure::connect);
+        }
+    }
+    unreachable!("an endpoint argument stands for at least one URL")
+}
+
+pub(crate) async fn custom_dns_connect(mut request: Request, dns_override_v4: Option<Ipv4Addr>, dns_override_v6: Option<Ipv6Addr>, config: Option<WebSocketConfig>) -> Result<WebSocket, Box<dyn std::error::Error>> {
+    let url = Url::parse(&request.uri().to_string())?;
+    let maybe_tls_stream = open_stream(&url, dns_override_v4, dns_override_v6).await?;
+
+    request.headers_mut().insert(HOST, HeaderValue::from_str(url.host_str().unwrap())?);
+
+    let (socket, _) = tokio_tungstenite::client_async_with_config(request, maybe_tls_stream, config).await?;
+    Ok(socket)
+}
+
+/// Opens a TCP connection to `url`, with TLS for `wss` and `https`, at an overridden
+/// address if given and within the forced IP version.
+pub(crate) async fn open_stream(url: &Url, dns_override_v4: Option<Ipv4Addr>, dns_override_v6: Option<Ipv6Addr>) -> Result<MaybeTlsStream<TcpStream>, Box<dyn std::error::Error>> {
+    // With -4 or -6 only the override of that family applies
+    let (dns_override_v4, dns_override_v6) = match (config::ip_version(), dns_override_v4, dns_override_v6) {
+        (Some(config::IpVersion::V4), None, Some(_)) => return Err("--resolve-v6 cannot be used with -4".into()),
+        (Some(config::IpVersion::V6), Some(_), None) => return Err("--resolve-v4 cannot be used with -6".into()),
+        (Some(config::IpVersion::V4), v4, _) => (v4, None),
+        (Some(config::IpVersion::V6), _, v6) => (None, v6),
+        (None, v4, v6) => (v4, v6),
+    };
+    let addr = if let Some(ip) = dns_override_v4 {
+        SocketAddr::new(IpAddr::V4(ip), url.port_or_known_default().ok_or("Unknown port for the URL scheme")?)
+    } else if let Some(ip) = dns_override_v6 {
+        SocketAddr::new(IpAddr::V6(ip), url.port_or_known_default().ok_or("Unknown port for the URL scheme")?)
+    } else {
+        let host = url.host_str().ok_or("Missing host in URL")?;
+        let mut addrs = tokio::net::lookup_host((host, url.port_or_known_default().unwrap_or(443)))
+            .await?
+            .filter(|addr| config::ip_version().is_none_or(|version| version.matches(&addr.ip())));
+        match config::ip_version() {
+            Some(version) => addrs.next().ok_or_else(|| format!("{} has no {} address", host, version))?,
+            None => addrs.next().ok_or("Failed to resolve host")?,
+        }
+    };
+
+    let tcp_stream = TcpStream::connect(addr).await?;
+    let maybe_tls_stream = if matches!(url.scheme(), "ws" | "http") {
+        MaybeTlsStream::Plain(tcp_stream)
+    } else {
+        // A pinned address may not be the certificate's host, a resolved one has to be
+        let pinned = dns_override_v4.is_some() || dns_override_v6.is_some();
+        let tls_connector = TlsConnector::builder().danger_accept_invalid_certs(pinned).build()?;
+        let tokio_tls_connector = TokioTlsConnector::from(tls_connector);
+        MaybeTlsStream::NativeTls(tokio_tls_connector.connect(url.host_str().unwrap_or(""), tcp_stream).await?)
+    };
+    Ok(maybe_tls_stream)
+}
+
+pub(crate) async fn fetch_block_number(socket: &mut Connection, block_hash: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let params = json!([block_hash]);
+    let response = send_and_receive(socket, "chain_getBlock", params).await?;
+    let block = response.get("block").ok_or("Block key not found in response")?;
+    let header = block.get("header").ok_or("Header key not found in response")?;
+    let number = header.get("number").ok_or("Number key not found in response")?;
+    let block_number_str = number.as_str().ok_or("Block number not found in response")?;
+    let block_number = u64::from_str_radix(block_number_str.trim_start_matches("0x"), 16)
+                       .map_err(|_| Box::<dyn std::error::Error>::from("Invalid block number format"))?;
+    Ok(block_number)
+}
+
+/// Reads the block number from a header, the best block when `hash` is `None`.
+pub(crate) async fn fetch_header_number(socket: &mut Connection, hash: Option<&str>) -> Result<u64, Box<dyn std::error::Error>> {
+    let params = match hash {
+        Some(hash) => json!([hash]),
+        None => json!([]),
+    };
+    let header = send_and_receive(socket, "chain_getHeader", params).await?;
+    let number = header["number"].as_str().ok_or("Block number not found in header")?;
+    Ok(u64::from_str_radix(number.trim_start_matches("0x"), 16)?)
+}
+
+pub(crate) async fn fetch_block_hash(socket: &mut Connection, number: u64) -> Result<String, Box<dyn std::error::Error>> {
+    let response = send_and_receive(socket, "chain_getBlockHash", json!([number])).await?;
+    Ok(response.as_str().ok_or_else(|| format!("No block hash for block {}", number))?.to_string())
+}
+
+pub(crate) fn is_block_hash(block: &str) -> bool {
+    block.len() == 66 && block.starts_with("0x")
+}
+
+/// Resolves `--at`, a block number (decimal or `0x` hex) or a block hash, to a block hash.
+pub(crate) async fn resolve_at(socket: &mut Connection, at: Option<&str>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let at = match at {
+        Some(at) => at,
+        None => return Ok(None),
+    };
+    if is_block_hash(at) {
+        return Ok(Some(at.to_string()));
+    }
+    let number = match at.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => at.parse::<u64>().map_err(|_| format!("Invalid block '{}', expected a number or a block hash", at))?,
+    };
+    Ok(Some(fetch_block_hash(socket, number).await?))
+}
+
+/// The `--at` block, or the finalized head so that commands reading many keys see one state.
+pub(crate) async fn pinned_block(socket: &mut Connection, at: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(hash) = resolve_at(socket, at).await? {
+        return Ok(hash);
+    }
+    let head = send_and_receive(socket, "chain_getFinalizedHead", json!([])).await?;
+    Ok(head.as_str().ok_or("Failed to get the finalized head")?.to_string())
+}
+
+pub(crate) async fn fetch_block_head_hash(socket: &mut Connection) -> Result<String, Box<dyn std::error::Error>> {
+    let params = json!([]);
+    let response = send_and_receive(socket, "chain_getHead", params).await?;
+    if let Some(hash) = response.as_str() {
+        Ok(hash.to_string())
+    } else {
+        Err("Failed to get block hash as string".into())
+    }
+}
+
+pub(crate) async fn send_and_receive(
+    socket: &mut Connection,
+    method: &str,
+    params: serde_json::Value
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let response = send_with_fallback(socket, method, &params).await?;
+    if response.get("error").is_some() {
+        stats::record_failure("rpc");
+    }
+    Ok(response["result"].clone())
+}
+
+/// Calls `method` and returns the whole response, retrying with an equivalent method
+/// when the node predates (or dropped) the requested one. An alternative's result is
+/// converted to the shape `method` returns.
+pub(crate) async fn send_with_fallback(
+    socket: &mut Connection,
+    method: &str,
+    params: &Value
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let response = send_request(socket, method, params).await?;
+    if !fallback::is_method_not_found(&response) {
+        return Ok(response);
+    }
+    for alternative in fallback::alternatives(method) {
+        let Some(alternative_params) = (alternative.params)(params) else { continue };
+        let mut alternative_response = send_request(socket, alternative.method, &alternative_params).await?;
+        if !fallback::is_method_not_found(&alternative_response) {
+            eprintln!("{} is not available, used {} instead", method, alternative.method);
+            if alternative_response.get("error").is_none() {
+                alternative_response["result"] = (alternative.result)(alternative_response["result"].take());
+            }
+            return Ok(alternative_response);
+        }
+    }
+    Ok(response)
+}
+
+/// Calls `method` and returns the whole response. A failed call is sent again up to
+/// `--retries` times, reconnecting if the connection ended, unless it may have reached the
+/// node and `method` is not idempotent: that failure is reported as [`retry::Ambiguous`].
+/// A connection found half-open is re-dialed for one more try even without `--retries`.
+pub(crate) async fn send_request(
+    socket: &mut Connection,
+    method: &str,
+    params: &Value
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut attempts = 0;
+    loop {
+        // The error is not Send, it must be gone before the next await
+        let wait = {
+            let (error, sent) = match attempt(socket, method, params).await {
+                Ok(response) => return Ok(response),
+                Err(failure) => failure,
+            };
+            if sent && !retry::is_idempotent(method) {
+                return Err(Box::new(retry::Ambiguous { method: method.to_string(), cause: error }));
+            }
+            attempts += 1;
+            let retries = retry::retries().max(u32::from(failure::is_stalled(error.as_ref())));
+            if attempts > retries {
+                return Err(error);
+            }
+            let wait = retry::backoff(attempts);
+            eprintln!("Warning: {} failed, retry {} of {} in {:?}: {}", method, attempts, retries, wait, redact::text(&error.to_string()));
+            wait
+        };
+        tokio::time::sleep(wait).await;
+        if socket.is_closed() {
+            match socket.reconnect().await.map_err(|e| e.to_string()) {
+                Ok(fresh) => *socket = fresh,
+                Err(e) => eprintln!("Warning: reconnecting failed: {}", redact::text(&e)),
+            }
+        }
+    }
+}
+
+/// One try at a call, failing with the error and whether the call may have reached the
+/// node: it was written out but no answer came, or none within `--call-timeout`.
+async fn attempt(socket: &Connection, method: &str, params: &Value) -> Result<Value, (Box<dyn std::error::Error>, bool)> {
+    let _permit = in_flight::acquire(1).await;
+    let pending = socket.send(&[(method, params)]).await.map_err(|e| (e, false))?;
+    let pending = pending.into_iter().next().ok_or_else(|| ("No call was sent".into(), false))?;
+    let response = match retry::timeout() {
+        Some(limit) => tokio::time::timeout(limit, pending.response()).await
+            .unwrap_or_else(|_| Err(format!("No answer within {}s", limit.as_secs_f64()).into())),
+        None => pending.response().await,
+    };
+    response.map_err(|e| {
+        // Over HTTP the request is written by the POST, a failure to connect never sent it
+        let unsent = e.downcast_ref::<failure::ConnectionFailure>().is_some_and(|failure| matches!(failure.stage, "tcp" | "tls" | "handshake"));
+        (e, !unsent)
+    })
+}
@@ -0,0 +1,116 @@
+//! Parachain liveness as seen from the relay chain, from `ParaInclusion` backing and
+//! inclusion events.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::storage;
+
+#[derive(Default)]
+struct Para {
+    backed: u64,
+    included: u64,
+    last_included: Option<u64>,
+    stalled: bool,
+}
+
+impl Para {
+    fn blocks_since_included(&self, block: u64, since: u64) -> u64 {
+        block.saturating_sub(self.last_included.unwrap_or(since))
+    }
+}
+
+/// Para ID of a `CandidateBacked` or `CandidateIncluded` event, whose first field is the receipt.
+fn candidate_para(event: &Value, name: &str) -> Option<u64> {
+    event.get(name)?.get(0)?["descriptor"]["para_id"].as_u64()
+}
+
+async fn record_block(socket: &mut Connection, metadata: &Metadata, paras: &mut BTreeMap<u64, Para>, block: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = crate::fetch_block_hash(socket, block).await?;
+    for event in storage::pallet_events(socket, metadata, "ParaInclusion", &hash).await? {
+        if let Some(id) = candidate_para(&event, "CandidateBacked") {
+            paras.entry(id).or_default().backed += 1;
+        }
+        if let Some(id) = candidate_para(&event, "CandidateIncluded") {
+            let para = paras.entry(id).or_default();
+            para.included += 1;
+            para.last_included = Some(block);
+        }
+    }
+    Ok(())
+}
+
+/// Scans the last `window` relay blocks (up to `at` when given) and reports, per parachain, how many candidates
+/// were backed and included. Paras without an inclusion in the window are flagged as
+/// stalled. In follow mode new blocks are polled and a JSON line is printed whenever a
+/// para stalls or recovers.
+pub async fn monitor(socket: &mut Connection, window: u64, follow: bool, interval: Duration, at: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.pallet("ParaInclusion").is_none() {
+        return Err("No ParaInclusion pallet, is this a relay chain endpoint?".into());
+    }
+    let window = window.max(1);
+    let head = crate::fetch_header_number(socket, at).await?;
+    let start = head.saturating_sub(window - 1);
+
+    let mut paras: BTreeMap<u64, Para> = BTreeMap::new();
+    let registered = storage::read(socket, &metadata, "Paras", "Parachains", &[], at).await?;
+    for id in registered.as_array().into_iter().flatten().filter_map(Value::as_u64) {
+        paras.entry(id).or_default();
+    }
+    for block in start..=head {
+        record_block(socket, &metadata, &mut paras, block).await?;
+    }
+
+    let mut report = Vec::new();
+    for (id, para) in paras.iter_mut() {
+        let blocks_since = para.blocks_since_included(head, start);
+        para.stalled = para.included == 0;
+        if para.stalled {
+            eprintln!("ALERT: para {} had no candidate included in the last {} blocks", id, window);
+        }
+        report.push(json!({
+            "para_id": id,
+            "backed": para.backed,
+            "included": para.included,
+            "last_included": para.last_included,
+            "blocks_since_included": blocks_since,
+            "average_interval": if para.included > 0 { json!(window as f64 / para.included as f64) } else { Value::Null },
+            "stalled": para.stalled,
+        }));
+    }
+    let summary = json!({ "from_block": start, "to_block": head, "window": window, "paras": report });
+    crate::output::line(&if follow { summary.to_string() } else { serde_json::to_string_pretty(&summary)? })?;
+    if !follow {
+        return Ok(());
+    }
+
+    let mut last_block = head;
+    loop {
+        tokio::time::sleep(interval).await;
+        let head = crate::fetch_header_number(socket, None).await?;
+        for block in last_block + 1..=head {
+            record_block(socket, &metadata, &mut paras, block).await?;
+            for (id, para) in paras.iter_mut() {
+                let blocks_since = para.blocks_since_included(block, start);
+                let stalled = blocks_since >= window;
+                if stalled == para.stalled {
+                    continue;
+                }
+                para.stalled = stalled;
+                let status = if stalled { "stalled" } else { "recovered" };
+                if stalled {
+                    eprintln!("ALERT: para {} has had no candidate included for {} blocks", id, blocks_since);
+                }
+                crate::output::line(&json!({ "block": block, "para_id": id, "status": status, "last_included": para.last_included }).to_string())?;
+            }
+        }
+        last_block = last_block.max(head);
+    }
+}
@@ -0,0 +1,638 @@
+//! Runtime metadata (V14, V15 and V16) parsing, enough to look up pallet storage,
+//! constants, view functions and types for decoding.
+
+// The structs mirror the metadata layout, not every field is read by every command
+#![allow(dead_code)]
+
+use serde_json::json;
+
+use crate::hashing;
+use crate::hex;
+use crate::rpc::Connection;
+use crate::scale::Input;
+
+const MAGIC: u32 = 0x6174_656d;
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: Option<String>,
+    pub ty: u32,
+    pub type_name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub index: u8,
+    pub docs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Bool,
+    Char,
+    Str,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    U256,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    I256,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeDef {
+    Composite(Vec<Field>),
+    Variant(Vec<Variant>),
+    Sequence(u32),
+    Array(u32, u32),
+    Tuple(Vec<u32>),
+    Primitive(Primitive),
+    Compact(u32),
+    BitSequence(u32, u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Type {
+    pub path: Vec<String>,
+    pub params: Vec<(String, Option<u32>)>,
+    pub def: TypeDef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Hasher {
+    #[value(name = "blake2_128")]
+    Blake2_128,
+    #[value(name = "blake2_256")]
+    Blake2_256,
+    #[value(name = "blake2_128_concat")]
+    Blake2_128Concat,
+    #[value(name = "twox_128")]
+    Twox128,
+    #[value(name = "twox_256")]
+    Twox256,
+    #[value(name = "twox_64_concat")]
+    Twox64Concat,
+    #[value(name = "identity")]
+    Identity,
+}
+
+impl Hasher {
+    pub fn hash(self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Hasher::Blake2_128 => hashing::blake2_128(input),
+            Hasher::Blake2_256 => hashing::blake2_256(input),
+            Hasher::Blake2_128Concat => [hashing::blake2_128(input), input.to_vec()].concat(),
+            Hasher::Twox128 => hashing::twox_128(input),
+            Hasher::Twox256 => hashing::twox_256(input),
+            Hasher::Twox64Concat => [hashing::twox_64(input), input.to_vec()].concat(),
+            Hasher::Identity => input.to_vec(),
+        }
+    }
+
+    /// Length of the hash part, before any concatenated plain key.
+    pub fn hash_len(self) -> usize {
+        match self {
+            Hasher::Blake2_128 | Hasher::Blake2_128Concat | Hasher::Twox128 => 16,
+            Hasher::Blake2_256 | Hasher::Twox256 => 32,
+            Hasher::Twox64Concat => 8,
+            Hasher::Identity => 0,
+        }
+    }
+
+    /// Whether the plain key follows the hash, so it can be recovered from the storage key.
+    pub fn is_transparent(self) -> bool {
+        matches!(self, Hasher::Blake2_128Concat | Hasher::Twox64Concat | Hasher::Identity)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StorageType {
+    Plain(u32),
+    Map { hashers: Vec<Hasher>, key: u32, value: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageEntry {
+    pub name: String,
+    pub optional: bool,
+    pub ty: StorageType,
+    pub default: Vec<u8>,
+    pub docs: Vec<String>,
+}
+
+impl StorageEntry {
+    pub fn value_type(&self) -> u32 {
+        match &self.ty {
+            StorageType::Plain(ty) => *ty,
+            StorageType::Map { value, .. } => *value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub name: String,
+    pub ty: u32,
+    pub value: Vec<u8>,
+    pub docs: Vec<String>,
+}
+
+/// A pallet view function (V16): a read-only query dispatched through the
+/// `RuntimeViewFunction` runtime API by its 32-byte id.
+#[derive(Debug, Clone)]
+pub struct ViewFunction {
+    pub id: [u8; 32],
+    pub name: String,
+    pub inputs: Vec<(String, u32)>,
+    pub output: u32,
+    pub docs: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pallet {
+    pub name: String,
+    pub index: u8,
+    pub storage_prefix: Option<String>,
+    pub storage: Vec<StorageEntry>,
+    pub calls: Option<u32>,
+    pub event: Option<u32>,
+    pub error: Option<u32>,
+    pub constants: Vec<Constant>,
+    pub view_functions: Vec<ViewFunction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignedExtension {
+    pub identifier: String,
+    pub ty: u32,
+    pub additional_signed: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Extrinsic {
+    pub version: u8,
+    pub ty: Option<u32>,
+    // The address, call and signature types of signed extrinsics
+    pub address_ty: Option<u32>,
+    pub call_ty: Option<u32>,
+    pub signature_ty: Option<u32>,
+    pub signed_extensions: Vec<SignedExtension>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub version: u8,
+    pub types: Vec<Type>,
+    pub pallets: Vec<Pallet>,
+    pub extrinsic: Extrinsic,
+}
+
+impl Metadata {
+    pub fn parse(bytes: &[u8]) -> Result<Metadata, String> {
+        let mut input = Input::new(bytes);
+        if input.read_u32()? != MAGIC {
+            return Err("metadata does not start with the 'meta' magic".into());
+        }
+        let version = input.read_u8()?;
+        if !(14..=16).contains(&version) {
+            return Err(format!("unsupported metadata version V{}", version));
+        }
+
+        let types = parse_types(&mut input)?;
+        let pallets = input.read_list(|input| parse_pallet(input, version))?;
+        let mut extrinsic = if version == 14 {
+            let ty = read_type_id(&mut input)?;
+            let extrinsic_version = input.read_u8()?;
+            let signed_extensions = input.read_list(parse_signed_extension)?;
+            // V14 only has them as the generic parameters of `UncheckedExtrinsic`
+            let param = |name: &str| types.get(ty as usize)
+                .and_then(|ty| ty.params.iter().find(|(param, _)| param == name))
+                .and_then(|(_, ty)| *ty);
+            Extrinsic {
+                version: extrinsic_version,
+                ty: Some(ty),
+                address_ty: param("Address"),
+                call_ty: param("Call"),
+                signature_ty: param("Signature"),
+                signed_extensions,
+            }
+        } else if version == 16 {
+            parse_extrinsic_v16(&mut input)?
+        } else {
+            let extrinsic_version = input.read_u8()?;
+            let address_ty = read_type_id(&mut input)?;
+            let call_ty = read_type_id(&mut input)?;
+            let signature_ty = read_type_id(&mut input)?;
+            // The extra type, the tuple of the signed extensions listed next
+            read_type_id(&mut input)?;
+            let signed_extensions = input.read_list(parse_signed_extension)?;
+            Extrinsic {
+                version: extrinsic_version,
+                ty: None,
+                address_ty: Some(address_ty),
+                call_ty: Some(call_ty),
+                signature_ty: Some(signature_ty),
+                signed_extensions,
+            }
+        };
+
+        if version == 16 {
+            // V16 moved the call type to the outer enums, after the runtime APIs
+            input.read_list(parse_runtime_api_v16)?;
+            extrinsic.call_ty = Some(read_type_id(&mut input)?);
+        }
+
+        Ok(Metadata { version, types, pallets, extrinsic })
+    }
+
+    pub fn resolve(&self, ty: u32) -> Result<&Type, String> {
+        self.types.get(ty as usize).ok_or_else(|| format!("type {} not found in metadata", ty))
+    }
+
+    pub fn pallet(&self, name: &str) -> Option<&Pallet> {
+        self.pallets.iter().find(|pallet| pallet.name == name)
+    }
+
+    pub fn constant(&self, pallet: &str, name: &str) -> Option<&Constant> {
+        self.pallet(pallet)?.constants.iter().find(|constant| constant.name == name)
+    }
+
+    /// Decodes a numeric constant such as `Babe::EpochDuration` or `Staking::SessionsPerEra`.
+    pub fn constant_u64(&self, pallet: &str, name: &str) -> Option<u64> {
+        let constant = self.constant(pallet, name)?;
+        let mut bytes = [0u8; 8];
+        let len = constant.value.len().min(8);
+        bytes[..len].copy_from_slice(&constant.value[..len]);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    pub fn storage_entry(&self, pallet: &str, entry: &str) -> Option<&StorageEntry> {
+        self.pallet(pallet)?.storage.iter().find(|storage| storage.name == entry)
+    }
+
+    /// Builds the storage key for `pallet::entry`, hashing each SCALE-encoded map key with
+    /// the hasher the metadata declares for it. Fewer keys than hashers yields a prefix.
+    pub fn storage_key(&self, pallet: &str, entry: &str, keys: &[Vec<u8>]) -> Result<String, String> {
+        let storage = self.storage_entry(pallet, entry).ok_or_else(|| format!("storage {}::{} not found in metadata", pallet, entry))?;
+        let hashers = match &storage.ty {
+            StorageType::Plain(_) => Vec::new(),
+            StorageType::Map { hashers, .. } => hashers.clone(),
+        };
+        if keys.len() > hashers.len() {
+            return Err(format!("{}::{} takes {} keys, got {}", pallet, entry, hashers.len(), keys.len()));
+        }
+        let mut key = storage_prefix(pallet, entry);
+        for (hasher, value) in hashers.iter().zip(keys) {
+            key.extend(hasher.hash(value));
+        }
+        Ok(hex::encode(&key))
+    }
+}
+
+/// `twox128(pallet) ++ twox128(entry)`, the prefix shared by every key of a storage entry.
+pub fn storage_prefix(pallet: &str, entry: &str) -> Vec<u8> {
+    [hashing::twox_128(pallet.as_bytes()), hashing::twox_128(entry.as_bytes())].concat()
+}
+
+fn read_type_id(input: &mut Input) -> Result<u32, String> {
+    Ok(input.read_compact()? as u32)
+}
+
+fn read_docs(input: &mut Input) -> Result<Vec<String>, String> {
+    input.read_list(Input::read_string)
+}
+
+fn parse_field(input: &mut Input) -> Result<Field, String> {
+    let name = input.read_option(Input::read_string)?;
+    let ty = read_type_id(input)?;
+    let type_name = input.read_option(Input::read_string)?;
+    read_docs(input)?;
+    Ok(Field { name, ty, type_name })
+}
+
+fn parse_types(input: &mut Input) -> Result<Vec<Type>, String> {
+    let entries = input.read_list(|input| {
+        let id = read_type_id(input)?;
+        let path = input.read_list(Input::read_string)?;
+        let params = input.read_list(|input| {
+            let name = input.read_string()?;
+            let ty = input.read_option(read_type_id)?;
+            Ok((name, ty))
+        })?;
+        let def = match input.read_u8()? {
+            0 => TypeDef::Composite(input.read_list(parse_field)?),
+            1 => TypeDef::Variant(input.read_list(|input| {
+                let name = input.read_string()?;
+                let fields = input.read_list(parse_field)?;
+                let index = input.read_u8()?;
+                let docs = read_docs(input)?;
+                Ok(Variant { name, fields, index, docs })
+            })?),
+            2 => TypeDef::Sequence(read_type_id(input)?),
+            3 => {
+                let len = input.read_u32()?;
+                TypeDef::Array(len, read_type_id(input)?)
+            }
+            4 => TypeDef::Tuple(input.read_list(read_type_id)?),
+            5 => TypeDef::Primitive(match input.read_u8()? {
+                0 => Primitive::Bool,
+                1 => Primitive::Char,
+                2 => Primitive::Str,
+                3 => Primitive::U8,
+                4 => Primitive::U16,
+                5 => Primitive::U32,
+                6 => Primitive::U64,
+                7 => Primitive::U128,
+                8 => Primitive::U256,
+                9 => Primitive::I8,
+                10 => Primitive::I16,
+                11 => Primitive::I32,
+                12 => Primitive::I64,
+                13 => Primitive::I128,
+                14 => Primitive::I256,
+                other => return Err(format!("unknown primitive type {}", other)),
+            }),
+            6 => TypeDef::Compact(read_type_id(input)?),
+            7 => {
+                let store = read_type_id(input)?;
+                TypeDef::BitSequence(store, read_type_id(input)?)
+            }
+            other => return Err(format!("unknown type definition {}", other)),
+        };
+        read_docs(input)?;
+        Ok((id, Type { path, params, def }))
+    })?;
+
+    // Ids are dense and sorted in practice, but index by id rather than trusting that
+    let mut types = vec![Type { path: Vec::new(), params: Vec::new(), def: TypeDef::Tuple(Vec::new()) }; entries.len()];
+    for (id, ty) in entries {
+        let slot = types.get_mut(id as usize).ok_or_else(|| format!("type id {} out of range", id))?;
+        *slot = ty;
+    }
+    Ok(types)
+}
+
+fn parse_hasher(input: &mut Input) -> Result<Hasher, String> {
+    Ok(match input.read_u8()? {
+        0 => Hasher::Blake2_128,
+        1 => Hasher::Blake2_256,
+        2 => Hasher::Blake2_128Concat,
+        3 => Hasher::Twox128,
+        4 => Hasher::Twox256,
+        5 => Hasher::Twox64Concat,
+        6 => Hasher::Identity,
+        other => return Err(format!("unknown storage hasher {}", other)),
+    })
+}
+
+fn parse_pallet(input: &mut Input, version: u8) -> Result<Pallet, String> {
+    let name = input.read_string()?;
+    let storage = input.read_option(|input| {
+        let prefix = input.read_string()?;
+        let entries = input.read_list(|input| {
+            let name = input.read_string()?;
+            let optional = input.read_u8()? == 0;
+            let ty = match input.read_u8()? {
+                0 => StorageType::Plain(read_type_id(input)?),
+                1 => {
+                    let hashers = input.read_list(parse_hasher)?;
+                    let key = read_type_id(input)?;
+                    let value = read_type_id(input)?;
+                    StorageType::Map { hashers, key, value }
+                }
+                other => return Err(format!("unknown storage entry type {}", other)),
+            };
+            let default = input.read_vec()?.to_vec();
+            let docs = read_docs(input)?;
+            if version >= 16 {
+                skip_item_deprecation(input)?;
+            }
+            Ok(StorageEntry { name, optional, ty, default, docs })
+        })?;
+        Ok((prefix, entries))
+    })?;
+    // V16 follows the calls, event and error types with the deprecation of their variants
+    let read_enum_type = |input: &mut Input| {
+        let ty = read_type_id(input)?;
+        if version >= 16 {
+            skip_enum_deprecation(input)?;
+        }
+        Ok(ty)
+    };
+    let calls = input.read_option(read_enum_type)?;
+    let event = input.read_option(read_enum_type)?;
+    let constants = input.read_list(|input| {
+        let name = input.read_string()?;
+        let ty = read_type_id(input)?;
+        let value = input.read_vec()?.to_vec();
+        let docs = read_docs(input)?;
+        if version >= 16 {
+            skip_item_deprecation(input)?;
+        }
+        Ok(Constant { name, ty, value, docs })
+    })?;
+    let error = input.read_option(read_enum_type)?;
+    let mut view_functions = Vec::new();
+    if version >= 16 {
+        // Associated types
+        input.read_list(|input| {
+            input.read_string()?;
+            read_type_id(input)?;
+            read_docs(input)
+        })?;
+        view_functions = input.read_list(parse_view_function)?;
+    }
+    let index = input.read_u8()?;
+    if version >= 15 {
+        read_docs(input)?;
+    }
+    if version >= 16 {
+        skip_item_deprecation(input)?;
+    }
+
+    let (storage_prefix, storage) = match storage {
+        Some((prefix, entries)) => (Some(prefix), entries),
+        None => (None, Vec::new()),
+    };
+    Ok(Pallet { name, index, storage_prefix, storage, calls, event, error, constants, view_functions })
+}
+
+/// Skips an `ItemDeprecationInfo`: not deprecated, deprecated without a note, or with a
+/// note and an optional `since`.
+fn skip_item_deprecation(input: &mut Input) -> Result<(), String> {
+    match input.read_u8()? {
+        0 | 1 => Ok(()),
+        2 => {
+            input.read_string()?;
+            input.read_option(Input::read_string).map(drop)
+        }
+        other => Err(format!("unknown deprecation info {}", other)),
+    }
+}
+
+/// Skips an `EnumDeprecationInfo`, the deprecation of each deprecated variant by index.
+fn skip_enum_deprecation(input: &mut Input) -> Result<(), String> {
+    input.read_list(|input| {
+        input.read_u8()?;
+        match input.read_u8()? {
+            0 => Ok(()),
+            1 => {
+                input.read_string()?;
+                input.read_option(Input::read_string).map(drop)
+            }
+            other => Err(format!("unknown variant deprecation info {}", other)),
+        }
+    }).map(drop)
+}
+
+fn parse_view_function(input: &mut Input) -> Result<ViewFunction, String> {
+    let id = input.read_array::<32>()?;
+    let name = input.read_string()?;
+    let inputs = input.read_list(|input| Ok((input.read_string()?, read_type_id(input)?)))?;
+    let output = read_type_id(input)?;
+    let docs = read_docs(input)?;
+    skip_item_deprecation(input)?;
+    Ok(ViewFunction { id, name, inputs, output, docs })
+}
+
+/// V16 extrinsic metadata: the supported versions, the address and signature types and
+/// the transaction extensions of each extension version.
+fn parse_extrinsic_v16(input: &mut Input) -> Result<Extrinsic, String> {
+    let versions = input.read_vec()?.to_vec();
+    let address_ty = read_type_id(input)?;
+    let signature_ty = read_type_id(input)?;
+    let by_version = input.read_list(|input| Ok((input.read_u8()?, input.read_list(read_type_id)?)))?;
+    let extensions = input.read_list(|input| {
+        let identifier = input.read_string()?;
+        let ty = read_type_id(input)?;
+        let implicit = read_type_id(input)?;
+        Ok(SignedExtension { identifier, ty, additional_signed: implicit })
+    })?;
+    let version = if versions.contains(&4) { 4 } else { versions.iter().copied().max().unwrap_or(4) };
+    // Signed (v4) extrinsics use the extensions of extension version 0
+    let signed_extensions = by_version.iter().find(|(extension_version, _)| *extension_version == 0).or(by_version.first())
+        .map(|(_, indices)| indices.iter().filter_map(|index| extensions.get(*index as usize).cloned()).collect())
+        .unwrap_or(extensions);
+    Ok(Extrinsic {
+        version,
+        ty: None,
+        address_ty: Some(address_ty),
+        call_ty: None,
+        signature_ty: Some(signature_ty),
+        signed_extensions,
+    })
+}
+
+/// Skips a V16 runtime API with its methods.
+fn parse_runtime_api_v16(input: &mut Input) -> Result<(), String> {
+    input.read_string()?;
+    input.read_list(|input| {
+        input.read_string()?;
+        input.read_list(|input| {
+            input.read_string()?;
+            read_type_id(input)
+        })?;
+        read_type_id(input)?;
+        read_docs(input)?;
+        skip_item_deprecation(input)
+    })?;
+    read_docs(input)?;
+    input.read_compact()?;
+    skip_item_deprecation(input)
+}
+
+fn parse_signed_extension(input: &mut Input) -> Result<SignedExtension, String> {
+    let identifier = input.read_string()?;
+    let ty = read_type_id(input)?;
+    let additional_signed = read_type_id(input)?;
+    Ok(SignedExtension { identifier, ty, additional_signed })
+}
+
+/// Fetches and parses the runtime metadata, optionally at a given block hash.
+/// Loads metadata saved to a file, as raw bytes, as a hex string (optionally JSON quoted,
+/// e.g. `gavel call <endpoint> state_getMetadata > metadata.json`) or as a JSON-RPC response.
+pub fn load(path: &std::path::Path) -> Result<Metadata, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => text.trim(),
+        Err(_) => return Ok(Metadata::parse(&bytes)?),
+    };
+    let encoded = if text.starts_with('{') {
+        let response: serde_json::Value = serde_json::from_str(text)?;
+        response["result"].as_str().ok_or("No metadata hex under \"result\"")?.to_string()
+    } else {
+        text.trim_matches('"').to_string()
+    };
+    if !encoded.starts_with("0x") {
+        return Ok(Metadata::parse(&bytes)?);
+    }
+    Ok(Metadata::parse(&hex::decode(&encoded)?)?)
+}
+
+pub async fn fetch(socket: &mut Connection, at: Option<&str>) -> Result<Metadata, Box<dyn std::error::Error>> {
+    let params = match at {
+        Some(hash) => json!([hash]),
+        None => json!([]),
+    };
+    let response = crate::send_and_receive(socket, "state_getMetadata", params).await?;
+    let encoded = response.as_str().ok_or("state_getMetadata did not return metadata")?;
+    Ok(Metadata::parse(&hex::decode(encoded)?)?)
+}
+
+/// Metadata of a given `version` from the `Metadata_metadata_at_version` runtime API,
+/// `None` when the runtime does not provide it (`state_getMetadata` returns V14).
+pub async fn fetch_version(socket: &mut Connection, version: u32, at: Option<&str>) -> Result<Option<Metadata>, Box<dyn std::error::Error>> {
+    let mut params = vec![json!("Metadata_metadata_at_version"), json!(hex::encode(&version.to_le_bytes()))];
+    params.extend(at.map(|hash| json!(hash)));
+    let response = crate::send_and_receive(socket, "state_call", json!(params)).await?;
+    let bytes = hex::decode(response.as_str().ok_or("Metadata_metadata_at_version returned nothing")?)?;
+    match Input::new(&bytes).read_option(|input| input.read_vec().map(<[u8]>::to_vec))? {
+        Some(bytes) => Ok(Some(Metadata::parse(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Alice's sr25519 public key
+    const ALICE: &str = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+    #[test]
+    fn builds_storage_keys_as_substrate_does() {
+        let alice = hex::decode(ALICE).unwrap();
+        let key = [storage_prefix("System", "Account"), Hasher::Blake2_128Concat.hash(&alice)].concat();
+        assert_eq!(
+            hex::encode(&key),
+            "0x26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9de1e86a9a8c739864cf3cc5ec2bea59fd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d",
+        );
+    }
+
+    #[test]
+    fn hashers_keep_their_lengths() {
+        let alice = hex::decode(ALICE).unwrap();
+        let expected = [
+            (Hasher::Blake2_128, "0xde1e86a9a8c739864cf3cc5ec2bea59f"),
+            (Hasher::Blake2_128Concat, "0xde1e86a9a8c739864cf3cc5ec2bea59fd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"),
+            (Hasher::Blake2_256, "0x2e3fb4c297a84c5cebc0e78257d213d0927ccc7596044c6ba013dd05522aacba"),
+            (Hasher::Twox128, "0x518366b5b1bc7c99bae0ba710af1ac66"),
+            (Hasher::Twox256, "0x518366b5b1bc7c99bae0ba710af1ac66ecc0fd2f7c15bbe1eb86dbf45c7899e8"),
+            (Hasher::Twox64Concat, "0x518366b5b1bc7c99d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"),
+            (Hasher::Identity, ALICE),
+        ];
+        for (hasher, hash) in expected {
+            let hashed = hasher.hash(&alice);
+            assert_eq!(hex::encode(&hashed), hash, "{:?}", hasher);
+            assert_eq!(hashed.len(), hasher.hash_len() + if hasher.is_transparent() { alice.len() } else { 0 });
+        }
+    }
+}
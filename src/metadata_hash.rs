@@ -0,0 +1,518 @@
+//! The metadata hash of RFC 78 that the `CheckMetadataHash` signed extension signs, so an
+//! offline signer can check the metadata it decodes a transaction with.
+//!
+//! The types reachable from the extrinsic and its signed extensions are renumbered in the
+//! order of their ids and each type, or each variant of an enum, becomes a leaf. The
+//! digest commits to the root of the binary merkle tree over the leaves, the extrinsic
+//! format and the chain's spec version, name, SS58 prefix, decimals and token symbol.
+//!
+//! Signers that hold no metadata, like the Ledger Polkadot app, get a proof instead: the
+//! leaves needed to decode one transaction and the hashes of the subtrees left out, from
+//! which they recompute the root and so the hash they sign.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{json, Value};
+
+use crate::hashing::blake3_256;
+use crate::metadata::{self, Field, Metadata, Primitive, TypeDef};
+use crate::rpc::Connection;
+use crate::scale::{encode_compact, Input};
+
+// `TypeRef` indices past the primitives, which take 0 to 14 in declaration order
+const COMPACT_U8: u8 = 15;
+const VOID: u8 = 21;
+const PER_ID: u8 = 22;
+
+/// What the digest covers besides the metadata itself.
+pub struct ChainInfo {
+    pub spec_version: u32,
+    pub spec_name: String,
+    pub base58_prefix: u16,
+    pub decimals: u8,
+    pub token_symbol: String,
+}
+
+fn encode_str(out: &mut Vec<u8>, value: &str) {
+    out.extend(encode_compact(value.len() as u128));
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn encode_option_str(out: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            encode_str(out, value);
+        }
+        None => out.push(0),
+    }
+}
+
+/// Types without data (`()`, empty structs and enums) are referenced as `Void` and not stored.
+fn is_void(def: &TypeDef) -> bool {
+    matches!(def, TypeDef::Composite(fields) if fields.is_empty())
+        || matches!(def, TypeDef::Tuple(fields) if fields.is_empty())
+        || matches!(def, TypeDef::Variant(variants) if variants.is_empty())
+}
+
+/// The types reachable from `roots` that get a leaf. Primitives and compacts are referenced
+/// inline and types without data not at all.
+fn reachable(metadata: &Metadata, roots: &[u32]) -> Result<BTreeSet<u32>, String> {
+    let mut reached = BTreeSet::new();
+    let mut pending = roots.to_vec();
+    while let Some(ty) = pending.pop() {
+        let def = &metadata.resolve(ty)?.def;
+        if matches!(def, TypeDef::Primitive(_) | TypeDef::Compact(_)) || is_void(def) || !reached.insert(ty) {
+            continue;
+        }
+        match def {
+            TypeDef::Composite(fields) => pending.extend(fields.iter().map(|field| field.ty)),
+            TypeDef::Variant(variants) => pending.extend(variants.iter().flat_map(|variant| &variant.fields).map(|field| field.ty)),
+            TypeDef::Sequence(inner) | TypeDef::Array(_, inner) => pending.push(*inner),
+            TypeDef::Tuple(fields) => pending.extend(fields),
+            _ => {}
+        }
+    }
+    Ok(reached)
+}
+
+/// A leaf: the type and, for enums, which variant.
+type LeafId = (u32, Option<u8>);
+
+/// Types referenced from the extrinsic mapped to their new ids, in the order of the old ones.
+struct Registry<'a> {
+    metadata: &'a Metadata,
+    ids: BTreeMap<u32, u32>,
+}
+
+impl<'a> Registry<'a> {
+    fn new(metadata: &'a Metadata, roots: &[u32]) -> Result<Self, String> {
+        let ids = reachable(metadata, roots)?.into_iter().enumerate().map(|(new_id, id)| (id, new_id as u32)).collect();
+        Ok(Registry { metadata, ids })
+    }
+
+    fn type_ref(&self, out: &mut Vec<u8>, ty: u32) -> Result<(), String> {
+        let def = &self.metadata.resolve(ty)?.def;
+        match def {
+            TypeDef::Primitive(primitive) => out.push(*primitive as u8),
+            TypeDef::Compact(inner) => out.push(self.compact_ref(*inner)?),
+            _ if is_void(def) => out.push(VOID),
+            _ => {
+                out.push(PER_ID);
+                out.extend(encode_compact(self.ids[&ty] as u128));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `TypeRef` of `Compact<ty>`, looking through single-field wrappers such as `Perbill`.
+    fn compact_ref(&self, ty: u32) -> Result<u8, String> {
+        let def = &self.metadata.resolve(ty)?.def;
+        match def {
+            TypeDef::Primitive(primitive @ (Primitive::U8 | Primitive::U16 | Primitive::U32 | Primitive::U64 | Primitive::U128 | Primitive::U256)) => {
+                Ok(COMPACT_U8 + *primitive as u8 - Primitive::U8 as u8)
+            }
+            TypeDef::Composite(fields) if fields.len() == 1 => self.compact_ref(fields[0].ty),
+            TypeDef::Tuple(fields) if fields.len() == 1 => self.compact_ref(fields[0]),
+            _ if is_void(def) => Ok(VOID),
+            _ => Err(format!("type {} cannot be compact encoded", ty)),
+        }
+    }
+
+    fn fields(&self, out: &mut Vec<u8>, fields: &[Field]) -> Result<(), String> {
+        out.extend(encode_compact(fields.len() as u128));
+        for field in fields {
+            encode_option_str(out, field.name.as_deref());
+            self.type_ref(out, field.ty)?;
+            encode_option_str(out, field.type_name.as_deref());
+        }
+        Ok(())
+    }
+
+    /// The encoded leaves, sorted by new type id and then variant index.
+    fn leaves(&self) -> Result<Vec<(LeafId, Vec<u8>)>, String> {
+        let mut leaves = Vec::new();
+        for (&ty, &id) in &self.ids {
+            let resolved = self.metadata.resolve(ty)?;
+            let mut path = encode_compact(resolved.path.len() as u128);
+            for segment in &resolved.path {
+                encode_str(&mut path, segment);
+            }
+            let mut defs = Vec::new();
+            match &resolved.def {
+                TypeDef::Composite(fields) => {
+                    let mut def = vec![0];
+                    self.fields(&mut def, fields)?;
+                    defs.push((None, def));
+                }
+                TypeDef::Variant(variants) => {
+                    let mut variants: Vec<_> = variants.iter().collect();
+                    variants.sort_by_key(|variant| variant.index);
+                    for variant in variants {
+                        let mut def = vec![1];
+                        encode_str(&mut def, &variant.name);
+                        self.fields(&mut def, &variant.fields)?;
+                        def.extend(encode_compact(variant.index as u128));
+                        defs.push((Some(variant.index), def));
+                    }
+                }
+                TypeDef::Sequence(inner) => {
+                    let mut def = vec![2];
+                    self.type_ref(&mut def, *inner)?;
+                    defs.push((None, def));
+                }
+                TypeDef::Array(len, inner) => {
+                    let mut def = vec![3];
+                    def.extend(len.to_le_bytes());
+                    self.type_ref(&mut def, *inner)?;
+                    defs.push((None, def));
+                }
+                TypeDef::Tuple(fields) => {
+                    let mut def = vec![4];
+                    def.extend(encode_compact(fields.len() as u128));
+                    for field in fields {
+                        self.type_ref(&mut def, *field)?;
+                    }
+                    defs.push((None, def));
+                }
+                TypeDef::BitSequence(store, order) => {
+                    let num_bytes = match self.metadata.resolve(*store)?.def {
+                        TypeDef::Primitive(Primitive::U8) => 1,
+                        TypeDef::Primitive(Primitive::U16) => 2,
+                        TypeDef::Primitive(Primitive::U32) => 4,
+                        TypeDef::Primitive(Primitive::U64) => 8,
+                        _ => return Err(format!("unsupported bit sequence store type {}", store)),
+                    };
+                    let lsb_first = self.metadata.resolve(*order)?.path.last().is_some_and(|name| name == "Lsb0");
+                    defs.push((None, vec![5, num_bytes, lsb_first as u8]));
+                }
+                TypeDef::Primitive(_) | TypeDef::Compact(_) => {}
+            }
+            for (variant, def) in defs {
+                leaves.push(((ty, variant), [path.as_slice(), &def, &encode_compact(id as u128)].concat()));
+            }
+        }
+        Ok(leaves)
+    }
+
+    /// Decodes a value of type `ty` from `input`, noting the leaves a signer needs for it.
+    fn visit(&self, ty: u32, input: &mut Input, accessed: &mut BTreeSet<LeafId>) -> Result<(), String> {
+        let def = &self.metadata.resolve(ty)?.def;
+        if is_void(def) {
+            return Ok(());
+        }
+        match def {
+            TypeDef::Primitive(Primitive::Str) => {
+                input.read_vec()?;
+            }
+            TypeDef::Primitive(primitive) => {
+                let len = match primitive {
+                    Primitive::Bool | Primitive::U8 | Primitive::I8 => 1,
+                    Primitive::U16 | Primitive::I16 => 2,
+                    Primitive::Char | Primitive::U32 | Primitive::I32 => 4,
+                    Primitive::U64 | Primitive::I64 => 8,
+                    Primitive::U128 | Primitive::I128 => 16,
+                    _ => 32,
+                };
+                input.read_bytes(len)?;
+            }
+            TypeDef::Compact(inner) => {
+                if self.compact_ref(*inner)? != VOID {
+                    input.read_compact()?;
+                }
+            }
+            TypeDef::Composite(fields) => {
+                accessed.insert((ty, None));
+                for field in fields {
+                    self.visit(field.ty, input, accessed)?;
+                }
+            }
+            TypeDef::Variant(variants) => {
+                let index = input.read_u8()?;
+                let variant = variants.iter().find(|variant| variant.index == index)
+                    .ok_or_else(|| format!("type {} has no variant {}", ty, index))?;
+                accessed.insert((ty, Some(index)));
+                for field in &variant.fields {
+                    self.visit(field.ty, input, accessed)?;
+                }
+            }
+            TypeDef::Sequence(inner) => {
+                accessed.insert((ty, None));
+                for _ in 0..input.read_length()? {
+                    self.visit(*inner, input, accessed)?;
+                }
+            }
+            TypeDef::Array(len, inner) => {
+                accessed.insert((ty, None));
+                for _ in 0..*len {
+                    self.visit(*inner, input, accessed)?;
+                }
+            }
+            TypeDef::Tuple(fields) => {
+                accessed.insert((ty, None));
+                for field in fields {
+                    self.visit(*field, input, accessed)?;
+                }
+            }
+            TypeDef::BitSequence(store, _) => {
+                accessed.insert((ty, None));
+                let store_bits = match self.metadata.resolve(*store)?.def {
+                    TypeDef::Primitive(Primitive::U8) => 8,
+                    TypeDef::Primitive(Primitive::U16) => 16,
+                    TypeDef::Primitive(Primitive::U32) => 32,
+                    _ => 64,
+                };
+                let bits = input.read_compact()? as usize;
+                input.read_bytes(bits.div_ceil(store_bits) * store_bits / 8)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The tree over `leaves` as an array: node `i` has children `2i + 1` and `2i + 2`, leaf `j`
+/// is node `n - 1 + j`. Built bottom up, the last two nodes of a level hash into the first
+/// parent from the right.
+fn merkle_tree(leaves: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let inner = leaves.len().saturating_sub(1);
+    let mut nodes = vec![Vec::new(); inner];
+    nodes.extend(leaves.iter().map(|leaf| blake3_256(leaf)));
+    for node in (0..inner).rev() {
+        nodes[node] = blake3_256(&[nodes[2 * node + 1].as_slice(), &nodes[2 * node + 2]].concat());
+    }
+    nodes
+}
+
+/// Collects, left to right, the proven leaves under `node` and the hashes of the subtrees
+/// without any.
+fn prove(nodes: &[Vec<u8>], node: usize, proven: &BTreeSet<usize>, leaves: &mut Vec<usize>, hashes: &mut Vec<u8>) {
+    let is_under = |mut leaf: usize| {
+        while leaf > node {
+            leaf = (leaf - 1) / 2;
+        }
+        leaf == node
+    };
+    if proven.contains(&node) {
+        leaves.push(node);
+    } else if !proven.iter().any(|&leaf| is_under(leaf)) {
+        hashes.extend(&nodes[node]);
+    } else {
+        prove(nodes, 2 * node + 1, proven, leaves, hashes);
+        prove(nodes, 2 * node + 2, proven, leaves, hashes);
+    }
+}
+
+/// The registry of the types the extrinsic references and its encoded `ExtrinsicMetadata`.
+fn extrinsic_types(metadata: &Metadata) -> Result<(Registry<'_>, Vec<u8>), String> {
+    if metadata.version != 15 {
+        return Err(format!("The metadata hash is defined over V15 metadata, not V{}", metadata.version));
+    }
+    let extrinsic = &metadata.extrinsic;
+    let missing = |name: &str| format!("metadata has no extrinsic {} type", name);
+    let address = extrinsic.address_ty.ok_or_else(|| missing("address"))?;
+    let call = extrinsic.call_ty.ok_or_else(|| missing("call"))?;
+    let signature = extrinsic.signature_ty.ok_or_else(|| missing("signature"))?;
+    let mut roots = vec![address, call, signature];
+    for extension in &extrinsic.signed_extensions {
+        roots.extend([extension.ty, extension.additional_signed]);
+    }
+    let registry = Registry::new(metadata, &roots)?;
+
+    let mut encoded = vec![extrinsic.version];
+    for ty in [address, call, signature] {
+        registry.type_ref(&mut encoded, ty)?;
+    }
+    encoded.extend(encode_compact(extrinsic.signed_extensions.len() as u128));
+    for extension in &extrinsic.signed_extensions {
+        encode_str(&mut encoded, &extension.identifier);
+        registry.type_ref(&mut encoded, extension.ty)?;
+        registry.type_ref(&mut encoded, extension.additional_signed)?;
+    }
+    Ok((registry, encoded))
+}
+
+/// The encoded `ExtraInfo`, what the digest commits to besides the types.
+fn extra_info(chain: &ChainInfo) -> Vec<u8> {
+    let mut encoded = chain.spec_version.to_le_bytes().to_vec();
+    encode_str(&mut encoded, &chain.spec_name);
+    encoded.extend(chain.base58_prefix.to_le_bytes());
+    encoded.push(chain.decimals);
+    encode_str(&mut encoded, &chain.token_symbol);
+    encoded
+}
+
+/// The 32-byte metadata hash of `metadata` on the chain described by `chain`.
+pub fn digest(metadata: &Metadata, chain: &ChainInfo) -> Result<Vec<u8>, String> {
+    let (registry, extrinsic) = extrinsic_types(metadata)?;
+    let leaves: Vec<Vec<u8>> = registry.leaves()?.into_iter().map(|(_, leaf)| leaf).collect();
+    let root = merkle_tree(&leaves).first().cloned().unwrap_or_else(|| vec![0; 32]);
+
+    // `MetadataDigest::V1`
+    let mut digest = vec![1];
+    digest.extend(root);
+    digest.extend(blake3_256(&extrinsic));
+    digest.extend(extra_info(chain));
+    Ok(blake3_256(&digest))
+}
+
+/// The encoded `MetadataProof` for signing `call` with the signed extension data `extra` and
+/// `additional`: the `Proof` of the leaves needed to decode them, all the address and
+/// signature types included, the `ExtrinsicMetadata` and the `ExtraInfo`.
+pub fn proof(metadata: &Metadata, chain: &ChainInfo, call: &[u8], extra: &[u8], additional: &[u8]) -> Result<Vec<u8>, String> {
+    let (registry, extrinsic) = extrinsic_types(metadata)?;
+    let leaves = registry.leaves()?;
+    let types = &metadata.extrinsic;
+
+    let mut accessed = BTreeSet::new();
+    let mut input = Input::new(call);
+    registry.visit(types.call_ty.unwrap_or_default(), &mut input, &mut accessed).map_err(|e| format!("Failed to decode the call: {}", e))?;
+    if input.remaining() > 0 {
+        return Err(format!("{} bytes left after decoding the call", input.remaining()));
+    }
+    let (mut extra, mut additional) = (Input::new(extra), Input::new(additional));
+    for extension in &types.signed_extensions {
+        let context = |e: String| format!("Failed to decode signed extension {}: {}", extension.identifier, e);
+        registry.visit(extension.ty, &mut extra, &mut accessed).map_err(context)?;
+        registry.visit(extension.additional_signed, &mut additional, &mut accessed).map_err(context)?;
+    }
+    if extra.remaining() > 0 || additional.remaining() > 0 {
+        return Err("Bytes left after decoding the signed extensions".into());
+    }
+    // Every address and signature type, the signer fills those in after the proof is made
+    let signing = reachable(metadata, &[types.address_ty.unwrap_or_default(), types.signature_ty.unwrap_or_default()])?;
+
+    let first_leaf = leaves.len().saturating_sub(1);
+    let proven: BTreeSet<usize> = leaves.iter().enumerate()
+        .filter(|(_, (id, _))| accessed.contains(id) || signing.contains(&id.0))
+        .map(|(index, _)| first_leaf + index)
+        .collect();
+    let leaves: Vec<Vec<u8>> = leaves.into_iter().map(|(_, leaf)| leaf).collect();
+    let (mut indices, mut hashes) = (Vec::new(), Vec::new());
+    if !proven.is_empty() {
+        prove(&merkle_tree(&leaves), 0, &proven, &mut indices, &mut hashes);
+    }
+
+    // `Proof { leaves, leaf_indices, nodes }`
+    let mut encoded = encode_compact(indices.len() as u128);
+    for &index in &indices {
+        encoded.extend(&leaves[index - first_leaf]);
+    }
+    encoded.extend(encode_compact(indices.len() as u128));
+    for &index in &indices {
+        encoded.extend((index as u32).to_le_bytes());
+    }
+    encoded.extend(encode_compact(hashes.len() as u128 / 32));
+    encoded.extend(hashes);
+    encoded.extend(extrinsic);
+    encoded.extend(extra_info(chain));
+    Ok(encoded)
+}
+
+/// V15 metadata at `at`, which the hash is defined over. `state_getMetadata` only serves
+/// V14, which cannot produce it.
+async fn fetch_v15(socket: &mut Connection, at: &str) -> Result<Metadata, Box<dyn std::error::Error>> {
+    metadata::fetch_version(socket, 15, Some(at)).await
+        .map_err(|e| format!("Metadata_metadata_at_version(15) failed, the metadata hash needs V15 metadata: {}", e))?
+        .ok_or_else(|| "The runtime does not provide V15 metadata, which the metadata hash is defined over".into())
+}
+
+/// The V15 metadata at block `at` of a runtime with `spec_version` and `spec_name` and its
+/// chain info, with the decimals and token symbol the node reports in `system_properties`.
+pub async fn fetch(socket: &mut Connection, at: &str, spec_version: u32, spec_name: &str) -> Result<(Metadata, ChainInfo), Box<dyn std::error::Error>> {
+    let metadata = fetch_v15(socket, at).await?;
+    let properties = crate::send_and_receive(socket, "system_properties", json!([])).await?;
+    // Multi-token chains list several, the first is the native one
+    let first = |value: &Value| match value {
+        Value::Array(values) => values.first().cloned(),
+        value if value.is_null() => None,
+        value => Some(value.clone()),
+    };
+    let decimals = first(&properties["tokenDecimals"]).and_then(|value| value.as_u64())
+        .ok_or("system_properties has no tokenDecimals, needed for the metadata hash")?;
+    let token_symbol = first(&properties["tokenSymbol"]).and_then(|value| value.as_str().map(String::from))
+        .ok_or("system_properties has no tokenSymbol, needed for the metadata hash")?;
+    let chain = ChainInfo {
+        spec_version,
+        spec_name: spec_name.to_string(),
+        base58_prefix: metadata.constant_u64("System", "SS58Prefix").unwrap_or(42) as u16,
+        decimals: decimals as u8,
+        token_symbol,
+    };
+    Ok((metadata, chain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+
+    fn fixture(name: &str) -> Metadata {
+        let path = format!("{}/fixtures/metadata/{}_v15.scale.zst", env!("CARGO_MANIFEST_DIR"), name);
+        let bytes = zstd::stream::decode_all(std::fs::File::open(path).unwrap()).unwrap();
+        Metadata::parse(&bytes).unwrap()
+    }
+
+    /// The chain info the reference implementation's tests use for every fixture.
+    fn chain() -> ChainInfo {
+        ChainInfo { spec_version: 1, spec_name: "nice".into(), base58_prefix: 1, decimals: 1, token_symbol: "lol".into() }
+    }
+
+    #[test]
+    fn matches_the_reference_implementation() {
+        for (name, expected) in [
+            ("polkadot", "0x72b3e70cb722edeb45a9380720ecad79b09b4113ab2dee5f5d974f170fb77a7e"),
+            ("acala", "0xbd64dee496517c5288c47014fe0f57c2e12e42a7d627caeafa95e9f992e7e774"),
+        ] {
+            assert_eq!(hex::encode(&digest(&fixture(name), &chain()).unwrap()), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn commits_to_the_chain_info() {
+        let metadata = fixture("acala");
+        let other = ChainInfo { spec_version: 2, ..chain() };
+        assert_ne!(digest(&metadata, &chain()).unwrap(), digest(&metadata, &other).unwrap());
+    }
+
+    /// `transfer_keep_alive` to //Alice and a `batch` of one `remark` on Polkadot, with a
+    /// mortal and an immortal era.
+    fn transactions() -> [(Vec<u8>, Vec<u8>, Vec<u8>); 2] {
+        let alice = "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+        let genesis = "91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
+        let additional = hex::decode(&format!("0xe82600001a000000{}{}", genesis, genesis)).unwrap();
+        [
+            (hex::decode(&format!("0x050300{}02093d00", alice)).unwrap(), hex::decode("0x35001400").unwrap(), additional.clone()),
+            (hex::decode("0x1a00040000081234").unwrap(), hex::decode("0x000000").unwrap(), additional),
+        ]
+    }
+
+    #[test]
+    fn proof_matches_the_reference_implementation() {
+        // Their `Proof` part is byte for byte what merkleized-metadata 0.1.0 generates
+        let expected = [
+            "0x09fde63287bde025bc80d5422a4928ae8371685669d795f8a54d49d78b368e2d",
+            "0x9969ac5dad37edfca9c67b8056137f49d3ed426cdb937349218a513fc5d1a92a",
+        ];
+        let metadata = fixture("polkadot");
+        for ((call, extra, additional), expected) in transactions().iter().zip(expected) {
+            let proof = proof(&metadata, &chain(), call, extra, additional).unwrap();
+            assert_eq!(hex::encode(&blake3_256(&proof)), expected);
+        }
+    }
+
+    #[test]
+    fn proof_needs_the_whole_call() {
+        let metadata = fixture("polkadot");
+        let (mut call, extra, additional) = transactions()[0].clone();
+        call.push(0);
+        assert!(proof(&metadata, &chain(), &call, &extra, &additional).unwrap_err().contains("left after decoding the call"));
+        assert!(proof(&metadata, &chain(), &call[..10], &extra, &additional).is_err());
+    }
+
+    #[test]
+    fn needs_v15_metadata() {
+        let mut metadata = fixture("acala");
+        metadata.version = 14;
+        assert!(digest(&metadata, &chain()).unwrap_err().contains("V15"));
+    }
+}
@@ -0,0 +1,44 @@
+//! Live metrics for long-running modes: the run statistics and latency histograms served
+//! to Prometheus, and dumped as JSON to stderr on SIGUSR1.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::stats;
+
+/// Serves the metrics in the Prometheus text format on `listen`, whatever the path,
+/// until the process exits.
+pub async fn serve(listen: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(listen).await.map_err(|e| format!("Failed to listen on {}: {}", listen, e))?;
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else { continue };
+            tokio::spawn(async move {
+                // The request itself doesn't matter, read enough of it to answer politely
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = stats::prometheus();
+                let response = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(), body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Prints the latency histograms to stderr every time the process gets SIGUSR1.
+pub fn dump_on_sigusr1() {
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut signals) = signal(SignalKind::user_defined1()) else { return };
+        while signals.recv().await.is_some() {
+            eprintln!("{}", serde_json::to_string_pretty(&stats::latency()).unwrap_or_default());
+        }
+    });
+}
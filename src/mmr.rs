@@ -0,0 +1,492 @@
+//! Batch MMR proof generation: one proof per input line, spread over a small pool of
+//! connections and written to one file per proof. Cross-checking one proof against a
+//! second endpoint, and verifying it locally against the node's root, so a relayer does
+//! not trust a single node's proof.
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use futures_util::future::join_all;
+use serde_json::{json, Value};
+
+use crate::hashing::keccak_256;
+use crate::rpc::Connection;
+use crate::scale::Input;
+
+struct Job {
+    line: usize,
+    blocks: Vec<u64>,
+    file: PathBuf,
+}
+
+/// Parses a line of block numbers separated by commas or whitespace, with `a-b` for an
+/// inclusive range.
+fn parse_blocks(line: &str) -> Result<Vec<u64>, String> {
+    let mut blocks = Vec::new();
+    for token in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|token| !token.is_empty()) {
+        match token.split_once('-') {
+            Some((from, to)) => {
+                let from: u64 = from.parse().map_err(|_| format!("invalid block number '{}'", from))?;
+                let to: u64 = to.parse().map_err(|_| format!("invalid block number '{}'", to))?;
+                if to < from {
+                    return Err(format!("empty range '{}'", token));
+                }
+                blocks.extend(from..=to);
+            }
+            None => blocks.push(token.parse().map_err(|_| format!("invalid block number '{}'", token))?),
+        }
+    }
+    Ok(blocks)
+}
+
+/// Reads one proof request per line from `input` (`-` for stdin), skipping blank lines
+/// and `#` comments. Files are named after the block range, e.g. `mmr-100-110.json`.
+fn read_jobs(input: &Path, output_dir: &Path) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    let reader: Box<dyn BufRead> = if input == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::io::BufReader::new(std::fs::File::open(input)?))
+    };
+    let mut jobs: Vec<Job> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let blocks = parse_blocks(line).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        let (first, last) = (blocks.iter().min().unwrap(), blocks.iter().max().unwrap());
+        let mut name = if first == last { format!("mmr-{}", first) } else { format!("mmr-{}-{}", first, last) };
+        if jobs.iter().any(|job| job.file.file_stem().and_then(|stem| stem.to_str()) == Some(&name)) {
+            name = format!("{}-line{}", name, index + 1);
+        }
+        jobs.push(Job { line: index + 1, blocks, file: output_dir.join(format!("{}.json", name)) });
+    }
+    Ok(jobs)
+}
+
+async fn worker(mut socket: Connection, queue: &Mutex<VecDeque<Job>>) -> Vec<Value> {
+    let mut reports = Vec::new();
+    loop {
+        let job = queue.lock().unwrap().pop_front();
+        let Some(job) = job else { break };
+        let proof = crate::send_request(&mut socket, "mmr_generateProof", &json!([job.blocks])).await;
+        let outcome = match proof {
+            Ok(response) if response.get("error").is_some() => Err(response["error"].to_string()),
+            Ok(response) => serde_json::to_string_pretty(&response["result"])
+                .map_err(|e| e.to_string())
+                .and_then(|text| std::fs::write(&job.file, text).map_err(|e| e.to_string())),
+            Err(e) => Err(e.to_string()),
+        };
+        let mut report = json!({
+            "line": job.line,
+            "blocks": job.blocks.len(),
+            "first": job.blocks.iter().min(),
+            "last": job.blocks.iter().max(),
+        });
+        match outcome {
+            Ok(()) => report["file"] = json!(job.file.display().to_string()),
+            Err(e) => {
+                crate::stats::record_failure("rpc");
+                eprintln!("Proof for line {} failed: {}", job.line, e);
+                report["error"] = json!(e);
+            }
+        }
+        reports.push(report);
+    }
+    reports
+}
+
+/// Generates one proof per line of `input` over `connections` sockets, writing each to its
+/// own file in `output_dir`, and prints a summary of the files written and failures.
+pub async fn batch(endpoint: &str, input: &Path, output_dir: &Path, connections: usize, ipv4: Option<&Ipv4Addr>, ipv6: Option<&Ipv6Addr>, max_response_size: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(output_dir)?;
+    let jobs = read_jobs(input, output_dir)?;
+    let total = jobs.len();
+    let mut sockets = Vec::new();
+    for _ in 0..connections.clamp(1, total.max(1)) {
+        sockets.push(crate::connect(endpoint, ipv4, ipv6, max_response_size).await?);
+    }
+
+    let queue = Mutex::new(jobs.into_iter().collect::<VecDeque<_>>());
+    let mut reports: Vec<Value> = join_all(sockets.into_iter().map(|socket| worker(socket, &queue))).await.into_iter().flatten().collect();
+    reports.sort_by_key(|report| report["line"].as_u64());
+    let failed = reports.iter().filter(|report| report.get("error").is_some()).count();
+
+    crate::output::result(&json!({
+        "proofs": total,
+        "written": total - failed,
+        "failed": failed,
+        "results": reports,
+    }))?;
+    if failed > 0 {
+        return Err(format!("{} of {} proofs failed", failed, total).into());
+    }
+    Ok(())
+}
+
+/// A decoded `mmr_generateProof` answer: the opaque leaves and the `LeafProof` fields.
+fn decode_proof(proof: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let leaves = crate::hex::decode(proof["leaves"].as_str().ok_or("No leaves in the MMR proof")?)?;
+    let leaves = Input::new(&leaves).read_list(|input| input.read_vec().map(crate::hex::encode))?;
+    let bytes = crate::hex::decode(proof["proof"].as_str().ok_or("No proof in the MMR proof")?)?;
+    let mut input = Input::new(&bytes);
+    let leaf_indices = input.read_list(Input::read_u64)?;
+    let leaf_count = input.read_u64()?;
+    let items = input.read_list(|input| input.read_bytes(32).map(crate::hex::encode))?;
+    Ok(json!({ "leaves": leaves, "leaf_indices": leaf_indices, "leaf_count": leaf_count, "items": items }))
+}
+
+/// The MMR root at `hash` and the proof of `blocks` against it.
+async fn prove_at(socket: &mut Connection, blocks: &[u64], best_known: u64, hash: &str) -> Result<(Value, Value), Box<dyn std::error::Error>> {
+    let root = crate::send_and_receive(socket, "mmr_root", json!([hash])).await?;
+    let proof = crate::send_and_receive(socket, "mmr_generateProof", json!([blocks, best_known, hash])).await?;
+    Ok((root, proof))
+}
+
+/// Generates the proof of `blocks` (the finalized head by default) on both endpoints,
+/// pinned to the primary's finalized head, and compares the block hash, MMR root, leaves,
+/// leaf indices, leaf count and proof items. Prints the report and fails on any mismatch.
+pub async fn cross_check(primary: &mut Connection, secondary: &mut Connection, secondary_endpoint: &str, blocks: Option<Vec<u64>>) -> Result<(), Box<dyn std::error::Error>> {
+    let finalized = crate::send_and_receive(primary, "chain_getFinalizedHead", json!([])).await?;
+    let hash = finalized.as_str().ok_or("Failed to get the finalized head")?.to_string();
+    let best_known = crate::fetch_header_number(primary, Some(&hash)).await?;
+    let blocks = blocks.unwrap_or_else(|| vec![best_known]);
+
+    let (root, proof) = prove_at(primary, &blocks, best_known, &hash).await?;
+    let mut mismatches = Vec::new();
+    // A node on another fork proves against another root, that alone is worth reporting
+    let secondary_hash = crate::fetch_block_hash(secondary, best_known).await
+        .map_err(|e| format!("{} does not have block {}: {}", secondary_endpoint, best_known, e))?;
+    if secondary_hash != hash {
+        mismatches.push("block_hash");
+    }
+    let (secondary_root, secondary_proof) = prove_at(secondary, &blocks, best_known, &secondary_hash).await?;
+    if secondary_root != root {
+        mismatches.push("root");
+    }
+    let (decoded, secondary_decoded) = (decode_proof(&proof)?, decode_proof(&secondary_proof)?);
+    for field in ["leaves", "leaf_indices", "leaf_count", "items"] {
+        if decoded[field] != secondary_decoded[field] {
+            mismatches.push(field);
+        }
+    }
+
+    let mut report = json!({
+        "blocks": blocks,
+        "best_known": best_known,
+        "at": hash,
+        "root": root,
+        "matches": mismatches.is_empty(),
+        "mismatches": mismatches,
+        "proof": proof,
+        "decoded": decoded,
+    });
+    if !mismatches.is_empty() {
+        report["secondary"] = json!({ "endpoint": crate::redact::text(secondary_endpoint), "at": secondary_hash, "root": secondary_root, "proof": secondary_proof, "decoded": secondary_decoded });
+    }
+    crate::output::result(&report)?;
+    if !mismatches.is_empty() {
+        return Err(format!("{} disagrees on the MMR proof: {} differ", secondary_endpoint, mismatches.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Height of the node at `pos` in its tree, leaves being 0.
+fn pos_height(pos: u64) -> u32 {
+    let mut pos = pos + 1;
+    // Jump left until the position is all ones, a leftmost peak of that height
+    while pos.count_ones() != 64 - pos.leading_zeros() {
+        pos -= (1 << (63 - pos.leading_zeros())) - 1;
+    }
+    63 - pos.leading_zeros()
+}
+
+/// Size of the MMR once it has `leaves` leaves.
+fn mmr_size(leaves: u64) -> u64 {
+    2 * leaves - leaves.count_ones() as u64
+}
+
+/// Position of leaf `index` among all the nodes.
+fn leaf_pos(index: u64) -> u64 {
+    mmr_size(index + 1) - (index + 1).trailing_zeros() as u64 - 1
+}
+
+/// Positions of the peaks of an MMR of `size` nodes, left to right.
+fn peaks(size: u64) -> Vec<u64> {
+    let mut height = 0;
+    while (1u64 << (height + 2)) - 2 < size {
+        height += 1;
+    }
+    let mut pos = (1 << (height + 1)) - 2;
+    let mut peaks = vec![pos];
+    while height > 0 {
+        // Right sibling of the peak, then down its left children until inside the MMR
+        pos += (2 << height) - 1;
+        while pos > size - 1 {
+            if height == 0 {
+                return peaks;
+            }
+            height -= 1;
+            pos -= 2 << height;
+        }
+        peaks.push(pos);
+    }
+    peaks
+}
+
+/// Root of the tree under the peak at `peak` from the leaves below it, taking the
+/// siblings they lack from `items`.
+fn peak_root(leaves: Vec<(u64, Vec<u8>)>, peak: u64, items: &mut impl Iterator<Item = Vec<u8>>) -> Result<Vec<u8>, String> {
+    let mut queue: VecDeque<(u64, Vec<u8>, u32)> = leaves.into_iter().map(|(pos, hash)| (pos, hash, 0)).collect();
+    while let Some((pos, hash, height)) = queue.pop_front() {
+        if pos == peak {
+            return if queue.is_empty() { Ok(hash) } else { Err("leaves left over at a peak".into()) };
+        }
+        let is_right = pos_height(pos + 1) > height;
+        let (sibling, parent) = if is_right {
+            (pos - ((2 << height) - 1), pos + 1)
+        } else {
+            (pos + ((2 << height) - 1), pos + (2 << height))
+        };
+        let sibling = match queue.front() {
+            Some((next, _, _)) if *next == sibling => queue.pop_front().unwrap().1,
+            _ => items.next().ok_or("too few proof items")?,
+        };
+        let hash = if is_right { keccak_256(&[sibling, hash].concat()) } else { keccak_256(&[hash, sibling].concat()) };
+        if parent >= peak {
+            return Ok(hash);
+        }
+        queue.push_back((parent, hash, height + 1));
+    }
+    Err("no leaves under the peak".into())
+}
+
+/// The root an MMR of `leaf_count` leaves would have given these leaf hashes by index and
+/// the proof `items`, as `ckb-merkle-mountain-range` computes it with Keccak-256: peak
+/// roots left to right, then bagged from the right, `hash(right ++ left)`.
+fn root_from_proof(mut leaves: Vec<(u64, Vec<u8>)>, leaf_count: u64, items: Vec<Vec<u8>>) -> Result<Vec<u8>, String> {
+    let size = mmr_size(leaf_count);
+    let mut leaves: Vec<(u64, Vec<u8>)> = leaves.drain(..).map(|(index, hash)| (leaf_pos(index), hash)).collect();
+    leaves.sort_by_key(|(pos, _)| *pos);
+    leaves.dedup_by_key(|(pos, _)| *pos);
+    if leaves.iter().any(|(pos, _)| *pos >= size) {
+        return Err("leaf index beyond the leaf count".into());
+    }
+    let mut items = items.into_iter();
+    let mut peak_hashes = Vec::new();
+    for peak in peaks(size) {
+        let split = leaves.iter().take_while(|(pos, _)| *pos <= peak).count();
+        let under: Vec<_> = leaves.drain(..split).collect();
+        peak_hashes.push(match under.len() {
+            0 => match items.next() {
+                // A peak without leaves is a proof item, or the bagged peaks to its right
+                Some(item) => item,
+                None => break,
+            },
+            1 if under[0].0 == peak => under.into_iter().next().unwrap().1,
+            _ => peak_root(under, peak, &mut items)?,
+        });
+    }
+    peak_hashes.extend(items.next());
+    if items.next().is_some() {
+        return Err("proof items left over".into());
+    }
+    let mut root = peak_hashes.pop().ok_or("empty proof")?;
+    while let Some(left) = peak_hashes.pop() {
+        root = keccak_256(&[root, left].concat());
+    }
+    Ok(root)
+}
+
+/// Recomputes the root from a decoded proof, failing if the proof does not even fit an MMR.
+fn computed_root(decoded: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let hashes = |field: &str| -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        decoded[field].as_array().into_iter().flatten().map(|item| Ok(crate::hex::decode(item.as_str().unwrap_or_default())?)).collect()
+    };
+    let leaves: Vec<Vec<u8>> = hashes("leaves")?.iter().map(|leaf| keccak_256(leaf)).collect();
+    let indices: Vec<u64> = decoded["leaf_indices"].as_array().into_iter().flatten().filter_map(Value::as_u64).collect();
+    if indices.len() != leaves.len() {
+        return Err(format!("{} leaves but {} leaf indices", leaves.len(), indices.len()).into());
+    }
+    let leaf_count = decoded["leaf_count"].as_u64().unwrap_or_default();
+    Ok(root_from_proof(indices.into_iter().zip(leaves).collect(), leaf_count, hashes("items")?)?)
+}
+
+/// The parent block an `MmrLeaf` commits to: after its version byte, the parent's number
+/// and hash.
+fn leaf_parent(leaf: &[u8]) -> Option<(u64, String)> {
+    let mut input = Input::new(leaf);
+    input.read_bytes(1).ok()?;
+    let number = u32::from_le_bytes(input.read_bytes(4).ok()?.try_into().ok()?);
+    Some((number as u64, crate::hex::encode(input.read_bytes(32).ok()?)))
+}
+
+/// Generates the proof of `blocks` (the finalized head, or `--at`, by default) and verifies
+/// it locally: the root computed from the leaves and proof items must equal `mmr_root` at
+/// the same block. With several blocks each is also proven and verified on its own, for a
+/// result per leaf, and each leaf's parent must be the chain's block. Prints the report and
+/// fails unless everything verifies.
+pub async fn verify(socket: &mut Connection, blocks: Option<Vec<u64>>, at: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, at).await?;
+    let best_known = crate::fetch_header_number(socket, Some(&hash)).await?;
+    let blocks = blocks.unwrap_or_else(|| vec![best_known]);
+
+    let (root, proof) = prove_at(socket, &blocks, best_known, &hash).await?;
+    let root = crate::hex::decode(root.as_str().ok_or("mmr_root returned no root")?)?;
+    let decoded = decode_proof(&proof)?;
+    let computed = computed_root(&decoded);
+    let verified = computed.as_ref().is_ok_and(|computed| *computed == root);
+
+    let mut leaves = Vec::new();
+    for (position, block) in blocks.iter().enumerate() {
+        let (leaf, leaf_verified) = if blocks.len() == 1 {
+            (decoded["leaves"][0].clone(), verified)
+        } else {
+            let (_, single) = prove_at(socket, &[*block], best_known, &hash).await?;
+            let single = decode_proof(&single)?;
+            (single["leaves"][0].clone(), computed_root(&single).is_ok_and(|computed| computed == root))
+        };
+        let bytes = crate::hex::decode(leaf.as_str().unwrap_or_default())?;
+        let mut report = json!({
+            "block": block,
+            "leaf_index": decoded["leaf_indices"][position],
+            "leaf_hash": crate::hex::encode(&keccak_256(&bytes)),
+            "verified": leaf_verified,
+        });
+        if let Some((number, parent)) = leaf_parent(&bytes) {
+            let expected = crate::fetch_block_hash(socket, number).await?;
+            report["parent"] = json!({ "number": number, "hash": parent, "matches": number + 1 == *block && expected == parent });
+        }
+        leaves.push(report);
+    }
+    let failed = leaves.iter().filter(|leaf| leaf["verified"] != true || leaf["parent"]["matches"] == false).count();
+
+    crate::output::result(&json!({
+        "blocks": blocks,
+        "best_known": best_known,
+        "at": hash,
+        "root": crate::hex::encode(&root),
+        "computed_root": computed.as_ref().map(|computed| crate::hex::encode(computed)).ok(),
+        "error": computed.as_ref().err().map(|e| e.to_string()),
+        "verified": verified && failed == 0,
+        "leaves": leaves,
+    }))?;
+    if !verified {
+        return Err("The MMR proof does not verify against the node's root".into());
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} leaves failed verification", failed, blocks.len()).into());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Roots and proof items from polkadot-ckb-merkle-mountain-range 0.8.1 with Keccak-256,
+    // the MMR of leaf `i` hashing to `keccak_256([i])`
+    const ONE_LEAF_ROOT: &str = "0xbc36789e7a1e281436464229828f817d6612f7b477d66591ff96a9e064bcc98a";
+    const FOUR_LEAVES_ROOT: &str = "0xdd5115b5dcca3db0bffa31064a0d21f21362cd02e1263e47d69e38bbeec1d359";
+    const SEVEN_LEAVES_ROOT: &str = "0x4dc9f223ea6f2cb405111c4d226e1bf46eff97c52427e576942571aa306be51b";
+    const ELEVEN_LEAVES_ROOT: &str = "0x59d5dfabff33ea4520e961149dda2e67ba134bc1fef4f5b50201293627c921c1";
+
+    fn leaves(indices: &[u64]) -> Vec<(u64, Vec<u8>)> {
+        indices.iter().map(|index| (*index, keccak_256(&[*index as u8]))).collect()
+    }
+
+    fn items(items: &[&str]) -> Vec<Vec<u8>> {
+        items.iter().map(|item| crate::hex::decode(item).unwrap()).collect()
+    }
+
+    fn root(indices: &[u64], leaf_count: u64, proof: &[&str]) -> String {
+        crate::hex::encode(&root_from_proof(leaves(indices), leaf_count, items(proof)).unwrap())
+    }
+
+    #[test]
+    fn lays_out_nodes_as_ckb_does() {
+        let heights: Vec<u32> = (0..26).map(pos_height).collect();
+        assert_eq!(heights, [0, 0, 1, 0, 0, 1, 2, 0, 0, 1, 0, 0, 1, 2, 3, 0, 0, 1, 0, 0, 1, 2, 0, 0, 1, 0]);
+        let positions: Vec<u64> = (0..12).map(leaf_pos).collect();
+        assert_eq!(positions, [0, 1, 3, 4, 7, 8, 10, 11, 15, 16, 18, 19]);
+        for (leaves, size, expected) in [
+            (1, 1, vec![0]),
+            (2, 3, vec![2]),
+            (3, 4, vec![2, 3]),
+            (4, 7, vec![6]),
+            (7, 11, vec![6, 9, 10]),
+            (11, 19, vec![14, 17, 18]),
+            (19, 35, vec![30, 33, 34]),
+        ] {
+            assert_eq!(mmr_size(leaves), size);
+            assert_eq!(peaks(size), expected, "{} leaves", leaves);
+        }
+    }
+
+    #[test]
+    fn proves_a_single_leaf() {
+        assert_eq!(root(&[0], 1, &[]), ONE_LEAF_ROOT);
+    }
+
+    #[test]
+    fn proves_leaves_under_one_peak() {
+        let proof = [
+            "0xbc36789e7a1e281436464229828f817d6612f7b477d66591ff96a9e064bcc98a",
+            "0x2b07d07815e57c23883128aa268a683b3b39aca921fa5f247e9a30c4035d7107",
+        ];
+        assert_eq!(root(&[1], 4, &proof), FOUR_LEAVES_ROOT);
+        let proof = [
+            "0x5fe7f977e71dba2ea1a68e21057beebb9be2ac30c6410aa38d4f3fbe41dcffd2",
+            "0xf2ee15ea639b73fa3db9b34a245bdfa015c260c598b211bf05a1ecc4b3e3b4f2",
+        ];
+        assert_eq!(root(&[0, 3], 4, &proof), FOUR_LEAVES_ROOT);
+    }
+
+    #[test]
+    fn proves_leaves_across_peaks() {
+        // Leaf 6 is a peak of its own, between the two peaks given as items
+        let proof = [
+            "0xdd5115b5dcca3db0bffa31064a0d21f21362cd02e1263e47d69e38bbeec1d359",
+            "0xdb94105de7ec693ff714992c1ee72b496d3fb7bffb8184e03d1fdd8441f49576",
+        ];
+        assert_eq!(root(&[6], 7, &proof), SEVEN_LEAVES_ROOT);
+        let proof = [
+            "0x791521f02a712f28265f5200914f9772b133bc2692260f8c8f426e176b1713ed",
+            "0xd33e25809fcaa2b6900567812852539da8559dc8b76a7ce3fc5ddd77e8d19a69",
+            "0x0ef9d8f8804d174666011a394cab7901679a8944d24249fd148a6a36071151f8",
+        ];
+        assert_eq!(root(&[9], 11, &proof), ELEVEN_LEAVES_ROOT);
+        let proof = [
+            "0x5fe7f977e71dba2ea1a68e21057beebb9be2ac30c6410aa38d4f3fbe41dcffd2",
+            "0x2b07d07815e57c23883128aa268a683b3b39aca921fa5f247e9a30c4035d7107",
+            "0xf9d70df2915506deac3e2cbd36624c90fe2a7f6bdc9599b837377b725bffa243",
+            "0xd33e25809fcaa2b6900567812852539da8559dc8b76a7ce3fc5ddd77e8d19a69",
+        ];
+        assert_eq!(root(&[0, 9, 10], 11, &proof), ELEVEN_LEAVES_ROOT);
+    }
+
+    #[test]
+    fn takes_the_peaks_to_the_right_bagged() {
+        // The last item is both peaks right of leaf 0's, already bagged together
+        let proof = [
+            "0x5fe7f977e71dba2ea1a68e21057beebb9be2ac30c6410aa38d4f3fbe41dcffd2",
+            "0x2b07d07815e57c23883128aa268a683b3b39aca921fa5f247e9a30c4035d7107",
+            "0xf9d70df2915506deac3e2cbd36624c90fe2a7f6bdc9599b837377b725bffa243",
+            "0x0d70c071e6befa647914fab16565d46dd4c12cf340a8a2cd0af5513251c77ef5",
+        ];
+        assert_eq!(root(&[0], 11, &proof), ELEVEN_LEAVES_ROOT);
+    }
+
+    #[test]
+    fn rejects_proofs_that_do_not_fit() {
+        assert!(root_from_proof(leaves(&[4]), 4, Vec::new()).is_err());
+        assert!(root_from_proof(leaves(&[1]), 4, items(&[ONE_LEAF_ROOT])).is_err());
+        assert!(root_from_proof(leaves(&[0]), 1, items(&[ONE_LEAF_ROOT, ONE_LEAF_ROOT])).is_err());
+        assert_ne!(root(&[1], 4, &[ONE_LEAF_ROOT, ONE_LEAF_ROOT]), FOUR_LEAVES_ROOT);
+    }
+}
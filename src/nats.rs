@@ -0,0 +1,133 @@
+//! A minimal NATS publisher for the `nats://[user:pass@]host:4222/subject` sink. Records
+//! published to a JetStream subject wait for the stream's ack; on a plain subject a
+//! PING/PONG round trip confirms the server has them.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use url::Url;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Publisher {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    subject: String,
+    inbox: String,
+    // Unknown until the first publish tells whether a stream answers on the subject
+    jetstream: Option<bool>,
+}
+
+enum Reply {
+    Message { headers: String, payload: Vec<u8> },
+    Pong,
+}
+
+impl Publisher {
+    pub fn connect(url: &Url) -> Result<Publisher, Box<dyn std::error::Error>> {
+        let host = url.host_str().ok_or("Missing host in the NATS URL")?;
+        let subject = url.path().trim_start_matches('/').replace('/', ".");
+        if subject.is_empty() {
+            return Err("Missing subject in the NATS URL, expected nats://host:4222/subject".into());
+        }
+        let stream = TcpStream::connect((host, url.port().unwrap_or(4222)))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut publisher = Publisher {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            subject,
+            inbox: format!("_INBOX.gavel.{:016x}", rand::random::<u64>()),
+            jetstream: None,
+        };
+
+        let mut info = String::new();
+        publisher.reader.read_line(&mut info)?;
+        if !info.starts_with("INFO") {
+            return Err(format!("Unexpected NATS greeting: {}", info.trim()).into());
+        }
+        let mut connect = json!({ "verbose": false, "pedantic": false, "headers": true, "no_responders": true, "name": "gavel", "lang": "rust" });
+        if !url.username().is_empty() {
+            connect["user"] = json!(url.username());
+            connect["pass"] = json!(url.password().unwrap_or_default());
+        }
+        write!(publisher.writer, "CONNECT {}\r\nSUB {} 1\r\n", connect, publisher.inbox)?;
+        publisher.flush()?;
+        Ok(publisher)
+    }
+
+    /// Waits for the next message on the inbox or PONG, answering the server's PINGs.
+    fn read_reply(&mut self) -> Result<Reply, Box<dyn std::error::Error>> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err("NATS server closed the connection".into());
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.first().copied() {
+                Some("PING") => self.writer.write_all(b"PONG\r\n")?,
+                Some("PONG") => return Ok(Reply::Pong),
+                Some("+OK") | Some("INFO") => {}
+                Some("-ERR") => return Err(format!("NATS error: {}", line.trim()).into()),
+                Some("MSG") | Some("HMSG") => {
+                    let numbers: Vec<usize> = parts.iter().rev().take(if parts[0] == "HMSG" { 2 } else { 1 }).filter_map(|n| n.parse().ok()).collect();
+                    let (total, header_len) = match numbers.as_slice() {
+                        [total] => (*total, 0),
+                        [total, header_len] => (*total, *header_len),
+                        _ => return Err(format!("Malformed NATS message line: {}", line.trim()).into()),
+                    };
+                    let mut data = vec![0u8; total + 2];
+                    self.reader.read_exact(&mut data)?;
+                    data.truncate(total);
+                    let payload = data.split_off(header_len);
+                    return Ok(Reply::Message { headers: String::from_utf8_lossy(&data).to_string(), payload });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.writer.write_all(b"PING\r\n")?;
+        loop {
+            if let Reply::Pong = self.read_reply()? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn publish(&mut self, key: &str, value: &str, reply: bool) -> std::io::Result<()> {
+        // The key doubles as JetStream's deduplication id, making redelivery harmless
+        let headers = format!("NATS/1.0\r\nNats-Msg-Id: {}\r\n\r\n", key);
+        let reply_to = if reply { format!(" {}", self.inbox) } else { String::new() };
+        write!(self.writer, "HPUB {}{} {} {}\r\n{}{}\r\n", self.subject, reply_to, headers.len(), headers.len() + value.len(), headers, value)
+    }
+
+    pub fn send(&mut self, records: &[(String, String)]) -> Result<(), Box<dyn std::error::Error>> {
+        for (key, value) in records {
+            if self.jetstream == Some(false) {
+                self.publish(key, value, false)?;
+                continue;
+            }
+            self.publish(key, value, true)?;
+            self.writer.flush()?;
+            let Reply::Message { headers, payload } = self.read_reply()? else { continue };
+            if headers.starts_with("NATS/1.0 503") {
+                // No stream listens on the subject, fall back to core NATS
+                self.jetstream = Some(false);
+                self.publish(key, value, false)?;
+                continue;
+            }
+            self.jetstream = Some(true);
+            let ack: Value = serde_json::from_slice(&payload).unwrap_or_default();
+            if let Some(error) = ack.get("error") {
+                return Err(format!("JetStream rejected the record: {}", error).into());
+            }
+        }
+        if self.jetstream == Some(false) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
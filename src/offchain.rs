@@ -0,0 +1,56 @@
+use serde_json::{json, Value};
+
+use crate::rpc::Connection;
+use crate::{hex, stats};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum StorageKind {
+    Persistent,
+    Local,
+}
+
+impl StorageKind {
+    fn as_rpc(self) -> &'static str {
+        match self {
+            StorageKind::Persistent => "PERSISTENT",
+            StorageKind::Local => "LOCAL",
+        }
+    }
+}
+
+/// Reads an offchain storage value, returning it as hex and, when printable, as a string.
+pub async fn get(socket: &mut Connection, kind: StorageKind, key: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let key = hex::from_hex_or_string(key)?;
+    let response = crate::send_request(socket, "offchain_localStorageGet", &json!([kind.as_rpc(), key])).await?;
+    if let Some(error) = response.get("error") {
+        stats::record_failure("rpc");
+        return Err(format!("offchain_localStorageGet failed (the node needs --rpc-methods unsafe): {}", error).into());
+    }
+
+    let value = response["result"].as_str().map(|value| {
+        let string = hex::decode(value).ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .filter(|string| !string.chars().any(char::is_control));
+        json!({ "hex": value, "string": string })
+    });
+    Ok(json!({
+        "kind": kind.as_rpc(),
+        "key": key,
+        "value": value,
+    }))
+}
+
+pub async fn set(socket: &mut Connection, kind: StorageKind, key: &str, value: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let key = hex::from_hex_or_string(key)?;
+    let value = hex::from_hex_or_string(value)?;
+    let response = crate::send_request(socket, "offchain_localStorageSet", &json!([kind.as_rpc(), key, value])).await?;
+    if let Some(error) = response.get("error") {
+        stats::record_failure("rpc");
+        return Err(format!("offchain_localStorageSet failed (the node needs --rpc-methods unsafe): {}", error).into());
+    }
+    Ok(json!({
+        "kind": kind.as_rpc(),
+        "key": key,
+        "value": value,
+    }))
+}
@@ -0,0 +1,321 @@
+//! Where commands write what they print: stdout, or a file given with `--output` that
+//! can rotate by size with `--rotate`. Results are rendered in the `--format` chosen,
+//! streaming records are always JSON lines.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use serde_json::{Map, Value};
+
+/// How a command's result is rendered. `csv` and `table` have one row per element of an
+/// array result (or one row for anything else), with nested objects flattened into
+/// dotted column names and arrays kept as JSON.
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Ndjson,
+    Yaml,
+    Csv,
+    Table,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// `--rotate size=500M,keep=10,gzip`: start a new file once the current one would grow
+/// past `size`, keeping the `keep` most recent rotated files, optionally gzipped.
+#[derive(Clone, Debug)]
+pub struct Rotate {
+    max_size: u64,
+    keep: usize,
+    gzip: bool,
+}
+
+pub fn parse_rotate(spec: &str) -> Result<Rotate, String> {
+    let mut rotate = Rotate { max_size: 0, keep: 5, gzip: false };
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('=') {
+            Some(("size", size)) => rotate.max_size = crate::parse_byte_size(size)? as u64,
+            Some(("keep", keep)) => rotate.keep = keep.parse().map_err(|_| format!("invalid keep count '{}'", keep))?,
+            None if part == "gzip" => rotate.gzip = true,
+            _ => return Err(format!("unknown rotate option '{}', expected size=<SIZE>, keep=<N> or gzip", part)),
+        }
+    }
+    if rotate.max_size == 0 {
+        return Err("--rotate needs a size, e.g. size=500M".into());
+    }
+    Ok(rotate)
+}
+
+struct Output {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotate: Option<Rotate>,
+    compressing: Option<JoinHandle<()>>,
+}
+
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+/// Sets the format results are rendered in.
+pub fn set_format(format: Option<Format>) {
+    if let Some(format) = format {
+        let _ = FORMAT.set(format);
+    }
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Sends results and the records of streaming modes to `path` (appending) instead of stdout.
+pub fn configure(path: Option<&Path>, rotate: Option<Rotate>) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = path else {
+        return match rotate {
+            Some(_) => Err("--rotate needs --output".into()),
+            None => Ok(()),
+        };
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Failed to open output file {}: {}", path.display(), e))?;
+    let size = file.metadata()?.len();
+    *OUTPUT.lock().unwrap() = Some(Output { path: path.to_path_buf(), file, size, rotate, compressing: None });
+    Ok(())
+}
+
+fn rotated(path: &Path, index: usize, gzip: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}{}", index, if gzip { ".gz" } else { "" }));
+    PathBuf::from(name)
+}
+
+impl Output {
+    /// Shifts `file.1` .. `file.<keep>` up by one, dropping the oldest, moves the current
+    /// file to `file.1` and starts a new one. Compression runs on a background thread.
+    fn rotate(&mut self, rotate: &Rotate) -> std::io::Result<()> {
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+        self.file.flush()?;
+        for gzip in [false, true] {
+            let _ = std::fs::remove_file(rotated(&self.path, rotate.keep, gzip));
+            for index in (1..rotate.keep).rev() {
+                let _ = std::fs::rename(rotated(&self.path, index, gzip), rotated(&self.path, index + 1, gzip));
+            }
+        }
+        if rotate.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            std::fs::rename(&self.path, rotated(&self.path, 1, false))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+
+        if rotate.gzip && rotate.keep > 0 {
+            let (plain, compressed) = (rotated(&self.path, 1, false), rotated(&self.path, 1, true));
+            self.compressing = Some(std::thread::spawn(move || {
+                let result = File::open(&plain).and_then(|input| {
+                    gzip_file(input, &compressed)?;
+                    std::fs::remove_file(&plain)
+                });
+                if let Err(e) = result {
+                    eprintln!("Warning: failed to compress {}: {}", plain.display(), e);
+                }
+            }));
+        }
+        Ok(())
+    }
+}
+
+fn gzip_file(input: File, compressed: &Path) -> std::io::Result<()> {
+    let output = std::io::BufWriter::new(File::create(compressed)?);
+    crate::gzip::compress(std::io::BufReader::new(input), output)
+}
+
+/// Writes one record, followed by a newline, to the output file or stdout, and queues
+/// it for the `--sink` if there is one.
+pub fn line(text: &str) -> std::io::Result<()> {
+    let text = &crate::redact::text(text);
+    crate::sink::send(text);
+    write(text)
+}
+
+/// `value` in the `--format` chosen.
+fn render(value: &Value) -> std::io::Result<String> {
+    Ok(match format() {
+        Format::Json => serde_json::to_string_pretty(value)?,
+        Format::Ndjson => match value {
+            Value::Array(items) => items.iter().map(Value::to_string).collect::<Vec<_>>().join("\n"),
+            _ => value.to_string(),
+        },
+        Format::Yaml => {
+            let mut text = String::new();
+            yaml(value, 0, &mut text);
+            text.trim_end().to_string()
+        }
+        Format::Csv => {
+            let (columns, rows) = table(value);
+            std::iter::once(columns).chain(rows)
+                .map(|row| row.iter().map(|cell| csv_field(cell)).collect::<Vec<_>>().join(","))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Format::Table => text_table(value),
+    })
+}
+
+/// Writes a command's result in the `--format` chosen to the output file or stdout.
+pub fn result(value: &Value) -> std::io::Result<()> {
+    write(&render(value)?)
+}
+
+/// Writes a result holding secrets in the `--format` chosen to stdout, never to the
+/// output file where records pile up.
+pub fn secret_result(value: &Value) -> std::io::Result<()> {
+    println!("{}", render(value)?);
+    Ok(())
+}
+
+/// `value` as an aligned text table, as `--format table` renders it.
+pub fn text_table(value: &Value) -> String {
+    let (columns, rows) = table(value);
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| std::iter::once(&columns).chain(&rows).map(|row| row[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    std::iter::once(&columns).chain(&rows)
+        .map(|row| row.iter().zip(&widths).map(|(cell, width)| format!("{:<1$}", cell, width)).collect::<Vec<_>>().join("  ").trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The column names and rows of a result: the elements of an array, else the value itself.
+fn table(value: &Value) -> (Vec<String>, Vec<Vec<String>>) {
+    let items = match value {
+        Value::Array(items) => items.iter().collect(),
+        _ => vec![value],
+    };
+    let flattened: Vec<Map<String, Value>> = items.into_iter().map(|item| {
+        let mut row = Map::new();
+        flatten("", item, &mut row);
+        row
+    }).collect();
+    let mut columns: Vec<String> = Vec::new();
+    for key in flattened.iter().flat_map(|row| row.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+    let rows = flattened.iter()
+        .map(|row| columns.iter().map(|column| row.get(column).map(cell).unwrap_or_default()).collect())
+        .collect();
+    (columns, rows)
+}
+
+fn flatten(prefix: &str, value: &Value, row: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, field) in fields {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&name, field, row);
+            }
+        }
+        _ => {
+            row.insert(if prefix.is_empty() { "value".to_string() } else { prefix.to_string() }, value.clone());
+        }
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn yaml(value: &Value, indent: usize, out: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, field) in fields {
+                out.push_str(&format!("{}{}:", pad, yaml_scalar(&Value::String(key.clone()))));
+                yaml_nested(field, indent, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                out.push_str(&format!("{}-", pad));
+                yaml_nested(item, indent, out);
+            }
+        }
+        _ => out.push_str(&format!("{}{}\n", pad, yaml_scalar(value))),
+    }
+}
+
+/// The value after a `key:` or `-`: inline if it is a scalar, else indented below.
+fn yaml_nested(value: &Value, indent: usize, out: &mut String) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            out.push('\n');
+            yaml(value, indent + 2, out);
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('\n');
+            yaml(value, indent + 2, out);
+        }
+        _ => out.push_str(&format!(" {}\n", yaml_scalar(value))),
+    }
+}
+
+/// A scalar, with strings double-quoted (JSON escapes are valid YAML) unless they read
+/// back as the same plain string, which hex like `0x12` and numbers would not.
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        Value::String(text) => {
+            let plain = !text.is_empty()
+                && text.chars().all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+                && !text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.')
+                && !["true", "false", "null", "yes", "no", "on", "off", "~"].contains(&text.to_ascii_lowercase().as_str());
+            if plain { text.clone() } else { value.to_string() }
+        }
+        other => other.to_string(),
+    }
+}
+
+fn write(text: &str) -> std::io::Result<()> {
+    let mut output = OUTPUT.lock().unwrap();
+    let Some(output) = output.as_mut() else {
+        println!("{}", text);
+        return Ok(());
+    };
+    let length = text.len() as u64 + 1;
+    if let Some(rotate) = output.rotate.clone() {
+        if output.size > 0 && output.size + length > rotate.max_size {
+            output.rotate(&rotate)?;
+        }
+    }
+    writeln!(output.file, "{}", text)?;
+    output.size += length;
+    Ok(())
+}
+
+/// Waits for a rotated file still being compressed, so exiting doesn't leave it half done.
+pub fn finish() {
+    if let Some(compressing) = OUTPUT.lock().unwrap().as_mut().and_then(|output| output.compressing.take()) {
+        let _ = compressing.join();
+    }
+}
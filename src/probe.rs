@@ -0,0 +1,357 @@
+//! `gavel probe`: finds out how an endpoint behaves under load before a long run trusts it.
+//!
+//! The rate-limit probe ramps the request rate step by step until the provider pushes
+//! back: a JSON-RPC rate-limit error (`-32005`, or a message about limits), a close frame,
+//! HTTP 429 on the WebSocket handshake or a latency cliff. The last clean step is the
+//! apparent ceiling. It then retries at growing intervals to measure how long the
+//! provider keeps refusing, the ban duration.
+//!
+//! The backends probe reconnects over and over, optionally to every address the host
+//! resolves to, and groups the answers by peer id and node version to count the nodes a
+//! load balancer spreads connections over, with the health of each.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// JSON-RPC error code for "limit exceeded", used by Substrate RPC middleware and most providers.
+const LIMIT_EXCEEDED: i64 = -32005;
+
+/// Time to wait for the answers still outstanding at the end of a step.
+const DRAIN: Duration = Duration::from_secs(5);
+
+/// A step whose median latency grew this many times over the first step's is a cliff.
+const CLIFF_FACTOR: f64 = 5.0;
+
+/// A backend or node this many blocks behind the best one seen is lagging.
+pub const MAX_LAG: u64 = 3;
+
+#[derive(clap::Args, Debug)]
+pub struct Ramp {
+    #[clap(long, default_value = "system_health", help = "Method to call, with no params.")]
+    pub method: String,
+    #[clap(long, default_value = "5", help = "Requests per second of the first step.")]
+    pub start_rps: u64,
+    #[clap(long, default_value = "1000", help = "Stop ramping at this rate even without pushback.")]
+    pub max_rps: u64,
+    #[clap(long, default_value = "5", help = "Seconds per step; the rate doubles every step.")]
+    pub step_secs: u64,
+    #[clap(long, default_value = "600", help = "Seconds to keep retrying after pushback when measuring the ban duration.")]
+    pub ban_timeout: u64,
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct Target<'a> {
+    pub endpoint: &'a str,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub max_response_size: Option<usize>,
+}
+
+impl Target<'_> {
+    /// Connects, telling a handshake refused with HTTP 429 apart from other failures.
+    async fn connect(&self) -> Result<Result<Socket, String>, Box<dyn std::error::Error>> {
+        match crate::connect_websocket(self.endpoint, self.ipv4.as_ref(), self.ipv6.as_ref(), self.max_response_size).await {
+            Ok(socket) => Ok(Ok(socket)),
+            Err(e) => match crate::failure::details(e.as_ref()) {
+                Some(details) if details["status"] == 429 => Ok(Err(match details["retry-after"].as_str() {
+                    Some(retry_after) => format!("HTTP 429 on handshake (Retry-After: {})", retry_after),
+                    None => "HTTP 429 on handshake".to_string(),
+                })),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// Whether a JSON-RPC error is the provider limiting the request rate.
+pub fn is_rate_limit(error: &Value) -> bool {
+    let message = error["message"].as_str().unwrap_or_default().to_lowercase();
+    error["code"].as_i64() == Some(LIMIT_EXCEEDED) || error["code"].as_i64() == Some(429)
+        || ["rate limit", "too many", "limit exceeded", "throttl"].iter().any(|needle| message.contains(needle))
+}
+
+pub fn percentile(sorted: &[f64], fraction: f64) -> Option<f64> {
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).checked_sub(1)?;
+    sorted.get(index).map(|ms| (ms * 100.0).round() / 100.0)
+}
+
+#[derive(Default)]
+struct Step {
+    sent: u64,
+    answered: u64,
+    limited: u64,
+    errors: u64,
+    latencies_ms: Vec<f64>,
+    // Why the step saw pushback, the first signal only
+    signal: Option<String>,
+    elapsed: Duration,
+}
+
+impl Step {
+    fn p50(&self) -> Option<f64> {
+        percentile(&self.latencies_ms, 0.5)
+    }
+
+    fn report(&self, rps: u64) -> Value {
+        json!({
+            "rps": rps,
+            "sent": self.sent,
+            "answered": self.answered,
+            "limited": self.limited,
+            "errors": self.errors,
+            "timeouts": self.sent - self.answered,
+            "throughput_rps": (self.answered as f64 / self.elapsed.as_secs_f64().max(0.001) * 10.0).round() / 10.0,
+            "p50_ms": self.p50(),
+            "p95_ms": percentile(&self.latencies_ms, 0.95),
+            "signal": self.signal,
+        })
+    }
+}
+
+/// Sends `rps` requests a second for `duration` on one connection, without waiting for
+/// answers, so the provider sees the rate and not our round trips.
+async fn run_step(socket: &mut Socket, method: &str, rps: u64, duration: Duration, next_id: &mut u64) -> Step {
+    let mut step = Step::default();
+    let total = rps * duration.as_secs().max(1);
+    let mut pending: HashMap<u64, Instant> = HashMap::new();
+    let mut ticks = tokio::time::interval(Duration::from_secs(1) / rps.max(1) as u32);
+    let start = Instant::now();
+    let (mut sink, mut stream) = socket.split();
+
+    loop {
+        let draining = step.sent == total;
+        if draining && (pending.is_empty() || start.elapsed() > duration + DRAIN) {
+            break;
+        }
+        tokio::select! {
+            _ = ticks.tick(), if !draining => {
+                *next_id += 1;
+                let request = json!({ "jsonrpc": "2.0", "id": *next_id, "method": method, "params": [] }).to_string();
+                if sink.send(Message::Text(request)).await.is_err() {
+                    step.signal.get_or_insert_with(|| "connection dropped while sending".into());
+                    break;
+                }
+                pending.insert(*next_id, Instant::now());
+                step.sent += 1;
+            }
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let response: Value = serde_json::from_str(&text).unwrap_or_default();
+                    let Some(sent) = response["id"].as_u64().and_then(|id| pending.remove(&id)) else { continue };
+                    step.answered += 1;
+                    if let Some(error) = response.get("error") {
+                        if is_rate_limit(error) {
+                            step.limited += 1;
+                            step.signal.get_or_insert_with(|| format!("error {}: {}", error["code"], error["message"].as_str().unwrap_or_default()));
+                        } else {
+                            step.errors += 1;
+                        }
+                    } else {
+                        step.latencies_ms.push(sent.elapsed().as_secs_f64() * 1000.0);
+                    }
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    step.signal.get_or_insert_with(|| match frame {
+                        Some(frame) => format!("close {}: {}", u16::from(frame.code), frame.reason),
+                        None => "close without a code".into(),
+                    });
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    step.signal.get_or_insert_with(|| format!("connection dropped: {}", e));
+                    break;
+                }
+                None => {
+                    step.signal.get_or_insert_with(|| "connection dropped".into());
+                    break;
+                }
+            },
+            _ = tokio::time::sleep(DRAIN), if draining => {}
+        }
+    }
+    step.elapsed = start.elapsed();
+    step.latencies_ms.sort_by(f64::total_cmp);
+    step
+}
+
+/// Whether one request on a new connection gets a normal answer, for measuring the ban.
+async fn accepted(target: &Target<'_>, method: &str) -> bool {
+    let Ok(Ok(socket)) = target.connect().await else { return false };
+    let mut socket = crate::Connection::new(socket);
+    match tokio::time::timeout(DRAIN, crate::send_request(&mut socket, method, &json!([]))).await {
+        Ok(Ok(response)) => response.get("error").is_none_or(|error| !is_rate_limit(error)),
+        _ => false,
+    }
+}
+
+pub async fn rate_limit(target: &Target<'_>, ramp: &Ramp) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = match target.connect().await? {
+        Ok(socket) => socket,
+        Err(signal) => return Err(format!("{} before the first request, the endpoint is already limiting this address", signal).into()),
+    };
+    let duration = Duration::from_secs(ramp.step_secs.max(1));
+    let mut steps = Vec::new();
+    let mut ceiling: Option<f64> = None;
+    let mut baseline_ms: Option<f64> = None;
+    let mut signal = None;
+    let mut next_id = 0;
+    let mut rps = ramp.start_rps.max(1);
+
+    while rps <= ramp.max_rps {
+        let step = run_step(&mut socket, &ramp.method, rps, duration, &mut next_id).await;
+        eprintln!("{} rps: {} of {} answered, {} limited, p50 {} ms", rps, step.answered, step.sent, step.limited, step.p50().map_or("-".into(), |p50| p50.to_string()));
+        let mut report = step.report(rps);
+        let cliff = match (baseline_ms, step.p50()) {
+            (Some(baseline), Some(p50)) if p50 > baseline * CLIFF_FACTOR && p50 > baseline + 50.0 => {
+                Some(format!("latency cliff: p50 {:.1} ms against {:.1} ms at the first step", p50, baseline))
+            }
+            _ => None,
+        };
+        baseline_ms = baseline_ms.or(step.p50());
+        if let Some(pushback) = step.signal.clone().or(cliff) {
+            report["signal"] = json!(pushback);
+            steps.push(report);
+            signal = Some(pushback);
+            break;
+        }
+        ceiling = Some(report["throughput_rps"].as_f64().unwrap_or_default());
+        steps.push(report);
+        rps *= 2;
+    }
+    drop(socket);
+
+    // Back off exponentially so the probing itself doesn't keep extending the ban
+    let mut ban = Value::Null;
+    if signal.is_some() {
+        let limited_at = Instant::now();
+        let mut wait = Duration::from_secs(1);
+        ban = json!({ "recovered": false, "at_least_secs": ramp.ban_timeout });
+        while limited_at.elapsed() < Duration::from_secs(ramp.ban_timeout) {
+            if accepted(target, &ramp.method).await {
+                ban = json!({ "recovered": true, "secs": limited_at.elapsed().as_secs() });
+                break;
+            }
+            tokio::time::sleep(wait).await;
+            wait = (wait * 2).min(Duration::from_secs(60));
+        }
+    }
+
+    Ok(json!({
+        "endpoint": target.endpoint,
+        "method": ramp.method,
+        "rate_limited": signal.is_some(),
+        "signal": signal,
+        "ceiling_rps": ceiling,
+        "ban": ban,
+        "steps": steps,
+    }))
+}
+
+/// What one connection saw of the backend it landed on.
+struct Sample {
+    address: Option<IpAddr>,
+    peer_id: Value,
+    version: Value,
+    health: Value,
+    best: Option<u64>,
+    latency_ms: f64,
+}
+
+async fn sample(target: &Target<'_>, address: Option<IpAddr>) -> Result<Sample, Box<dyn std::error::Error>> {
+    let (ipv4, ipv6) = match address {
+        Some(IpAddr::V4(v4)) => (Some(v4), None),
+        Some(IpAddr::V6(v6)) => (None, Some(v6)),
+        None => (target.ipv4, target.ipv6),
+    };
+    let mut socket = crate::connect(target.endpoint, ipv4.as_ref(), ipv6.as_ref(), target.max_response_size).await?;
+    let start = Instant::now();
+    let peer_id = crate::send_and_receive(&mut socket, "system_localPeerId", json!([])).await?;
+    let version = crate::send_and_receive(&mut socket, "system_version", json!([])).await?;
+    let health = crate::send_and_receive(&mut socket, "system_health", json!([])).await?;
+    let header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([])).await?;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0 / 4.0;
+    let best = header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok());
+    socket.close().await;
+    Ok(Sample { address, peer_id, version, health, best, latency_ms })
+}
+
+/// Opens `connections` connections one after the other (spread over every resolved
+/// address with `all_addresses`) and clusters them by the node that answered.
+pub async fn backends(target: &Target<'_>, connections: u32, all_addresses: bool) -> Result<Value, Box<dyn std::error::Error>> {
+    let addresses: Vec<Option<IpAddr>> = if all_addresses {
+        crate::fleet::addresses(target.endpoint).await?.into_iter().map(Some).collect()
+    } else {
+        vec![None]
+    };
+    let mut samples = Vec::new();
+    let mut failures = Vec::new();
+    for index in 0..connections.max(1) as usize {
+        let address = addresses[index % addresses.len()];
+        match tokio::time::timeout(DRAIN * 2, sample(target, address)).await {
+            Ok(Ok(sample)) => samples.push(sample),
+            Ok(Err(e)) => failures.push(json!({
+                "address": address,
+                "error": e.to_string(),
+                "failure": crate::failure::details(e.as_ref()),
+            })),
+            Err(_) => failures.push(json!({ "address": address, "error": format!("no answer within {}s", (DRAIN * 2).as_secs()) })),
+        }
+    }
+
+    // Nodes with RPC access to system_localPeerId disabled still differ by version
+    let mut clusters: BTreeMap<(String, String), Vec<(usize, &Sample)>> = BTreeMap::new();
+    for (index, sample) in samples.iter().enumerate() {
+        let key = (sample.peer_id.as_str().unwrap_or("unknown").to_string(), sample.version.as_str().unwrap_or("unknown").to_string());
+        clusters.entry(key).or_default().push((index, sample));
+    }
+    let best_seen = samples.iter().filter_map(|sample| sample.best).max();
+    // A backend first seen late suggests more connections would find more of them
+    let last_new = clusters.values().map(|hits| hits[0].0 + 1).max();
+
+    let backends: Vec<Value> = clusters.iter().map(|((peer_id, version), hits)| {
+        let latest = hits.last().map(|(_, sample)| *sample).unwrap();
+        let best = hits.iter().filter_map(|(_, sample)| sample.best).max();
+        let lag = best_seen.zip(best).map(|(seen, best)| seen.saturating_sub(best));
+        let mut latencies: Vec<f64> = hits.iter().map(|(_, sample)| sample.latency_ms).collect();
+        latencies.sort_by(f64::total_cmp);
+        let addresses: BTreeSet<String> = hits.iter().filter_map(|(_, sample)| sample.address.map(|address| address.to_string())).collect();
+        let syncing = latest.health["isSyncing"].as_bool();
+        let peers = latest.health["peers"].as_u64();
+        let should_have_peers = latest.health["shouldHavePeers"].as_bool().unwrap_or(true);
+        let healthy = syncing == Some(false) && (!should_have_peers || peers.is_some_and(|peers| peers > 0)) && lag.is_some_and(|lag| lag <= MAX_LAG);
+        json!({
+            "peer_id": peer_id,
+            "version": version,
+            "hits": hits.len(),
+            "share": (hits.len() as f64 / samples.len() as f64 * 1000.0).round() / 1000.0,
+            "addresses": addresses,
+            "best": best,
+            "lag": lag,
+            "peers": peers,
+            "is_syncing": syncing,
+            "p50_ms": percentile(&latencies, 0.5),
+            "healthy": healthy,
+        })
+    }).collect();
+
+    Ok(json!({
+        "endpoint": target.endpoint,
+        "connections": connections.max(1),
+        "answered": samples.len(),
+        "backends_found": backends.len(),
+        "unhealthy": backends.iter().filter(|backend| backend["healthy"] == false).count(),
+        "last_new_backend_at": last_new,
+        "backends": backends,
+        "failures": failures,
+    }))
+}
@@ -0,0 +1,521 @@
+//! `gavel proxy`: a WebSocket JSON-RPC gateway in front of one node that records what
+//! clients send, with per-method latency and error rates, and can inject failures for
+//! chaos testing.
+//!
+//! Each client gets its own upstream connection, so subscriptions and request ids need
+//! no translation.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::cache::Cache;
+use crate::stats;
+
+pub struct Options {
+    pub upstream: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub max_response_size: Option<usize>,
+    pub record_payloads: bool,
+    pub chaos: Chaos,
+    pub filter: Filter,
+    // Shared by all clients
+    pub cache: Mutex<Cache>,
+}
+
+/// Faults to inject for chaos testing of clients, all off by default.
+#[derive(clap::Args, Debug)]
+pub struct Chaos {
+    #[clap(long, default_value = "0", value_parser = parse_rate, help = "Fraction of requests to answer with an injected error instead of forwarding, e.g. 0.05.")]
+    pub fail_rate: f64,
+    #[clap(long, default_value = "0", help = "Milliseconds to delay every message from the upstream.")]
+    pub delay_ms: u64,
+    #[clap(long, default_value = "0", help = "Up to this many more milliseconds of random delay per message.")]
+    pub jitter_ms: u64,
+    #[clap(long, default_value = "0", value_parser = parse_rate, help = "Fraction of upstream messages on which to drop the client connection without a close frame.")]
+    pub drop_rate: f64,
+    #[clap(long, default_value = "0", value_parser = parse_rate, help = "Fraction of upstream messages to deliver truncated, as malformed JSON.")]
+    pub malformed_rate: f64,
+    #[clap(long, default_value = "0", value_parser = parse_rate, help = "Fraction of upstream messages to hold back and deliver after the next one.")]
+    pub reorder_rate: f64,
+}
+
+// How long a held back message waits for another one to overtake it
+const REORDER_WINDOW: Duration = Duration::from_millis(500);
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+impl Chaos {
+    async fn delay(&self) {
+        let jitter = if self.jitter_ms > 0 { rand::random::<u64>() % (self.jitter_ms + 1) } else { 0 };
+        if self.delay_ms + jitter > 0 {
+            tokio::time::sleep(Duration::from_millis(self.delay_ms + jitter)).await;
+        }
+    }
+}
+
+struct Pending {
+    method: String,
+    params: Value,
+    sent: Instant,
+    // Where to cache the answer, for calls pinned to a block hash
+    cache_keys: Vec<String>,
+}
+
+/// The state of one client connection, shared by both directions.
+#[derive(Default)]
+struct Session {
+    // Requests forwarded upstream, by id
+    pending: HashMap<String, Pending>,
+    // Answers the proxy gave itself for part of a batch, by the id of a forwarded
+    // request in the same batch, to be merged into the upstream's batch response
+    merged: HashMap<String, Vec<Value>>,
+}
+
+/// Methods unsafe to expose publicly, as Substrate nodes classify them under
+/// `--rpc-methods safe`: key management, peer and log control, offchain storage and
+/// expensive state scans.
+const UNSAFE_METHODS: &[&str] = &[
+    "author_insertKey", "author_rotateKeys", "author_hasKey", "author_hasSessionKeys", "author_removeExtrinsic",
+    "system_addReservedPeer", "system_removeReservedPeer", "system_reservedPeers", "system_nodeRoles",
+    "system_networkState", "system_unstable_networkState", "system_peers", "system_addLogFilter", "system_resetLogFilter",
+    "offchain_*",
+    "state_queryStorage", "state_getPairs", "state_traceBlock", "state_trieMigrationStatus",
+    "dev_*", "sync_state_genSyncSpec", "system_dryRun", "system_dryRunAt",
+];
+
+/// Which methods clients may call: any allow pattern (all when there are none) and no
+/// deny pattern. Patterns match whole method names, with `*` matching any run of
+/// characters.
+pub struct Filter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+fn matches(pattern: &str, method: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == method,
+        Some((prefix, rest)) => {
+            let Some(method) = method.strip_prefix(prefix) else { return false };
+            (0..=method.len()).filter(|i| method.is_char_boundary(*i)).any(|i| matches(rest, &method[i..]))
+        }
+    }
+}
+
+impl Filter {
+    pub fn new(allow: Vec<String>, mut deny: Vec<String>, deny_unsafe: bool) -> Filter {
+        if deny_unsafe {
+            deny.extend(UNSAFE_METHODS.iter().map(|method| method.to_string()));
+        }
+        Filter { allow, deny }
+    }
+
+    fn permits(&self, method: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|pattern| matches(pattern, method)))
+            && !self.deny.iter().any(|pattern| matches(pattern, method))
+    }
+}
+
+/// Methods whose answer can't change once a block hash is given, with the position of
+/// that hash in their params.
+const PINNED_METHODS: &[(&str, usize)] = &[
+    ("chain_getBlock", 0), ("chain_getHeader", 0), ("state_getMetadata", 0), ("state_getRuntimeVersion", 0),
+    ("state_getStorage", 1), ("state_getStorageHash", 1), ("state_getStorageSize", 1), ("state_getReadProof", 1),
+    ("state_call", 2), ("state_getKeysPaged", 3),
+];
+
+/// The position of the block hash in the params of `method`, if it takes one.
+pub fn block_hash_position(method: &str) -> Option<usize> {
+    PINNED_METHODS.iter().find(|(pinned, _)| *pinned == method).map(|(_, at)| *at)
+}
+
+/// The cache keys of a request's answer: its method and params when pinned to a block
+/// hash, and for metadata also the runtime version at that hash when it is cached
+/// (clients usually ask for it first), since every block of a runtime version shares
+/// the same metadata.
+fn cache_keys(cache: &Cache, request: &Value) -> Vec<String> {
+    let method = request["method"].as_str().unwrap_or_default();
+    let Some(at) = block_hash_position(method) else { return Vec::new() };
+    let Some(hash) = request["params"].get(at).and_then(Value::as_str) else { return Vec::new() };
+    if !(hash.len() == 66 && hash.starts_with("0x") && hash[2..].bytes().all(|byte| byte.is_ascii_hexdigit())) {
+        return Vec::new();
+    }
+    let mut keys = vec![format!("{}{}", method, request["params"])];
+    if method == "state_getMetadata" {
+        if let Some(version) = cache.get(&format!("state_getRuntimeVersion{}", json!([hash]))) {
+            keys.push(format!("metadata:{}:{}", version["specName"].as_str().unwrap_or_default(), version["specVersion"]));
+        }
+    }
+    keys
+}
+
+pub fn parse_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("invalid rate '{}', expected a fraction between 0 and 1", rate)),
+    }
+}
+
+/// Method names as they appear in metrics; client input is not trusted to be one.
+fn method_name(request: &Value) -> String {
+    match request["method"].as_str() {
+        Some(method) if !method.is_empty() && method.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => method.to_string(),
+        _ => "invalid".to_string(),
+    }
+}
+
+fn is_request(message: &Value) -> bool {
+    message.get("method").is_some() && message.get("id").is_some_and(|id| !id.is_null())
+}
+
+/// Writes one annotated exchange to the output (and `--sink`). `note` marks answers the
+/// proxy gave itself (`invalid`, `denied`, `injected` or `cached`).
+fn record(options: &Options, client: SocketAddr, pending: &Pending, response: &Value, note: Option<&str>) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let mut line = json!({
+        "time": crate::clock::format_unix_millis(now),
+        "client": client.to_string(),
+        "id": response["id"],
+        "method": pending.method,
+        "latency_ms": (pending.sent.elapsed().as_secs_f64() * 100000.0).round() / 100.0,
+        "ok": response.get("error").is_none(),
+    });
+    if let Some(error) = response.get("error") {
+        line["error"] = error.clone();
+    }
+    if let Some(note) = note {
+        line[note] = json!(true);
+    }
+    if options.record_payloads {
+        line["params"] = pending.params.clone();
+        crate::redact::params(&pending.method, &mut line["params"]);
+        line["result"] = response.get("result").cloned().unwrap_or(Value::Null);
+    }
+    if let Err(e) = crate::output::line(&line.to_string()) {
+        eprintln!("Warning: failed to record traffic: {}", e);
+    }
+}
+
+/// Answers `request` from the proxy itself with a JSON-RPC error.
+fn answer(options: &Options, client: SocketAddr, request: &Value, code: i64, message: String, note: &str) -> Value {
+    let response = json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": code, "message": message } });
+    let request = Pending { method: method_name(request), params: request["params"].clone(), sent: Instant::now(), cache_keys: Vec::new() };
+    stats::record_latency(&request.method, request.sent.elapsed());
+    stats::record_method_error(&request.method);
+    record(options, client, &request, &response, Some(note));
+    response
+}
+
+/// A client message split into the answers the proxy gives itself and the items to
+/// forward upstream.
+struct Screened {
+    batch: bool,
+    answers: Vec<Value>,
+    forwarded: Vec<Value>,
+    // Every item is forwarded, so the message can go upstream as it came
+    whole: bool,
+}
+
+/// Answers the items of a client message that must not or need not reach the upstream:
+/// anything that does not parse or names no method, denied methods, injected failures
+/// and cached calls. The filter fails closed, so what it cannot read is never forwarded.
+fn screen(options: &Options, client: SocketAddr, text: &str) -> Screened {
+    let Ok(parsed) = serde_json::from_str::<Value>(text) else {
+        let answers = vec![answer(options, client, &Value::Null, -32700, "Parse error".into(), "invalid")];
+        return Screened { batch: false, answers, forwarded: Vec::new(), whole: false };
+    };
+    let (batch, items) = match parsed {
+        Value::Array(items) => (true, items),
+        item => (false, vec![item]),
+    };
+    let item_count = items.len();
+    let mut answers = Vec::new();
+    let mut forwarded = Vec::new();
+    for item in items {
+        let Some(method) = item.get("method").and_then(Value::as_str) else {
+            answers.push(answer(options, client, &item, -32600, "Invalid request".into(), "invalid"));
+            continue;
+        };
+        if !options.filter.permits(method) {
+            if is_request(&item) {
+                answers.push(answer(options, client, &item, -32601, format!("Method {} is not allowed", method), "denied"));
+            }
+            continue;
+        }
+        if is_request(&item) && roll(options.chaos.fail_rate) {
+            answers.push(answer(options, client, &item, -32603, "Internal error (injected by gavel proxy)".into(), "injected"));
+            continue;
+        }
+        if let Some(response) = is_request(&item).then(|| answer_cached(options, client, &item)).flatten() {
+            answers.push(response);
+            continue;
+        }
+        forwarded.push(item);
+    }
+    let whole = forwarded.len() == item_count;
+    Screened { batch, answers, forwarded, whole }
+}
+
+/// Answers `request` from the cache, if its answer is there.
+fn answer_cached(options: &Options, client: SocketAddr, request: &Value) -> Option<Value> {
+    let result = {
+        let cache = options.cache.lock().unwrap();
+        cache_keys(&cache, request).iter().find_map(|key| cache.get(key))?
+    };
+    let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+    let request = Pending { method: method_name(request), params: request["params"].clone(), sent: Instant::now(), cache_keys: Vec::new() };
+    stats::record_latency(&request.method, request.sent.elapsed());
+    record(options, client, &request, &response, Some("cached"));
+    Some(response)
+}
+
+/// Accounts for the responses in `message` (one or a batch) to requests still pending,
+/// merging in the proxy's own answers to the rest of a batch.
+fn complete(options: &Options, client: SocketAddr, session: &Mutex<Session>, message: &mut Value) {
+    let mut merged = Vec::new();
+    let responses = match &*message {
+        Value::Array(responses) => responses.iter().collect(),
+        response => vec![response],
+    };
+    for response in responses {
+        let id = response["id"].to_string();
+        let (request, answers) = {
+            let mut session = session.lock().unwrap();
+            (session.pending.remove(&id), session.merged.remove(&id))
+        };
+        merged.extend(answers.into_iter().flatten());
+        let Some(request) = request else { continue };
+        stats::record_latency(&request.method, request.sent.elapsed());
+        if response.get("error").is_some() {
+            stats::record_method_error(&request.method);
+        } else if !response["result"].is_null() && !request.cache_keys.is_empty() {
+            let mut cache = options.cache.lock().unwrap();
+            for key in &request.cache_keys {
+                cache.insert(key.clone(), response["result"].clone());
+            }
+        }
+        record(options, client, &request, response, None);
+    }
+    if let Value::Array(responses) = message {
+        responses.extend(merged);
+    }
+}
+
+async fn relay(options: Arc<Options>, stream: TcpStream, client: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let websocket = tokio_tungstenite::accept_async(stream).await?;
+    let upstream = crate::connect_websocket(&options.upstream, options.ipv4.as_ref(), options.ipv6.as_ref(), options.max_response_size).await?;
+    let (mut client_sink, mut client_stream) = websocket.split();
+    let (mut upstream_sink, mut upstream_stream) = upstream.split();
+    let session: Arc<Mutex<Session>> = Arc::default();
+
+    // Both directions answer the client, so its writes go through one task
+    let (to_client, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if client_sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Set when chaos drops the connection, to stop reading from the client as well
+    let dropped = Arc::new(Notify::new());
+    let downstream = {
+        let (options, session, to_client, dropped) = (options.clone(), session.clone(), to_client.clone(), dropped.clone());
+        tokio::spawn(async move {
+            let chaos = &options.chaos;
+            let mut held: Option<Message> = None;
+            loop {
+                let next = match held {
+                    Some(_) => match tokio::time::timeout(REORDER_WINDOW, upstream_stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            // Nothing came to overtake the held back message
+                            if to_client.send(held.take().unwrap()).is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    },
+                    None => upstream_stream.next().await,
+                };
+                let Some(Ok(mut message)) = next else { break };
+                if let Message::Text(text) = &message {
+                    let mut text = text.clone();
+                    stats::record_received(text.len());
+                    chaos.delay().await;
+                    if let Ok(mut response) = serde_json::from_str::<Value>(&text) {
+                        let length = response.as_array().map(Vec::len);
+                        complete(&options, client, &session, &mut response);
+                        if response.as_array().map(Vec::len) != length {
+                            text = response.to_string();
+                        }
+                    }
+                    if roll(chaos.drop_rate) {
+                        eprintln!("Chaos: dropping the connection of {}", client);
+                        dropped.notify_one();
+                        return;
+                    }
+                    if roll(chaos.malformed_rate) {
+                        text = String::from_utf8_lossy(&text.as_bytes()[..text.len() / 2]).to_string();
+                    }
+                    message = Message::Text(text);
+                    if held.is_none() && roll(chaos.reorder_rate) {
+                        held = Some(message);
+                        continue;
+                    }
+                }
+                let closing = matches!(message, Message::Close(_));
+                if to_client.send(message).is_err() || closing {
+                    return;
+                }
+                if let Some(held) = held.take() {
+                    if to_client.send(held).is_err() {
+                        return;
+                    }
+                }
+            }
+            if let Some(held) = held {
+                let _ = to_client.send(held);
+            }
+            let _ = to_client.send(Message::Close(None));
+        })
+    };
+
+    loop {
+        let message = tokio::select! {
+            next = client_stream.next() => match next {
+                Some(Ok(message)) => message,
+                _ => break,
+            },
+            _ = dropped.notified() => break,
+        };
+        let Message::Text(text) = &message else {
+            if let Message::Close(_) = message {
+                break;
+            }
+            continue;
+        };
+        let Screened { batch, mut answers, forwarded, whole } = screen(&options, client, text);
+        let requests: Vec<&Value> = forwarded.iter().filter(|item| is_request(item)).collect();
+        let message = match (batch, whole) {
+            (_, true) => message.clone(),
+            (true, false) => Message::Text(Value::Array(forwarded.clone()).to_string()),
+            (false, false) => Message::Text(String::new()),
+        };
+
+        {
+            let mut session = session.lock().unwrap();
+            for request in &requests {
+                let cache_keys = cache_keys(&options.cache.lock().unwrap(), request);
+                session.pending.insert(request["id"].to_string(), Pending { method: method_name(request), params: request["params"].clone(), sent: Instant::now(), cache_keys });
+            }
+            match requests.first() {
+                Some(first) if !answers.is_empty() => {
+                    session.merged.insert(first["id"].to_string(), std::mem::take(&mut answers));
+                }
+                _ => {}
+            }
+        }
+        if !answers.is_empty() {
+            // Nothing forwarded will be answered, so reply right away
+            let reply = if batch { Value::Array(answers) } else { answers.remove(0) };
+            let _ = to_client.send(Message::Text(reply.to_string()));
+        }
+        if forwarded.is_empty() {
+            continue;
+        }
+        stats::record_sent(requests.len() as u64, text.len());
+        if upstream_sink.send(message).await.is_err() {
+            break;
+        }
+    }
+    let _ = upstream_sink.close().await;
+    downstream.abort();
+    drop(to_client);
+    let _ = writer.await;
+    Ok(())
+}
+
+/// Accepts clients on `listen` until interrupted, then prints the per-method summary.
+pub async fn proxy(options: Options, listen: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(listen).await.map_err(|e| format!("Failed to listen on {}: {}", listen, e))?;
+    eprintln!("Proxying ws://{} to {}", listen, crate::redact::text(&options.upstream));
+    let options = Arc::new(options);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, client) = accepted?;
+                let options = options.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = relay(options, stream, client).await {
+                        eprintln!("Warning: client {}: {}", client, crate::redact::text(&e.to_string()));
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    eprintln!("{}", serde_json::to_string_pretty(&stats::methods())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> Options {
+        Options {
+            upstream: String::new(),
+            ipv4: None,
+            ipv6: None,
+            max_response_size: None,
+            record_payloads: false,
+            chaos: Chaos { fail_rate: 0.0, delay_ms: 0, jitter_ms: 0, drop_rate: 0.0, malformed_rate: 0.0, reorder_rate: 0.0 },
+            filter: Filter::new(Vec::new(), Vec::new(), true),
+            cache: Mutex::new(Cache::new(0)),
+        }
+    }
+
+    #[test]
+    fn denies_unsafe_methods_in_a_batch() {
+        let client = "127.0.0.1:1".parse().unwrap();
+        let text = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "system_health" },
+            { "jsonrpc": "2.0", "id": 2, "method": "author_insertKey", "params": ["babe", "//Alice", "0x00"] },
+            { "jsonrpc": "2.0", "id": 3, "method": "system_dryRun", "params": ["0x00"] },
+        ]).to_string();
+        let screened = screen(&options(), client, &text);
+        assert!(screened.batch && !screened.whole);
+        assert_eq!(screened.forwarded, vec![json!({ "jsonrpc": "2.0", "id": 1, "method": "system_health" })]);
+        let denied: Vec<(&Value, &Value)> = screened.answers.iter().map(|answer| (&answer["id"], &answer["error"]["code"])).collect();
+        assert_eq!(denied, vec![(&json!(2), &json!(-32601)), (&json!(3), &json!(-32601))]);
+    }
+
+    #[test]
+    fn never_forwards_what_it_cannot_read() {
+        let client = "127.0.0.1:1".parse().unwrap();
+        for text in [
+            r#"{"jsonrpc":"2.0","id":1,"method":"author_insertKey","params":["babe","//Alice","0x00"]"#,
+            r#"[{"jsonrpc":"2.0","id":1,"method":"author_insertKey"},"#,
+            "author_insertKey",
+        ] {
+            let screened = screen(&options(), client, text);
+            assert!(screened.forwarded.is_empty() && !screened.whole, "{}", text);
+            assert_eq!(screened.answers, vec![json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "Parse error" } })]);
+        }
+        let screened = screen(&options(), client, r#"[{"jsonrpc":"2.0","id":1,"method":7},{"jsonrpc":"2.0","id":2}]"#);
+        assert!(screened.forwarded.is_empty());
+        assert!(screened.answers.iter().all(|answer| answer["error"]["code"] == json!(-32600)));
+    }
+}
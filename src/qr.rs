@@ -0,0 +1,437 @@
+//! QR code generation (byte mode, error correction level M) with terminal and PNG output,
+//! and the multipart framing Polkadot Vault scans.
+
+use std::io::Write;
+
+/// Largest payload per frame, as used by the Polkadot Vault tooling.
+const FRAME_SIZE: usize = 1024;
+
+/// Error correction codewords per block and number of blocks for level M, by version.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28];
+const NUM_ERROR_CORRECTION_BLOCKS: [usize; 41] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49];
+
+/// Format bits of error correction level M.
+const ECC_FORMAT_BITS: u32 = 0;
+
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    function: Vec<bool>,
+}
+
+/// Splits a payload into Vault multipart frames: `0x00`, the frame count and the frame
+/// index as big-endian `u16`, then the frame data. Single frames are framed the same way.
+pub fn frames(payload: &[u8]) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = if payload.is_empty() { vec![payload] } else { payload.chunks(FRAME_SIZE).collect() };
+    chunks.iter().enumerate().map(|(index, chunk)| {
+        let mut frame = vec![0x00];
+        frame.extend((chunks.len() as u16).to_be_bytes());
+        frame.extend((index as u16).to_be_bytes());
+        frame.extend_from_slice(chunk);
+        frame
+    }).collect()
+}
+
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize) -> usize {
+    num_raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * NUM_ERROR_CORRECTION_BLOCKS[version]
+}
+
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+    z as u8
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_multiply(*y, factor);
+        }
+    }
+    result
+}
+
+impl QrCode {
+    /// Encodes `data` in byte mode with the smallest version that fits.
+    pub fn encode(data: &[u8]) -> Result<QrCode, String> {
+        let version = (1..=40).find(|version| {
+            let count_bits = if *version < 10 { 8 } else { 16 };
+            data.len() < 1 << count_bits && 4 + count_bits + data.len() * 8 <= num_data_codewords(*version) * 8
+        }).ok_or_else(|| format!("{} bytes do not fit in a QR code", data.len()))?;
+
+        let mut bits: Vec<bool> = Vec::new();
+        let mut push = |value: usize, len: usize| (0..len).rev().for_each(|i| bits.push((value >> i) & 1 == 1));
+        push(0b0100, 4);
+        push(data.len(), if version < 10 { 8 } else { 16 });
+        data.iter().for_each(|byte| push(*byte as usize, 8));
+        let capacity = num_data_codewords(version) * 8;
+        let terminator = (capacity - bits.len()).min(4);
+        bits.extend(std::iter::repeat_n(false, terminator));
+        bits.extend(std::iter::repeat_n(false, (8 - bits.len() % 8) % 8));
+
+        let mut codewords: Vec<u8> = bits.chunks(8).map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | *bit as u8)).collect();
+        for pad in [0xEC, 0x11].iter().cycle() {
+            if codewords.len() >= capacity / 8 {
+                break;
+            }
+            codewords.push(*pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode { size, modules: vec![false; size * size], function: vec![false; size * size] };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(&codewords, version));
+
+        let mask = (0..8).min_by_key(|mask| {
+            qr.apply_mask(*mask);
+            qr.draw_format_bits(*mask);
+            let penalty = qr.penalty();
+            qr.apply_mask(*mask);
+            penalty
+        }).unwrap_or(0);
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+        Ok(qr)
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                    if (0..size as i32).contains(&xx) && (0..size as i32).contains(&yy) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+        let positions = alignment_positions(version, size);
+        let last = positions.len().saturating_sub(1);
+        for (i, x) in positions.iter().enumerate() {
+            for (j, y) in positions.iter().enumerate() {
+                // The finder patterns already cover three corners
+                let corner = (i == 0 || i == last) && (j == 0 || j == last) && !(i == last && j == last);
+                if corner {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        self.set_function((*x as i32 + dx) as usize, (*y as i32 + dy) as usize, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+        // Reserve the format areas, the real bits are drawn once the mask is chosen
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let mut remainder = version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+            }
+            let bits = (version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let data = ECC_FORMAT_BITS << 3 | mask as u32;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+                    if !self.function[y * size + x] && i < data.len() * 8 {
+                        self.modules[y * size + x] = (data[i >> 3] >> (7 - (i & 7))) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                self.modules[index] ^= invert && !self.function[index];
+            }
+        }
+    }
+
+    /// Penalty score of the current masking, following the four rules of ISO 18004.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut result = 0;
+        let lines = (0..size).flat_map(|a| {
+            [(0..size).map(|b| self.get(b, a)).collect::<Vec<_>>(), (0..size).map(|b| self.get(a, b)).collect::<Vec<_>>()]
+        });
+        const FINDER: [bool; 11] = [true, false, true, true, true, false, true, false, false, false, false];
+        for line in lines {
+            let mut run = 1;
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    result += run - 2;
+                }
+                run = 1;
+            }
+            for window in line.windows(11) {
+                if window == FINDER || window.iter().rev().eq(FINDER.iter()) {
+                    result += 40;
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+                if color == self.get(x + 1, y) && color == self.get(x, y + 1) && color == self.get(x + 1, y + 1) {
+                    result += 3;
+                }
+            }
+        }
+        let total = size * size;
+        let dark = self.modules.iter().filter(|dark| **dark).count();
+        let k = (dark * 20).abs_diff(total * 10).div_ceil(total).saturating_sub(1);
+        result + k * 10
+    }
+
+    /// Renders with half-block characters, two module rows per line. Light modules are drawn
+    /// as blocks so the code scans on dark terminal backgrounds.
+    pub fn to_terminal(&self) -> String {
+        const QUIET: usize = 2;
+        let span = self.size + 2 * QUIET;
+        let light = |x: usize, y: usize| {
+            x < QUIET || y < QUIET || x >= self.size + QUIET || y >= self.size + QUIET || !self.get(x - QUIET, y - QUIET)
+        };
+        let mut out = String::new();
+        for y in (0..span).step_by(2) {
+            for x in 0..span {
+                let top = light(x, y);
+                let bottom = y + 1 >= span || light(x, y + 1);
+                out.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Writes an 8-bit grayscale PNG with `scale` pixels per module and a 4-module quiet zone.
+    pub fn write_png(&self, path: &std::path::Path, scale: usize) -> Result<(), Box<dyn std::error::Error>> {
+        const QUIET: usize = 4;
+        let width = (self.size + 2 * QUIET) * scale;
+        let mut raw = Vec::with_capacity((width + 1) * width);
+        for py in 0..width {
+            raw.push(0); // no filter
+            for px in 0..width {
+                let (x, y) = (px / scale, py / scale);
+                let inside = (QUIET..self.size + QUIET).contains(&x) && (QUIET..self.size + QUIET).contains(&y);
+                raw.push(if inside && self.get(x - QUIET, y - QUIET) { 0x00 } else { 0xFF });
+            }
+        }
+
+        let mut ihdr = Vec::new();
+        ihdr.extend((width as u32).to_be_bytes());
+        ihdr.extend((width as u32).to_be_bytes());
+        ihdr.extend([8, 0, 0, 0, 0]);
+
+        let mut file = std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        file.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_chunk(&mut file, b"IHDR", &ihdr)?;
+        write_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(&mut file, b"IEND", &[])?;
+        Ok(())
+    }
+}
+
+fn alignment_positions(version: usize, size: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let num_align = version / 7 + 2;
+    let step = (version * 8 + num_align * 3 + 5) / (num_align * 4 - 4) * 2;
+    let mut result: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    result.push(6);
+    result.reverse();
+    result
+}
+
+fn add_ecc_and_interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(num_blocks);
+    let mut k = 0;
+    for i in 0..num_blocks {
+        let len = short_block_len - ecc_len + usize::from(i >= num_short_blocks);
+        let mut block = data[k..k + len].to_vec();
+        k += len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < num_short_blocks {
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            // Skip the padding byte of short blocks
+            if i != short_block_len - ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind.as_slice(), data].concat()).to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks, QR images are small enough.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        out.push(u8::from(i + 1 == blocks.len()));
+        out.extend((block.len() as u16).to_le_bytes());
+        out.extend((!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend((b << 16 | a).to_be_bytes());
+    out
+}
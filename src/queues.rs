@@ -0,0 +1,122 @@
+//! Downward (DMP) and upward (UMP, via `MessageQueue`) message queue backlogs per parachain.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::storage;
+
+#[derive(Default)]
+struct Queue {
+    dmp_messages: u64,
+    dmp_bytes: u64,
+    dmp_oldest_sent_at: Option<u64>,
+    dmp_head: Value,
+    ump_messages: u64,
+    ump_bytes: u64,
+    ump_pages: u64,
+}
+
+/// Extracts the para ID from a `MessageQueue` origin such as `{"Ump": {"Para": 2000}}`.
+fn ump_para(origin: &Value) -> Option<u64> {
+    origin.get("Ump").and_then(|queue| queue.get("Para")).and_then(Value::as_u64)
+}
+
+async fn read_queues(socket: &mut Connection, metadata: &Metadata, at: &str) -> Result<BTreeMap<u64, Queue>, Box<dyn std::error::Error>> {
+    let mut queues: BTreeMap<u64, Queue> = BTreeMap::new();
+
+    if metadata.storage_entry("Dmp", "DownwardMessageQueues").is_some() {
+        for (para, messages) in storage::read_map(socket, metadata, "Dmp", "DownwardMessageQueues", Some(at)).await? {
+            let messages = messages.as_array().cloned().unwrap_or_default();
+            let queue = queues.entry(para.as_u64().unwrap_or_default()).or_default();
+            queue.dmp_messages = messages.len() as u64;
+            queue.dmp_bytes = messages.iter().map(|message| message["msg"].as_str().map(|msg| (msg.len() as u64 - 2) / 2).unwrap_or_default()).sum();
+            queue.dmp_oldest_sent_at = messages.first().and_then(|message| message["sent_at"].as_u64());
+        }
+        for (para, head) in storage::read_map(socket, metadata, "Dmp", "DownwardMessageQueueHeads", Some(at)).await? {
+            queues.entry(para.as_u64().unwrap_or_default()).or_default().dmp_head = head;
+        }
+    }
+
+    if metadata.storage_entry("MessageQueue", "BookStateFor").is_some() {
+        for (origin, book) in storage::read_map(socket, metadata, "MessageQueue", "BookStateFor", Some(at)).await? {
+            if let Some(para) = ump_para(&origin) {
+                let queue = queues.entry(para).or_default();
+                queue.ump_messages = book["message_count"].as_u64().unwrap_or_default();
+                queue.ump_bytes = book["size"].as_u64().unwrap_or_default();
+                queue.ump_pages = book["count"].as_u64().unwrap_or_default();
+            }
+        }
+    } else if metadata.storage_entry("Ump", "RelayDispatchQueueSize").is_some() {
+        // Runtimes before the MessageQueue migration track (count, size) per para
+        for (para, size) in storage::read_map(socket, metadata, "Ump", "RelayDispatchQueueSize", Some(at)).await? {
+            let queue = queues.entry(para.as_u64().unwrap_or_default()).or_default();
+            queue.ump_messages = size[0].as_u64().unwrap_or_default();
+            queue.ump_bytes = size[1].as_u64().unwrap_or_default();
+        }
+    }
+    Ok(queues)
+}
+
+/// Reports queue sizes at the finalized head (or `--at`), compared with `window` blocks earlier.
+/// A queue that grew over the window is flagged and an alert written to stderr.
+pub async fn backlog(socket: &mut Connection, para: Option<u64>, window: u64, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::pinned_block(socket, at).await?;
+    let metadata = metadata::fetch(socket, Some(&at)).await?;
+    if metadata.pallet("Dmp").is_none() && metadata.pallet("MessageQueue").is_none() {
+        return Err("No Dmp or MessageQueue pallet, is this a relay chain endpoint?".into());
+    }
+    let current_block = crate::fetch_header_number(socket, Some(&at)).await?;
+    let previous_block = current_block.saturating_sub(window);
+    let previous_hash = crate::fetch_block_hash(socket, previous_block).await?;
+
+    let current = read_queues(socket, &metadata, &at).await?;
+    let previous = read_queues(socket, &metadata, &previous_hash).await?;
+
+    let mut paras = Vec::new();
+    for (id, queue) in &current {
+        if para.is_some_and(|para| para != *id) {
+            continue;
+        }
+        let before = previous.get(id);
+        let dmp_change = queue.dmp_messages as i64 - before.map(|queue| queue.dmp_messages).unwrap_or_default() as i64;
+        let ump_change = queue.ump_messages as i64 - before.map(|queue| queue.ump_messages).unwrap_or_default() as i64;
+        let growing = (dmp_change > 0 && queue.dmp_messages > 0) || (ump_change > 0 && queue.ump_messages > 0);
+        if growing {
+            eprintln!("ALERT: para {} message backlog grew over the last {} blocks (dmp {:+}, ump {:+})", id, window, dmp_change, ump_change);
+        }
+        if queue.dmp_messages == 0 && queue.ump_messages == 0 && para.is_none() {
+            continue;
+        }
+        paras.push(json!({
+            "para_id": id,
+            "dmp": {
+                "messages": queue.dmp_messages,
+                "bytes": queue.dmp_bytes,
+                "oldest_sent_at": queue.dmp_oldest_sent_at,
+                "age_blocks": queue.dmp_oldest_sent_at.map(|sent_at| current_block.saturating_sub(sent_at)),
+                "head": queue.dmp_head,
+                "change": dmp_change,
+            },
+            "ump": {
+                "messages": queue.ump_messages,
+                "bytes": queue.ump_bytes,
+                "pages": queue.ump_pages,
+                "change": ump_change,
+            },
+            "alert": growing,
+        }));
+    }
+    if let (Some(para), true) = (para, paras.is_empty()) {
+        return Err(format!("No message queues found for para {}", para).into());
+    }
+
+    Ok(json!({
+        "block_number": current_block,
+        "block_hash": at,
+        "compared_to": previous_block,
+        "paras": paras,
+    }))
+}
@@ -0,0 +1,378 @@
+//! Fetching a range of blocks over a small pool of connections, each pipelining a batch
+//! of blocks at a time, and streaming them out in height order as JSON lines.
+//!
+//! With `--include`, the blocks are split into tables instead: whole blocks, headers,
+//! decoded extrinsics and decoded events, each written to its own files under
+//! `--out-dir`, partitioned by block range and keyed by block number and hash.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::join_all;
+use serde_json::{json, Value};
+use tokio::time::Instant;
+
+use crate::checkpoints::Checkpoints;
+use crate::hashing::blake2_256;
+use crate::metadata::Metadata;
+use crate::probe::Target;
+use crate::rpc::Connection;
+use crate::stats;
+use crate::verify::{self, Level};
+
+#[derive(clap::Args, Debug)]
+pub struct Range {
+    #[clap(long, conflicts_with_all = ["block_number", "verify_roots", "request_timeout"], help = "First block of a range to fetch, streamed as one JSON line per block.")]
+    pub from: Option<u64>,
+    #[clap(long, requires = "from", help = "Last block of the range, defaults to the finalized head.")]
+    pub to: Option<u64>,
+    #[clap(long, default_value = "16", requires = "from", help = "Blocks of the range in flight at once across all connections.")]
+    pub concurrency: usize,
+    #[clap(long, default_value = "4", requires = "from", help = "Connections to spread the range over, capped by --concurrency.")]
+    pub connections: usize,
+    #[clap(long, value_enum, value_delimiter = ',', requires = "out_dir", help = "Tables to write under --out-dir, e.g. events,extrinsics,headers.")]
+    pub include: Vec<Part>,
+    #[clap(long, requires_all = ["from", "include"], help = "Directory for the --include tables, one subdirectory per table.")]
+    pub out_dir: Option<PathBuf>,
+    #[clap(long, default_value = "10000", requires = "out_dir", help = "Blocks per partition file of each table.")]
+    pub partition: u64,
+}
+
+/// A table of the range written with `--include`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Part {
+    Blocks,
+    Headers,
+    Extrinsics,
+    Events,
+}
+
+impl Part {
+    fn name(self) -> &'static str {
+        match self {
+            Part::Blocks => "blocks",
+            Part::Headers => "headers",
+            Part::Extrinsics => "extrinsics",
+            Part::Events => "events",
+        }
+    }
+
+    /// The rows of this table for one fetched block.
+    fn rows(self, record: &Value) -> Vec<Value> {
+        let (number, hash) = (&record["number"], &record["hash"]);
+        match self {
+            Part::Blocks => vec![json!({ "number": number, "hash": hash, "block": record["block"], "justifications": record["justifications"] })],
+            Part::Headers => vec![json!({ "number": number, "hash": hash, "header": record["block"]["header"] })],
+            // The events of each extrinsic are in the events table, by phase
+            Part::Extrinsics => record["extrinsics"].as_array().into_iter().flatten().map(|extrinsic| {
+                let mut row = json!({ "number": number, "block_hash": hash });
+                for (field, value) in extrinsic.as_object().into_iter().flatten().filter(|(field, _)| *field != "events") {
+                    row[field] = value.clone();
+                }
+                row
+            }).collect(),
+            Part::Events => crate::events::rows(number, hash, &record["events"]),
+        }
+    }
+}
+
+/// The `--include` tables: `<dir>/<table>/<first>-<last>.jsonl`, one file per
+/// `partition` blocks.
+struct Tables {
+    dir: PathBuf,
+    partition: u64,
+    parts: Vec<Part>,
+    open: BTreeMap<Part, (u64, BufWriter<File>)>,
+}
+
+impl Tables {
+    fn new(dir: &Path, partition: u64, parts: &[Part]) -> Result<Tables, Box<dyn std::error::Error>> {
+        let mut parts = parts.to_vec();
+        parts.sort();
+        parts.dedup();
+        for part in &parts {
+            let table = dir.join(part.name());
+            std::fs::create_dir_all(&table).map_err(|e| format!("Failed to create {}: {}", table.display(), e))?;
+        }
+        Ok(Tables { dir: dir.to_path_buf(), partition: partition.max(1), parts, open: BTreeMap::new() })
+    }
+
+    fn write(&mut self, record: &Value) -> std::io::Result<()> {
+        let number = record["number"].as_u64().unwrap_or_default();
+        let first = number - number % self.partition;
+        for &part in &self.parts {
+            let rows = part.rows(record);
+            if !matches!(self.open.get(&part), Some((open, _)) if *open == first) {
+                if let Some((_, mut previous)) = self.open.remove(&part) {
+                    previous.flush()?;
+                }
+                let path = self.dir.join(part.name()).join(format!("{:010}-{:010}.jsonl", first, first + self.partition - 1));
+                let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                self.open.insert(part, (first, BufWriter::new(file)));
+            }
+            let (_, writer) = self.open.get_mut(&part).unwrap();
+            for row in rows {
+                writeln!(writer, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.open.values_mut().try_for_each(|(_, writer)| writer.flush())
+    }
+}
+
+/// Whether the blocks need decoding, and the metadata of every runtime met so far.
+struct Decoding {
+    extrinsics: bool,
+    events: bool,
+    metadata: Mutex<BTreeMap<u64, Arc<Metadata>>>,
+}
+
+/// Blocks fetched out of order, held until every lower height is written.
+struct Ordered {
+    next: u64,
+    pending: BTreeMap<u64, Option<Value>>,
+    written: u64,
+    tables: Option<Tables>,
+}
+
+impl Ordered {
+    fn insert(&mut self, number: u64, record: Option<Value>) -> std::io::Result<()> {
+        self.pending.insert(number, record);
+        while let Some(record) = self.pending.remove(&self.next) {
+            if let Some(record) = record {
+                match &mut self.tables {
+                    Some(tables) => tables.write(&record)?,
+                    None => crate::output::line(&record.to_string())?,
+                }
+                self.written += 1;
+            }
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Sends one request per block as a single batch and returns the responses by block.
+async fn batch(socket: &mut Connection, method: &str, params: &[(u64, Value)]) -> Result<BTreeMap<u64, Value>, Box<dyn std::error::Error>> {
+    let calls: Vec<(&str, &Value)> = params.iter().map(|(_, params)| (method, params)).collect();
+    let responses = socket.batch(&calls).await?;
+    if responses.iter().any(|response| response.get("error").is_some()) {
+        stats::record_failure("rpc");
+    }
+    Ok(params.iter().map(|(number, _)| *number).zip(responses).collect())
+}
+
+/// Decodes the extrinsics and events of fetched blocks with the metadata of the runtime
+/// each was built with, under `extrinsics` and `events`.
+async fn decode(socket: &mut Connection, decoding: &Decoding, records: &mut [(u64, Option<Value>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let blocks: Vec<(u64, Value)> = records.iter().filter_map(|(number, record)| Some((*number, json!([record.as_ref()?["hash"]])))).collect();
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    let runtimes = batch(socket, "state_getRuntimeVersion", &blocks).await?;
+    // Extrinsic outcomes come from the events, which are needed either way
+    let key = crate::hex::encode(&crate::metadata::storage_prefix("System", "Events"));
+    let proven = verify::level() >= Level::Proofs;
+    let storage: Vec<(u64, Value)> = blocks.iter().map(|(number, params)| (*number, json!([key, params[0]]))).collect();
+    let stored = if proven { BTreeMap::new() } else { batch(socket, "state_getStorage", &storage).await? };
+
+    for (number, record) in records.iter_mut() {
+        let Some(record) = record else { continue };
+        let hash = record["hash"].as_str().unwrap_or_default().to_string();
+        let spec = runtimes[number]["result"]["specVersion"].as_u64().ok_or_else(|| format!("No runtime version for block {}", number))?;
+        let cached = decoding.metadata.lock().unwrap().get(&spec).cloned();
+        let metadata = match cached {
+            Some(metadata) => metadata,
+            None => {
+                let metadata = Arc::new(crate::metadata::fetch(socket, Some(&hash)).await?);
+                decoding.metadata.lock().unwrap().insert(spec, metadata.clone());
+                metadata
+            }
+        };
+        let entry = metadata.storage_entry("System", "Events").ok_or("System::Events not found in metadata")?;
+        let value = match proven {
+            true => verify::read_proof(socket, std::slice::from_ref(&key), &hash).await?.pop().flatten(),
+            false => stored[number]["result"].as_str().map(crate::hex::decode).transpose()?,
+        };
+        let bytes = value.unwrap_or_else(|| entry.default.clone());
+        let events = crate::decode::Decoder::new(&metadata).decode_all(entry.value_type(), &bytes)
+            .map_err(|e| format!("Failed to decode the events of block {}: {}", number, e))?;
+        if decoding.extrinsics {
+            let extrinsics = record["block"]["extrinsics"].as_array().map(Vec::as_slice).unwrap_or_default();
+            record["extrinsics"] = json!(crate::extrinsic::annotate(&metadata, extrinsics, &events));
+        }
+        if decoding.events {
+            let mut events = events;
+            for event in events.as_array_mut().into_iter().flatten() {
+                crate::extrinsic::resolve_dispatch_errors(&metadata, &mut event["event"]);
+            }
+            record["events"] = events;
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the hashes, then the bodies, of blocks `from..=to`. A block the node does not
+/// have or answers with an error is `None`, with a warning.
+async fn chunk(socket: &mut Connection, from: u64, to: u64, decoding: Option<&Decoding>) -> Result<Vec<(u64, Option<Value>)>, Box<dyn std::error::Error>> {
+    let numbers: Vec<(u64, Value)> = (from..=to).map(|number| (number, json!([number]))).collect();
+    let hashes = batch(socket, "chain_getBlockHash", &numbers).await?;
+    let mut found = Vec::new();
+    let mut records: Vec<(u64, Option<Value>)> = Vec::new();
+    for (number, response) in &hashes {
+        match response["result"].as_str() {
+            Some(hash) => found.push((*number, json!([hash]))),
+            None => {
+                eprintln!("Warning: no block {}: {}", number, response.get("error").map_or("not found".to_string(), Value::to_string));
+                records.push((*number, None));
+            }
+        }
+    }
+    let blocks = if found.is_empty() { BTreeMap::new() } else { batch(socket, "chain_getBlock", &found).await? };
+    for (number, hash) in &found {
+        let hash = hash[0].as_str().unwrap_or_default();
+        let response = &blocks[number];
+        if let Some(error) = response.get("error") {
+            eprintln!("Warning: block {} ({}) failed: {}", number, hash, error);
+            records.push((*number, None));
+            continue;
+        }
+        // Checked before the digest logs are decoded, they are part of the hash
+        if verify::level() >= Level::Roots {
+            let computed = crate::hex::encode(&blake2_256(&verify::encode_header(&response["result"]["block"]["header"])?));
+            if !computed.eq_ignore_ascii_case(hash) {
+                return Err(format!("The header served for block {} hashes to {}, not {}", number, computed, hash).into());
+            }
+        }
+        if verify::level() >= Level::Light {
+            crate::justification::prove_finalized(socket, hash).await?;
+        }
+        let mut record = json!({
+            "number": number,
+            "hash": hash,
+            "block": response["result"]["block"],
+            "justifications": response["result"]["justifications"],
+        });
+        crate::digest::decode_header_logs(&mut record["block"]["header"]);
+        records.push((*number, Some(record)));
+    }
+    if let Some(decoding) = decoding {
+        decode(socket, decoding, &mut records).await?;
+    }
+    Ok(records)
+}
+
+/// Takes chunks off the queue until it is empty, reconnecting once when the connection
+/// fails mid-chunk. Returns the error that stopped it, if any.
+async fn worker(target: &Target<'_>, mut socket: Connection, queue: &Mutex<VecDeque<(u64, u64)>>, ordered: &Mutex<Ordered>, checkpoints: Option<&Checkpoints>, decoding: Option<&Decoding>) -> Option<String> {
+    loop {
+        let next = queue.lock().unwrap().pop_front();
+        let (from, to) = next?;
+        let mut fetched = chunk(&mut socket, from, to, decoding).await;
+        if let Err(e) = &fetched {
+            eprintln!("Warning: blocks {}-{} failed, reconnecting: {}", from, to, crate::redact::text(&e.to_string()));
+            fetched = match crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await {
+                Ok(reconnected) => {
+                    socket = reconnected;
+                    chunk(&mut socket, from, to, decoding).await
+                }
+                Err(e) => Err(e),
+            };
+        }
+        let records = match fetched {
+            Ok(records) => records,
+            Err(e) => {
+                let mut ordered = ordered.lock().unwrap();
+                for number in from..=to {
+                    let _ = ordered.insert(number, None);
+                }
+                return Some(e.to_string());
+            }
+        };
+        // A checkpoint mismatch means the wrong chain, no retry helps
+        if let Some(checkpoints) = checkpoints {
+            for record in records.iter().filter_map(|(_, record)| record.as_ref()) {
+                if let Err(e) = checkpoints.verify(record["number"].as_u64().unwrap_or_default(), record["hash"].as_str().unwrap_or_default()) {
+                    return Some(e);
+                }
+            }
+        }
+        let mut ordered = ordered.lock().unwrap();
+        for (number, record) in records {
+            if let Err(e) = ordered.insert(number, record) {
+                return Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Streams blocks `range.from..=range.to` as JSON lines in height order, each with its
+/// number, hash, block and justifications, and with `decode` its decoded `extrinsics`,
+/// fetched `range.concurrency` at a time over `range.connections` sockets. Prints a
+/// summary to stderr.
+pub async fn fetch(target: &Target<'_>, range: &Range, decode: bool, checkpoints: Option<&Checkpoints>) -> Result<(), Box<dyn std::error::Error>> {
+    let from = range.from.ok_or("--from is required for a range")?;
+    let concurrency = range.concurrency.max(1);
+    let mut socket = crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?;
+    let to = match range.to {
+        Some(to) => to,
+        None => {
+            let finalized = crate::send_and_receive(&mut socket, "chain_getFinalizedHead", json!([])).await?;
+            let header = crate::send_and_receive(&mut socket, "chain_getHeader", json!([finalized])).await?;
+            header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+                .ok_or("Invalid finalized header")?
+        }
+    };
+    if to < from {
+        return Err(format!("--to {} is below --from {}", to, from).into());
+    }
+    let total = to - from + 1;
+    // Each connection pipelines its share of the blocks in flight as one batch
+    let per_connection = concurrency.div_ceil(range.connections.clamp(1, concurrency)) as u64;
+    let connections = total.div_ceil(per_connection).min(range.connections.clamp(1, concurrency) as u64) as usize;
+
+    let mut sockets = vec![socket];
+    for _ in 1..connections {
+        sockets.push(crate::connect(target.endpoint, target.ipv4.as_ref(), target.ipv6.as_ref(), target.max_response_size).await?);
+    }
+    let queue: VecDeque<(u64, u64)> = (from..=to).step_by(per_connection as usize).map(|start| (start, (start + per_connection - 1).min(to))).collect();
+    let queue = Mutex::new(queue);
+    let tables = match &range.out_dir {
+        Some(dir) => Some(Tables::new(dir, range.partition, &range.include)?),
+        None => None,
+    };
+    let extrinsics = decode || range.include.contains(&Part::Extrinsics);
+    let decoding = (extrinsics || range.include.contains(&Part::Events)).then(|| Decoding {
+        extrinsics,
+        events: range.include.contains(&Part::Events),
+        metadata: Mutex::new(BTreeMap::new()),
+    });
+    let ordered = Mutex::new(Ordered { next: from, pending: BTreeMap::new(), written: 0, tables });
+
+    eprintln!("Fetching blocks {} to {} over {} connections, {} in flight", from, to, connections, (concurrency as u64).min(total));
+    let started = Instant::now();
+    let stopped: Vec<String> = join_all(sockets.into_iter().map(|socket| worker(target, socket, &queue, &ordered, checkpoints, decoding.as_ref()))).await
+        .into_iter().flatten().collect();
+    let mut ordered = ordered.into_inner().unwrap();
+    if let Some(tables) = &mut ordered.tables {
+        tables.flush()?;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    eprintln!(
+        "Fetched {} of {} blocks in {:.1}s ({:.0} blocks/s), {} failed",
+        ordered.written, total, elapsed, ordered.written as f64 / elapsed.max(0.001), total - ordered.written,
+    );
+    if let Some(error) = stopped.first() {
+        return Err(error.clone().into());
+    }
+    if ordered.written < total {
+        return Err(format!("{} of {} blocks could not be fetched", total - ordered.written, total).into());
+    }
+    Ok(())
+}
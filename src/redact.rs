@@ -0,0 +1,147 @@
+//! Keeps secrets out of what gavel writes besides a command's own result: error messages
+//! and warnings, compose dry runs, and the records streamed to `--output` and `--sink`.
+//!
+//! Secrets are registered where they are resolved (the API key, config auth secrets,
+//! secret URIs and keystore passwords) and masked wherever they show up. URL passwords,
+//! secret-looking query parameters and secret fields are masked without registering.
+//! `--no-redact` turns all of it off, for debugging auth against a provider.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+const MASK: &str = "***";
+
+/// Query parameters providers put API keys in.
+const SECRET_PARAMS: [&str; 9] = ["key", "apikey", "api_key", "api-key", "token", "access_token", "auth", "secret", "password"];
+
+/// JSON fields holding secrets, e.g. the SURI of `author_insertKey` as the proxy records it.
+const SECRET_FIELDS: [&str; 7] = ["suri", "seed", "secret_seed", "mnemonic", "secret_phrase", "password", "authorization"];
+
+/// Shorter values are too likely to occur by chance to be masked everywhere.
+const MIN_SECRET_LEN: usize = 4;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Turns redaction off, for `--no-redact`.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Registers a secret to be masked wherever it appears from now on.
+pub fn secret(value: &str) {
+    if value.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|secret| secret == value) {
+        secrets.push(value.to_string());
+        // Longest first, so a secret containing another is masked whole
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    }
+}
+
+/// Registers the secret parts of a secret URI: the whole of it, the phrase or seed, and
+/// the `///password`.
+pub fn suri(suri: &str) {
+    secret(suri);
+    let (phrase, password) = suri.split_once("///").map_or((suri, None), |(phrase, password)| (phrase, Some(password)));
+    secret(phrase.split("//").next().unwrap_or_default().split('/').next().unwrap_or_default());
+    if let Some(password) = password {
+        secret(password);
+    }
+}
+
+/// `text` with every registered secret and every URL password or secret query
+/// parameter masked.
+pub fn text(text: &str) -> String {
+    if DISABLED.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let mut text = text.to_string();
+    for secret in SECRETS.lock().unwrap().iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), MASK);
+        }
+    }
+    if !text.contains("://") {
+        return text;
+    }
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("://") {
+        let end = rest[start..].find(|c: char| c.is_whitespace() || "\"'<>`,;)]}".contains(c)).map_or(rest.len(), |end| start + end);
+        redacted.push_str(&rest[..start]);
+        redacted.push_str(&url(&rest[start..end]));
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Masks the password of a URL's userinfo and the values of its secret query
+/// parameters. `url` starts at the `://` of the scheme.
+fn url(url: &str) -> String {
+    let (url, fragment) = url.split_once('#').map_or((url, None), |(url, fragment)| (url, Some(fragment)));
+    let (url, query) = url.split_once('?').map_or((url, None), |(url, query)| (url, Some(query)));
+    let authority_end = url[3..].find('/').map_or(url.len(), |end| end + 3);
+    let mut redacted = match url[..authority_end].rsplit_once('@') {
+        Some((userinfo, host)) => match userinfo[3..].split_once(':') {
+            Some((user, _)) => format!("://{}:{}@{}{}", user, MASK, host, &url[authority_end..]),
+            None => url.to_string(),
+        },
+        None => url.to_string(),
+    };
+    if let Some(query) = query {
+        let pairs: Vec<String> = query.split('&').map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SECRET_PARAMS.contains(&name.to_ascii_lowercase().as_str()) => format!("{}={}", name, MASK),
+            _ => pair.to_string(),
+        }).collect();
+        redacted.push('?');
+        redacted.push_str(&pairs.join("&"));
+    }
+    if let Some(fragment) = fragment {
+        redacted.push('#');
+        redacted.push_str(fragment);
+    }
+    redacted
+}
+
+/// Masks the secrets in every string of `value` and the whole of its secret fields.
+pub fn value(value: &mut Value) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    match value {
+        Value::String(string) => *string = text(string),
+        Value::Array(values) => values.iter_mut().for_each(self::value),
+        Value::Object(object) => {
+            for (name, value) in object.iter_mut() {
+                if SECRET_FIELDS.contains(&name.to_ascii_lowercase().as_str()) && !value.is_null() {
+                    *value = Value::String(MASK.to_string());
+                } else {
+                    self::value(value);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Masks the secret params of RPC methods that take them, for recording a call.
+pub fn params(method: &str, params: &mut Value) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // author_insertKey(key_type, suri, public)
+    if method == "author_insertKey" {
+        match params {
+            Value::Array(params) if params.len() > 1 => params[1] = Value::String(MASK.to_string()),
+            Value::Object(params) if params.contains_key("suri") => { params.insert("suri".to_string(), Value::String(MASK.to_string())); }
+            _ => {}
+        }
+    }
+    value(params);
+}
@@ -0,0 +1,175 @@
+//! A minimal Redis client for the `redis://[:password@]host:6379[/db]` sink. It caches the
+//! chain head from `gavel subscribe heads` records under `chain:<genesis>:best`,
+//! `chain:<genesis>:finalized` and the latest headers, so services can read the head
+//! without each holding an RPC connection.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json::Value;
+use url::Url;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Cache {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut encoded = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        encoded.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        encoded.extend_from_slice(arg.as_bytes());
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded
+}
+
+impl Cache {
+    pub fn connect(url: &Url) -> Result<Cache, Box<dyn std::error::Error>> {
+        let host = url.host_str().ok_or("Missing host in the Redis URL")?;
+        let stream = TcpStream::connect((host, url.port().unwrap_or(6379)))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut cache = Cache { reader: BufReader::new(stream.try_clone()?), writer: stream };
+
+        if let Some(password) = url.password() {
+            match url.username() {
+                "" => cache.call(&["AUTH", password])?,
+                user => cache.call(&["AUTH", user, password])?,
+            };
+        }
+        let database = url.path().trim_start_matches('/');
+        if !database.is_empty() {
+            cache.call(&["SELECT", database])?;
+        }
+        Ok(cache)
+    }
+
+    /// Reads one reply, failing on an error reply. Bulk and array contents are skipped.
+    fn read_reply(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err("Redis server closed the connection".into());
+        }
+        let line = line.trim_end();
+        let (kind, rest) = line.split_at(1.min(line.len()));
+        match kind {
+            "+" | ":" => Ok(rest.to_string()),
+            "-" => Err(format!("Redis error: {}", rest).into()),
+            "$" => {
+                let length: i64 = rest.parse()?;
+                if length >= 0 {
+                    let mut data = vec![0u8; length as usize + 2];
+                    self.reader.read_exact(&mut data)?;
+                    data.truncate(length as usize);
+                    return Ok(String::from_utf8_lossy(&data).to_string());
+                }
+                Ok(String::new())
+            }
+            "*" => {
+                for _ in 0..rest.parse::<i64>()?.max(0) {
+                    self.read_reply()?;
+                }
+                Ok(String::new())
+            }
+            _ => Err(format!("Unexpected Redis reply: {}", line).into()),
+        }
+    }
+
+    fn call(&mut self, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+        self.writer.write_all(&command(args))?;
+        self.read_reply()
+    }
+
+    /// Sets the head keys from the latest best and finalized head of each chain in the
+    /// batch, in one MSET so readers never see the number and header disagree. Records
+    /// that are not heads are skipped.
+    pub fn send(&mut self, records: &[(String, String)]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut keys = BTreeMap::new();
+        for (_, text) in records {
+            let record: Value = serde_json::from_str(text).unwrap_or_default();
+            let (Some(chain), Some(kind), Some(number)) = (record["chain"].as_str(), record["kind"].as_str(), record["number"].as_u64()) else { continue };
+            let mut header = record["header"].clone();
+            header["hash"] = record["hash"].clone();
+            let header_key = match kind {
+                "best" => format!("chain:{}:header", chain),
+                "finalized" => format!("chain:{}:finalized_header", chain),
+                _ => continue,
+            };
+            keys.insert(format!("chain:{}:{}", chain, kind), number.to_string());
+            keys.insert(header_key, header.to_string());
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut args = vec!["MSET"];
+        for (key, value) in &keys {
+            args.push(key);
+            args.push(value);
+        }
+        self.call(&args)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn encodes_commands_in_resp() {
+        // Lengths count bytes, not characters
+        assert_eq!(command(&["SET", "key", "välue", ""]), "*4\r\n$3\r\nSET\r\n$3\r\nkey\r\n$6\r\nvälue\r\n$0\r\n\r\n".as_bytes());
+    }
+
+    /// Answers each command with the next of `replies`, returning the raw commands read.
+    fn server(listener: TcpListener, replies: &'static [&'static str]) -> Vec<String> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut commands = Vec::new();
+        for reply in replies {
+            let mut command = String::new();
+            reader.read_line(&mut command).unwrap();
+            for _ in 0..command[1..].trim_end().parse::<usize>().unwrap() * 2 {
+                reader.read_line(&mut command).unwrap();
+            }
+            commands.push(command);
+            writer.write_all(reply.as_bytes()).unwrap();
+        }
+        commands
+    }
+
+    #[test]
+    fn sets_the_head_keys_in_one_mset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("redis://:secret@127.0.0.1:{}/2", listener.local_addr().unwrap().port())).unwrap();
+        let server = std::thread::spawn(move || server(listener, &["+OK\r\n", "+OK\r\n", "+OK\r\n", "-ERR wrong number of arguments\r\n"]));
+
+        let mut cache = Cache::connect(&url).unwrap();
+        let head = |kind: &str, number: u64| (String::new(), json!({"chain": "0xab", "kind": kind, "number": number, "hash": format!("0x{:02x}", number), "header": {}}).to_string());
+        cache.send(&[head("best", 10), head("finalized", 8), head("best", 11), (String::new(), "not json".to_string())]).unwrap();
+        // Batches without heads send nothing
+        cache.send(&[(String::new(), json!({"kind": "health"}).to_string())]).unwrap();
+        let error = cache.send(&[head("best", 12)]).unwrap_err();
+        assert_eq!(error.to_string(), "Redis error: ERR wrong number of arguments");
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0], String::from_utf8(command(&["AUTH", "secret"])).unwrap());
+        assert_eq!(commands[1], String::from_utf8(command(&["SELECT", "2"])).unwrap());
+        assert_eq!(commands[2], String::from_utf8(command(&[
+            "MSET",
+            "chain:0xab:best", "11",
+            "chain:0xab:finalized", "8",
+            "chain:0xab:finalized_header", r#"{"hash":"0x08"}"#,
+            "chain:0xab:header", r#"{"hash":"0x0b"}"#,
+        ])).unwrap());
+    }
+}
@@ -0,0 +1,86 @@
+//! Signing through a remote JSON-RPC service (e.g. in front of an HSM), reached over
+//! WebSocket or plain HTTP(S) POST, so keys never have to be on the machine running gavel.
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+use crate::compose::SigningPayload;
+use crate::hex;
+
+/// Posts a JSON-RPC request with HTTP/1.0, which keeps responses unchunked and ends them
+/// by closing the connection.
+async fn http_post<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, url: &Url, body: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        path, url.host_str().unwrap_or_default(), body.len(), body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("Malformed HTTP response from the remote signer")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(format!("Remote signer answered HTTP {}: {}", status, body.trim()).into());
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+async fn request(url: &str, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let parsed = Url::parse(url)?;
+    let response = match parsed.scheme() {
+        "ws" | "wss" => {
+            // A signer is not a node, so no --pin-peer or --chain checks
+            let mut socket = crate::Connection::new(crate::connect_once(url, None, None, None).await?);
+            crate::send_request(&mut socket, method, &params).await?
+        }
+        "http" | "https" => {
+            let body = json!({ "jsonrpc": "2.0", "id": "1", "method": method, "params": params }).to_string();
+            let host = parsed.host_str().ok_or("Missing host in the signer URL")?;
+            let port = parsed.port_or_known_default().ok_or("Unknown port for the signer URL")?;
+            let mut stream = TcpStream::connect((host, port)).await?;
+            if parsed.scheme() == "https" {
+                let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+                http_post(&mut connector.connect(host, stream).await?, &parsed, &body).await?
+            } else {
+                http_post(&mut stream, &parsed, &body).await?
+            }
+        }
+        other => return Err(format!("Unsupported signer URL scheme '{}', expected ws, wss, http or https", other).into()),
+    };
+    if let Some(error) = response.get("error") {
+        return Err(format!("Remote signer refused to sign: {}", error).into());
+    }
+    Ok(response["result"].clone())
+}
+
+/// Asks the service to sign `payload` for `address`. The request carries the bytes to
+/// sign (`data`, already hashed when over 256 bytes) and the decoded payload details for
+/// policy checks; the result is a SCALE encoded `MultiSignature`, either as a hex string
+/// or under `signature`. Returns the signature type and the signature.
+pub async fn sign(url: &str, method: &str, address: &str, payload: &SigningPayload) -> Result<(u8, Vec<u8>), Box<dyn std::error::Error>> {
+    let params = json!([{
+        "address": address,
+        "data": hex::encode(&payload.to_sign()),
+        "payload": payload.to_json(),
+    }]);
+    let result = request(url, method, params).await?;
+    let signature = result.as_str().or_else(|| result["signature"].as_str()).ok_or("Remote signer returned no signature")?;
+    let signature = hex::decode(signature)?;
+    let expected = match signature.first() {
+        Some(0) | Some(1) => 65,
+        Some(2) => 66,
+        _ => return Err("Remote signer returned an unknown signature type".into()),
+    };
+    if signature.len() != expected {
+        return Err(format!("Remote signer returned a {}-byte signature, expected a {}-byte MultiSignature", signature.len(), expected).into());
+    }
+    Ok((signature[0], signature[1..].to_vec()))
+}
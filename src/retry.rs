@@ -0,0 +1,93 @@
+//! Retrying calls after a failure, set with `--retries` and `--call-timeout`.
+//!
+//! A call that never went out can always be sent again. One that was sent but got no
+//! answer, because the connection dropped or `--call-timeout` passed, is ambiguous: the
+//! node may have acted on it. Only methods that read are retried then; for the others,
+//! such as `author_submitExtrinsic`, the failure says the outcome is unknown.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static POLICY: OnceLock<(u32, Option<Duration>)> = OnceLock::new();
+
+/// Methods that change node or chain state even though their prefix reads.
+const WRITES: &[&str] = &[
+    "system_addReservedPeer",
+    "system_removeReservedPeer",
+    "system_addLogFilter",
+    "system_resetLogFilter",
+];
+
+/// Prefixes of the methods that only read, so sending one twice does no harm.
+const READS: &[&str] = &[
+    "chain_get",
+    "state_get",
+    "state_call",
+    "state_query",
+    "state_traceBlock",
+    "childstate_get",
+    "system_",
+    "rpc_methods",
+    "mmr_",
+    "beefy_getFinalizedHead",
+    "grandpa_roundState",
+    "grandpa_proveFinality",
+    "babe_epochAuthorship",
+    "payment_",
+    "offchain_localStorageGet",
+    "author_pendingExtrinsics",
+    "author_hasKey",
+    "author_hasSessionKeys",
+    "archive_v1_",
+    "chainSpec_v1_",
+];
+
+/// Sets how many times a failed call is sent again and how long each waits for its answer.
+pub fn configure(retries: u32, timeout: Option<Duration>) {
+    let _ = POLICY.set((retries, timeout));
+}
+
+pub fn retries() -> u32 {
+    POLICY.get().map_or(0, |(retries, _)| *retries)
+}
+
+pub fn timeout() -> Option<Duration> {
+    POLICY.get().and_then(|(_, timeout)| *timeout)
+}
+
+/// Whether `method` can be sent again after an ambiguous failure. Subscriptions and
+/// unknown methods are not: they are assumed to have effects.
+pub fn is_idempotent(method: &str) -> bool {
+    !method.to_ascii_lowercase().contains("subscribe")
+        && !WRITES.contains(&method)
+        && READS.iter().any(|prefix| method.starts_with(prefix))
+}
+
+/// The wait before retry `attempt` (from 1), doubling from half a second.
+pub fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 << attempt.saturating_sub(1).min(6))
+}
+
+/// A call that was sent but never answered, of a method that is not retried. Its source is
+/// why the answer did not come.
+#[derive(Debug)]
+pub struct Ambiguous {
+    pub method: String,
+    pub cause: Box<dyn std::error::Error>,
+}
+
+impl std::fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} was sent but not answered ({}). It may or may not have taken effect on the node and was not retried, check before sending it again",
+            self.method, self.cause,
+        )
+    }
+}
+
+impl std::error::Error for Ambiguous {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
+}
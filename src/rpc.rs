@@ -0,0 +1,417 @@
+//! One JSON-RPC connection shared by any number of concurrent calls and subscriptions.
+//!
+//! Every request gets the connection's next id and a background task reads the socket,
+//! handing each response to the call waiting on its id (batch answers item by item) and
+//! each notification to its subscription. Calls can be pipelined and a late answer to a
+//! call that gave up no longer passes for the answer to the next one.
+//!
+//! Over HTTP every send is one POST whose answer goes through the same routing, so
+//! callers do not tell the transports apart, except that HTTP cannot subscribe.
+//!
+//! A WebSocket whose TCP connection went half-open takes sends but never answers. When
+//! calls have waited half of `--response-deadline` without anything arriving, the reader
+//! pings the endpoint; a node busy with a slow call still answers that. After the whole
+//! deadline the connection is torn down, failing its calls so that they are re-dialed.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::failure::ConnectionFailure;
+use crate::http::Http;
+use crate::{in_flight, stats};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Notifications kept for subscriptions that are not registered yet, either because the
+/// subscribe call has not returned or because nobody claims them.
+const MAX_EARLY: usize = 1024;
+
+/// Silence with calls waiting after which a connection is presumed half-open.
+const DEFAULT_DEADLINE: Duration = Duration::from_secs(30);
+
+static DEADLINE: OnceLock<Duration> = OnceLock::new();
+
+/// Sets `--response-deadline`, in seconds.
+pub fn set_response_deadline(seconds: Option<u64>) {
+    let _ = DEADLINE.set(seconds.map_or(DEFAULT_DEADLINE, Duration::from_secs));
+}
+
+pub fn response_deadline() -> Duration {
+    DEADLINE.get().copied().unwrap_or(DEFAULT_DEADLINE)
+}
+
+#[derive(Default)]
+struct Routes {
+    pending: HashMap<u64, oneshot::Sender<Result<String, ConnectionFailure>>>,
+    subscriptions: HashMap<String, mpsc::UnboundedSender<Value>>,
+    early: Vec<(String, Value)>,
+    // Since when calls have been waiting without anything arriving
+    quiet_since: Option<Instant>,
+    // Why the connection ended, once it has
+    closed: Option<ConnectionFailure>,
+}
+
+impl Routes {
+    fn failure(&self) -> ConnectionFailure {
+        self.closed.clone().unwrap_or_else(|| ConnectionFailure::new("closed", "Connection closed before receiving response"))
+    }
+
+    /// The calls still waiting, forgetting the ones their callers gave up on.
+    fn waiting(&mut self) -> usize {
+        self.pending.retain(|_, pending| !pending.is_closed());
+        if self.pending.is_empty() {
+            self.quiet_since = None;
+        }
+        self.pending.len()
+    }
+}
+
+type Sink = Arc<tokio::sync::Mutex<SplitSink<WebSocket, Message>>>;
+
+enum Transport {
+    WebSocket {
+        sink: Sink,
+        reader: JoinHandle<()>,
+    },
+    Http(Arc<Http>),
+}
+
+/// What a connection was opened with, to open it again after it ends.
+#[derive(Clone)]
+pub struct Origin {
+    pub endpoint: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub max_response_size: Option<usize>,
+}
+
+pub struct Connection {
+    transport: Transport,
+    next_id: AtomicU64,
+    routes: Arc<Mutex<Routes>>,
+    origin: Option<Origin>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Transport::WebSocket { reader, .. } = &self.transport {
+            reader.abort();
+        }
+    }
+}
+
+/// A subscription id as a string, whether the node uses strings or numbers.
+pub fn subscription_id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads the socket until it fails or stays silent past the response deadline while
+/// calls wait, then fails every call still waiting.
+async fn read(mut stream: SplitStream<WebSocket>, sink: Sink, routes: Arc<Mutex<Routes>>) {
+    let deadline = response_deadline();
+    let mut pinged: Option<Instant> = None;
+    let failure = loop {
+        let next = match tokio::time::timeout(deadline / 4, stream.next()).await {
+            Ok(next) => next,
+            Err(_) => {
+                let (quiet, waiting) = {
+                    let mut routes = routes.lock().unwrap();
+                    let waiting = routes.waiting();
+                    (routes.quiet_since.map(|since| since.elapsed()), waiting)
+                };
+                match (quiet, pinged) {
+                    // The ping had time to come back and did not
+                    (Some(quiet), Some(ping)) if quiet >= deadline && ping.elapsed() >= deadline / 2 => {
+                        break crate::failure::stalled(deadline, waiting);
+                    }
+                    (Some(quiet), None) if quiet >= deadline / 2 => {
+                        pinged = Some(Instant::now());
+                        // A sender holding the sink is writing already, a stuck write counts as silence
+                        if let Ok(mut sink) = sink.try_lock() {
+                            let _ = tokio::time::timeout(deadline / 2, sink.send(Message::Ping(Vec::new()))).await;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        let message = match crate::failure::message(next) {
+            Ok(message) => message,
+            Err(e) => break crate::failure::ended(e),
+        };
+        if let Message::Text(text) = message {
+            dispatch(&routes, text);
+        }
+        // Anything that arrives, a pong included, shows the connection is alive
+        pinged = None;
+        let mut locked = routes.lock().unwrap();
+        if locked.waiting() > 0 {
+            locked.quiet_since = Some(Instant::now());
+        }
+    };
+    let mut routes = routes.lock().unwrap();
+    routes.closed = Some(failure.clone());
+    for (_, pending) in routes.pending.drain() {
+        let _ = pending.send(Err(failure.clone()));
+    }
+    routes.subscriptions.clear();
+}
+
+/// Posts one request or batch and routes the answer, failing the calls it has no
+/// response for.
+async fn post(http: Arc<Http>, text: String, ids: Vec<u64>, routes: Arc<Mutex<Routes>>) {
+    let failure = match http.post(&text).await {
+        Ok(answer) => {
+            let failure = crate::failure::unanswered(&answer);
+            dispatch(&routes, answer);
+            failure
+        }
+        Err(e) => crate::failure::ended(e),
+    };
+    let mut routes = routes.lock().unwrap();
+    for id in ids {
+        if let Some(pending) = routes.pending.remove(&id) {
+            let _ = pending.send(Err(failure.clone()));
+        }
+    }
+}
+
+/// Routes a message from the endpoint, a batch answer item by item.
+fn dispatch(routes: &Mutex<Routes>, text: String) {
+    stats::record_received(text.len());
+    if text.trim_start().starts_with('[') {
+        let Ok(responses) = serde_json::from_str::<Vec<&RawValue>>(&text) else { return };
+        for response in responses {
+            route(routes, response.get().to_string());
+        }
+    } else {
+        route(routes, text);
+    }
+}
+
+/// Hands a response to the call waiting on its id, or a notification to its subscription.
+fn route(routes: &Mutex<Routes>, text: String) {
+    // Only the id and the notification params are parsed here, results can be huge
+    #[derive(Deserialize)]
+    struct Envelope<'a> {
+        #[serde(borrow)]
+        id: Option<&'a RawValue>,
+        #[serde(borrow)]
+        params: Option<&'a RawValue>,
+    }
+    let Ok(envelope) = serde_json::from_str::<Envelope>(&text) else { return };
+    if let Some(id) = envelope.id {
+        let id = id.get().trim_matches('"').parse::<u64>().ok();
+        if let Some(pending) = id.and_then(|id| routes.lock().unwrap().pending.remove(&id)) {
+            let _ = pending.send(Ok(text));
+        }
+        return;
+    }
+    let Some(mut params) = envelope.params.and_then(|params| serde_json::from_str::<Value>(params.get()).ok()) else { return };
+    let Some(id) = subscription_id(&params["subscription"]) else { return };
+    let result = params["result"].take();
+    let mut routes = routes.lock().unwrap();
+    match routes.subscriptions.get(&id) {
+        Some(subscription) => {
+            let _ = subscription.send(result);
+        }
+        None => {
+            if routes.early.len() == MAX_EARLY {
+                routes.early.remove(0);
+            }
+            routes.early.push((id, result));
+        }
+    }
+}
+
+/// The answer to one call, once it arrives.
+pub struct Pending {
+    method: String,
+    sent: Instant,
+    response: oneshot::Receiver<Result<String, ConnectionFailure>>,
+}
+
+impl Pending {
+    /// The response as the node sent it, for results too large to parse into a `Value`.
+    pub async fn text(self) -> Result<String, Box<dyn std::error::Error>> {
+        let text = self.response.await.map_err(|_| "Connection dropped")??;
+        stats::record_latency(&self.method, self.sent.elapsed());
+        Ok(text)
+    }
+
+    pub async fn response(self) -> Result<Value, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&self.text().await?)?)
+    }
+}
+
+pub struct Subscription {
+    id: String,
+    notifications: mpsc::UnboundedReceiver<Value>,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.routes.lock().unwrap().subscriptions.remove(&self.id);
+    }
+}
+
+impl Subscription {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The `result` of the next notification, or why the connection ended.
+    pub async fn next(&mut self) -> Result<Value, Box<dyn std::error::Error>> {
+        match self.notifications.recv().await {
+            Some(result) => Ok(result),
+            None => Err(Box::new(self.routes.lock().unwrap().failure())),
+        }
+    }
+}
+
+impl Connection {
+    pub fn new(socket: WebSocket) -> Self {
+        let (sink, stream) = socket.split();
+        let routes: Arc<Mutex<Routes>> = Arc::default();
+        let sink: Sink = Arc::new(tokio::sync::Mutex::new(sink));
+        let reader = tokio::spawn(read(stream, sink.clone(), routes.clone()));
+        let transport = Transport::WebSocket { sink, reader };
+        Connection { transport, next_id: AtomicU64::new(1), routes, origin: None }
+    }
+
+    pub fn http(http: Http) -> Self {
+        Connection { transport: Transport::Http(Arc::new(http)), next_id: AtomicU64::new(1), routes: Arc::default(), origin: None }
+    }
+
+    /// Remembers where the connection goes, so that [`Connection::reconnect`] can open it again.
+    pub fn with_origin(mut self, origin: Origin) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Whether the connection has ended and no call can be sent on it anymore.
+    pub fn is_closed(&self) -> bool {
+        self.routes.lock().unwrap().closed.is_some()
+    }
+
+    /// A new connection to the same endpoint, with the same DNS overrides and limits.
+    pub async fn reconnect(&self) -> Result<Connection, Box<dyn std::error::Error>> {
+        let origin = self.origin.as_ref().ok_or("The connection cannot be reopened, it was not made by connect")?;
+        crate::connect(&origin.endpoint, origin.ipv4.as_ref(), origin.ipv6.as_ref(), origin.max_response_size).await
+    }
+
+    /// Sends `calls` as one batch (a single call as a plain request) and returns their
+    /// answers to wait for, in order. The caller holds the in-flight slots.
+    pub async fn send(&self, calls: &[(&str, &Value)]) -> Result<Vec<Pending>, Box<dyn std::error::Error>> {
+        let mut requests = Vec::new();
+        let mut pending = Vec::new();
+        let mut ids = Vec::new();
+        {
+            let mut routes = self.routes.lock().unwrap();
+            if let Some(failure) = &routes.closed {
+                return Err(Box::new(failure.clone()));
+            }
+            routes.quiet_since.get_or_insert_with(Instant::now);
+            for (method, params) in calls {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                let (sender, response) = oneshot::channel();
+                routes.pending.insert(id, sender);
+                ids.push(id);
+                requests.push(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+                pending.push(Pending { method: method.to_string(), sent: Instant::now(), response });
+            }
+        }
+        let text = match requests.len() {
+            1 => requests[0].to_string(),
+            _ => Value::Array(requests).to_string(),
+        };
+        stats::record_sent(calls.len() as u64, text.len());
+        match &self.transport {
+            Transport::WebSocket { sink, .. } => {
+                if let Err(e) = sink.lock().await.send(Message::Text(text)).await {
+                    let mut routes = self.routes.lock().unwrap();
+                    for id in ids {
+                        routes.pending.remove(&id);
+                    }
+                    return Err(e.into());
+                }
+            }
+            Transport::Http(http) => {
+                tokio::spawn(post(http.clone(), text, ids, self.routes.clone()));
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Calls `method` and returns the whole response, with its `result` or `error`.
+    pub async fn request(&self, method: &str, params: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let _permit = in_flight::acquire(1).await;
+        let pending = self.send(&[(method, params)]).await?;
+        pending.into_iter().next().ok_or("No call was sent")?.response().await
+    }
+
+    /// Sends `calls` as one batch and returns their responses in order.
+    pub async fn batch(&self, calls: &[(&str, &Value)]) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let _permit = in_flight::acquire(calls.len()).await;
+        let mut responses = Vec::new();
+        for pending in self.send(calls).await? {
+            responses.push(pending.response().await?);
+        }
+        Ok(responses)
+    }
+
+    /// Subscribes with `method`; notifications that arrive before this returns are kept.
+    pub async fn subscribe(&self, method: &str, params: &Value) -> Result<Subscription, Box<dyn std::error::Error>> {
+        if let Transport::Http(_) = self.transport {
+            return Err(format!("{} needs a WebSocket endpoint, HTTP cannot deliver notifications", method).into());
+        }
+        let response = self.request(method, params).await?;
+        if let Some(error) = response.get("error") {
+            stats::record_failure("rpc");
+            return Err(format!("{} failed: {}", method, error).into());
+        }
+        let id = subscription_id(&response["result"]).ok_or_else(|| format!("{} did not return a subscription id", method))?;
+        let (sender, notifications) = mpsc::unbounded_channel();
+        let mut routes = self.routes.lock().unwrap();
+        let early = std::mem::take(&mut routes.early);
+        let (own, others): (Vec<_>, Vec<_>) = early.into_iter().partition(|(early, _)| *early == id);
+        routes.early = others;
+        for (_, result) in own {
+            let _ = sender.send(result);
+        }
+        routes.subscriptions.insert(id.clone(), sender);
+        Ok(Subscription { id, notifications, routes: self.routes.clone() })
+    }
+
+    /// Sends a close frame, calls still waiting fail once the endpoint closes its side.
+    /// Over HTTP the kept-alive connections are dropped.
+    pub async fn close(&self) {
+        match &self.transport {
+            Transport::WebSocket { sink, .. } => {
+                let _ = sink.lock().await.send(Message::Close(None)).await;
+            }
+            Transport::Http(http) => http.close(),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Minimal SCALE codec helpers for the few structures gavel decodes by hand.
+
+/// A cursor over SCALE-encoded bytes.
+pub struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Input { data, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < len {
+            return Err(format!("unexpected end of input: wanted {} bytes at offset {}, {} left", len, self.position, self.remaining()));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, String> {
+        Ok(u128::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(format!("invalid bool byte {}", other)),
+        }
+    }
+
+    pub fn read_compact(&mut self) -> Result<u128, String> {
+        let first = self.read_u8()?;
+        match first & 0b11 {
+            0b00 => Ok((first >> 2) as u128),
+            0b01 => Ok((u16::from_le_bytes([first, self.read_u8()?]) >> 2) as u128),
+            0b10 => {
+                let rest = self.read_bytes(3)?;
+                Ok((u32::from_le_bytes([first, rest[0], rest[1], rest[2]]) >> 2) as u128)
+            }
+            _ => {
+                let len = (first >> 2) as usize + 4;
+                if len > 16 {
+                    return Err(format!("compact integer of {} bytes does not fit in u128", len));
+                }
+                let mut bytes = [0u8; 16];
+                bytes[..len].copy_from_slice(self.read_bytes(len)?);
+                Ok(u128::from_le_bytes(bytes))
+            }
+        }
+    }
+
+    /// Reads a compact length prefix, guarding against lengths the input cannot hold.
+    pub fn read_length(&mut self) -> Result<usize, String> {
+        let len = self.read_compact()?;
+        if len > self.remaining() as u128 {
+            return Err(format!("length prefix {} exceeds the {} bytes left", len, self.remaining()));
+        }
+        Ok(len as usize)
+    }
+
+    pub fn read_vec(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_length()?;
+        self.read_bytes(len)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, String> {
+        let bytes = self.read_vec()?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("invalid UTF-8 string: {}", e))
+    }
+
+    pub fn read_option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(read(self)?)),
+            other => Err(format!("invalid option byte {}", other)),
+        }
+    }
+
+    pub fn read_list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let len = self.read_compact()?;
+        // Every element takes at least one byte, anything longer is corrupt input
+        if len > self.remaining() as u128 {
+            return Err(format!("list length {} exceeds the {} bytes left", len, self.remaining()));
+        }
+        (0..len).map(|_| read(self)).collect()
+    }
+}
+
+pub fn encode_compact(value: u128) -> Vec<u8> {
+    if value < 1 << 6 {
+        vec![(value as u8) << 2]
+    } else if value < 1 << 14 {
+        (((value as u16) << 2) | 0b01).to_le_bytes().to_vec()
+    } else if value < 1 << 30 {
+        (((value as u32) << 2) | 0b10).to_le_bytes().to_vec()
+    } else {
+        let len = (16 - value.leading_zeros() as usize / 8).max(4);
+        let mut encoded = vec![(((len - 4) as u8) << 2) | 0b11];
+        encoded.extend_from_slice(&value.to_le_bytes()[..len]);
+        encoded
+    }
+}
@@ -0,0 +1,238 @@
+//! `gavel serve`: a small read-only HTTP API over one node, so dashboards can query
+//! blocks, events and decoded storage without speaking JSON-RPC or SCALE.
+//!
+//! Every request is pinned to a block hash first, which makes its answer immutable and
+//! lets it be cached; only resolving `latest`, `finalized` and block numbers hits the node
+//! every time.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::cache::Cache;
+use crate::metadata::Metadata;
+use crate::rpc::Connection;
+use crate::{digest, hex, ss58, storage};
+
+const MAX_REQUEST: usize = 16 * 1024;
+
+/// Accepts `:8080` as shorthand for all interfaces, besides full socket addresses.
+pub fn parse_listen(listen: &str) -> Result<SocketAddr, String> {
+    let full = if listen.starts_with(':') { format!("0.0.0.0{}", listen) } else { listen.to_string() };
+    full.parse().map_err(|_| format!("invalid listen address '{}', expected e.g. :8080 or 127.0.0.1:8080", listen))
+}
+
+pub struct Upstream {
+    pub endpoint: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub max_response_size: Option<usize>,
+}
+
+struct Node {
+    socket: Option<Connection>,
+    // Decoding metadata per runtime spec version
+    metadata: BTreeMap<u64, Arc<Metadata>>,
+}
+
+struct State {
+    upstream: Upstream,
+    // One connection, requests take turns on it
+    node: tokio::sync::Mutex<Node>,
+    cache: Mutex<Cache>,
+}
+
+/// An HTTP error status with its message.
+struct Failure(u16, String);
+
+impl From<Box<dyn std::error::Error>> for Failure {
+    fn from(error: Box<dyn std::error::Error>) -> Failure {
+        Failure(502, error.to_string())
+    }
+}
+
+impl From<String> for Failure {
+    fn from(error: String) -> Failure {
+        Failure(400, error)
+    }
+}
+
+fn not_found(what: impl std::fmt::Display) -> Failure {
+    Failure(404, format!("{} not found", what))
+}
+
+/// Resolves `latest`, `finalized`, a block number or a block hash to a block hash.
+async fn resolve(socket: &mut Connection, block: &str) -> Result<String, Failure> {
+    let hash = match block {
+        "latest" | "best" => crate::send_and_receive(socket, "chain_getBlockHash", json!([])).await?,
+        "finalized" => crate::send_and_receive(socket, "chain_getFinalizedHead", json!([])).await?,
+        hash if hash.starts_with("0x") => {
+            hex::decode(hash).ok().filter(|bytes| bytes.len() == 32).ok_or_else(|| Failure(400, format!("invalid block hash '{}'", hash)))?;
+            return Ok(hash.to_lowercase());
+        }
+        number => {
+            let number: u64 = number.parse().map_err(|_| Failure(400, format!("invalid block '{}', expected latest, finalized, a number or a hash", number)))?;
+            crate::send_and_receive(socket, "chain_getBlockHash", json!([number])).await?
+        }
+    };
+    hash.as_str().map(str::to_string).ok_or_else(|| not_found(format!("block {}", block)))
+}
+
+async fn metadata_at(node: &mut Node, hash: &str) -> Result<Arc<Metadata>, Failure> {
+    let socket = node.socket.as_mut().ok_or_else(|| Failure(502, "not connected".into()))?;
+    let version = crate::send_and_receive(socket, "state_getRuntimeVersion", json!([hash])).await?;
+    let spec_version = version["specVersion"].as_u64().ok_or_else(|| not_found(format!("runtime at {}", hash)))?;
+    if let Some(metadata) = node.metadata.get(&spec_version) {
+        return Ok(metadata.clone());
+    }
+    let metadata = Arc::new(crate::metadata::fetch(socket, Some(hash)).await?);
+    node.metadata.insert(spec_version, metadata.clone());
+    Ok(metadata)
+}
+
+/// Storage map keys in the path: `0x` hex as the already encoded key, or an SS58 address.
+fn storage_key(segment: &str) -> Result<Vec<u8>, String> {
+    if segment.starts_with("0x") {
+        return hex::decode(segment);
+    }
+    ss58::parse_account(segment).map(|account| account.to_vec())
+        .map_err(|_| format!("invalid storage key '{}', expected 0x-prefixed SCALE or an SS58 address", segment))
+}
+
+/// Routes a GET to its handler, pinning it to a block and consulting the cache.
+async fn handle(state: &State, path: &str, query: &HashMap<String, String>) -> Result<Value, Failure> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    if let ["decode", "digest", encoded] = segments.as_slice() {
+        return Ok(digest::decode_hex(encoded)?);
+    }
+    if segments.as_slice() == ["health"] {
+        return Ok(json!({ "ok": true }));
+    }
+
+    let mut node = state.node.lock().await;
+    if node.socket.is_none() {
+        let upstream = &state.upstream;
+        node.socket = Some(crate::connect(&upstream.endpoint, upstream.ipv4.as_ref(), upstream.ipv6.as_ref(), upstream.max_response_size).await?);
+    }
+    let socket = node.socket.as_mut().unwrap();
+    let (block, route) = match segments.as_slice() {
+        ["block" | "header" | "events", block] => (block.to_string(), segments[0]),
+        ["runtime"] | ["storage", ..] => (query.get("at").cloned().unwrap_or_else(|| "latest".into()), segments[0]),
+        _ => return Err(Failure(404, format!("no route for {}", path))),
+    };
+    let hash = resolve(socket, &block).await?;
+    let key = format!("{}@{}", if route == "storage" { segments.join("/") } else { route.to_string() }, hash);
+    if let Some(cached) = state.cache.lock().unwrap().get(&key) {
+        return Ok(cached);
+    }
+
+    let value = match route {
+        "block" => {
+            let mut block = crate::send_and_receive(socket, "chain_getBlock", json!([hash])).await?;
+            if block.is_null() {
+                return Err(not_found(format!("block {}", hash)));
+            }
+            digest::decode_header_logs(&mut block["block"]["header"]);
+            block["hash"] = json!(hash);
+            block
+        }
+        "header" => {
+            let mut header = crate::send_and_receive(socket, "chain_getHeader", json!([hash])).await?;
+            if header.is_null() {
+                return Err(not_found(format!("block {}", hash)));
+            }
+            digest::decode_header_logs(&mut header);
+            header["hash"] = json!(hash);
+            header
+        }
+        "runtime" => crate::send_and_receive(socket, "state_getRuntimeVersion", json!([hash])).await?,
+        "events" => {
+            let metadata = metadata_at(&mut node, &hash).await?;
+            let socket = node.socket.as_mut().unwrap();
+            let mut events = storage::read(socket, &metadata, "System", "Events", &[], Some(&hash)).await?;
+            crate::extrinsic::resolve_dispatch_errors(&metadata, &mut events);
+            events
+        }
+        _ => {
+            let [_, pallet, entry, keys @ ..] = segments.as_slice() else {
+                return Err(Failure(400, "expected /storage/<Pallet>/<Entry>[/<key>...]".into()));
+            };
+            let keys = keys.iter().map(|key| storage_key(key)).collect::<Result<Vec<_>, _>>()?;
+            let metadata = metadata_at(&mut node, &hash).await?;
+            if metadata.storage_entry(pallet, entry).is_none() {
+                return Err(not_found(format!("storage {}::{}", pallet, entry)));
+            }
+            let socket = node.socket.as_mut().unwrap();
+            json!({ "at": hash, "value": storage::read(socket, &metadata, pallet, entry, &keys, Some(&hash)).await? })
+        }
+    };
+    state.cache.lock().unwrap().insert(key, value.clone());
+    Ok(value)
+}
+
+async fn respond(state: &State, stream: &mut TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        match stream.read(&mut buffer).await? {
+            0 => return Ok(()),
+            read => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request).to_string();
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or("/"));
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+    let result = if method == "GET" {
+        let result = handle(state, path, &query).await;
+        if let Err(Failure(502, _)) = &result {
+            // The connection may be broken, start over with a new one on the next request
+            state.node.lock().await.socket = None;
+        }
+        result
+    } else {
+        Err(Failure(405, format!("{} is not supported, only GET", method)))
+    };
+    let (status, body) = match result {
+        Ok(value) => (200, value),
+        Err(Failure(status, message)) => (status, json!({ "error": message })),
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Bad Gateway",
+    };
+    let body = serde_json::to_string_pretty(&body).unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nAccess-Control-Allow-Origin: *\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Serves the HTTP API on `listen` until the process is stopped.
+pub async fn serve(upstream: Upstream, listen: SocketAddr, cache_size: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = crate::connect(&upstream.endpoint, upstream.ipv4.as_ref(), upstream.ipv6.as_ref(), upstream.max_response_size).await?;
+    let listener = TcpListener::bind(listen).await.map_err(|e| format!("Failed to listen on {}: {}", listen, e))?;
+    eprintln!("Serving {} on http://{}", upstream.endpoint, listen);
+    let state = Arc::new(State {
+        upstream,
+        node: tokio::sync::Mutex::new(Node { socket: Some(socket), metadata: BTreeMap::new() }),
+        cache: Mutex::new(Cache::new(cache_size)),
+    });
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            let _ = respond(&state, &mut stream).await;
+        });
+    }
+}
@@ -0,0 +1,158 @@
+//! `--sink`: delivers the records of streaming modes to a message broker or database with
+//! at-least-once semantics, keyed by block hash (or number).
+//!
+//! Records are handed to a background thread that batches whatever is queued, retries a
+//! failed batch with backoff until it is acknowledged, and is drained before exit.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde_json::Value;
+use url::Url;
+
+const MAX_BATCH: usize = 500;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Once the command is done, give up on an unreachable broker after this many attempts
+const ATTEMPTS_AT_EXIT: u32 = 3;
+
+enum Event {
+    Record(String, String),
+    Finish,
+}
+
+struct Running {
+    sender: Sender<Event>,
+    thread: JoinHandle<()>,
+}
+
+static SINK: Mutex<Option<Running>> = Mutex::new(None);
+
+enum Sink {
+    Kafka(crate::kafka::Producer),
+    Nats(crate::nats::Publisher),
+    Postgres(crate::postgres::Loader),
+    Redis(crate::redis::Cache),
+}
+
+impl Sink {
+    fn open(url: &Url) -> Result<Sink, Box<dyn std::error::Error>> {
+        match url.scheme() {
+            "kafka" => Ok(Sink::Kafka(crate::kafka::Producer::connect(url)?)),
+            "nats" => Ok(Sink::Nats(crate::nats::Publisher::connect(url)?)),
+            "postgres" | "postgresql" => Ok(Sink::Postgres(crate::postgres::Loader::connect(url)?)),
+            "redis" => Ok(Sink::Redis(crate::redis::Cache::connect(url)?)),
+            other => Err(format!("Unsupported sink '{}', expected kafka://, nats://, postgres:// or redis://", other).into()),
+        }
+    }
+
+    fn send(&mut self, records: &[(String, String)]) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Sink::Kafka(producer) => producer.send(records),
+            Sink::Nats(publisher) => publisher.send(records),
+            Sink::Postgres(loader) => loader.send(records),
+            Sink::Redis(cache) => cache.send(records),
+        }
+    }
+}
+
+/// Checks the sink URL up front so a typo fails the command rather than the first send.
+pub fn parse(url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| format!("invalid sink URL '{}': {}", url, e))?;
+    match url.scheme() {
+        "kafka" | "nats" | "postgres" | "postgresql" | "redis" => Ok(url),
+        other => Err(format!("unsupported sink '{}', expected kafka://, nats://, postgres:// or redis://", other)),
+    }
+}
+
+fn deliver(url: Url, events: Receiver<Event>) {
+    let mut sink: Option<Sink> = None;
+    let mut finishing = false;
+    let mut open = true;
+    while open {
+        let mut batch = Vec::new();
+        let first = if finishing { events.try_recv().ok() } else { events.recv().ok() };
+        match first {
+            Some(Event::Record(key, value)) => batch.push((key, value)),
+            Some(Event::Finish) => finishing = true,
+            None => open = false,
+        }
+        while batch.len() < MAX_BATCH {
+            match events.try_recv() {
+                Ok(Event::Record(key, value)) => batch.push((key, value)),
+                Ok(Event::Finish) => finishing = true,
+                Err(_) => break,
+            }
+        }
+        if batch.is_empty() {
+            if finishing {
+                break;
+            }
+            continue;
+        }
+
+        let mut backoff = Duration::from_secs(1);
+        let mut attempts = 0;
+        loop {
+            let result = match sink.as_mut() {
+                Some(sink) => sink.send(&batch),
+                None => Sink::open(&url).and_then(|opened| sink.insert(opened).send(&batch)),
+            };
+            match result {
+                Ok(()) => break,
+                Err(e) => {
+                    sink = None;
+                    attempts += 1;
+                    crate::stats::record_failure("sink");
+                    if finishing && attempts >= ATTEMPTS_AT_EXIT {
+                        eprintln!("Error: giving up on the sink, {} records were not delivered: {}", batch.len(), crate::redact::text(&e.to_string()));
+                        return;
+                    }
+                    eprintln!("Warning: sink delivery failed, retrying in {}s: {}", backoff.as_secs(), crate::redact::text(&e.to_string()));
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+            // A Finish that arrived meanwhile bounds the retries too
+            if let Ok(Event::Finish) = events.try_recv() {
+                finishing = true;
+            }
+        }
+    }
+}
+
+pub fn configure(url: Option<Url>) {
+    if let Some(url) = url {
+        if let Some(password) = url.password() {
+            crate::redact::secret(password);
+        }
+        let (sender, receiver) = channel();
+        let thread = std::thread::spawn(move || deliver(url, receiver));
+        *SINK.lock().unwrap() = Some(Running { sender, thread });
+    }
+}
+
+/// The message key of a record: its block hash when it has one, its block number otherwise.
+fn key(record: &str) -> String {
+    let value: Value = serde_json::from_str(record).unwrap_or_default();
+    ["hash", "block_hash"].iter()
+        .find_map(|field| value[*field].as_str().map(str::to_string))
+        .or_else(|| value.get("block").filter(|block| !block.is_null()).map(|block| block.as_str().map_or_else(|| block.to_string(), str::to_string)))
+        .unwrap_or_default()
+}
+
+/// Queues a record for the sink, if one is configured.
+pub fn send(record: &str) {
+    if let Some(running) = SINK.lock().unwrap().as_ref() {
+        let _ = running.sender.send(Event::Record(key(record), record.to_string()));
+    }
+}
+
+/// Waits until every queued record is delivered, or the sink gives up.
+pub fn finish() {
+    if let Some(running) = SINK.lock().unwrap().take() {
+        let _ = running.sender.send(Event::Finish);
+        let _ = running.thread.join();
+    }
+}
@@ -0,0 +1,61 @@
+//! Parachain slot leases from the `Slots` pallet, with expiry blocks and estimated dates.
+
+use serde_json::{json, Value};
+
+use crate::rpc::Connection;
+use crate::{clock, epoch, metadata, storage};
+
+pub async fn leases(socket: &mut Connection, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::pinned_block(socket, at).await?;
+    let metadata = metadata::fetch(socket, Some(&at)).await?;
+    if metadata.pallet("Slots").is_none() {
+        return Err("No Slots pallet on this chain".into());
+    }
+    let period_length = metadata.constant_u64("Slots", "LeasePeriod").ok_or("Slots::LeasePeriod constant not found")?.max(1);
+    let offset = metadata.constant_u64("Slots", "LeaseOffset").unwrap_or(0);
+    let block_time_ms = metadata.constant_u64("Babe", "ExpectedBlockTime").or_else(|| epoch::slot_duration_ms(&metadata)).unwrap_or(6000);
+
+    let current_block = crate::fetch_header_number(socket, Some(&at)).await?;
+    let now = storage::read_u64(socket, &metadata, "Timestamp", "Now", &[], Some(&at)).await?.ok_or("Timestamp::Now not set")?;
+    let current_period = current_block.checked_sub(offset).map(|block| block / period_length);
+
+    let mut paras = Vec::new();
+    for (para_id, leases) in storage::read_map(socket, &metadata, "Slots", "Leases", Some(&at)).await? {
+        let leases = leases.as_array().cloned().unwrap_or_default();
+
+        // Index 0 is the current lease period, `None` marks a gap between won leases
+        let last = match leases.iter().rposition(|lease| !lease.is_null()) {
+            Some(last) => last as u64,
+            None => continue,
+        };
+        let holder = leases.iter().find(|lease| !lease.is_null()).cloned().unwrap_or_default();
+        let first_period = current_period.unwrap_or(0);
+        let expiry_block = offset + (first_period + last + 1) * period_length;
+        let blocks_remaining = expiry_block.saturating_sub(current_block);
+        let seconds_remaining = blocks_remaining * block_time_ms / 1000;
+        paras.push(json!({
+            "para_id": para_id,
+            "lease_holder": holder[0],
+            "deposit": holder[1],
+            "first_period": first_period,
+            "last_period": first_period + last,
+            "periods_remaining": last + 1,
+            "gaps": leases[..=last as usize].iter().filter(|lease| lease.is_null()).count(),
+            "expiry_block": expiry_block,
+            "blocks_remaining": blocks_remaining,
+            "expires_in": clock::format_duration(seconds_remaining),
+            "estimated_expiry": clock::format_unix_millis(now + seconds_remaining * 1000),
+        }));
+    }
+    paras.sort_by_key(|para| para["expiry_block"].as_u64().unwrap_or_default());
+
+    Ok(json!({
+        "block_number": current_block,
+        "block_hash": at,
+        "lease_period_length": period_length,
+        "lease_offset": offset,
+        "current_lease_period": current_period,
+        "next_period_start": current_period.map(|period| offset + (period + 1) * period_length),
+        "paras": paras,
+    }))
+}
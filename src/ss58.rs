@@ -0,0 +1,95 @@
+//! SS58 address encoding and decoding for AccountId32 values.
+
+use crate::hashing;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn base58(input: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::with_capacity(input.len() * 138 / 100 + 1);
+    for byte in input {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = input.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n(b'1', zeros)
+        .chain(digits.iter().rev().map(|digit| ALPHABET[*digit as usize]))
+        .map(char::from)
+        .collect()
+}
+
+fn unbase58(input: &str) -> Result<Vec<u8>, String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(input.len());
+    for c in input.bytes() {
+        let mut carry = ALPHABET.iter().position(|a| *a == c).ok_or_else(|| format!("invalid base58 character '{}'", c as char))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = input.bytes().take_while(|c| *c == b'1').count();
+    Ok(std::iter::repeat_n(0, zeros).chain(bytes.into_iter().rev()).collect())
+}
+
+fn checksum(payload: &[u8]) -> Vec<u8> {
+    hashing::blake2b(64, &[b"SS58PRE".as_slice(), payload].concat())[..2].to_vec()
+}
+
+/// Encodes a 32-byte account id with the given network prefix.
+pub fn encode(prefix: u16, account: &[u8]) -> String {
+    let mut payload = if prefix < 64 {
+        vec![prefix as u8]
+    } else {
+        vec![
+            ((prefix & 0b0000_0000_1111_1100) >> 2) as u8 | 0b0100_0000,
+            ((prefix >> 8) as u8) | (((prefix & 0b0000_0000_0000_0011) as u8) << 6),
+        ]
+    };
+    payload.extend_from_slice(account);
+    let checksum = checksum(&payload);
+    payload.extend(checksum);
+    base58(&payload)
+}
+
+/// Decodes an SS58 address into its network prefix and 32-byte account id.
+pub fn decode(address: &str) -> Result<(u16, [u8; 32]), String> {
+    let data = unbase58(address)?;
+    let (prefix, prefix_len) = match data.first() {
+        Some(first) if *first < 64 => (*first as u16, 1),
+        Some(first) if *first < 128 && data.len() > 1 => {
+            let (lower, upper) = (*first, data[1]);
+            (((lower & 0b0011_1111) as u16) << 2 | (upper >> 6) as u16 | ((upper & 0b0011_1111) as u16) << 8, 2)
+        }
+        _ => return Err(format!("invalid SS58 prefix in '{}'", address)),
+    };
+    if data.len() != prefix_len + 32 + 2 {
+        return Err(format!("'{}' is not an SS58 encoded 32-byte account", address));
+    }
+    let (payload, check) = data.split_at(prefix_len + 32);
+    if checksum(payload) != check {
+        return Err(format!("invalid SS58 checksum in '{}'", address));
+    }
+    let mut account = [0u8; 32];
+    account.copy_from_slice(&payload[prefix_len..]);
+    Ok((prefix, account))
+}
+
+/// Parses an account given either as an SS58 address or as `0x`-prefixed hex.
+pub fn parse_account(input: &str) -> Result<[u8; 32], String> {
+    if input.starts_with("0x") {
+        return crate::hex::decode(input)?.try_into().map_err(|_| format!("'{}' is not a 32-byte account", input));
+    }
+    decode(input).map(|(_, account)| account)
+}
@@ -0,0 +1,324 @@
+use serde_json::{json, Value};
+
+use crate::epoch;
+use crate::metadata;
+use crate::rpc::Connection;
+use crate::scale::Input;
+use crate::storage;
+
+const MILLIS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0 * 1000.0;
+
+/// Parameters of the NPoS inflation curve. They live in runtime code rather than in
+/// metadata, so they are taken from the command line with Polkadot's values as defaults.
+#[derive(clap::Args, Debug, Clone)]
+pub struct InflationCurve {
+    #[clap(long, default_value = "0.75", help = "Ideal staking rate of the inflation curve.")]
+    pub ideal_stake: f64,
+    #[clap(long, default_value = "0.025", help = "Inflation when nothing is staked.")]
+    pub min_inflation: f64,
+    #[clap(long, default_value = "0.1", help = "Inflation at the ideal staking rate.")]
+    pub max_inflation: f64,
+    #[clap(long, default_value = "0.05", help = "Falloff of inflation past the ideal staking rate.")]
+    pub falloff: f64,
+}
+
+impl InflationCurve {
+    /// Annual inflation for staking rate `x`, following the Web3 Foundation NPoS model.
+    pub fn inflation(&self, x: f64) -> f64 {
+        if x <= self.ideal_stake {
+            self.min_inflation + (self.max_inflation - self.min_inflation) * x / self.ideal_stake
+        } else {
+            self.min_inflation + (self.max_inflation - self.min_inflation) * 2f64.powf((self.ideal_stake - x) / self.falloff)
+        }
+    }
+}
+
+fn round(value: f64, digits: i32) -> f64 {
+    let factor = 10f64.powi(digits);
+    (value * factor).round() / factor
+}
+
+fn to_tokens(planck: u128, decimals: u32) -> f64 {
+    planck as f64 / 10f64.powi(decimals as i32)
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+/// The token decimals of the `--chain` bundle, else the ones the node reports.
+pub async fn token_decimals(socket: &mut Connection) -> Result<u32, Box<dyn std::error::Error>> {
+    if let Some(decimals) = crate::config::chain().and_then(|chain| chain.decimals) {
+        return Ok(decimals);
+    }
+    let properties = crate::send_and_receive(socket, "system_properties", json!([])).await?;
+    Ok(match &properties["tokenDecimals"] {
+        Value::Array(decimals) => decimals.first().and_then(Value::as_u64),
+        decimals => decimals.as_u64(),
+    }.unwrap_or(0) as u32)
+}
+
+/// Median commission (0 to 1) of the validators active in `era`.
+async fn median_commission(socket: &mut Connection, metadata: &metadata::Metadata, era: u32, at: Option<&str>) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    let validators = match storage::read_raw(socket, metadata, "Session", "Validators", &[], at).await? {
+        Some(bytes) => Input::new(&bytes).read_list(|input| input.read_bytes(32).map(<[u8]>::to_vec))?,
+        None => return Ok(None),
+    };
+    let keys = validators.into_iter()
+        .map(|account| metadata.storage_key("Staking", "ErasValidatorPrefs", &[era.to_le_bytes().to_vec(), account]))
+        .collect::<Result<Vec<_>, _>>()?;
+    let commissions = storage::query_many(socket, &keys, at).await?
+        .into_iter()
+        .flatten()
+        .filter_map(|prefs| Input::new(&prefs).read_compact().ok())
+        .map(|perbill| perbill as f64 / 1_000_000_000.0)
+        .collect();
+    Ok(median(commissions))
+}
+
+pub async fn apy(socket: &mut Connection, curve: &InflationCurve, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.pallet("Staking").is_none() {
+        return Err("Staking pallet not found in the runtime".into());
+    }
+    let decimals = token_decimals(socket).await?;
+
+    let active_era = storage::read(socket, &metadata, "Staking", "ActiveEra", &[], at).await?;
+    let era = active_era["index"].as_u64().ok_or("No active era")? as u32;
+    let issuance = storage::read_u128(socket, &metadata, "Balances", "TotalIssuance", &[], at).await?.ok_or("Balances::TotalIssuance not found")?;
+    let staked = storage::read_u128(socket, &metadata, "Staking", "ErasTotalStake", &[era.to_le_bytes().to_vec()], at).await?.unwrap_or(0);
+    let last_reward = match era.checked_sub(1) {
+        Some(previous) => storage::read_u128(socket, &metadata, "Staking", "ErasValidatorReward", &[previous.to_le_bytes().to_vec()], at).await?,
+        None => None,
+    };
+
+    // Era length in wall-clock time, from BABE timing and sessions per era
+    let sessions_per_era = metadata.constant_u64("Staking", "SessionsPerEra").ok_or("Staking::SessionsPerEra constant not found")?;
+    let epoch_duration = metadata.constant_u64("Babe", "EpochDuration").ok_or("Babe::EpochDuration constant not found")?;
+    let slot_duration = epoch::slot_duration_ms(&metadata).ok_or("Unable to determine the slot duration")?;
+    let eras_per_year = MILLIS_PER_YEAR / (sessions_per_era * epoch_duration * slot_duration) as f64;
+
+    let staking_rate = if issuance > 0 { staked as f64 / issuance as f64 } else { 0.0 };
+    let model_inflation = curve.inflation(staking_rate);
+    let model_apy = if staking_rate > 0.0 { model_inflation / staking_rate } else { 0.0 };
+
+    let realized = last_reward.filter(|_| staked > 0).map(|reward| {
+        let annual = reward as f64 * eras_per_year;
+        (annual / issuance as f64, annual / staked as f64)
+    });
+    let base_apy = realized.map(|(_, apy)| apy).unwrap_or(model_apy);
+    let commission = median_commission(socket, &metadata, era, at).await?;
+    let nominator_apy = commission.map(|commission| base_apy * (1.0 - commission));
+
+    Ok(json!({
+        "era": era,
+        "eras_per_year": round(eras_per_year, 2),
+        "token_decimals": decimals,
+        "total_issuance": to_tokens(issuance, decimals),
+        "total_staked": to_tokens(staked, decimals),
+        "staking_rate": round(staking_rate, 4),
+        "ideal_staking_rate": curve.ideal_stake,
+        "model": {
+            "inflation": round(model_inflation, 4),
+            "staker_apy": round(model_apy, 4),
+        },
+        "realized": realized.map(|(inflation, apy)| json!({
+            "era": era - 1,
+            "era_reward": to_tokens(last_reward.unwrap_or_default(), decimals),
+            "inflation": round(inflation, 4),
+            "staker_apy": round(apy, 4),
+        })),
+        "median_commission": commission.map(|commission| round(commission, 4)),
+        "validator_apy": round(base_apy, 4),
+        "nominator_apy": nominator_apy.map(|apy| round(apy, 4)),
+    }))
+}
+
+/// Columns of the CSV history, in order.
+const HISTORY_COLUMNS: [&str; 13] = [
+    "era", "active", "commission", "blocked", "total_stake", "own_stake", "nominators", "points",
+    "points_share", "era_reward", "validator_earnings", "slashed", "claimed",
+];
+
+/// A validator's exposure in one era: total and own stake and the number of nominators.
+/// Runtimes with paged exposure keep it in `ErasStakersOverview`, older ones in `ErasStakers`.
+fn exposure(overview: Option<&[u8]>, legacy: Option<&[u8]>) -> Result<Option<(u128, u128, u64)>, String> {
+    if let Some(bytes) = overview {
+        let mut input = Input::new(bytes);
+        return Ok(Some((input.read_compact()?, input.read_compact()?, input.read_u32()? as u64)));
+    }
+    let Some(bytes) = legacy else { return Ok(None) };
+    let mut input = Input::new(bytes);
+    let (total, own) = (input.read_compact()?, input.read_compact()?);
+    let others = input.read_list(|input| {
+        input.read_bytes(32)?;
+        input.read_compact()
+    })?;
+    // Eras without an exposure record an empty default
+    Ok(Some((total, own, others.len() as u64)).filter(|(total, _, _)| *total > 0))
+}
+
+/// Reward points of `account` and of all validators in an era.
+fn reward_points(bytes: &[u8], account: &[u8; 32]) -> Result<(u32, u32), String> {
+    let mut input = Input::new(bytes);
+    let total = input.read_u32()?;
+    let individual = input.read_list(|input| Ok((input.read_array::<32>()?, input.read_u32()?)))?;
+    Ok((individual.into_iter().find(|(id, _)| id == account).map_or(0, |(_, points)| points), total))
+}
+
+/// Walks the last `eras` completed eras still in history for one validator: commission,
+/// exposure, reward points, its share of the era payout, slashes and whether the reward
+/// was claimed.
+pub async fn history(socket: &mut Connection, validator: &str, eras: u32, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let (_, account) = crate::ss58::decode(validator)?;
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.pallet("Staking").is_none() {
+        return Err("Staking pallet not found in the runtime".into());
+    }
+    let decimals = token_decimals(socket).await?;
+    let active_era = storage::read(socket, &metadata, "Staking", "ActiveEra", &[], at).await?;
+    let active = active_era["index"].as_u64().ok_or("No active era")? as u32;
+    // Older eras are pruned from storage
+    let depth = metadata.constant_u64("Staking", "HistoryDepth").unwrap_or(84) as u32;
+    let first = active.saturating_sub(eras.min(depth)).max(active.saturating_sub(depth));
+
+    // One batch of storage keys per era, skipping entries this runtime doesn't have
+    let entries = ["ErasValidatorPrefs", "ErasStakersOverview", "ErasStakers", "ErasRewardPoints", "ErasValidatorReward", "ValidatorSlashInEra", "ClaimedRewards"];
+    let mut keys = Vec::new();
+    for era in first..active {
+        for entry in entries {
+            let era_key = era.to_le_bytes().to_vec();
+            let key_parts = match entry {
+                "ErasRewardPoints" | "ErasValidatorReward" => vec![era_key],
+                _ => vec![era_key, account.to_vec()],
+            };
+            keys.push(match metadata.storage_entry("Staking", entry) {
+                Some(_) => metadata.storage_key("Staking", entry, &key_parts)?,
+                None => String::new(),
+            });
+        }
+    }
+    let mut values = Vec::new();
+    for chunk in keys.chunks(storage::MAX_PAGE_SIZE) {
+        values.extend(storage::query_many(socket, chunk, at).await?);
+    }
+
+    let mut rows = Vec::new();
+    let (mut active_eras, mut total_points, mut earned, mut slashed_eras, mut slashed_total, mut unclaimed) = (0, 0u64, 0u128, 0, 0u128, Vec::new());
+    for (era, values) in (first..active).zip(values.chunks(entries.len())) {
+        let [prefs, overview, legacy, points, reward, slash, claimed] = values else { continue };
+        let (commission, blocked) = match prefs {
+            Some(bytes) => {
+                let mut input = Input::new(bytes);
+                (Some(input.read_compact()? as f64 / 1_000_000_000.0), input.read_bool().ok())
+            }
+            None => (None, None),
+        };
+        let exposure = exposure(overview.as_deref(), legacy.as_deref())?;
+        let (points, era_points) = match points {
+            Some(bytes) => reward_points(bytes, &account)?,
+            None => (0, 0),
+        };
+        let era_reward = reward.as_deref().map(|bytes| Input::new(bytes).read_u128()).transpose()?;
+        // Perbill of the exposure and the slashed amount
+        let slash = slash.as_deref().map(|bytes| {
+            let mut input = Input::new(bytes);
+            input.read_u32()?;
+            input.read_u128()
+        }).transpose()?;
+        let claimed = claimed.as_deref().map(|bytes| Input::new(bytes).read_list(|input| input.read_u32())).transpose()?.map(|pages| !pages.is_empty());
+
+        // The validator's payout is its points' share of the era reward; it keeps its
+        // commission plus its own stake's share of the rest
+        let payout = match (era_reward, era_points) {
+            (Some(reward), total) if total > 0 => reward as f64 * points as f64 / total as f64,
+            _ => 0.0,
+        };
+        let earnings = match exposure {
+            Some((total, own, _)) if total > 0 => {
+                let commission = commission.unwrap_or(0.0);
+                payout * commission + payout * (1.0 - commission) * own as f64 / total as f64
+            }
+            _ => 0.0,
+        };
+
+        let mut incidents = Vec::new();
+        if exposure.is_none() {
+            incidents.push("not_elected");
+        } else {
+            active_eras += 1;
+            if points == 0 {
+                incidents.push("no_points");
+            }
+            if claimed == Some(false) && payout > 0.0 {
+                incidents.push("unclaimed");
+                unclaimed.push(era);
+            }
+        }
+        if let Some(amount) = slash {
+            incidents.push("slashed");
+            slashed_eras += 1;
+            slashed_total += amount;
+        }
+        if blocked == Some(true) {
+            incidents.push("blocked");
+        }
+        total_points += points as u64;
+        earned += earnings as u128;
+
+        rows.push(json!({
+            "era": era,
+            "active": exposure.is_some(),
+            "commission": commission.map(|commission| round(commission, 4)),
+            "blocked": blocked,
+            "total_stake": exposure.map(|(total, _, _)| to_tokens(total, decimals)),
+            "own_stake": exposure.map(|(_, own, _)| to_tokens(own, decimals)),
+            "nominators": exposure.map(|(_, _, nominators)| nominators),
+            "points": points,
+            "points_share": if era_points > 0 { Some(round(points as f64 / era_points as f64, 6)) } else { None },
+            "era_reward": era_reward.map(|reward| to_tokens(reward, decimals)),
+            "validator_earnings": to_tokens(earnings as u128, decimals),
+            "slashed": slash.map(|amount| to_tokens(amount, decimals)),
+            "claimed": claimed,
+            "incidents": incidents,
+        }));
+    }
+
+    Ok(json!({
+        "validator": validator,
+        "eras": { "from": first, "to": active.saturating_sub(1), "history_depth": depth },
+        "token_decimals": decimals,
+        "summary": {
+            "active_eras": active_eras,
+            "total_points": total_points,
+            "validator_earnings": to_tokens(earned, decimals),
+            "slashed_eras": slashed_eras,
+            "slashed": to_tokens(slashed_total, decimals),
+            "unclaimed_eras": unclaimed,
+        },
+        "history": rows,
+    }))
+}
+
+/// The per-era rows of a history as CSV, incidents joined with `;`.
+pub fn history_csv(history: &Value) -> String {
+    let mut csv = HISTORY_COLUMNS.join(",") + ",incidents\n";
+    for row in history["history"].as_array().into_iter().flatten() {
+        let mut fields: Vec<String> = HISTORY_COLUMNS.iter().map(|column| match &row[*column] {
+            Value::Null => String::new(),
+            value => value.to_string(),
+        }).collect();
+        let incidents: Vec<&str> = row["incidents"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+        fields.push(incidents.join(";"));
+        csv += &(fields.join(",") + "\n");
+    }
+    csv
+}
@@ -0,0 +1,172 @@
+//! Storage size breakdown per pallet and storage entry, exact or estimated from a sample.
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata, StorageType};
+use crate::rpc::Connection;
+use crate::{hex, storage};
+
+#[derive(Default, Clone, Copy)]
+struct Size {
+    keys: f64,
+    key_bytes: f64,
+    value_bytes: f64,
+    exact: bool,
+}
+
+impl Size {
+    fn add(&mut self, other: Size) {
+        self.keys += other.keys;
+        self.key_bytes += other.key_bytes;
+        self.value_bytes += other.value_bytes;
+        self.exact &= other.exact;
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "keys": self.keys.round() as u64,
+            "key_bytes": self.key_bytes.round() as u64,
+            "value_bytes": self.value_bytes.round() as u64,
+            "exact": self.exact,
+        })
+    }
+}
+
+/// Fraction of the hash space covered by keys up to `key`, read from the 8 bytes that
+/// follow the 32-byte entry prefix. Only meaningful for hashed map keys.
+fn coverage(key: &[u8]) -> Option<f64> {
+    let bytes: [u8; 8] = key.get(32..40)?.try_into().ok()?;
+    let fraction = u64::from_be_bytes(bytes) as f64 / u64::MAX as f64;
+    if fraction > 0.0 { Some(fraction) } else { None }
+}
+
+/// Whether the keys of an entry start with a hash, spreading them evenly over the key
+/// space so that [`coverage`] tells how far a sample got.
+fn is_hashed(ty: &StorageType) -> bool {
+    match ty {
+        StorageType::Plain(_) => false,
+        StorageType::Map { hashers, .. } => hashers.first().is_some_and(|hasher| hasher.hash_len() >= 8),
+    }
+}
+
+/// Scales a sample ending at `last_key` up to the whole entry. Unhashed keys give no clue
+/// of the total, so their sample stays a lower bound.
+fn extrapolate(mut size: Size, last_key: &[u8], hashed: bool) -> Size {
+    if let (false, true, Some(fraction)) = (size.exact, hashed, coverage(last_key)) {
+        size.keys /= fraction;
+        size.key_bytes /= fraction;
+        size.value_bytes /= fraction;
+    }
+    size
+}
+
+/// Sizes one storage entry. With a `sample` limit, stops after that many keys and scales
+/// the counts of hashed keys by how much of the key space the sample covered.
+async fn entry_size(socket: &mut Connection, prefix: &[u8], hashed: bool, sample: Option<usize>, at: &str) -> Result<Size, Box<dyn std::error::Error>> {
+    let prefix = hex::encode(prefix);
+    let mut size = Size { exact: true, ..Default::default() };
+    let mut start_key: Option<String> = None;
+    let mut last_key = Vec::new();
+
+    loop {
+        let remaining = sample.map(|sample| sample - size.keys as usize).unwrap_or(storage::MAX_PAGE_SIZE);
+        let page_size = remaining.min(storage::MAX_PAGE_SIZE);
+        if page_size == 0 {
+            // The sample is exact when it took every key
+            size.exact = storage::keys_paged(socket, &prefix, 1, start_key.as_deref(), Some(at)).await?.is_empty();
+            break;
+        }
+        let keys = storage::keys_paged(socket, &prefix, page_size, start_key.as_deref(), Some(at)).await?;
+        if keys.is_empty() {
+            break;
+        }
+        let values = storage::query_many(socket, &keys, Some(at)).await?;
+        for (key, value) in keys.iter().zip(values) {
+            size.keys += 1.0;
+            size.key_bytes += (key.len() as f64 - 2.0) / 2.0;
+            size.value_bytes += value.map(|value| value.len()).unwrap_or_default() as f64;
+        }
+        let page_len = keys.len();
+        start_key = keys.last().cloned();
+        last_key = hex::decode(start_key.as_deref().unwrap_or_default())?;
+        if page_len < page_size {
+            break;
+        }
+    }
+    Ok(extrapolate(size, &last_key, hashed))
+}
+
+pub async fn state_size(socket: &mut Connection, pallet_filter: Option<&str>, sample: Option<usize>, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    // Pin every read to one block so the breakdown is consistent
+    let at = crate::pinned_block(socket, at).await?;
+    let metadata: Metadata = metadata::fetch(socket, Some(&at)).await?;
+
+    let mut pallets = Vec::new();
+    let mut total = Size { exact: true, ..Default::default() };
+    for pallet in &metadata.pallets {
+        if pallet_filter.is_some_and(|filter| !filter.eq_ignore_ascii_case(&pallet.name)) {
+            continue;
+        }
+        let prefix = match &pallet.storage_prefix {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        eprintln!("Sizing {}...", pallet.name);
+
+        let mut pallet_size = Size { exact: true, ..Default::default() };
+        let mut entries = Vec::new();
+        for entry in &pallet.storage {
+            let size = entry_size(socket, &metadata::storage_prefix(prefix, &entry.name), is_hashed(&entry.ty), sample, &at).await?;
+            pallet_size.add(size);
+            let mut entry_json = size.to_json();
+            entry_json["entry"] = json!(entry.name);
+            entries.push(entry_json);
+        }
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry["value_bytes"].as_u64().unwrap_or_default()));
+        total.add(pallet_size);
+
+        let mut pallet_json = pallet_size.to_json();
+        pallet_json["pallet"] = json!(pallet.name);
+        pallet_json["entries"] = json!(entries);
+        pallets.push(pallet_json);
+    }
+    if pallet_filter.is_some() && pallets.is_empty() {
+        return Err(format!("Pallet {} not found or has no storage", pallet_filter.unwrap_or_default()).into());
+    }
+    pallets.sort_by_key(|pallet| std::cmp::Reverse(pallet["value_bytes"].as_u64().unwrap_or_default()));
+
+    let code_size = crate::send_and_receive(socket, "state_getStorageSize", json!([hex::encode(b":code"), at])).await?;
+    Ok(json!({
+        "block_hash": at,
+        "sample": sample,
+        "total": total.to_json(),
+        "runtime_code_bytes": code_size,
+        "pallets": pallets,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Hasher;
+
+    fn sample() -> Size {
+        Size { keys: 100.0, key_bytes: 4800.0, value_bytes: 1000.0, exact: false }
+    }
+
+    #[test]
+    fn extrapolates_hashed_keys_only() {
+        // A sample ending a quarter of the way through the hash space
+        let last_key = [vec![0; 32], 0x4000_0000_0000_0000u64.to_be_bytes().to_vec()].concat();
+        let map = |hasher| StorageType::Map { hashers: vec![hasher], key: 0, value: 0 };
+        assert!(is_hashed(&map(Hasher::Blake2_128Concat)) && is_hashed(&map(Hasher::Twox64Concat)));
+        assert!(!is_hashed(&map(Hasher::Identity)) && !is_hashed(&StorageType::Plain(0)));
+
+        let estimate = extrapolate(sample(), &last_key, true);
+        assert_eq!((estimate.keys.round(), estimate.value_bytes.round()), (400.0, 4000.0));
+        let lower_bound = extrapolate(sample(), &last_key, false);
+        assert_eq!((lower_bound.keys, lower_bound.value_bytes), (100.0, 1000.0));
+        let exact = extrapolate(Size { exact: true, ..sample() }, &last_key, true);
+        assert_eq!(exact.keys, 100.0);
+    }
+}
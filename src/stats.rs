@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+
+struct RunStats {
+    requests: u64,
+    failures: BTreeMap<&'static str, u64>,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+// Commands record into a single process-wide collector so the summary covers
+// every connection a run opened, not just the last one.
+static STATS: Mutex<RunStats> = Mutex::new(RunStats {
+    requests: 0,
+    failures: BTreeMap::new(),
+    bytes_sent: 0,
+    bytes_received: 0,
+});
+
+/// Upper bounds of the latency histogram buckets, in milliseconds.
+const LATENCY_BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+#[derive(Default)]
+struct Histogram {
+    // One count per bucket plus the overflow bucket
+    buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: f64,
+}
+
+// Keyed by method and UTC hour of day, so slowdowns at the same time every night
+// (backups, compactions) show up as a hot row rather than vanishing into an average.
+static LATENCY: Mutex<BTreeMap<(String, u8), Histogram>> = Mutex::new(BTreeMap::new());
+
+// JSON-RPC error responses by method, next to the latency histograms' call counts
+static METHOD_ERRORS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryFormat {
+    Text,
+    Json,
+}
+
+/// Records `count` JSON-RPC requests sent in a single message of `bytes` bytes.
+pub fn record_sent(count: u64, bytes: usize) {
+    let mut stats = STATS.lock().unwrap();
+    stats.requests += count;
+    stats.bytes_sent += bytes as u64;
+}
+
+pub fn record_received(bytes: usize) {
+    STATS.lock().unwrap().bytes_received += bytes as u64;
+}
+
+fn utc_hour() -> u8 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    (seconds / 3600 % 24) as u8
+}
+
+/// Records how long a `method` call took, in the bucket of the current UTC hour.
+pub fn record_latency(method: &str, elapsed: Duration) {
+    let ms = elapsed.as_secs_f64() * 1000.0;
+    let bucket = LATENCY_BUCKETS_MS.iter().position(|bound| ms <= *bound as f64).unwrap_or(LATENCY_BUCKETS_MS.len());
+    let mut latency = LATENCY.lock().unwrap();
+    let histogram = latency.entry((method.to_string(), utc_hour())).or_default();
+    histogram.buckets[bucket] += 1;
+    histogram.count += 1;
+    histogram.sum_ms += ms;
+}
+
+/// Records a JSON-RPC error response to a `method` call.
+pub fn record_method_error(method: &str) {
+    *METHOD_ERRORS.lock().unwrap().entry(method.to_string()).or_insert(0) += 1;
+}
+
+/// Calls, errors, error rate and mean latency by method, over every hour.
+pub fn methods() -> Value {
+    let mut totals: BTreeMap<String, (u64, f64)> = BTreeMap::new();
+    for ((method, _), histogram) in LATENCY.lock().unwrap().iter() {
+        let total = totals.entry(method.clone()).or_default();
+        total.0 += histogram.count;
+        total.1 += histogram.sum_ms;
+    }
+    let errors = METHOD_ERRORS.lock().unwrap();
+    let mut methods = serde_json::Map::new();
+    for (method, (calls, sum_ms)) in totals {
+        let failed = errors.get(&method).copied().unwrap_or(0);
+        methods.insert(method, json!({
+            "calls": calls,
+            "errors": failed,
+            "error_rate": if calls > 0 { (failed as f64 / calls as f64 * 10000.0).round() / 10000.0 } else { 0.0 },
+            "mean_ms": if calls > 0 { (sum_ms / calls as f64 * 100.0).round() / 100.0 } else { 0.0 },
+        }));
+    }
+    Value::Object(methods)
+}
+
+/// The latency histograms by method and UTC hour. Bucket counts are per bucket (not
+/// cumulative), the last one counting calls slower than every bound.
+pub fn latency() -> Value {
+    let latency = LATENCY.lock().unwrap();
+    let mut methods = serde_json::Map::new();
+    for ((method, hour), histogram) in latency.iter() {
+        let hours = methods.entry(method.clone()).or_insert_with(|| json!({}));
+        hours[format!("{:02}", hour)] = json!({
+            "count": histogram.count,
+            "mean_ms": (histogram.sum_ms / histogram.count as f64 * 100.0).round() / 100.0,
+            "buckets": histogram.buckets,
+        });
+    }
+    json!({ "bucket_bounds_ms": LATENCY_BUCKETS_MS, "methods": methods })
+}
+
+/// The run statistics and latency histograms in the Prometheus text exposition format.
+pub fn prometheus() -> String {
+    let mut out = String::new();
+    {
+        let stats = STATS.lock().unwrap();
+        out.push_str("# TYPE gavel_requests_total counter\n");
+        out.push_str(&format!("gavel_requests_total {}\n", stats.requests));
+        out.push_str("# TYPE gavel_failures_total counter\n");
+        for (kind, count) in &stats.failures {
+            out.push_str(&format!("gavel_failures_total{{kind=\"{}\"}} {}\n", kind, count));
+        }
+        out.push_str("# TYPE gavel_bytes_sent_total counter\n");
+        out.push_str(&format!("gavel_bytes_sent_total {}\n", stats.bytes_sent));
+        out.push_str("# TYPE gavel_bytes_received_total counter\n");
+        out.push_str(&format!("gavel_bytes_received_total {}\n", stats.bytes_received));
+    }
+    out.push_str("# TYPE gavel_rpc_errors_total counter\n");
+    for (method, count) in METHOD_ERRORS.lock().unwrap().iter() {
+        out.push_str(&format!("gavel_rpc_errors_total{{method=\"{}\"}} {}\n", method, count));
+    }
+    out.push_str("# TYPE gavel_rpc_latency_seconds histogram\n");
+    for ((method, hour), histogram) in LATENCY.lock().unwrap().iter() {
+        let labels = format!("method=\"{}\",hour=\"{:02}\"", method, hour);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(histogram.buckets.iter()) {
+            cumulative += count;
+            out.push_str(&format!("gavel_rpc_latency_seconds_bucket{{{},le=\"{}\"}} {}\n", labels, *bound as f64 / 1000.0, cumulative));
+        }
+        out.push_str(&format!("gavel_rpc_latency_seconds_bucket{{{},le=\"+Inf\"}} {}\n", labels, histogram.count));
+        out.push_str(&format!("gavel_rpc_latency_seconds_sum{{{}}} {}\n", labels, histogram.sum_ms / 1000.0));
+        out.push_str(&format!("gavel_rpc_latency_seconds_count{{{}}} {}\n", labels, histogram.count));
+    }
+    out
+}
+
+pub fn record_failure(kind: &'static str) {
+    *STATS.lock().unwrap().failures.entry(kind).or_insert(0) += 1;
+}
+
+/// Classifies an error that aborted a command so it shows up in the failure breakdown.
+pub fn record_error(error: &(dyn std::error::Error + 'static)) {
+    let kind = if error.is::<crate::retry::Ambiguous>() {
+        "ambiguous"
+    } else if error.is::<tokio_tungstenite::tungstenite::Error>() || error.is::<crate::failure::ConnectionFailure>() {
+        "transport"
+    } else if error.is::<serde_json::Error>() {
+        "decode"
+    } else if error.is::<std::io::Error>() {
+        "io"
+    } else {
+        "other"
+    };
+    record_failure(kind);
+}
+
+pub fn summary(elapsed: Duration) -> Value {
+    let stats = STATS.lock().unwrap();
+    let seconds = elapsed.as_secs_f64();
+    let rps = if seconds > 0.0 { stats.requests as f64 / seconds } else { 0.0 };
+    json!({
+        "requests": stats.requests,
+        "failures": stats.failures,
+        "failed": stats.failures.values().sum::<u64>(),
+        "bytes_sent": stats.bytes_sent,
+        "bytes_received": stats.bytes_received,
+        "wall_time_ms": elapsed.as_millis() as u64,
+        "rps": (rps * 100.0).round() / 100.0,
+    })
+}
+
+/// Prints the run summary to stderr so it never mixes with the data on stdout.
+pub fn print_summary(format: SummaryFormat, elapsed: Duration) {
+    let summary = summary(elapsed);
+    match format {
+        SummaryFormat::Json => eprintln!("{}", summary),
+        SummaryFormat::Text => {
+            let failures = summary["failures"].as_object()
+                .map(|failures| failures.iter().map(|(kind, count)| format!("{}={}", kind, count)).collect::<Vec<_>>().join(", "))
+                .unwrap_or_default();
+            eprintln!("requests: {}, failed: {}{}", summary["requests"], summary["failed"],
+                      if failures.is_empty() { String::new() } else { format!(" ({})", failures) });
+            eprintln!("bytes sent: {}, bytes received: {}", summary["bytes_sent"], summary["bytes_received"]);
+            eprintln!("wall time: {} ms, average rps: {}", summary["wall_time_ms"], summary["rps"]);
+        }
+    }
+}
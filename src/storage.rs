@@ -0,0 +1,195 @@
+//! Reading runtime storage through the metadata: key construction, default values and decoding.
+
+use serde_json::{json, Value};
+
+use crate::decode::Decoder;
+use crate::encode::Encoder;
+use crate::hex;
+use crate::metadata::{Metadata, StorageType, TypeDef};
+use crate::rpc::Connection;
+use crate::verify::{self, Level};
+
+/// Reads the raw SCALE bytes of `pallet::entry`, falling back to the metadata default for
+/// entries declared with a default value. Returns `None` for absent optional entries.
+pub async fn read_raw(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, keys: &[Vec<u8>], at: Option<&str>) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let key = metadata.storage_key(pallet, entry, keys)?;
+    let value = if verify::level() >= Level::Proofs {
+        let hash = verify::state_block(socket, at).await?;
+        verify::read_proof(socket, &[key], &hash).await?.pop().flatten()
+    } else {
+        let params = match at {
+            Some(hash) => json!([key, hash]),
+            None => json!([key]),
+        };
+        let response = crate::send_and_receive(socket, "state_getStorage", params).await?;
+        response.as_str().map(hex::decode).transpose()?
+    };
+    match value {
+        Some(value) => Ok(Some(value)),
+        None => {
+            let storage = metadata.storage_entry(pallet, entry).ok_or("storage entry not found in metadata")?;
+            Ok(if storage.optional { None } else { Some(storage.default.clone()) })
+        }
+    }
+}
+
+/// Reads `pallet::entry` and decodes it to JSON with the type the metadata declares.
+pub async fn read(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, keys: &[Vec<u8>], at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let storage = metadata.storage_entry(pallet, entry).ok_or_else(|| format!("storage {}::{} not found in metadata", pallet, entry))?;
+    let ty = storage.value_type();
+    match read_raw(socket, metadata, pallet, entry, keys, at).await? {
+        Some(bytes) => Ok(Decoder::new(metadata).decode_all(ty, &bytes)?),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Reads a numeric storage value such as `Babe::EpochIndex` or `Session::CurrentIndex`.
+pub async fn read_u64(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, keys: &[Vec<u8>], at: Option<&str>) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let value = read(socket, metadata, pallet, entry, keys, at).await?;
+    Ok(match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    })
+}
+
+/// Reads a balance-like `u128` storage value without going through JSON numbers.
+pub async fn read_u128(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, keys: &[Vec<u8>], at: Option<&str>) -> Result<Option<u128>, Box<dyn std::error::Error>> {
+    match read_raw(socket, metadata, pallet, entry, keys, at).await? {
+        Some(bytes) => Ok(Some(crate::scale::Input::new(&bytes).read_u128()?)),
+        None => Ok(None),
+    }
+}
+
+/// Fetches many keys in one `state_queryStorageAt` round trip, returning values in key order.
+pub async fn query_many(socket: &mut Connection, keys: &[String], at: Option<&str>) -> Result<Vec<Option<Vec<u8>>>, Box<dyn std::error::Error>> {
+    if verify::level() >= Level::Proofs {
+        let hash = verify::state_block(socket, at).await?;
+        return verify::read_proof(socket, keys, &hash).await;
+    }
+    let params = match at {
+        Some(hash) => json!([keys, hash]),
+        None => json!([keys]),
+    };
+    let response = crate::send_and_receive(socket, "state_queryStorageAt", params).await?;
+    let changes = response.get(0).and_then(|set| set["changes"].as_array()).ok_or("Invalid state_queryStorageAt response")?;
+
+    let mut values = vec![None; keys.len()];
+    for change in changes {
+        let (key, value) = (change[0].as_str().unwrap_or_default(), change[1].as_str());
+        if let (Some(index), Some(value)) = (keys.iter().position(|k| k == key), value) {
+            values[index] = Some(hex::decode(value)?);
+        }
+    }
+    Ok(values)
+}
+
+/// Largest page most nodes accept for `state_getKeysPaged`.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Fetches up to `count` keys under `prefix` that sort after `start_key`, from the first
+/// one when there is none. The node skips a `start_key` itself, so the first page must
+/// not pass the prefix: it is the only key of a plain storage value.
+pub async fn keys_paged(socket: &mut Connection, prefix: &str, count: usize, start_key: Option<&str>, at: Option<&str>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let response = crate::send_and_receive(socket, "state_getKeysPaged", json!([prefix, count, start_key, at])).await?;
+    let keys = response.as_array().ok_or("Invalid state_getKeysPaged response")?;
+    Ok(keys.iter().filter_map(|key| key.as_str().map(str::to_string)).collect())
+}
+
+/// Collects every key under `prefix`, following `state_getKeysPaged` pages to the end.
+pub async fn all_keys(socket: &mut Connection, prefix: &str, at: Option<&str>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut keys: Vec<String> = Vec::new();
+    loop {
+        let page = keys_paged(socket, prefix, MAX_PAGE_SIZE, keys.last().map(String::as_str), at).await?;
+        let done = page.len() < MAX_PAGE_SIZE;
+        keys.extend(page);
+        if done {
+            return Ok(keys);
+        }
+    }
+}
+
+/// Reads every entry of a storage map, returning the decoded map keys with their values.
+pub async fn read_map(socket: &mut Connection, metadata: &Metadata, pallet: &str, entry: &str, at: Option<&str>) -> Result<Vec<(Value, Value)>, Box<dyn std::error::Error>> {
+    let storage = metadata.storage_entry(pallet, entry).ok_or_else(|| format!("storage {}::{} not found in metadata", pallet, entry))?;
+    let keys = all_keys(socket, &metadata.storage_key(pallet, entry, &[])?, at).await?;
+    let decoder = Decoder::new(metadata);
+    let mut pairs = Vec::new();
+    for chunk in keys.chunks(MAX_PAGE_SIZE) {
+        let values = query_many(socket, chunk, at).await?;
+        for (key, value) in chunk.iter().zip(values) {
+            if let Some(value) = value {
+                let key = decoder.decode_storage_key(storage, &hex::decode(key)?)?;
+                pairs.push((key, decoder.decode_all(storage.value_type(), &value)?));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// The pallet and entry names as the metadata spells them, matched regardless of case.
+pub fn entry_names(metadata: &Metadata, pallet: &str, entry: &str) -> Result<(String, String), String> {
+    let found = metadata.pallets.iter().find(|found| found.name.eq_ignore_ascii_case(pallet))
+        .ok_or_else(|| format!("Pallet {} not found in metadata", pallet))?;
+    let storage = found.storage.iter().find(|storage| storage.name.eq_ignore_ascii_case(entry))
+        .ok_or_else(|| format!("storage {}::{} not found in metadata", found.name, entry))?;
+    Ok((found.name.clone(), storage.name.clone()))
+}
+
+/// SCALE-encodes map keys of `pallet::entry` given as text, each as JSON in the shapes
+/// [`Encoder`] takes or else as a plain string, such as an SS58 address or `0x` hex. Fewer
+/// keys than the entry has address a prefix of it.
+pub fn encode_keys(metadata: &Metadata, pallet: &str, entry: &str, keys: &[String]) -> Result<Vec<Vec<u8>>, String> {
+    let storage = metadata.storage_entry(pallet, entry).ok_or_else(|| format!("storage {}::{} not found in metadata", pallet, entry))?;
+    let types = match &storage.ty {
+        StorageType::Plain(_) => Vec::new(),
+        StorageType::Map { hashers, key, .. } if hashers.len() == 1 => vec![*key],
+        // A map with several hashers has a tuple key, one element per hasher
+        StorageType::Map { key, .. } => match &metadata.resolve(*key)?.def {
+            TypeDef::Tuple(fields) => fields.clone(),
+            _ => return Err(format!("{}::{} has several hashers but no tuple key", pallet, entry)),
+        },
+    };
+    if keys.len() > types.len() {
+        return Err(format!("{}::{} takes {} keys, got {}", pallet, entry, types.len(), keys.len()));
+    }
+    let encoder = Encoder::new(metadata);
+    types.iter().zip(keys).map(|(ty, key)| {
+        let value = serde_json::from_str(key).unwrap_or_else(|_| Value::String(key.clone()));
+        encoder.encode(*ty, &value).map_err(|e| format!("Key '{}': {}", key, e))
+    }).collect()
+}
+
+/// Reads `pallet::entry` at the `--at` block (the best block by default, the finalized head
+/// under `--verify proofs`), building its key from `keys` given as text, and returns the
+/// key with the decoded value.
+pub async fn query(socket: &mut Connection, pallet: &str, entry: &str, keys: &[String], at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    // Proven reads need a block, the finalized head when none is given
+    let hash = match verify::level() >= Level::Proofs {
+        true => Some(verify::state_block(socket, at).await?),
+        false => crate::resolve_at(socket, at).await?,
+    };
+    let metadata = crate::metadata::fetch(socket, hash.as_deref()).await?;
+    let (pallet, entry) = entry_names(&metadata, pallet, entry)?;
+    let encoded = encode_keys(&metadata, &pallet, &entry, keys)?;
+    if let Some(StorageType::Map { hashers, .. }) = metadata.storage_entry(&pallet, &entry).map(|storage| &storage.ty) {
+        if encoded.len() < hashers.len() {
+            return Err(format!("{}::{} takes {} keys, got {}", pallet, entry, hashers.len(), encoded.len()).into());
+        }
+    }
+    Ok(json!({
+        "pallet": pallet,
+        "entry": entry,
+        "key": metadata.storage_key(&pallet, &entry, &encoded)?,
+        "block_hash": hash,
+        "value": read(socket, &metadata, &pallet, &entry, &encoded, hash.as_deref()).await?,
+    }))
+}
+
+/// Events emitted by `pallet` in the block `at`, e.g. `SolutionStored` for signed submissions.
+pub async fn pallet_events(socket: &mut Connection, metadata: &Metadata, pallet: &str, at: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let events = read(socket, metadata, "System", "Events", &[], Some(at)).await?;
+    Ok(events.as_array()
+        .map(|records| records.iter().filter_map(|record| record["event"].get(pallet).cloned()).collect())
+        .unwrap_or_default())
+}
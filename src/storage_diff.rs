@@ -0,0 +1,159 @@
+//! Compares the storage under a prefix at two blocks, decoding entries through the
+//! metadata of the block each side was read at.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Value};
+
+use crate::decode::Decoder;
+use crate::hashing;
+use crate::metadata::{self, Metadata, StorageEntry};
+use crate::rpc::Connection;
+use crate::{hex, storage};
+
+/// Storage entries by their 32-byte `twox128(pallet) ++ twox128(entry)` prefix.
+pub struct EntryIndex<'m> {
+    decoder: Decoder<'m>,
+    entries: HashMap<Vec<u8>, (&'m str, &'m StorageEntry)>,
+}
+
+impl<'m> EntryIndex<'m> {
+    pub fn new(metadata: &'m Metadata) -> Self {
+        let mut entries = HashMap::new();
+        for pallet in &metadata.pallets {
+            if let Some(prefix) = &pallet.storage_prefix {
+                for entry in &pallet.storage {
+                    entries.insert(metadata::storage_prefix(prefix, &entry.name), (pallet.name.as_str(), entry));
+                }
+            }
+        }
+        EntryIndex { decoder: Decoder::new(metadata), entries }
+    }
+
+    /// Describes one key/value pair, keeping the raw hex wherever decoding fails.
+    pub fn describe(&self, key: &[u8], value: &[u8]) -> (Value, Value) {
+        let value = match self.entry(key) {
+            Some((_, entry)) => self.decoder.decode_all(entry.value_type(), value).unwrap_or_else(|_| json!(hex::encode(value))),
+            None => json!(hex::encode(value)),
+        };
+        (self.describe_key(key), value)
+    }
+
+    /// Describes a key alone: its storage entry and decoded map keys, when known.
+    pub fn describe_key(&self, key: &[u8]) -> Value {
+        let (pallet, entry) = match self.entry(key) {
+            Some(found) => found,
+            None => return json!({ "key": hex::encode(key) }),
+        };
+        let mut description = json!({
+            "key": hex::encode(key),
+            "storage": format!("{}::{}", pallet, entry.name),
+        });
+        if let Ok(args) = self.decoder.decode_storage_key(entry, key) {
+            if !args.is_null() {
+                description["args"] = args;
+            }
+        }
+        description
+    }
+
+    fn entry(&self, key: &[u8]) -> Option<&(&'m str, &'m StorageEntry)> {
+        key.get(..32).and_then(|prefix| self.entries.get(prefix))
+    }
+}
+
+/// Turns `Pallet`, `Pallet::Entry` (or `Pallet.Entry`) and raw `0x` prefixes into a key prefix.
+pub fn resolve_prefix(prefix: Option<&str>, metadata: &[&Metadata]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let prefix = match prefix {
+        Some(prefix) => prefix,
+        None => return Ok(Vec::new()),
+    };
+    if prefix.starts_with("0x") {
+        return Ok(hex::decode(prefix)?);
+    }
+    let (pallet_name, entry_name) = match prefix.split_once("::").or_else(|| prefix.split_once('.')) {
+        Some((pallet, entry)) => (pallet, Some(entry)),
+        None => (prefix, None),
+    };
+    let pallet = metadata.iter().find_map(|metadata| metadata.pallet(pallet_name))
+        .ok_or_else(|| format!("Pallet {} not found in metadata", pallet_name))?;
+    let storage_prefix = pallet.storage_prefix.as_deref().ok_or_else(|| format!("Pallet {} has no storage", pallet_name))?;
+    Ok(match entry_name {
+        Some(entry) => metadata::storage_prefix(storage_prefix, entry),
+        None => hashing::twox_128(storage_prefix.as_bytes()),
+    })
+}
+
+async fn read_all(socket: &mut Connection, prefix: &str, at: &str) -> Result<BTreeMap<String, Vec<u8>>, Box<dyn std::error::Error>> {
+    let keys = storage::all_keys(socket, prefix, Some(at)).await?;
+    let mut pairs = BTreeMap::new();
+    for chunk in keys.chunks(storage::MAX_PAGE_SIZE) {
+        let values = storage::query_many(socket, chunk, Some(at)).await?;
+        for (key, value) in chunk.iter().zip(values) {
+            if let Some(value) = value {
+                pairs.insert(key.clone(), value);
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// Runtime spec version at a block, to show whether the range spans an upgrade.
+async fn spec_version(socket: &mut Connection, at: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let version = crate::send_and_receive(socket, "state_getRuntimeVersion", json!([at])).await?;
+    Ok(version["specVersion"].clone())
+}
+
+pub async fn diff(socket: &mut Connection, from_block: u64, to_block: u64, prefix: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let from_hash = crate::fetch_block_hash(socket, from_block).await?;
+    let to_hash = crate::fetch_block_hash(socket, to_block).await?;
+    let from_metadata = metadata::fetch(socket, Some(&from_hash)).await?;
+    let to_metadata = metadata::fetch(socket, Some(&to_hash)).await?;
+    let key_prefix = hex::encode(&resolve_prefix(prefix, &[&to_metadata, &from_metadata])?);
+
+    let before = read_all(socket, &key_prefix, &from_hash).await?;
+    let after = read_all(socket, &key_prefix, &to_hash).await?;
+    let (from_index, to_index) = (EntryIndex::new(&from_metadata), EntryIndex::new(&to_metadata));
+
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+    for (key, old) in &before {
+        let key_bytes = hex::decode(key)?;
+        match after.get(key) {
+            None => {
+                let (mut entry, value) = from_index.describe(&key_bytes, old);
+                entry["value"] = value;
+                removed.push(entry);
+            }
+            Some(new) if new != old => {
+                let (mut entry, old_value) = from_index.describe(&key_bytes, old);
+                entry["before"] = old_value;
+                entry["after"] = to_index.describe(&key_bytes, new).1;
+                changed.push(entry);
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, new) in &after {
+        if !before.contains_key(key) {
+            let (mut entry, value) = to_index.describe(&hex::decode(key)?, new);
+            entry["value"] = value;
+            added.push(entry);
+        }
+    }
+
+    Ok(json!({
+        "from": { "number": from_block, "hash": from_hash, "spec_version": spec_version(socket, &from_hash).await? },
+        "to": { "number": to_block, "hash": to_hash, "spec_version": spec_version(socket, &to_hash).await? },
+        "prefix": key_prefix,
+        "summary": {
+            "keys_before": before.len(),
+            "keys_after": after.len(),
+            "added": added.len(),
+            "removed": removed.len(),
+            "changed": changed.len(),
+        },
+        "added": added,
+        "removed": removed,
+        "changed": changed,
+    }))
+}
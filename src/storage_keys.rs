@@ -0,0 +1,69 @@
+//! Every key under a storage prefix, read page by page with `state_getKeysPaged` and
+//! streamed as JSON lines, optionally with the values, for auditing a map such as
+//! `Staking::Nominators` without a script.
+
+use serde_json::Value;
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::storage_diff::{self, EntryIndex};
+use crate::{hex, storage};
+
+/// The hex key prefix of `Pallet`, `Pallet::Entry` or a raw `0x` prefix, narrowed by the
+/// leading map keys of the entry when `keys` are given.
+fn key_prefix(metadata: &Metadata, prefix: &str, keys: &[String]) -> Result<String, Box<dyn std::error::Error>> {
+    if keys.is_empty() {
+        return Ok(hex::encode(&storage_diff::resolve_prefix(Some(prefix), &[metadata])?));
+    }
+    let (pallet, entry) = prefix.split_once("::").or_else(|| prefix.split_once('.'))
+        .ok_or("Map keys need a Pallet::Entry prefix")?;
+    let (pallet, entry) = storage::entry_names(metadata, pallet, entry)?;
+    let encoded = storage::encode_keys(metadata, &pallet, &entry, keys)?;
+    Ok(metadata.storage_key(&pallet, &entry, &encoded)?)
+}
+
+/// Streams the keys under `prefix` at the `--at` block (the finalized head by default, so
+/// that every page sees the same state), `page_size` per request, each with its storage
+/// entry and decoded map keys and, with `values`, its decoded value.
+pub async fn dump(socket: &mut Connection, prefix: &str, keys: &[String], page_size: usize, values: bool, at: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, at).await?;
+    let metadata = metadata::fetch(socket, Some(&hash)).await?;
+    let prefix = key_prefix(&metadata, prefix, keys)?;
+    let index = EntryIndex::new(&metadata);
+    eprintln!("Reading keys under {} at block {}", prefix, hash);
+
+    let mut total = 0;
+    let mut start: Option<String> = None;
+    loop {
+        let page = storage::keys_paged(socket, &prefix, page_size, start.as_deref(), Some(&hash)).await?;
+        let page_values = if values && !page.is_empty() {
+            storage::query_many(socket, &page, Some(&hash)).await?
+        } else {
+            Vec::new()
+        };
+        for (position, key) in page.iter().enumerate() {
+            let bytes = hex::decode(key)?;
+            let record = match page_values.get(position) {
+                Some(Some(value)) => {
+                    let (mut record, value) = index.describe(&bytes, value);
+                    record["value"] = value;
+                    record
+                }
+                Some(None) => {
+                    let mut record = index.describe_key(&bytes);
+                    record["value"] = Value::Null;
+                    record
+                }
+                None => index.describe_key(&bytes),
+            };
+            crate::output::line(&record.to_string())?;
+        }
+        total += page.len();
+        if page.len() < page_size {
+            break;
+        }
+        start = page.last().cloned();
+    }
+    eprintln!("{} keys", total);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use futures_util::stream::{self, StreamExt};
+use serde_json::{json, Value};
+
+use crate::rpc::{Connection, Subscription};
+
+/// The block time assumed when the runtime declares none, Polkadot's.
+const DEFAULT_BLOCK_TIME_MS: u64 = 6_000;
+
+static IDLE_BLOCKS: OnceLock<u64> = OnceLock::new();
+
+/// Declares subscriptions dead after `blocks` expected blocks pass without a message,
+/// for `--idle-timeout`.
+pub fn set_idle_timeout(blocks: Option<u64>) {
+    if let Some(blocks) = blocks {
+        let _ = IDLE_BLOCKS.set(blocks);
+    }
+}
+
+/// How long a subscription may stay silent before it counts as dead: `--idle-timeout`
+/// blocks at the runtime's expected block time. `None` waits forever.
+pub async fn idle_limit(socket: &mut Connection) -> Result<Option<Idle>, Box<dyn std::error::Error>> {
+    let Some(&blocks) = IDLE_BLOCKS.get() else { return Ok(None) };
+    let metadata = crate::metadata::fetch(socket, None).await?;
+    let block_time_ms = crate::epoch::slot_duration_ms(&metadata).unwrap_or(DEFAULT_BLOCK_TIME_MS);
+    Ok(Some(Idle { blocks, limit: Duration::from_millis(block_time_ms * blocks) }))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Idle {
+    blocks: u64,
+    limit: Duration,
+}
+
+/// Waits for `next` (a notification), failing when `idle` passes without one.
+pub async fn within<T>(idle: Option<Idle>, next: impl Future<Output = Result<T, Box<dyn std::error::Error>>>) -> Result<T, Box<dyn std::error::Error>> {
+    let Some(idle) = idle else {
+        return next.await;
+    };
+    match tokio::time::timeout(idle.limit, next).await {
+        Ok(next) => next,
+        Err(_) => Err(format!(
+            "No notification for {} ({} expected blocks), the subscription is considered dead",
+            crate::clock::format_duration(idle.limit.as_secs()), idle.blocks,
+        ).into()),
+    }
+}
+
+/// The next best or finalized head, whichever comes first.
+pub async fn next_head(best: &mut Subscription, finalized: &mut Subscription) -> Result<(&'static str, Value), Box<dyn std::error::Error>> {
+    tokio::select! {
+        header = best.next() => Ok(("best", header?)),
+        header = finalized.next() => Ok(("finalized", header?)),
+    }
+}
+
+/// The next runtime version notification, new heads only show the connection is alive.
+async fn next_version(versions: &mut Subscription, mut heads: Option<&mut Subscription>, idle: Option<Idle>) -> Result<Value, Box<dyn std::error::Error>> {
+    loop {
+        let version = within(idle, async {
+            match heads.as_deref_mut() {
+                Some(heads) => tokio::select! {
+                    version = versions.next() => version.map(Some),
+                    head = heads.next() => head.map(|_| None),
+                },
+                None => versions.next().await.map(Some),
+            }
+        }).await?;
+        if let Some(version) = version {
+            return Ok(version);
+        }
+    }
+}
+
+/// Reports spec version changes, once or continuously, optionally running a hook per change.
+pub async fn runtime_versions(mut socket: Connection, endpoint: &str, follow: bool, exec: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let idle = idle_limit(&mut socket).await?;
+    let mut versions = socket.subscribe("state_subscribeRuntimeVersion", &json!([])).await?;
+    // Runtime upgrades are months apart, new heads are what shows the stream is alive
+    let mut heads = match idle {
+        Some(_) => Some(socket.subscribe("chain_subscribeNewHeads", &json!([])).await?),
+        None => None,
+    };
+
+    // The first notification is the runtime currently in use
+    let current = next_version(&mut versions, heads.as_mut(), idle).await?;
+    let mut spec_version = current["specVersion"].as_u64().ok_or("Invalid runtime version notification")?;
+    eprintln!("{} is running {} spec version {}, waiting for changes", endpoint, current["specName"].as_str().unwrap_or("unknown"), spec_version);
+
+    loop {
+        let version = next_version(&mut versions, heads.as_mut(), idle).await?;
+        let new_spec_version = version["specVersion"].as_u64().ok_or("Invalid runtime version notification")?;
+        if new_spec_version == spec_version {
+            continue;
+        }
+
+        let change = json!({
+            "endpoint": endpoint,
+            "spec_name": version["specName"],
+            "previous_spec_version": spec_version,
+            "spec_version": new_spec_version,
+            "impl_version": version["implVersion"],
+            "transaction_version": version["transactionVersion"],
+        });
+        crate::output::line(&change.to_string())?;
+
+        if let Some(command) = exec {
+            run_hook(command, &change).await;
+        }
+
+        spec_version = new_spec_version;
+        if !follow {
+            return Ok(());
+        }
+    }
+}
+
+async fn run_hook(command: &str, change: &Value) {
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("GAVEL_ENDPOINT", change["endpoint"].as_str().unwrap_or_default())
+        .env("GAVEL_SPEC_NAME", change["spec_name"].as_str().unwrap_or_default())
+        .env("GAVEL_SPEC_VERSION", change["spec_version"].to_string())
+        .env("GAVEL_PREVIOUS_SPEC_VERSION", change["previous_spec_version"].to_string())
+        .status()
+        .await;
+    match status {
+        Ok(status) if !status.success() => eprintln!("Hook exited with {}", status),
+        Err(e) => eprintln!("Failed to run hook: {}", e),
+        _ => {}
+    }
+}
+
+/// Streams every new best and finalized head as a JSON line tagged with the chain's
+/// genesis hash, e.g. to keep a `--sink redis://` cache of the chain head up to date.
+pub async fn heads(mut socket: Connection, endpoint: &str, mut deriver: Option<crate::derive::Deriver>) -> Result<(), Box<dyn std::error::Error>> {
+    let genesis = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([0])).await?;
+    let genesis = genesis.as_str().ok_or("Invalid genesis hash")?.to_string();
+    let idle = idle_limit(&mut socket).await?;
+    let mut best = socket.subscribe("chain_subscribeNewHeads", &json!([])).await?;
+    let mut finalized = socket.subscribe("chain_subscribeFinalizedHeads", &json!([])).await?;
+    eprintln!("Following the heads of {} (genesis {})", endpoint, genesis);
+
+    loop {
+        let (kind, header) = within(idle, next_head(&mut best, &mut finalized)).await?;
+        let header = &header;
+        let number = header["number"].as_str().and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+            .ok_or("Invalid header notification")?;
+        let hash = crate::hex::encode(&crate::hashing::blake2_256(&crate::verify::encode_header(header)?));
+        let mut record = json!({ "chain": genesis, "kind": kind, "number": number, "hash": hash, "header": header });
+        let mut alerts = Vec::new();
+        if let Some(deriver) = deriver.as_mut() {
+            deriver.augment(&mut socket, &mut record).await?;
+            alerts = deriver.take_alerts();
+        }
+        crate::output::line(&record.to_string())?;
+        for alert in alerts {
+            crate::output::line(&alert.to_string())?;
+        }
+    }
+}
+
+/// The subscriptions `subscribe stream` follows.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    NewHeads,
+    FinalizedHeads,
+    RuntimeVersion,
+}
+
+impl Stream {
+    const ALL: [Stream; 3] = [Stream::NewHeads, Stream::FinalizedHeads, Stream::RuntimeVersion];
+
+    /// The methods to subscribe and unsubscribe with.
+    fn methods(self) -> (&'static str, &'static str) {
+        match self {
+            Stream::NewHeads => ("chain_subscribeNewHeads", "chain_unsubscribeNewHeads"),
+            Stream::FinalizedHeads => ("chain_subscribeFinalizedHeads", "chain_unsubscribeFinalizedHeads"),
+            Stream::RuntimeVersion => ("state_subscribeRuntimeVersion", "state_unsubscribeRuntimeVersion"),
+        }
+    }
+}
+
+/// Streams every notification of `streams` (all of them when empty) as a JSON line
+/// until interrupted, then unsubscribes from each before closing the connection.
+pub async fn stream(mut socket: Connection, endpoint: &str, streams: &[Stream]) -> Result<(), Box<dyn std::error::Error>> {
+    let streams: Vec<Stream> = Stream::ALL.into_iter().filter(|stream| streams.is_empty() || streams.contains(stream)).collect();
+    let idle = idle_limit(&mut socket).await?;
+
+    // Notifications of the first subscriptions that arrive before the later ones are
+    // confirmed are kept by the connection, nothing is dropped
+    let mut subscriptions = Vec::new();
+    for &stream in &streams {
+        let (method, _) = stream.methods();
+        subscriptions.push((stream, socket.subscribe(method, &json!([])).await?));
+    }
+    let ids: Vec<(Stream, String)> = subscriptions.iter().map(|(stream, subscription)| (*stream, subscription.id().to_string())).collect();
+    let names: Vec<&str> = streams.iter().map(|stream| stream.methods().0).collect();
+    eprintln!("Streaming {} from {} until Ctrl-C", names.join(", "), endpoint);
+
+    let mut notifications = stream::select_all(subscriptions.into_iter().map(|(stream, subscription)| {
+        stream::unfold(subscription, move |mut subscription| async move {
+            let notification = subscription.next().await.map(|result| (stream, subscription.id().to_string(), result));
+            Some((notification, subscription))
+        }).boxed()
+    }));
+    loop {
+        let notification = tokio::select! {
+            notification = within(idle, async { notifications.next().await.ok_or("No subscriptions left")? }) => notification?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let (stream, id, result) = notification;
+        let record = json!({ "method": stream.methods().0, "subscription": id, "result": result });
+        crate::output::line(&record.to_string())?;
+    }
+    drop(notifications);
+
+    for (stream, id) in &ids {
+        let (_, unsubscribe) = stream.methods();
+        if let Err(e) = crate::send_request(&mut socket, unsubscribe, &json!([id])).await {
+            eprintln!("Warning: {} {} failed: {}", unsubscribe, id, e);
+        }
+    }
+    socket.close().await;
+    eprintln!("Unsubscribed from {} subscriptions", ids.len());
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! A minimal ustar writer for bundling a few generated files into one archive, and a
+//! reader for the regular files of one.
+
+const BLOCK: usize = 512;
+
+pub struct Tar {
+    data: Vec<u8>,
+    mtime: u64,
+}
+
+/// Writes `value` as a NUL-terminated octal number filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+impl Tar {
+    /// An empty archive whose entries are all stamped with `mtime` (unix seconds).
+    pub fn new(mtime: u64) -> Self {
+        Tar { data: Vec::new(), mtime }
+    }
+
+    /// Appends a regular file. Names are limited to 100 bytes.
+    pub fn append(&mut self, name: &str, contents: &[u8]) -> Result<(), String> {
+        if name.len() > 100 {
+            return Err(format!("tar entry name '{}' is longer than 100 bytes", name));
+        }
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], contents.len() as u64);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is computed with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+        let digits = format!("{:06o}\0 ", checksum);
+        header[148..156].copy_from_slice(digits.as_bytes());
+
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(contents);
+        self.data.resize(self.data.len().next_multiple_of(BLOCK), 0);
+        Ok(())
+    }
+
+    /// The archive bytes, closed by the two empty blocks.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data.resize(self.data.len() + 2 * BLOCK, 0);
+        self.data
+    }
+}
+
+/// Reads an octal header field, NUL- or space-terminated.
+fn read_octal(field: &[u8]) -> Result<u64, String> {
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| format!("invalid tar header number '{}'", digits))
+}
+
+/// The regular files of a tar archive, with their full names.
+pub fn entries(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+    while let Some(header) = data.get(position..position + BLOCK) {
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let text = |field: &[u8]| String::from_utf8_lossy(field).trim_end_matches('\0').to_string();
+        let mut name = text(&header[..100]);
+        let prefix = text(&header[345..500]);
+        if &header[257..262] == b"ustar" && !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+        let size = read_octal(&header[124..136])? as usize;
+        let start = position + BLOCK;
+        let contents = data.get(start..start + size).ok_or_else(|| format!("tar entry {} is truncated", name))?;
+        if matches!(header[156], b'0' | 0) {
+            entries.push((name, contents.to_vec()));
+        }
+        position = start + size.next_multiple_of(BLOCK);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_ustar_headers() {
+        // Python's tarfile reads this archive back
+        let mut tar = Tar::new(1_700_000_000);
+        tar.append("incident/manifest.json", b"{\"ended_unix_ms\": 1000}").unwrap();
+        tar.append("incident/empty.log", b"").unwrap();
+        assert!(tar.append(&"x".repeat(101), b"").is_err());
+        let archive = tar.finish();
+        assert_eq!(archive.len(), 5 * BLOCK);
+        let header = &archive[..BLOCK];
+        assert_eq!(&header[100..108], b"0000644\0");
+        assert_eq!(&header[124..136], b"00000000027\0");
+        assert_eq!(&header[136..148], b"14524770400\0");
+        assert_eq!(&header[148..156], b"012304\0 ");
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        assert!(archive[3 * BLOCK..].iter().all(|&byte| byte == 0));
+        assert_eq!(entries(&archive).unwrap(), [
+            ("incident/manifest.json".to_string(), b"{\"ended_unix_ms\": 1000}".to_vec()),
+            ("incident/empty.log".to_string(), Vec::new()),
+        ]);
+    }
+
+    #[test]
+    fn reads_regular_files_with_long_names() {
+        // Python's tarfile in ustar format: a directory, a file whose name is split into
+        // prefix and name, a symlink and an empty file
+        let archive = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tar/ustar.tar")).unwrap();
+        let files = entries(&archive).unwrap();
+        let name = format!("capture/{}/heads-finalized-and-best-{}.jsonl", "nested-directory-".repeat(4), "x".repeat(40));
+        let heads: String = (0..30).map(|number| format!("{{\"kind\":\"best\",\"number\":{}}}\n", number)).collect();
+        assert_eq!(entries(&archive[..2 * BLOCK]).unwrap_err(), format!("tar entry {} is truncated", name));
+        assert_eq!(files, [(name, heads.into_bytes()), ("capture/errors.jsonl".to_string(), Vec::new())]);
+    }
+}
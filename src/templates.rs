@@ -0,0 +1,314 @@
+//! Named sets of JSON-RPC requests, run in one batch with their params filled in from
+//! variables and from the results of earlier requests of the set.
+//!
+//! ```json
+//! { "description": "Header and hash of a block",
+//!   "vars": { "block": null },
+//!   "requests": [
+//!     { "name": "hash", "method": "chain_getBlockHash", "params": ["${block}"] },
+//!     { "name": "header", "method": "chain_getHeader", "params": ["${hash}"] }
+//! ] }
+//! ```
+//!
+//! The same template can be written in YAML (`.yaml` or `.yml`) or TOML (`.toml`):
+//!
+//! ```yaml
+//! description: Header and hash of a block
+//! vars: { block: null }
+//! requests:
+//!   - { name: hash, method: chain_getBlockHash, params: ["${block}"] }
+//!   - { name: header, method: chain_getHeader, params: ["${hash}"] }
+//! ```
+//!
+//! A param that is exactly `${name}` is replaced by the value, JSON included; inside a
+//! longer string the value is spliced in as text. `${name.field}` reaches into an object
+//! result. Requests that refer to results wait for them, the rest go out together.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{fallback, stats};
+
+use crate::rpc::Connection;
+
+/// The node info `fetch` batches before the block itself.
+const FETCH: &str = r#"{
+  "description": "Node info and the hash of a block (default: the best block), as fetch batches them",
+  "vars": { "block": null },
+  "requests": [
+    { "name": "version", "method": "system_version" },
+    { "name": "client", "method": "system_name" },
+    { "name": "chain", "method": "system_chain" },
+    { "name": "health", "method": "system_health" },
+    { "name": "block_hash", "method": "chain_getBlockHash", "params": ["${block}"] },
+    { "name": "finalized_head", "method": "chain_getFinalizedHead" },
+    { "name": "runtime_version", "method": "state_getRuntimeVersion" },
+    { "name": "peers", "method": "system_peers" },
+    { "name": "sync_state", "method": "system_syncState" }
+  ]
+}"#;
+
+const BUILTIN: [(&str, &str); 1] = [("fetch", FETCH)];
+
+/// Template file extensions, in the order they are looked up in the templates directory.
+const EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "toml"];
+
+#[derive(Deserialize, Debug)]
+pub struct Template {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, Value>,
+    pub requests: Vec<Request>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Request {
+    pub name: String,
+    pub method: String,
+    #[serde(default = "no_params")]
+    pub params: Value,
+}
+
+fn no_params() -> Value {
+    json!([])
+}
+
+/// Parses a template in the format its file extension names, JSON by default.
+fn parse(name: &str, text: &str, extension: Option<&str>) -> Result<Template, String> {
+    let template: Template = match extension {
+        Some("yaml" | "yml") => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+        Some("toml") => toml::from_str(text).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(text).map_err(|e| e.to_string()),
+    }.map_err(|e| format!("Invalid template {}: {}", name, e))?;
+    if let Some(request) = template.requests.iter().find(|request| template.vars.contains_key(&request.name)) {
+        return Err(format!("Template {} names a request '{}' like one of its variables", name, request.name));
+    }
+    Ok(template)
+}
+
+/// The built-in template `name`, e.g. `fetch`.
+pub fn builtin(name: &str) -> Option<Template> {
+    BUILTIN.iter().find(|(builtin, _)| *builtin == name).map(|(name, text)| parse(name, text, None).expect("built-in templates are valid"))
+}
+
+/// The templates directory: `$GAVEL_TEMPLATES`, else `~/.config/gavel/templates`.
+fn default_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("GAVEL_TEMPLATES") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("gavel").join("templates"))
+}
+
+fn read(name: &str, path: &Path) -> Result<Template, Box<dyn std::error::Error>> {
+    Ok(parse(name, &std::fs::read_to_string(path)?, path.extension().and_then(|extension| extension.to_str()))?)
+}
+
+/// Loads a template by file path, by name from the templates directory (`<name>.json`,
+/// `.yaml`, `.yml` or `.toml`), or by built-in name, in that order.
+pub fn load(name: &str, dir: Option<&Path>) -> Result<Template, Box<dyn std::error::Error>> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return read(name, path);
+    }
+    let dir = dir.map(Path::to_path_buf).or_else(default_dir);
+    let found = dir.as_ref().and_then(|dir| EXTENSIONS.iter().map(|extension| dir.join(format!("{}.{}", name, extension))).find(|path| path.is_file()));
+    if let Some(path) = found {
+        return read(name, &path);
+    }
+    if let Some(template) = builtin(name) {
+        return Ok(template);
+    }
+    let mut known: Vec<String> = BUILTIN.iter().map(|(name, _)| name.to_string()).collect();
+    if let Some(entries) = dir.as_ref().and_then(|dir| std::fs::read_dir(dir).ok()) {
+        known.extend(entries.filter_map(|entry| {
+            let path = entry.ok()?.path();
+            EXTENSIONS.contains(&path.extension()?.to_str()?).then(|| path.file_stem()?.to_str().map(str::to_string))?
+        }));
+    }
+    Err(format!("Unknown template '{}', known: {}", name, known.join(", ")).into())
+}
+
+/// Parses `name=value` variables; values are JSON when they parse as JSON, else strings.
+pub fn parse_vars(vars: &[String]) -> Result<BTreeMap<String, Value>, String> {
+    vars.iter().map(|var| {
+        let (name, value) = var.split_once('=').ok_or_else(|| format!("Template variable '{}' is not name=value", var))?;
+        Ok((name.to_string(), serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))))
+    }).collect()
+}
+
+/// The names a value refers to with `${name}` or `${name.field}`.
+fn references(value: &Value, names: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(end) = rest[start..].find('}') else { break };
+                let path = &rest[start + 2..start + end];
+                names.push(path.split('.').next().unwrap_or_default().to_string());
+                rest = &rest[start + end + 1..];
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| references(value, names)),
+        Value::Object(object) => object.values().for_each(|value| references(value, names)),
+        _ => {}
+    }
+}
+
+fn lookup<'a>(scope: &'a Map<String, Value>, path: &str) -> Result<&'a Value, String> {
+    let mut parts = path.split('.');
+    let name = parts.next().unwrap_or_default();
+    let mut value = scope.get(name).ok_or_else(|| format!("Template variable '{}' is not set", name))?;
+    for part in parts {
+        value = match part.parse::<usize>() {
+            Ok(index) => value.get(index),
+            Err(_) => value.get(part),
+        }.ok_or_else(|| format!("'{}' has no field '{}'", path, part))?;
+    }
+    Ok(value)
+}
+
+/// `value` with every `${...}` filled in from `scope`.
+fn substitute(value: &Value, scope: &Map<String, Value>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(text) => {
+            if let Some(path) = text.strip_prefix("${").and_then(|rest| rest.strip_suffix('}')).filter(|path| !path.contains('}')) {
+                return lookup(scope, path).cloned();
+            }
+            let mut substituted = String::new();
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(end) = rest[start..].find('}') else { break };
+                substituted.push_str(&rest[..start]);
+                match lookup(scope, &rest[start + 2..start + end])? {
+                    Value::String(text) => substituted.push_str(text),
+                    value => substituted.push_str(&value.to_string()),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            substituted.push_str(rest);
+            Value::String(substituted)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(|value| substitute(value, scope)).collect::<Result<_, _>>()?),
+        Value::Object(object) => Value::Object(object.iter().map(|(key, value)| Ok((key.clone(), substitute(value, scope)?))).collect::<Result<_, String>>()?),
+        other => other.clone(),
+    })
+}
+
+/// The variables in scope: the template's defaults overridden by `vars`.
+fn scope(template: &Template, vars: &BTreeMap<String, Value>) -> Map<String, Value> {
+    template.vars.iter().chain(vars).map(|(name, value)| (name.clone(), value.clone())).collect()
+}
+
+/// The requests of a template that refers to no results, as one batch with each
+/// request's name as its id.
+pub fn batch(template: &Template, vars: &BTreeMap<String, Value>) -> Result<Vec<Value>, String> {
+    let scope = scope(template, vars);
+    template.requests.iter().map(|request| {
+        Ok(json!({ "jsonrpc": "2.0", "id": request.name, "method": request.method, "params": substitute(&request.params, &scope)? }))
+    }).collect()
+}
+
+/// Runs a template, batching every request whose references are resolved, and returns
+/// each request's result, or its error, by name.
+pub async fn run(socket: &mut Connection, template: &Template, vars: &BTreeMap<String, Value>) -> Result<Value, Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    let mut scope = scope(template, vars);
+    let mut results = Map::new();
+    let mut errors = Map::new();
+    let mut pending: Vec<&Request> = template.requests.iter().collect();
+    let mut rounds = 0;
+    while !pending.is_empty() {
+        let (ready, waiting): (Vec<&Request>, Vec<&Request>) = pending.into_iter().partition(|request| {
+            let mut names = Vec::new();
+            references(&request.params, &mut names);
+            names.iter().all(|name| scope.contains_key(name) || !template.requests.iter().any(|other| &other.name == name))
+        });
+        if ready.is_empty() {
+            let names: Vec<&str> = waiting.iter().map(|request| request.name.as_str()).collect();
+            return Err(format!("Requests {} refer to each other's results in a cycle", names.join(", ")).into());
+        }
+        let batch = ready.iter().map(|request| {
+            Ok(json!({ "jsonrpc": "2.0", "id": request.name, "method": request.method, "params": substitute(&request.params, &scope)? }))
+        }).collect::<Result<Vec<Value>, String>>()?;
+        let calls: Vec<(&str, &Value)> = batch.iter().map(|request| (request["method"].as_str().unwrap_or_default(), &request["params"])).collect();
+        let responses = socket.batch(&calls).await?;
+        for (request, response) in batch.iter().zip(&responses) {
+            let name = request["id"].as_str().unwrap_or_default();
+            let result = match response.get("error") {
+                // Retried one by one, send_and_receive falls back to equivalent methods
+                Some(_) if fallback::is_method_not_found(response) && !fallback::alternatives(request["method"].as_str().unwrap_or_default()).is_empty() => {
+                    crate::send_and_receive(socket, request["method"].as_str().unwrap_or_default(), request["params"].clone()).await?
+                }
+                Some(error) => {
+                    stats::record_failure("rpc");
+                    errors.insert(name.to_string(), error.clone());
+                    Value::Null
+                }
+                None => response["result"].clone(),
+            };
+            scope.insert(name.to_string(), result.clone());
+            results.insert(name.to_string(), result);
+        }
+        pending = waiting;
+        rounds += 1;
+    }
+    Ok(json!({
+        "description": template.description,
+        "results": results,
+        "errors": errors,
+        "rounds": rounds,
+        "latency_ms": started.elapsed().as_millis() as u64,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = "
+description: Header and hash of a block
+vars: { block: null }
+requests:
+  - { name: hash, method: chain_getBlockHash, params: [\"${block}\"] }
+  - name: header
+    method: chain_getHeader
+    params: [\"${hash}\"]
+";
+
+    const TOML: &str = r#"
+description = "Header and hash of a block"
+vars = { block = 5 }
+
+[[requests]]
+name = "hash"
+method = "chain_getBlockHash"
+params = ["${block}"]
+
+[[requests]]
+name = "header"
+method = "chain_getHeader"
+"#;
+
+    #[test]
+    fn parses_every_format() {
+        let yaml = parse("yaml", YAML, Some("yaml")).unwrap();
+        assert_eq!(yaml.requests.len(), 2);
+        assert_eq!(yaml.requests[1].params, json!(["${hash}"]));
+        assert_eq!(yaml.vars["block"], Value::Null);
+
+        let toml = parse("toml", TOML, Some("toml")).unwrap();
+        assert_eq!(toml.vars["block"], json!(5));
+        assert_eq!(toml.requests[1].params, json!([]));
+        assert_eq!(batch(&toml, &BTreeMap::new()).unwrap()[0]["params"], json!([5]));
+
+        assert!(parse("json", FETCH, Some("json")).is_ok());
+        assert!(parse("yaml", "requests: [", Some("yml")).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Substrate's base-16 Patricia-Merkle trie: the root, as used for the extrinsics root,
+//! and lookups in the read proofs nodes serve against a state root.
+
+use std::collections::HashMap;
+
+use crate::hashing::blake2_256;
+use crate::scale::{self, Input};
+
+/// Values at least this long are stored by hash under state version 1.
+const INLINE_VALUE_THRESHOLD: usize = 33;
+
+const LEAF_PREFIX: u8 = 0b01 << 6;
+const BRANCH_WITHOUT_VALUE: u8 = 0b10 << 6;
+const BRANCH_WITH_VALUE: u8 = 0b11 << 6;
+const HASHED_VALUE_LEAF: u8 = 0b001 << 5;
+const HASHED_VALUE_BRANCH: u8 = 0b0001 << 4;
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+/// Node header: prefix bits followed by the partial key length, see `sp_trie::node_header`.
+fn header(prefix: u8, prefix_bits: u32, nibble_count: usize) -> Vec<u8> {
+    let max_value = 255u8 >> prefix_bits;
+    let first = (max_value as usize - 1).min(nibble_count);
+    if first == nibble_count {
+        return vec![prefix + first as u8];
+    }
+    let mut encoded = vec![prefix + max_value];
+    let mut rest = nibble_count - first;
+    loop {
+        if rest < 256 {
+            encoded.push((rest - 1) as u8);
+            return encoded;
+        }
+        encoded.push(255);
+        rest -= 255;
+    }
+}
+
+/// Packs nibbles two per byte, left-padding odd counts with a zero nibble.
+fn partial_key(nibbles: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(nibbles.len().div_ceil(2));
+    let (odd, pairs) = nibbles.split_at(nibbles.len() % 2);
+    if let Some(first) = odd.first() {
+        encoded.push(*first);
+    }
+    encoded.extend(pairs.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+fn encode_value(value: &[u8], hashed: bool, out: &mut Vec<u8>) {
+    if hashed {
+        out.extend(blake2_256(value));
+    } else {
+        out.extend(scale::encode_compact(value.len() as u128));
+        out.extend_from_slice(value);
+    }
+}
+
+fn encode_node(items: &[(Vec<u8>, &[u8])], depth: usize, state_version: u8) -> Vec<u8> {
+    let hashed = |value: &[u8]| state_version >= 1 && value.len() >= INLINE_VALUE_THRESHOLD;
+
+    if items.len() == 1 {
+        let (key, value) = &items[0];
+        let partial = &key[depth..];
+        let mut node = if hashed(value) { header(HASHED_VALUE_LEAF, 3, partial.len()) } else { header(LEAF_PREFIX, 2, partial.len()) };
+        node.extend(partial_key(partial));
+        encode_value(value, hashed(value), &mut node);
+        return node;
+    }
+
+    // Shared prefix below `depth` becomes the branch's partial key
+    let first = &items[0].0;
+    let common = (depth..first.len())
+        .take_while(|&i| items.iter().all(|(key, _)| key.len() > i && key[i] == first[i]))
+        .count();
+    let split = depth + common;
+    let value = items.iter().find(|(key, _)| key.len() == split).map(|(_, value)| *value);
+
+    let mut node = match value {
+        Some(value) if hashed(value) => header(HASHED_VALUE_BRANCH, 4, common),
+        Some(_) => header(BRANCH_WITH_VALUE, 2, common),
+        None => header(BRANCH_WITHOUT_VALUE, 2, common),
+    };
+    node.extend(partial_key(&first[depth..split]));
+
+    let mut bitmap = 0u16;
+    let mut children = Vec::new();
+    for nibble in 0..16u8 {
+        let group: Vec<(Vec<u8>, &[u8])> = items.iter()
+            .filter(|(key, _)| key.len() > split && key[split] == nibble)
+            .cloned()
+            .collect();
+        if group.is_empty() {
+            continue;
+        }
+        bitmap |= 1 << nibble;
+        let child = encode_node(&group, split + 1, state_version);
+        // Children shorter than a hash are inlined
+        let reference = if child.len() < 32 { child } else { blake2_256(&child) };
+        children.extend(scale::encode_compact(reference.len() as u128));
+        children.extend(reference);
+    }
+
+    node.extend(bitmap.to_le_bytes());
+    if let Some(value) = value {
+        encode_value(value, hashed(value), &mut node);
+    }
+    node.extend(children);
+    node
+}
+
+/// Root of a trie mapping each item's SCALE-compact index to the item, e.g. a block's
+/// extrinsics root.
+pub fn ordered_trie_root(values: &[Vec<u8>], state_version: u8) -> Vec<u8> {
+    if values.is_empty() {
+        return blake2_256(&[0]);
+    }
+    let mut items: Vec<(Vec<u8>, &[u8])> = values.iter()
+        .enumerate()
+        .map(|(index, value)| (nibbles(&scale::encode_compact(index as u128)), value.as_slice()))
+        .collect();
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    blake2_256(&encode_node(&items, 0, state_version))
+}
+
+/// Reads a node header: its kind's prefix bits and the partial key length, the inverse of
+/// `header`.
+fn decode_header(first: u8, input: &mut Input, prefix_bits: u32) -> Result<usize, String> {
+    let max_value = 255u8 >> prefix_bits;
+    let mut count = (first & max_value) as usize;
+    if count < max_value as usize {
+        return Ok(count);
+    }
+    count -= 1;
+    loop {
+        let next = input.read_u8()? as usize;
+        if next < 255 {
+            return Ok(count + next + 1);
+        }
+        count += 255;
+    }
+}
+
+fn read_partial_key(input: &mut Input, nibble_count: usize) -> Result<Vec<u8>, String> {
+    let packed = nibbles(input.read_bytes(nibble_count.div_ceil(2))?);
+    Ok(packed[packed.len() - nibble_count..].to_vec())
+}
+
+/// A value stored in a node, inline or by hash under state version 1.
+enum Stored<'a> {
+    Inline(&'a [u8]),
+    Hashed(&'a [u8]),
+}
+
+struct Node<'a> {
+    partial: Vec<u8>,
+    value: Option<Stored<'a>>,
+    // Child references by nibble: a hash, or the node itself when shorter than one
+    children: [Option<&'a [u8]>; 16],
+}
+
+fn decode_node(encoded: &[u8]) -> Result<Option<Node<'_>>, String> {
+    let mut input = Input::new(encoded);
+    let first = input.read_u8()?;
+    let (branch, value, hashed, nibble_count) = match first & (0b11 << 6) {
+        LEAF_PREFIX => (false, true, false, decode_header(first, &mut input, 2)?),
+        BRANCH_WITHOUT_VALUE => (true, false, false, decode_header(first, &mut input, 2)?),
+        BRANCH_WITH_VALUE => (true, true, false, decode_header(first, &mut input, 2)?),
+        _ if first == 0 => return Ok(None),
+        _ if first & (0b111 << 5) == HASHED_VALUE_LEAF => (false, true, true, decode_header(first, &mut input, 3)?),
+        _ if first & (0b1111 << 4) == HASHED_VALUE_BRANCH => (true, true, true, decode_header(first, &mut input, 4)?),
+        _ => return Err(format!("Invalid trie node header {:#04x}", first)),
+    };
+    let partial = read_partial_key(&mut input, nibble_count)?;
+    let bitmap = if branch { input.read_u16()? } else { 0 };
+    let value = match (value, hashed) {
+        (false, _) => None,
+        (true, false) => Some(Stored::Inline(input.read_vec()?)),
+        (true, true) => Some(Stored::Hashed(input.read_bytes(32)?)),
+    };
+    let mut children = [None; 16];
+    for (nibble, child) in children.iter_mut().enumerate() {
+        if bitmap & (1 << nibble) != 0 {
+            *child = Some(input.read_vec()?);
+        }
+    }
+    Ok(Some(Node { partial, value, children }))
+}
+
+/// Looks `key` up in the trie with state root `root` through the nodes of a read proof.
+/// Returns the value, `None` when the proof shows the key is absent, and an error when the
+/// proof lacks a node on the way, so the node cannot have answered from that state.
+pub fn read_proof(root: &[u8], key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, String> {
+    let nodes: HashMap<Vec<u8>, &[u8]> = proof.iter().map(|node| (blake2_256(node), node.as_slice())).collect();
+    let lookup = |hash: &[u8]| nodes.get(hash).copied().ok_or_else(|| format!("The proof lacks trie node {}", crate::hex::encode(hash)));
+
+    let key = nibbles(key);
+    let mut rest = key.as_slice();
+    let mut encoded = lookup(root)?;
+    loop {
+        let Some(node) = decode_node(encoded)? else {
+            return Ok(None);
+        };
+        if !rest.starts_with(&node.partial) {
+            return Ok(None);
+        }
+        rest = &rest[node.partial.len()..];
+        let Some((nibble, below)) = rest.split_first() else {
+            return Ok(match node.value {
+                Some(Stored::Inline(value)) => Some(value.to_vec()),
+                Some(Stored::Hashed(hash)) => Some(lookup(hash)?.to_vec()),
+                None => None,
+            });
+        };
+        // No child on the way to the key, as below a leaf, means the key is absent
+        let Some(child) = node.children[*nibble as usize] else {
+            return Ok(None);
+        };
+        rest = below;
+        encoded = if child.len() == 32 { lookup(child)? } else { child };
+    }
+}
@@ -0,0 +1,260 @@
+//! Rust-like type expressions such as `Vec<(AccountId32, u128)>`, registered as types in a
+//! metadata registry so the type-driven `Decoder` and `Encoder` work on arbitrary bytes.
+//!
+//! Besides primitives, tuples, arrays and `Vec`/`Option`/`Compact`/`Result`, the common
+//! Substrate names are understood (`AccountId32`, `H256`, `Bytes`, `BTreeMap`, ...). A bare
+//! number is a type id of the registry, e.g. of metadata loaded from a file.
+
+use crate::metadata::{Extrinsic, Field, Metadata, Primitive, Type, TypeDef, Variant};
+
+/// A registry with no pallets, for type expressions that don't need runtime metadata.
+pub fn empty_registry() -> Metadata {
+    Metadata {
+        version: 15,
+        types: Vec::new(),
+        pallets: Vec::new(),
+        extrinsic: Extrinsic { version: 4, ty: None, address_ty: None, call_ty: None, signature_ty: None, signed_extensions: Vec::new() },
+    }
+}
+
+/// Parses `expr` and adds the types it needs to `metadata`, returning the type id to decode
+/// or encode with.
+pub fn register(metadata: &mut Metadata, expr: &str) -> Result<u32, String> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens, position: 0, metadata };
+    let ty = parser.parse_type()?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(ty),
+        Some(token) => Err(format!("unexpected '{}' in type '{}'", token, expr)),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else if c == ':' {
+            // Paths like `sp_core::H256` keep only their last segment
+            chars.next();
+            if chars.next() != Some(':') {
+                return Err(format!("unexpected ':' in type '{}'", expr));
+            }
+            tokens.pop();
+        } else if "<>()[];,&'".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}' in type '{}'", c, expr));
+        }
+    }
+    Ok(tokens)
+}
+
+fn primitive(name: &str) -> Option<Primitive> {
+    Some(match name {
+        "bool" => Primitive::Bool,
+        "char" => Primitive::Char,
+        "str" | "String" => Primitive::Str,
+        "u8" => Primitive::U8,
+        "u16" => Primitive::U16,
+        "u32" | "BlockNumber" | "Perbill" | "Permill" | "Percent" => Primitive::U32,
+        "u64" | "Moment" | "Nonce" => Primitive::U64,
+        "u128" | "Balance" => Primitive::U128,
+        "u256" | "U256" => Primitive::U256,
+        "i8" => Primitive::I8,
+        "i16" => Primitive::I16,
+        "i32" => Primitive::I32,
+        "i64" => Primitive::I64,
+        "i128" => Primitive::I128,
+        "i256" => Primitive::I256,
+        _ => return None,
+    })
+}
+
+/// Fixed-size hashes and keys, decoded as hex.
+fn fixed_bytes(name: &str) -> Option<u32> {
+    Some(match name {
+        "H160" | "EthereumAddress" | "AccountId20" => 20,
+        "H256" | "Hash" | "BlockHash" => 32,
+        "H512" | "Signature" => 64,
+        _ => return None,
+    })
+}
+
+struct Parser<'r> {
+    tokens: Vec<String>,
+    position: usize,
+    metadata: &'r mut Metadata,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of type")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected '{}', found '{}'", expected, token)),
+        }
+    }
+
+    fn add(&mut self, path: &[&str], def: TypeDef) -> u32 {
+        self.metadata.types.push(Type { path: path.iter().map(|segment| segment.to_string()).collect(), params: Vec::new(), def });
+        self.metadata.types.len() as u32 - 1
+    }
+
+    fn parse_type(&mut self) -> Result<u32, String> {
+        match self.next()?.as_str() {
+            "&" => {
+                // `&[u8]` and `&'static str` read like their owned versions
+                if self.peek() == Some("'") {
+                    self.next()?;
+                    self.next()?;
+                }
+                if self.peek() == Some("[") && self.tokens.get(self.position + 2).map(String::as_str) == Some("]") {
+                    self.next()?;
+                    let inner = self.parse_type()?;
+                    self.expect("]")?;
+                    return Ok(self.add(&[], TypeDef::Sequence(inner)));
+                }
+                self.parse_type()
+            }
+            "(" => {
+                let mut types = Vec::new();
+                while self.peek() != Some(")") {
+                    types.push(self.parse_type()?);
+                    if self.peek() != Some(")") {
+                        self.expect(",")?;
+                    }
+                }
+                self.expect(")")?;
+                // `(T)` is just T, `(T,)` a one-element tuple
+                if types.len() == 1 && self.tokens[self.position - 2] != "," {
+                    return Ok(types[0]);
+                }
+                Ok(self.add(&[], TypeDef::Tuple(types)))
+            }
+            "[" => {
+                let inner = self.parse_type()?;
+                self.expect(";")?;
+                let len = self.next()?;
+                let len: u32 = len.parse().map_err(|_| format!("invalid array length '{}'", len))?;
+                self.expect("]")?;
+                Ok(self.add(&[], TypeDef::Array(len, inner)))
+            }
+            name => {
+                let name = name.to_string();
+                let args = self.parse_args()?;
+                self.named(&name, args)
+            }
+        }
+    }
+
+    /// Generic arguments in `<...>`; numbers (e.g. the bound of `BoundedVec<T, S>`) are kept as
+    /// type ids and only resolved when used as a type.
+    fn parse_args(&mut self) -> Result<Vec<u32>, String> {
+        let mut args = Vec::new();
+        if self.peek() != Some("<") {
+            return Ok(args);
+        }
+        self.next()?;
+        while self.peek() != Some(">") {
+            args.push(self.parse_type()?);
+            if self.peek() != Some(">") {
+                self.expect(",")?;
+            }
+        }
+        self.expect(">")?;
+        Ok(args)
+    }
+
+    fn named(&mut self, name: &str, args: Vec<u32>) -> Result<u32, String> {
+        let arity = |count: usize| -> Result<(), String> {
+            if args.len() < count {
+                return Err(format!("{} takes {} type argument{}", name, count, if count == 1 { "" } else { "s" }));
+            }
+            Ok(())
+        };
+        if let Ok(id) = name.parse::<u32>() {
+            // Only checked when decoding, so bounds like `ConstU32<100>` are fine
+            return Ok(id);
+        }
+        if let Some(primitive) = primitive(name) {
+            return Ok(self.add(&[], TypeDef::Primitive(primitive)));
+        }
+        if let Some(len) = fixed_bytes(name) {
+            let byte = self.add(&[], TypeDef::Primitive(Primitive::U8));
+            let array = self.add(&[], TypeDef::Array(len, byte));
+            return Ok(self.add(&[name], TypeDef::Composite(vec![unnamed(array)])));
+        }
+        Ok(match name {
+            "AccountId32" | "AccountId" => {
+                let byte = self.add(&[], TypeDef::Primitive(Primitive::U8));
+                let array = self.add(&[], TypeDef::Array(32, byte));
+                self.add(&["AccountId32"], TypeDef::Composite(vec![unnamed(array)]))
+            }
+            "Bytes" => {
+                let byte = self.add(&[], TypeDef::Primitive(Primitive::U8));
+                self.add(&[], TypeDef::Sequence(byte))
+            }
+            "Vec" | "VecDeque" | "BTreeSet" | "BoundedVec" | "WeakBoundedVec" | "BoundedBTreeSet" => {
+                arity(1)?;
+                self.add(&[], TypeDef::Sequence(args[0]))
+            }
+            "BTreeMap" | "BoundedBTreeMap" => {
+                arity(2)?;
+                let entry = self.add(&[], TypeDef::Tuple(vec![args[0], args[1]]));
+                self.add(&[], TypeDef::Sequence(entry))
+            }
+            "Option" => {
+                arity(1)?;
+                let variants = vec![variant("None", 0, Vec::new()), variant("Some", 1, vec![unnamed(args[0])])];
+                self.add(&["Option"], TypeDef::Variant(variants))
+            }
+            "Result" => {
+                arity(2)?;
+                let variants = vec![variant("Ok", 0, vec![unnamed(args[0])]), variant("Err", 1, vec![unnamed(args[1])])];
+                self.add(&["Result"], TypeDef::Variant(variants))
+            }
+            "Compact" => {
+                arity(1)?;
+                self.add(&[], TypeDef::Compact(args[0]))
+            }
+            "Box" | "Arc" | "Rc" | "Cow" => {
+                arity(1)?;
+                args[0]
+            }
+            "BitVec" => {
+                // The decoder reads bits least significant first, as `BitVec<u8, Lsb0>` stores them
+                let byte = self.add(&[], TypeDef::Primitive(Primitive::U8));
+                self.add(&[], TypeDef::BitSequence(byte, byte))
+            }
+            "ConstU32" | "ConstU64" => args.first().copied().unwrap_or_default(),
+            other => return Err(format!("unknown type '{}', use a primitive, a known Substrate type or a metadata type id", other)),
+        })
+    }
+}
+
+fn unnamed(ty: u32) -> Field {
+    Field { name: None, ty, type_name: None }
+}
+
+fn variant(name: &str, index: u8, fields: Vec<Field>) -> Variant {
+    Variant { name: name.to_string(), fields, index, docs: Vec::new() }
+}
@@ -0,0 +1,141 @@
+//! Integrity checks of fetched data against the commitments that cover it, and the global
+//! `--verify` level saying how far gavel trusts the endpoint:
+//!
+//! - `none`: answers are taken as they come.
+//! - `roots`: header hashes are recomputed, and a fetched block's extrinsics root.
+//! - `proofs`: storage values come from read proofs checked against the state root of a
+//!   header whose hash was recomputed, read at the finalized head unless `--at` says
+//!   otherwise. Key listings and runtime calls still have to be taken on trust.
+//! - `light`: the blocks read from must also be proven final by a GRANDPA justification
+//!   whose precommits verify against a trusted authority set. The first is read from the
+//!   proven state of the highest `--checkpoints` entry at or below the block, and each
+//!   set after it from the block enacting it, once the set before proved that block final.
+
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+
+use crate::hashing::blake2_256;
+use crate::rpc::Connection;
+use crate::{hex, scale, trie};
+
+/// How much of what the endpoint serves gavel checks, each level including the ones before.
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    None,
+    Roots,
+    Proofs,
+    Light,
+}
+
+static LEVEL: OnceLock<Level> = OnceLock::new();
+
+/// Sets the `--verify` level for the rest of the run.
+pub fn set_level(level: Option<Level>) {
+    if let Some(level) = level {
+        let _ = LEVEL.set(level);
+    }
+}
+
+pub fn level() -> Level {
+    LEVEL.get().copied().unwrap_or_default()
+}
+
+fn hash_field(header: &Value, field: &str) -> Result<Vec<u8>, String> {
+    let bytes = hex::decode(header[field].as_str().ok_or_else(|| format!("header has no {}", field))?)?;
+    if bytes.len() != 32 {
+        return Err(format!("header {} is not 32 bytes", field));
+    }
+    Ok(bytes)
+}
+
+/// Re-encodes a JSON header in SCALE, the preimage of the block hash.
+pub fn encode_header(header: &Value) -> Result<Vec<u8>, String> {
+    let number = header["number"].as_str().ok_or("header has no number")?;
+    let number = u64::from_str_radix(number.trim_start_matches("0x"), 16).map_err(|e| format!("invalid header number: {}", e))?;
+    let logs = header["digest"]["logs"].as_array().ok_or("header has no digest logs")?;
+
+    let mut encoded = hash_field(header, "parentHash")?;
+    encoded.extend(scale::encode_compact(number as u128));
+    encoded.extend(hash_field(header, "stateRoot")?);
+    encoded.extend(hash_field(header, "extrinsicsRoot")?);
+    encoded.extend(scale::encode_compact(logs.len() as u128));
+    for log in logs {
+        encoded.extend(hex::decode(log.as_str().ok_or("digest log is not hex")?)?);
+    }
+    Ok(encoded)
+}
+
+fn check(expected: &str, computed: &[u8]) -> Value {
+    let computed = hex::encode(computed);
+    json!({
+        "expected": expected,
+        "computed": computed,
+        "ok": expected.eq_ignore_ascii_case(&computed),
+    })
+}
+
+/// Recomputes the header hash and the extrinsics root of a `chain_getBlock` result.
+///
+/// Must run on the raw block, before digest logs are decoded for display.
+pub fn verify_roots(block: &Value, block_hash: &str, state_version: u8) -> Result<Value, String> {
+    let header = &block["block"]["header"];
+    let extrinsics = block["block"]["extrinsics"].as_array().ok_or("block has no extrinsics")?
+        .iter()
+        .map(|extrinsic| hex::decode(extrinsic.as_str().unwrap_or_default()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let header_hash = check(block_hash, &blake2_256(&encode_header(header)?));
+    let extrinsics_root = check(
+        header["extrinsicsRoot"].as_str().unwrap_or_default(),
+        &trie::ordered_trie_root(&extrinsics, state_version),
+    );
+    let ok = header_hash["ok"] == true && extrinsics_root["ok"] == true;
+    Ok(json!({
+        "ok": ok,
+        "header_hash": header_hash,
+        "extrinsics_root": extrinsics_root,
+        "state_version": state_version,
+    }))
+}
+
+/// The header of block `hash`, checked to hash to it.
+pub async fn header(socket: &mut Connection, hash: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let header = crate::send_and_receive(socket, "chain_getHeader", json!([hash])).await?;
+    if header.is_null() {
+        return Err(format!("Block {} not found", hash).into());
+    }
+    let computed = hex::encode(&blake2_256(&encode_header(&header)?));
+    if !computed.eq_ignore_ascii_case(hash) {
+        return Err(format!("The header served for block {} hashes to {}", hash, computed).into());
+    }
+    Ok(header)
+}
+
+/// The block storage is read at under the `--verify` level: the `--at` block or the
+/// finalized head, proven final under `light`.
+pub async fn state_block(socket: &mut Connection, at: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, at).await?;
+    if level() >= Level::Light {
+        crate::justification::prove_finalized(socket, &hash).await?;
+    }
+    Ok(hash)
+}
+
+/// Reads `keys` at block `hash` through `state_getReadProof`, checking the proof against
+/// the state root of the block's header. Values are in key order, `None` for keys the
+/// proof shows absent.
+pub async fn read_proof(socket: &mut Connection, keys: &[String], hash: &str) -> Result<Vec<Option<Vec<u8>>>, Box<dyn std::error::Error>> {
+    let header = header(socket, hash).await?;
+    let state_root = hash_field(&header, "stateRoot")?;
+    let response = crate::send_and_receive(socket, "state_getReadProof", json!([keys, hash])).await?;
+    let proof = response["proof"].as_array().ok_or("Invalid state_getReadProof response")?
+        .iter()
+        .map(|node| hex::decode(node.as_str().unwrap_or_default()))
+        .collect::<Result<Vec<_>, _>>()?;
+    keys.iter()
+        .map(|key| trie::read_proof(&state_root, &hex::decode(key)?, &proof).map_err(|e| format!("Storage {} at {}: {}", key, hash, e).into()))
+        .collect()
+}
@@ -0,0 +1,94 @@
+//! Pallet view functions (metadata V16): read-only queries a pallet declares, found in the
+//! metadata and dispatched through the `RuntimeViewFunction` runtime API with their
+//! arguments SCALE encoded, the result decoded against the declared output type.
+
+use serde_json::{json, Value};
+
+use crate::compose::same_name;
+use crate::decode::Decoder;
+use crate::encode::Encoder;
+use crate::metadata::{self, Metadata, ViewFunction};
+use crate::rpc::Connection;
+use crate::scale::{encode_compact, Input};
+use crate::{extensions, hex};
+
+fn describe(metadata: &Metadata, pallet: &str, function: &ViewFunction) -> Value {
+    json!({
+        "pallet": pallet,
+        "name": function.name,
+        "id": hex::encode(&function.id),
+        "inputs": function.inputs.iter().map(|(name, ty)| json!({ "name": name, "type": extensions::type_name(metadata, *ty) })).collect::<Vec<_>>(),
+        "output": extensions::type_name(metadata, function.output),
+        "docs": function.docs.join("\n").trim(),
+    })
+}
+
+/// V16 metadata at `at`, the first version that declares view functions.
+async fn fetch_metadata(socket: &mut Connection, at: &str) -> Result<Metadata, Box<dyn std::error::Error>> {
+    metadata::fetch_version(socket, 16, Some(at)).await?
+        .ok_or_else(|| "The runtime does not provide V16 metadata, so it declares no view functions".into())
+}
+
+/// The view functions of every pallet, or of `pallet`.
+pub fn list(metadata: &Metadata, pallet: Option<&str>) -> Result<Value, String> {
+    let pallets: Vec<_> = metadata.pallets.iter().filter(|candidate| pallet.is_none_or(|pallet| same_name(&candidate.name, pallet))).collect();
+    if let (Some(pallet), true) = (pallet, pallets.is_empty()) {
+        return Err(format!("Pallet {} not found in metadata", pallet));
+    }
+    Ok(Value::Array(pallets.iter()
+        .flat_map(|pallet| pallet.view_functions.iter().map(|function| describe(metadata, &pallet.name, function)))
+        .collect()))
+}
+
+/// Lists the view functions at the `--at` block (the finalized head by default), or calls
+/// `pallet.function(args)` when a function is given, with the arguments in declaration order.
+pub async fn view(socket: &mut Connection, pallet: Option<&str>, function: Option<&str>, args: &[Value], at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let hash = crate::pinned_block(socket, at).await?;
+    let metadata = fetch_metadata(socket, &hash).await?;
+    let (pallet, function) = match (pallet, function) {
+        (pallet, None) => return Ok(list(&metadata, pallet)?),
+        (Some(pallet), Some(function)) => (pallet, function),
+        (None, Some(_)) => return Err("A view function needs its pallet".into()),
+    };
+    let pallet = metadata.pallets.iter().find(|candidate| same_name(&candidate.name, pallet))
+        .ok_or_else(|| format!("Pallet {} not found in metadata", pallet))?;
+    let view_function = pallet.view_functions.iter().find(|candidate| same_name(&candidate.name, function))
+        .ok_or_else(|| format!("Pallet {} has no view function {}", pallet.name, function))?;
+    if view_function.inputs.len() != args.len() {
+        let names: Vec<&str> = view_function.inputs.iter().map(|(name, _)| name.as_str()).collect();
+        return Err(format!("{}.{} takes {} arguments ({}), got {}", pallet.name, view_function.name, names.len(), names.join(", "), args.len()).into());
+    }
+
+    let encoder = Encoder::new(&metadata);
+    let mut input = Vec::new();
+    for ((name, ty), arg) in view_function.inputs.iter().zip(args) {
+        input.extend(encoder.encode(*ty, arg).map_err(|e| format!("argument {}: {}", name, e))?);
+    }
+    // `execute_view_function(query_id: ViewFunctionId, input: Vec<u8>)`
+    let mut payload = view_function.id.to_vec();
+    payload.extend(encode_compact(input.len() as u128));
+    payload.extend(input);
+    let response = crate::send_and_receive(socket, "state_call", json!(["RuntimeViewFunction_execute_view_function", hex::encode(&payload), hash])).await?;
+    let bytes = hex::decode(response.as_str().ok_or("RuntimeViewFunction_execute_view_function returned nothing")?)?;
+
+    // `Result<Vec<u8>, ViewFunctionDispatchError>`
+    let mut result = Input::new(&bytes);
+    let output = match result.read_u8()? {
+        0 => Decoder::new(&metadata).decode_all(view_function.output, result.read_vec()?)?,
+        1 => {
+            let error = match result.read_u8()? {
+                0 => "not implemented by the runtime".to_string(),
+                1 => format!("view function {} not found", hex::encode(result.read_bytes(32)?)),
+                2 => "the runtime could not decode the arguments".to_string(),
+                other => format!("unknown dispatch error {}", other),
+            };
+            return Err(format!("{}.{} failed: {}", pallet.name, view_function.name, error).into());
+        }
+        other => return Err(format!("Invalid view function result variant {}", other).into()),
+    };
+    let mut description = describe(&metadata, &pallet.name, view_function);
+    description["block_hash"] = json!(hash);
+    description["args"] = json!(args);
+    description["result"] = output;
+    Ok(description)
+}
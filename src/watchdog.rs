@@ -0,0 +1,178 @@
+//! `gavel watchdog`: keeps comparing a node of our own against public reference endpoints
+//! and alerts when it falls behind them, loses its peers or starts syncing again.
+//!
+//! Every check prints a JSON line with both sides' heads. A problem has to last
+//! `--alert-after` checks in a row before it alerts, so one slow answer does not page
+//! anyone, and a `recovered` line follows once the node is back in line.
+//!
+//! With `--csv`, our node's heights, peers and sync target are also appended to a CSV
+//! file every `--sample-interval`, for charting its behaviour over weeks in a spreadsheet.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::time::Instant;
+
+use crate::{clock, config, fleet};
+
+#[derive(clap::Args, Debug)]
+pub struct WatchdogOptions {
+    #[clap(long, help = "Our node's endpoint, e.g. ws://127.0.0.1:9944.")]
+    pub local: String,
+    #[clap(long, required = true, value_delimiter = ',', help = "Reference endpoints, profiles or chain bundles from the config file, whose endpoints all count.")]
+    pub reference: Vec<String>,
+    #[clap(long, default_value = "5", help = "Blocks our best or finalized head may trail the references' before it counts as behind.")]
+    pub max_lag: u64,
+    #[clap(long, default_value = "1", help = "Fewest peers our node may have.")]
+    pub min_peers: u64,
+    #[clap(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..), help = "Consecutive failed checks before alerting.")]
+    pub alert_after: u32,
+    #[clap(long, default_value = "12s", value_parser = clock::parse_duration, help = "Time between checks.")]
+    pub interval: Duration,
+    #[clap(long, default_value = "10s", value_parser = clock::parse_duration, help = "Time to wait for each endpoint per check.")]
+    pub timeout: Duration,
+    #[clap(long, help = "CSV file to append a row of our node's time, height, finalized height, peers and sync target to.")]
+    pub csv: Option<PathBuf>,
+    #[clap(long, requires = "csv", value_parser = clock::parse_duration, help = "Time between --csv rows, e.g. 5m, defaults to every check.")]
+    pub sample_interval: Option<Duration>,
+}
+
+const CSV_HEADER: &str = "time,height,finalized,peers,sync_target";
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+async fn genesis(endpoint: &str, max_response_size: Option<usize>) -> Result<String, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let hash = crate::send_and_receive(&mut socket, "chain_getBlockHash", json!([0])).await?;
+    Ok(hash.as_str().ok_or("No genesis hash")?.to_string())
+}
+
+async fn heads(endpoint: &str, max_response_size: Option<usize>) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut socket = crate::connect(endpoint, None, None, max_response_size).await?;
+    let best = crate::send_and_receive(&mut socket, "chain_getHeader", json!([])).await?;
+    let finalized = crate::send_and_receive(&mut socket, "chain_getFinalizedHead", json!([])).await?;
+    let finalized = crate::send_and_receive(&mut socket, "chain_getHeader", json!([finalized])).await?;
+    let health = crate::send_and_receive(&mut socket, "system_health", json!([])).await?;
+    // Light clients and some providers do not expose the sync state
+    let sync = crate::send_and_receive(&mut socket, "system_syncState", json!([])).await.ok();
+    Ok(json!({
+        "endpoint": endpoint,
+        "best": fleet::header_number(&best),
+        "finalized": fleet::header_number(&finalized),
+        "peers": health["peers"],
+        "is_syncing": health["isSyncing"],
+        "sync_target": sync.as_ref().and_then(|sync| sync["highestBlock"].as_u64()),
+    }))
+}
+
+/// Appends a row for our node's report to `path`, with the header first when the file is
+/// new. A node that did not answer gets a row with only the time, so gaps show in charts.
+fn append_sample(path: &Path, time: &str, local: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", CSV_HEADER)?;
+    }
+    let field = |name: &str| local[name].as_u64().map(|value| value.to_string()).unwrap_or_default();
+    writeln!(file, "{},{},{},{},{}", time, field("best"), field("finalized"), field("peers"), field("sync_target"))?;
+    Ok(())
+}
+
+/// What is wrong with our node against the highest heads the references report.
+fn problems(local: &Value, best: u64, finalized: u64, options: &WatchdogOptions) -> Vec<String> {
+    if let Some(error) = local["error"].as_str() {
+        return vec![format!("unreachable: {}", error)];
+    }
+    let mut problems = Vec::new();
+    let best_lag = best.saturating_sub(local["best"].as_u64().unwrap_or_default());
+    if best_lag > options.max_lag {
+        problems.push(format!("best head {} blocks behind", best_lag));
+    }
+    let finalized_lag = finalized.saturating_sub(local["finalized"].as_u64().unwrap_or_default());
+    if finalized_lag > options.max_lag {
+        problems.push(format!("finalized head {} blocks behind", finalized_lag));
+    }
+    let peers = local["peers"].as_u64().unwrap_or_default();
+    if peers < options.min_peers {
+        problems.push(format!("{} peers", peers));
+    }
+    if local["is_syncing"] == true {
+        problems.push("syncing".to_string());
+    }
+    problems
+}
+
+/// Checks `options.local` against the references every `--interval` until stopped, after
+/// making sure they are all on the same chain.
+pub async fn watchdog(options: &WatchdogOptions, max_response_size: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    let references: Vec<String> = options.reference.iter()
+        .flat_map(|reference| config::endpoints(reference))
+        .map(str::to_string)
+        .collect();
+    let local_genesis = genesis(&options.local, max_response_size).await?;
+    for reference in &references {
+        match genesis(reference, max_response_size).await {
+            Ok(hash) if hash != local_genesis => return Err(format!("{} serves another chain than {} (genesis {})", reference, options.local, hash).into()),
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: reference {} failed: {}", reference, crate::redact::text(&e.to_string())),
+        }
+    }
+    eprintln!("Watching {} against {} reference endpoints", options.local, references.len());
+
+    let mut failing = 0u32;
+    let mut alerted = false;
+    let mut last_sample: Option<Instant> = None;
+    loop {
+        let (reports, local) = tokio::join!(
+            fleet::probe_all(&references, options.timeout, |endpoint| heads(endpoint, max_response_size)),
+            fleet::probe_all(std::slice::from_ref(&options.local), options.timeout, |endpoint| heads(endpoint, max_response_size)),
+        );
+        let local = local.into_iter().next().unwrap_or_default();
+        let answered = reports.iter().filter(|report| report.get("error").is_none()).count();
+        let best = reports.iter().filter_map(|report| report["best"].as_u64()).max();
+        let finalized = reports.iter().filter_map(|report| report["finalized"].as_u64()).max();
+
+        let time = clock::format_unix_millis(unix_ms());
+        if let Some(path) = &options.csv {
+            if last_sample.is_none_or(|last| last.elapsed() >= options.sample_interval.unwrap_or_default()) {
+                last_sample = Some(Instant::now());
+                append_sample(path, &time, &local)?;
+            }
+        }
+        let mut record = json!({
+            "kind": "check",
+            "time": time,
+            "local": local,
+            "references_answered": answered,
+            "reference_best": best,
+            "reference_finalized": finalized,
+        });
+        match best.zip(finalized) {
+            Some((best, finalized)) => {
+                let problems = problems(&local, best, finalized, options);
+                failing = if problems.is_empty() { 0 } else { failing + 1 };
+                record["problems"] = json!(problems);
+                crate::output::line(&record.to_string())?;
+                if failing >= options.alert_after && !alerted {
+                    alerted = true;
+                    eprintln!("ALERT: {} {}", options.local, problems.join(", "));
+                    crate::output::line(&json!({ "kind": "alert", "endpoint": options.local, "problems": problems, "checks": failing }).to_string())?;
+                } else if failing == 0 && alerted {
+                    alerted = false;
+                    crate::output::line(&json!({ "kind": "recovered", "endpoint": options.local }).to_string())?;
+                }
+            }
+            // Without a reference there is nothing to compare against, the streak carries on
+            None => {
+                eprintln!("Warning: no reference endpoint answered");
+                crate::output::line(&record.to_string())?;
+            }
+        }
+        tokio::time::sleep(options.interval).await;
+    }
+}
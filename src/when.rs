@@ -0,0 +1,157 @@
+//! Translates between block numbers, slots and wall-clock time, for incident timelines.
+//!
+//! Past blocks are exact, from their `Timestamp::Now`. Future blocks are extrapolated from
+//! the average block time over a recent window. Slots are aligned to unix time on BABE and
+//! Aura chains, so a slot maps straight to a timestamp; finding its block is a binary
+//! search over past timestamps.
+
+use serde_json::{json, Value};
+
+use crate::metadata::{self, Metadata};
+use crate::rpc::Connection;
+use crate::{clock, epoch, storage};
+
+/// Blocks back from the head to average the block time over.
+pub const DEFAULT_WINDOW: u64 = 600;
+
+pub enum Target {
+    Block(u64),
+    Slot(u64),
+}
+
+async fn timestamp_at(socket: &mut Connection, metadata: &Metadata, number: u64) -> Result<u64, Box<dyn std::error::Error>> {
+    let hash = crate::fetch_block_hash(socket, number).await?;
+    Ok(storage::read_u64(socket, metadata, "Timestamp", "Now", &[], Some(&hash)).await?.unwrap_or_default())
+}
+
+/// The last block with a timestamp at or before `millis`, by binary search up to `head`.
+async fn block_at(socket: &mut Connection, metadata: &Metadata, millis: u64, head: u64) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let (mut low, mut high) = (0, head);
+    let mut found = (0, 0);
+    while low <= high {
+        let middle = low + (high - low) / 2;
+        let timestamp = timestamp_at(socket, metadata, middle).await?;
+        if timestamp <= millis {
+            found = (middle, timestamp);
+            low = middle + 1;
+        } else if middle == 0 {
+            break;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Ok(found)
+}
+
+pub async fn when(socket: &mut Connection, target: Target, window: u64, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.storage_entry("Timestamp", "Now").is_none() {
+        return Err("Timestamp pallet not found, the chain has no on-chain time".into());
+    }
+    let slot_duration_ms = epoch::slot_duration_ms(&metadata).ok_or("Unable to determine the slot duration")?;
+    let head = crate::fetch_header_number(socket, at).await?;
+    let head_time = timestamp_at(socket, &metadata, head).await?;
+
+    // Blocks before the first timestamped one (the genesis) would skew the average
+    let window_start = head.saturating_sub(window).max(1);
+    let average_block_ms = if window_start < head {
+        let start_time = timestamp_at(socket, &metadata, window_start).await?;
+        head_time.saturating_sub(start_time) / (head - window_start)
+    } else {
+        slot_duration_ms
+    }.max(1);
+
+    let mut result = match target {
+        Target::Block(number) if number <= head => {
+            let timestamp = timestamp_at(socket, &metadata, number).await?;
+            if timestamp == 0 {
+                return Err(format!("block {} has no timestamp (the genesis block never does)", number).into());
+            }
+            json!({
+                "block": number,
+                "estimated": false,
+                "timestamp_ms": timestamp,
+                "time": clock::format_unix_millis(timestamp),
+                "slot": timestamp / slot_duration_ms,
+                "ago": clock::format_duration(head_time.saturating_sub(timestamp) / 1000),
+            })
+        }
+        Target::Block(number) => {
+            let timestamp = head_time + (number - head) * average_block_ms;
+            json!({
+                "block": number,
+                "estimated": true,
+                "timestamp_ms": timestamp,
+                "time": clock::format_unix_millis(timestamp),
+                "slot": timestamp / slot_duration_ms,
+                "in": clock::format_duration((timestamp - head_time) / 1000),
+            })
+        }
+        Target::Slot(slot) => {
+            let timestamp = slot * slot_duration_ms;
+            let mut result = json!({
+                "slot": slot,
+                "timestamp_ms": timestamp,
+                "time": clock::format_unix_millis(timestamp),
+            });
+            if timestamp <= head_time {
+                let (block, block_time) = block_at(socket, &metadata, timestamp, head).await?;
+                // A slot without a block of its own belongs to the last block before it
+                result["block"] = json!(block);
+                result["produced"] = json!(block_time / slot_duration_ms == slot);
+                result["estimated"] = json!(false);
+                result["ago"] = json!(clock::format_duration((head_time - timestamp) / 1000));
+            } else {
+                result["block"] = json!(head + (timestamp - head_time) / average_block_ms);
+                result["estimated"] = json!(true);
+                result["in"] = json!(clock::format_duration((timestamp - head_time) / 1000));
+            }
+            result
+        }
+    };
+    result["head"] = json!({ "block": head, "time": clock::format_unix_millis(head_time), "slot": head_time / slot_duration_ms });
+    result["slot_duration_ms"] = json!(slot_duration_ms);
+    result["average_block_ms"] = json!(average_block_ms);
+    Ok(result)
+}
+
+/// The block closest in time to `millis` (unix milliseconds), searched up to the `--at`
+/// block or the best block.
+pub async fn block_at_time(socket: &mut Connection, millis: u64, at: Option<&str>) -> Result<Value, Box<dyn std::error::Error>> {
+    let at = crate::resolve_at(socket, at).await?;
+    let at = at.as_deref();
+    let metadata = metadata::fetch(socket, at).await?;
+    if metadata.storage_entry("Timestamp", "Now").is_none() {
+        return Err("Timestamp pallet not found, the chain has no on-chain time".into());
+    }
+    let head = crate::fetch_header_number(socket, at).await?;
+    let head_time = timestamp_at(socket, &metadata, head).await?;
+    if millis > head_time {
+        return Err(format!(
+            "{} is after the head, block {} at {}",
+            clock::format_unix_millis(millis), head, clock::format_unix_millis(head_time),
+        ).into());
+    }
+
+    let (mut block, mut block_time) = block_at(socket, &metadata, millis, head).await?;
+    if block == 0 {
+        // The genesis has no timestamp, earlier times go to the first block
+        block = 1.min(head);
+        block_time = timestamp_at(socket, &metadata, block).await?;
+    } else if block < head {
+        let next_time = timestamp_at(socket, &metadata, block + 1).await?;
+        if next_time - millis < millis - block_time {
+            (block, block_time) = (block + 1, next_time);
+        }
+    }
+    Ok(json!({
+        "time": clock::format_unix_millis(millis),
+        "block": block,
+        "block_hash": crate::fetch_block_hash(socket, block).await?,
+        "block_time": clock::format_unix_millis(block_time),
+        "timestamp_ms": block_time,
+        "offset_ms": block_time as i64 - millis as i64,
+    }))
+}